@@ -0,0 +1,43 @@
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Embeds the commit the server is built from as `GIT_SHA` and the build
+/// time as `BUILD_TIMESTAMP`, reported by `/api/version`.
+///
+/// A `GIT_SHA` set in the build environment wins, for builds from a source
+/// archive without `.git`; otherwise the checked out commit is asked from
+/// git, and left unset when that fails. `SOURCE_DATE_EPOCH` overrides the
+/// build time for reproducible builds.
+fn main() {
+    println!("cargo:rerun-if-env-changed=GIT_SHA");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    for path in [".git/HEAD", ".git/refs/heads"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+
+    let sha = std::env::var("GIT_SHA")
+        .ok()
+        .map(|sha| sha.trim().to_string())
+        .filter(|sha| !sha.is_empty())
+        .or_else(|| {
+            let output = Command::new("git").args(["rev-parse", "--short=12", "HEAD"]).output().ok()?;
+            output
+                .status
+                .success()
+                .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        });
+    if let Some(sha) = sha {
+        println!("cargo:rustc-env=GIT_SHA={}", sha);
+    }
+
+    let timestamp = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|secs| secs.trim().parse::<u64>().ok())
+        .or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs()));
+    if let Some(timestamp) = timestamp {
+        println!("cargo:rustc-env=BUILD_TIMESTAMP={}", timestamp);
+    }
+}
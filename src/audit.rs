@@ -0,0 +1,422 @@
+use anyhow::{Context, Result};
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, MatchedPath},
+    http::request::Parts,
+};
+use serde::Serialize;
+use serde_json::Value;
+use std::convert::Infallible;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::client_ip::ClientIp;
+use crate::keys::{self, Authenticated, KeyRole};
+
+/// Where audit entries are written.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum AuditTarget {
+    /// Standard output, interleaved with the regular logs
+    #[default]
+    Stdout,
+    /// An append-only file, rotated by size
+    File(PathBuf),
+}
+
+impl FromStr for AuditTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "" => Err("expected 'stdout' or a file path".to_string()),
+            "stdout" => Ok(Self::Stdout),
+            path => Ok(Self::File(path.into())),
+        }
+    }
+}
+
+impl fmt::Display for AuditTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Stdout => f.write_str("stdout"),
+            Self::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// Who made a request and through which route, for audit entries.
+///
+/// Extracted from the request id header, the key recorded by the auth
+/// middleware, the client address and the matched route; never rejects a
+/// request.
+#[derive(Debug, Clone, Default)]
+pub struct AuditContext {
+    request_id: Option<String>,
+    client_ip: Option<String>,
+    key_fingerprint: Option<String>,
+    key_role: Option<KeyRole>,
+    route: Option<String>,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for AuditContext {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let key = parts.extensions.get::<Authenticated>();
+        Ok(Self {
+            request_id: parts
+                .headers
+                .get("x-request-id")
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_owned),
+            client_ip: parts.extensions.get::<ClientIp>().map(|client| client.shown.clone()),
+            key_fingerprint: key.map(|key| key.fingerprint.clone()),
+            key_role: key.map(|key| key.role),
+            route: parts
+                .extensions
+                .get::<MatchedPath>()
+                .map(|path| path.as_str().to_owned()),
+        })
+    }
+}
+
+/// One audited destructive operation.
+#[derive(Debug, Serialize)]
+pub struct AuditEntry {
+    /// Always `true`; tells audit lines apart from regular logs on stdout
+    audit: bool,
+    /// RFC 3339 UTC time the entry was recorded
+    timestamp: String,
+    request_id: Option<String>,
+    /// Client address, anonymized when `ANONYMIZE_IPS` is on
+    client_ip: Option<String>,
+    key_fingerprint: Option<String>,
+    key_role: Option<KeyRole>,
+    route: Option<String>,
+    /// Operation name, e.g. `reset` or `delete_by_filter`
+    operation: &'static str,
+    /// Shard key the operation was scoped to
+    tenant: Option<String>,
+    /// Collection, ids or filter the operation targeted
+    target: Value,
+    /// Number of points removed, when known
+    points_affected: Option<u64>,
+    /// `success`, `error` or `cancelled`
+    outcome: &'static str,
+    error: Option<String>,
+}
+
+impl AuditEntry {
+    /// Starts an entry for `operation` made in the given request context.
+    pub fn new(context: &AuditContext, operation: &'static str) -> Self {
+        Self {
+            audit: true,
+            timestamp: keys::format_rfc3339(keys::unix_now()),
+            request_id: context.request_id.clone(),
+            client_ip: context.client_ip.clone(),
+            key_fingerprint: context.key_fingerprint.clone(),
+            key_role: context.key_role,
+            route: context.route.clone(),
+            operation,
+            tenant: None,
+            target: Value::Null,
+            points_affected: None,
+            outcome: "success",
+            error: None,
+        }
+    }
+
+    /// Records the shard key the operation was scoped to.
+    pub fn tenant(mut self, tenant: Option<&str>) -> Self {
+        self.tenant = tenant.map(str::to_owned);
+        self
+    }
+
+    /// Records what the operation targeted.
+    pub fn target(mut self, target: Value) -> Self {
+        self.target = target;
+        self
+    }
+
+    /// Marks the operation as successful.
+    pub fn succeeded(mut self, points_affected: Option<u64>) -> Self {
+        self.points_affected = points_affected;
+        self
+    }
+
+    /// Marks the operation as failed with the given error.
+    pub fn failed(mut self, error: &anyhow::Error) -> Self {
+        self.outcome = "error";
+        self.error = Some(format!("{:#}", error));
+        self
+    }
+}
+
+/// An audited operation still in progress.
+///
+/// Finish it with `succeeded` or `failed`. If it is dropped unfinished, e.g.
+/// because the client disconnected and the handler was cancelled, it is
+/// recorded as `cancelled`: the operation may or may not have been applied.
+#[must_use = "an unfinished audit entry is recorded as cancelled"]
+pub struct PendingAudit {
+    log: AuditLog,
+    entry: Option<AuditEntry>,
+}
+
+impl PendingAudit {
+    /// Records the operation as successful.
+    pub fn succeeded(mut self, points_affected: Option<u64>) {
+        if let Some(entry) = self.entry.take() {
+            self.log.record(entry.succeeded(points_affected));
+        }
+    }
+
+    /// Records the operation as failed with the given error.
+    pub fn failed(mut self, error: &anyhow::Error) {
+        if let Some(entry) = self.entry.take() {
+            self.log.record(entry.failed(error));
+        }
+    }
+}
+
+impl Drop for PendingAudit {
+    fn drop(&mut self) {
+        if let Some(mut entry) = self.entry.take() {
+            entry.outcome = "cancelled";
+            entry.error = Some("request was cancelled before the operation finished".into());
+            self.log.record(entry);
+        }
+    }
+}
+
+enum Command {
+    Write(String),
+    Flush(oneshot::Sender<()>),
+}
+
+/// Append-only JSON-lines audit log.
+///
+/// Entries are handed to a background task that writes them through a
+/// buffered writer, flushing after every batch and on `flush`. File
+/// targets are rotated to `<path>.1`, `<path>.2`, ... once they reach
+/// `max_bytes`.
+#[derive(Clone)]
+pub struct AuditLog {
+    tx: mpsc::UnboundedSender<Command>,
+}
+
+impl AuditLog {
+    /// Opens the target and starts the writer task.
+    ///
+    /// # Arguments
+    /// * `target` - Stdout or the path of the audit file
+    /// * `max_bytes` - Rotate the file once it reaches this size (0 disables rotation)
+    /// * `max_files` - Number of rotated files kept
+    pub async fn open(target: &AuditTarget, max_bytes: u64, max_files: usize) -> Result<Self> {
+        let writer = match target {
+            AuditTarget::Stdout => Writer::stdout(),
+            AuditTarget::File(path) => Writer::file(path.clone(), max_bytes, max_files).await?,
+        };
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(writer.run(rx));
+        Ok(Self { tx })
+    }
+
+    /// Queues an entry for writing.
+    pub fn record(&self, entry: AuditEntry) {
+        match serde_json::to_string(&entry) {
+            Ok(line) => {
+                if self.tx.send(Command::Write(line)).is_err() {
+                    tracing::error!(operation = entry.operation, "Audit writer has stopped; entry lost");
+                }
+            }
+            Err(e) => tracing::error!("Failed to serialize audit entry: {}", e),
+        }
+    }
+
+    /// Starts auditing an operation that is about to run.
+    ///
+    /// The entry is written when the returned guard is finished or dropped,
+    /// so it is not lost when the request future is dropped mid-operation.
+    pub fn begin(&self, entry: AuditEntry) -> PendingAudit {
+        PendingAudit {
+            log: self.clone(),
+            entry: Some(entry),
+        }
+    }
+
+    /// Waits until every queued entry has been written and flushed.
+    pub async fn flush(&self) {
+        let (done_tx, done_rx) = oneshot::channel();
+        if self.tx.send(Command::Flush(done_tx)).is_ok() {
+            done_rx.await.ok();
+        }
+    }
+}
+
+/// Background writer owning the output.
+struct Writer {
+    out: BufWriter<Box<dyn AsyncWrite + Send + Unpin>>,
+    /// Rotation settings; `None` for stdout
+    rotation: Option<Rotation>,
+}
+
+struct Rotation {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    written: u64,
+}
+
+impl Writer {
+    fn stdout() -> Self {
+        Self {
+            out: BufWriter::new(Box::new(tokio::io::stdout())),
+            rotation: None,
+        }
+    }
+
+    async fn file(path: PathBuf, max_bytes: u64, max_files: usize) -> Result<Self> {
+        let file = open_append(&path).await?;
+        let written = file.metadata().await.map(|m| m.len()).unwrap_or(0);
+        Ok(Self {
+            out: BufWriter::new(Box::new(file)),
+            rotation: Some(Rotation {
+                path,
+                max_bytes,
+                max_files: max_files.max(1),
+                written,
+            }),
+        })
+    }
+
+    async fn run(mut self, mut rx: mpsc::UnboundedReceiver<Command>) {
+        while let Some(command) = rx.recv().await {
+            let mut pending = vec![command];
+            // Write everything that queued up meanwhile before flushing once
+            while let Ok(command) = rx.try_recv() {
+                pending.push(command);
+            }
+
+            let mut waiters = Vec::new();
+            for command in pending {
+                match command {
+                    Command::Write(line) => {
+                        if let Err(e) = self.write_line(&line).await {
+                            tracing::error!("Failed to write audit entry: {:#}", e);
+                        }
+                    }
+                    Command::Flush(done) => waiters.push(done),
+                }
+            }
+            if let Err(e) = self.out.flush().await {
+                tracing::error!("Failed to flush audit log: {}", e);
+            }
+            for done in waiters {
+                done.send(()).ok();
+            }
+        }
+    }
+
+    async fn write_line(&mut self, line: &str) -> Result<()> {
+        let len = line.len() as u64 + 1;
+        if let Some(rotation) = &self.rotation {
+            if rotation.max_bytes > 0 && rotation.written > 0 && rotation.written + len > rotation.max_bytes {
+                self.rotate().await?;
+            }
+        }
+        self.out.write_all(line.as_bytes()).await?;
+        self.out.write_all(b"\n").await?;
+        if let Some(rotation) = &mut self.rotation {
+            rotation.written += len;
+        }
+        Ok(())
+    }
+
+    /// Shifts `<path>.N` to `<path>.N+1`, moves the current file to `<path>.1`
+    /// and starts a new one. The oldest file beyond `max_files` is overwritten.
+    async fn rotate(&mut self) -> Result<()> {
+        let Some(rotation) = &mut self.rotation else {
+            return Ok(());
+        };
+        self.out.flush().await?;
+
+        let rotated = |n: usize| {
+            let mut name = rotation.path.clone().into_os_string();
+            name.push(format!(".{}", n));
+            PathBuf::from(name)
+        };
+        for n in (1..rotation.max_files).rev() {
+            match tokio::fs::rename(rotated(n), rotated(n + 1)).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        tokio::fs::rename(&rotation.path, rotated(1))
+            .await
+            .with_context(|| format!("failed to rotate audit log {}", rotation.path.display()))?;
+
+        self.out = BufWriter::new(Box::new(open_append(&rotation.path).await?));
+        rotation.written = 0;
+        Ok(())
+    }
+}
+
+async fn open_append(path: &Path) -> Result<tokio::fs::File> {
+    tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+        .with_context(|| format!("failed to open audit log {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    async fn entries(path: &Path) -> Vec<Value> {
+        let text = tokio::fs::read_to_string(path).await.expect("audit log is readable");
+        text.lines().map(|line| serde_json::from_str(line).expect("entry is JSON")).collect()
+    }
+
+    #[tokio::test]
+    async fn operations_cut_short_are_recorded_as_cancelled() {
+        let path = std::env::temp_dir().join(format!("rust-qdrant-audit-{}.log", uuid::Uuid::new_v4()));
+        let log = AuditLog::open(&AuditTarget::File(path.clone()), 0, 1).await.unwrap();
+        let context = AuditContext::default();
+
+        // A handler dropped mid-operation, like one whose client disconnected
+        let entry = log.begin(AuditEntry::new(&context, "reset").target(serde_json::json!({ "collection": "docs" })));
+        let operation = async move {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            entry.succeeded(Some(1));
+        };
+        assert!(tokio::time::timeout(Duration::from_millis(50), operation).await.is_err());
+
+        log.begin(AuditEntry::new(&context, "purge_trash")).succeeded(Some(3));
+        log.begin(AuditEntry::new(&context, "delete_by_ids")).failed(&anyhow::anyhow!("Qdrant is down"));
+        log.flush().await;
+
+        let entries = entries(&path).await;
+        tokio::fs::remove_file(&path).await.ok();
+        let outcomes: Vec<_> = entries.iter().map(|e| (e["operation"].as_str(), e["outcome"].as_str())).collect();
+        assert_eq!(
+            outcomes,
+            [
+                (Some("reset"), Some("cancelled")),
+                (Some("purge_trash"), Some("success")),
+                (Some("delete_by_ids"), Some("error"))
+            ]
+        );
+        assert_eq!(entries[0]["target"]["collection"], "docs");
+        assert!(entries[0]["points_affected"].is_null());
+        assert_eq!(entries[1]["points_affected"], 3);
+        assert_eq!(entries[2]["error"], "Qdrant is down");
+    }
+}
@@ -0,0 +1,455 @@
+use anyhow::{anyhow, Context, Result};
+use axum::http::Method;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+use crate::keys::{format_rfc3339, year_month};
+use crate::paths;
+
+/// OpenAI tokens with budgets of their own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenKind {
+    /// Tokens sent to embedding models
+    Embedding,
+    /// Prompt and completion tokens of chat models, including summaries
+    Chat,
+}
+
+impl TokenKind {
+    /// Name used in configuration, metrics and responses.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Embedding => "embedding",
+            Self::Chat => "chat",
+        }
+    }
+}
+
+/// Calendar period (UTC) a budget applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Period {
+    Daily,
+    Monthly,
+}
+
+impl Period {
+    /// Name used in metrics and responses.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Daily => "daily",
+            Self::Monthly => "monthly",
+        }
+    }
+}
+
+/// Token limits per kind for one period.
+///
+/// Parsed from a comma-separated list of `kind=tokens` entries, e.g.
+/// `embedding=5000000,chat=200000`; a kind that is left out is unlimited.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BudgetLimits {
+    pub embedding: Option<u64>,
+    pub chat: Option<u64>,
+}
+
+impl BudgetLimits {
+    /// Returns the limit for a kind, if any.
+    pub fn get(&self, kind: TokenKind) -> Option<u64> {
+        match kind {
+            TokenKind::Embedding => self.embedding,
+            TokenKind::Chat => self.chat,
+        }
+    }
+}
+
+impl FromStr for BudgetLimits {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut limits = Self::default();
+        for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (kind, tokens) = entry
+                .split_once('=')
+                .ok_or_else(|| anyhow!("expected 'kind=tokens', got '{}'", entry))?;
+            let tokens = tokens
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| anyhow!("invalid token count '{}' for {}", tokens, kind))?;
+            match kind.trim() {
+                "embedding" => limits.embedding = Some(tokens),
+                "chat" => limits.chat = Some(tokens),
+                other => return Err(anyhow!("unknown kind '{}' (expected embedding or chat)", other)),
+            }
+        }
+        Ok(limits)
+    }
+}
+
+/// A hard limit that refuses further OpenAI calls.
+#[derive(Debug, Clone)]
+pub struct BudgetExceeded {
+    pub kind: TokenKind,
+    pub period: Period,
+    pub limit: u64,
+    /// RFC 3339 UTC time at which the period ends
+    pub resets_at: String,
+}
+
+impl BudgetExceeded {
+    /// Machine-readable code returned as `error_code`.
+    pub fn code(&self) -> &'static str {
+        match self.period {
+            Period::Daily => "daily_budget_exceeded",
+            Period::Monthly => "monthly_budget_exceeded",
+        }
+    }
+
+    /// Human-readable explanation returned as `error`.
+    pub fn message(&self) -> String {
+        format!(
+            "The {} {} token budget of {} tokens is used up; it resets at {}",
+            self.period.as_str(),
+            self.kind.as_str(),
+            self.limit,
+            self.resets_at
+        )
+    }
+}
+
+/// A budget threshold crossed by recorded usage.
+#[derive(Debug, Clone, Copy)]
+pub struct Crossing {
+    pub kind: TokenKind,
+    pub period: Period,
+    /// Whether the hard limit (rather than the soft threshold) was reached
+    pub hard: bool,
+    pub used: u64,
+    pub limit: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+struct KindTokens {
+    embedding: u64,
+    chat: u64,
+}
+
+impl KindTokens {
+    fn get_mut(&mut self, kind: TokenKind) -> &mut u64 {
+        match kind {
+            TokenKind::Embedding => &mut self.embedding,
+            TokenKind::Chat => &mut self.chat,
+        }
+    }
+
+    fn get(&self, kind: TokenKind) -> u64 {
+        match kind {
+            TokenKind::Embedding => self.embedding,
+            TokenKind::Chat => self.chat,
+        }
+    }
+}
+
+/// Token totals of the current day and month, as stored on disk.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Counters {
+    /// Days since 1970-01-01 (UTC) the daily totals belong to
+    day: u64,
+    /// Year and month (UTC) the monthly totals belong to
+    month: (i64, i64),
+    daily: KindTokens,
+    monthly: KindTokens,
+    /// Monthly tokens per API key fingerprint, for `KEY_ENTITLEMENTS` quotas
+    #[serde(default)]
+    keys: HashMap<String, u64>,
+}
+
+impl Counters {
+    /// Clears the totals of periods that have ended by `now`.
+    fn roll(&mut self, now: u64) {
+        if self.day != now / 86_400 {
+            self.day = now / 86_400;
+            self.daily = KindTokens::default();
+        }
+        if self.month != year_month(now) {
+            self.month = year_month(now);
+            self.monthly = KindTokens::default();
+            self.keys.clear();
+        }
+    }
+
+    fn totals(&self, period: Period) -> &KindTokens {
+        match period {
+            Period::Daily => &self.daily,
+            Period::Monthly => &self.monthly,
+        }
+    }
+}
+
+/// Global daily and monthly OpenAI token budgets.
+///
+/// Totals are tracked per `TokenKind` for the current UTC day and month
+/// and, when a file is configured, saved by `persist` so a restart keeps
+/// counting where it left off. The monthly tokens of each API key, which
+/// `KEY_ENTITLEMENTS` quotas are checked against, are saved with them.
+#[derive(Debug, Default)]
+pub struct TokenBudget {
+    daily: BudgetLimits,
+    monthly: BudgetLimits,
+    /// Percentage of a limit at which a warning is logged
+    soft_percent: u8,
+    path: Option<PathBuf>,
+    counters: Mutex<Counters>,
+    /// Whether the counters changed since they were last persisted
+    dirty: AtomicBool,
+}
+
+impl TokenBudget {
+    /// Creates the budget, resuming from the totals saved at `path`, if any.
+    ///
+    /// # Arguments
+    /// * `daily` - Limits per UTC day
+    /// * `monthly` - Limits per UTC calendar month
+    /// * `soft_percent` - Percentage of a limit that triggers a warning
+    /// * `path` - File the totals are saved to; kept in memory only when `None`
+    pub async fn load(
+        daily: BudgetLimits,
+        monthly: BudgetLimits,
+        soft_percent: u8,
+        path: Option<PathBuf>,
+    ) -> Result<Self> {
+        let counters = match &path {
+            Some(path) => match tokio::fs::read(path).await {
+                Ok(bytes) => serde_json::from_slice(&bytes)
+                    .with_context(|| format!("failed to parse token usage file {}", path.display()))?,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Counters::default(),
+                Err(e) => {
+                    return Err(e)
+                        .with_context(|| format!("failed to read token usage file {}", path.display()))
+                }
+            },
+            None => Counters::default(),
+        };
+        Ok(Self {
+            daily,
+            monthly,
+            soft_percent,
+            path,
+            counters: Mutex::new(counters),
+            dirty: AtomicBool::new(false),
+        })
+    }
+
+    /// Checks whether requests using `kinds` may still call OpenAI.
+    pub fn check(&self, kinds: &[TokenKind], now: u64) -> Result<(), BudgetExceeded> {
+        let counters = self.lock(now);
+        for &kind in kinds {
+            for period in [Period::Daily, Period::Monthly] {
+                if let Some(limit) = self.limits(period).get(kind) {
+                    if counters.totals(period).get(kind) >= limit {
+                        return Err(BudgetExceeded {
+                            kind,
+                            period,
+                            limit,
+                            resets_at: format_rfc3339(period_end(period, now)),
+                        });
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds tokens to the current totals.
+    ///
+    /// # Returns
+    /// The soft thresholds and hard limits these tokens crossed
+    pub fn record(&self, kind: TokenKind, tokens: u64, now: u64) -> Vec<Crossing> {
+        if tokens == 0 {
+            return Vec::new();
+        }
+        let mut counters = self.lock(now);
+        let mut crossings = Vec::new();
+        for period in [Period::Daily, Period::Monthly] {
+            let total = match period {
+                Period::Daily => counters.daily.get_mut(kind),
+                Period::Monthly => counters.monthly.get_mut(kind),
+            };
+            let before = *total;
+            *total += tokens;
+            let Some(limit) = self.limits(period).get(kind) else {
+                continue;
+            };
+            let soft = limit * u64::from(self.soft_percent) / 100;
+            for (threshold, hard) in [(soft, false), (limit, true)] {
+                if before < threshold && *total >= threshold {
+                    crossings.push(Crossing { kind, period, hard, used: *total, limit });
+                }
+            }
+        }
+        self.dirty.store(true, Ordering::Relaxed);
+        crossings
+    }
+
+    /// Adds tokens to the monthly total of the API key with the given fingerprint.
+    pub fn record_key(&self, fingerprint: &str, tokens: u64, now: u64) {
+        if tokens == 0 {
+            return;
+        }
+        *self.lock(now).keys.entry(fingerprint.to_string()).or_default() += tokens;
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns the tokens the API key with the given fingerprint used this month.
+    pub fn key_used(&self, fingerprint: &str, now: u64) -> u64 {
+        self.lock(now).keys.get(fingerprint).copied().unwrap_or(0)
+    }
+
+    /// Describes the usage and limits of every kind and period.
+    pub fn status(&self, now: u64) -> serde_json::Value {
+        let counters = self.lock(now);
+        let describe = |period: Period| {
+            let totals = counters.totals(period);
+            let limits = self.limits(period);
+            let kinds: serde_json::Map<String, serde_json::Value> = [TokenKind::Embedding, TokenKind::Chat]
+                .into_iter()
+                .map(|kind| {
+                    let used = totals.get(kind);
+                    let limit = limits.get(kind);
+                    let status = serde_json::json!({
+                        "used_tokens": used,
+                        "limit": limit,
+                        "remaining_tokens": limit.map(|limit| limit.saturating_sub(used))
+                    });
+                    (kind.as_str().to_string(), status)
+                })
+                .collect();
+            serde_json::json!({
+                "resets_at": format_rfc3339(period_end(period, now)),
+                "kinds": kinds
+            })
+        };
+        serde_json::json!({
+            "soft_limit_percent": self.soft_percent,
+            "daily": describe(Period::Daily),
+            "monthly": describe(Period::Monthly)
+        })
+    }
+
+    /// Clears the totals of the current day and month. Per-key totals are kept.
+    pub fn reset(&self, now: u64) {
+        let mut counters = self.lock(now);
+        counters.daily = KindTokens::default();
+        counters.monthly = KindTokens::default();
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Saves the totals if they changed since the last call.
+    ///
+    /// The file is replaced atomically through a temporary file next to it.
+    pub async fn persist(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let counters = self.counters.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        let result = write_atomic(path, &serde_json::to_vec(&counters)?).await;
+        if result.is_err() {
+            // Retry with the next call
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// Returns whether any limit is configured.
+    pub fn is_limited(&self) -> bool {
+        [self.daily, self.monthly]
+            .iter()
+            .any(|limits| limits.embedding.is_some() || limits.chat.is_some())
+    }
+
+    fn limits(&self, period: Period) -> &BudgetLimits {
+        match period {
+            Period::Daily => &self.daily,
+            Period::Monthly => &self.monthly,
+        }
+    }
+
+    /// Locks the totals, clearing those of periods that have ended.
+    fn lock(&self, now: u64) -> MutexGuard<'_, Counters> {
+        let mut counters = self.counters.lock().unwrap_or_else(|e| e.into_inner());
+        counters.roll(now);
+        counters
+    }
+}
+
+/// Kinds of OpenAI tokens a route uses, so budgets can be checked before
+/// the request runs.
+pub fn route_kinds(method: &Method, route: &str) -> &'static [TokenKind] {
+    match route {
+        paths::CHAT => &[TokenKind::Chat],
+        paths::ASK => &[TokenKind::Embedding, TokenKind::Chat],
+        paths::EMBED
+        | paths::COMPARE_MODELS
+        | paths::SEARCH
+        | paths::SCORE_DISTRIBUTION
+        | paths::SIMILARITY
+        | paths::ADMIN_REINDEX => &[TokenKind::Embedding],
+        paths::DOCUMENTS if method == Method::POST => &[TokenKind::Embedding],
+        paths::IMPORT | paths::RESTORE_VERSION => &[TokenKind::Embedding],
+        _ => &[],
+    }
+}
+
+/// Unix time in seconds at which the period containing `now` ends.
+fn period_end(period: Period, now: u64) -> u64 {
+    match period {
+        Period::Daily => (now / 86_400 + 1) * 86_400,
+        Period::Monthly => {
+            // Step day by day to the 1st of the next month
+            let (year, month) = year_month(now);
+            let mut end = now - now % 86_400;
+            while year_month(end) == (year, month) {
+                end += 86_400;
+            }
+            end
+        }
+    }
+}
+
+async fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut tmp = path.to_path_buf().into_os_string();
+    tmp.push(".tmp");
+    tokio::fs::write(&tmp, contents)
+        .await
+        .with_context(|| format!("failed to write token usage file {}", path.display()))?;
+    tokio::fs::rename(&tmp, path)
+        .await
+        .with_context(|| format!("failed to replace token usage file {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_totals_last_the_month_and_outlive_a_budget_reset() {
+        let budget = TokenBudget::default();
+        // 2025-01-31 and 2025-02-01 (UTC)
+        let (january, february) = (1_738_281_600, 1_738_368_000);
+
+        budget.record_key("sha256:a", 40, january);
+        budget.record_key("sha256:a", 2, january);
+        budget.record(TokenKind::Chat, 42, january);
+        budget.reset(january);
+        assert_eq!(budget.key_used("sha256:a", january), 42);
+        assert_eq!(budget.key_used("sha256:b", january), 0);
+        assert_eq!(budget.key_used("sha256:a", february), 0);
+    }
+}
@@ -0,0 +1,90 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::config::Config;
+use crate::keys::{format_rfc3339, KeyRole};
+use crate::services::conversations::SessionBackend;
+
+/// Version of the crate the server was built from.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Commit the server was built from, embedded by the build script.
+pub const GIT_SHA: &str = match option_env!("GIT_SHA") {
+    Some(sha) => sha,
+    None => "unknown",
+};
+
+/// When the build script last ran, in seconds since the epoch.
+const BUILD_TIMESTAMP: Option<&str> = option_env!("BUILD_TIMESTAMP");
+
+/// What `/api/version` reports about the build and the running instance.
+#[derive(Debug, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_sha: &'static str,
+    /// RFC 3339 time of the build, `None` when the build script did not record it
+    pub build_timestamp: Option<String>,
+    /// Optional features by name and whether this instance serves them
+    pub features: BTreeMap<&'static str, bool>,
+    pub models: Models,
+}
+
+/// Models the instance is configured with.
+#[derive(Debug, Serialize)]
+pub struct Models {
+    pub embedding: String,
+    /// Models `/api/chat` accepts
+    pub chat: Vec<String>,
+    pub rewrite: String,
+    pub vision: Option<String>,
+}
+
+impl BuildInfo {
+    /// Describes the build and what `config` turns on.
+    ///
+    /// Features are read from the running configuration rather than fixed
+    /// at compile time, so a feature switched off is reported as such.
+    pub fn new(config: &Config) -> Self {
+        let openai = !config.disable_openai;
+        let features = BTreeMap::from([
+            ("openai", openai),
+            ("chat_images", openai && config.vision_model.is_some()),
+            ("history_summary", openai && config.history_summary_threshold > 0),
+            ("persistent_sessions", config.session_store == SessionBackend::Qdrant),
+            ("document_versions", config.document_versions > 0),
+            ("trash_auto_purge", config.trash_auto_purge),
+            ("stale_reembed", config.stale_reembed_interval_secs > 0),
+            ("chat_cache", config.chat_cache_ttl_secs > 0),
+            ("embed_coalescing", config.embed_coalesce_window_ms > 0),
+            ("search_coalescing", config.search_coalescing),
+            ("reset", config.enable_reset),
+            ("admin", config.api_keys.has_role(KeyRole::Admin)),
+            ("compression", config.compression_enabled),
+            ("tls", config.tls_cert_path.is_some()),
+            ("demo_mode", config.demo_mode),
+        ]);
+        Self {
+            version: VERSION,
+            git_sha: GIT_SHA,
+            build_timestamp: BUILD_TIMESTAMP
+                .and_then(|secs| secs.parse().ok())
+                .map(format_rfc3339),
+            features,
+            models: Models {
+                embedding: config.embedding_model.clone(),
+                chat: config.chat_models.clone(),
+                rewrite: config.rewrite_model.clone(),
+                vision: config.vision_model.clone(),
+            },
+        }
+    }
+
+    /// Names of the features turned on, for logging.
+    pub fn enabled_features(&self) -> Vec<&'static str> {
+        self.features
+            .iter()
+            .filter(|(_, enabled)| **enabled)
+            .map(|(name, _)| *name)
+            .collect()
+    }
+}
@@ -0,0 +1,335 @@
+use axum::{
+    extract::ConnectInfo,
+    http::{HeaderMap, Request},
+};
+use ipnet::IpNet;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Address a request came from, stored in the request extensions.
+#[derive(Debug, Clone)]
+pub struct ClientIp {
+    pub addr: IpAddr,
+    /// The address as written to logs and audit entries, anonymized when
+    /// `ANONYMIZE_IPS` is on
+    pub shown: String,
+}
+
+impl ClientIp {
+    pub fn new(addr: IpAddr, anonymize: bool) -> Self {
+        let shown = if anonymize { anonymize_ip(addr) } else { addr.to_string() };
+        Self { addr, shown }
+    }
+}
+
+/// Which peers may report the client address in forwarding headers.
+///
+/// Headers from other peers are ignored, since anyone can set them.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies {
+    /// Trust whichever peer connects (`TRUST_PROXY`); only safe when the
+    /// server can't be reached except through a proxy
+    pub any: bool,
+    /// Proxies trusted by address (`TRUSTED_PROXIES`); also skipped when
+    /// walking the forwarding chain back to the client
+    pub networks: IpList,
+}
+
+impl TrustedProxies {
+    fn trusts(&self, peer: Option<IpAddr>) -> bool {
+        self.any || peer.is_some_and(|peer| self.networks.contains(peer))
+    }
+}
+
+/// Returns the address of the client that sent a request.
+///
+/// The socket peer is the client unless it is a trusted proxy. Then the
+/// forwarding chain from `Forwarded`, `X-Forwarded-For` or `X-Real-IP`
+/// (the first one present) is walked from the right, skipping the
+/// addresses of trusted proxies, and the first other entry is the client.
+/// Entries further left were written by the client or untrusted hops and
+/// are never used.
+///
+/// # Returns
+/// * `Some(IpAddr)` - The client's address
+/// * `None` - If it is unknown, e.g. on a unix socket without forwarding
+///   headers or when a proxy reported an obfuscated address
+pub fn resolve<B>(request: &Request<B>, proxies: &TrustedProxies) -> Option<IpAddr> {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip().to_canonical());
+    if !proxies.trusts(peer) {
+        return peer;
+    }
+
+    let chain = forwarding_chain(request.headers());
+    let mut hops = chain.iter().rev();
+    match hops.find(|hop| !hop.is_some_and(|addr| proxies.networks.contains(addr))) {
+        Some(hop) => *hop,
+        // Every hop is a trusted proxy, so the leftmost is the client
+        None => chain.first().copied().flatten().or(peer),
+    }
+}
+
+/// Reads the forwarding chain, client first, from the `Forwarded` header,
+/// falling back to `X-Forwarded-For` and then `X-Real-IP`.
+///
+/// Entries that aren't IP addresses are `None`.
+fn forwarding_chain(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let entries = |name: &str| -> Vec<&str> {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .collect()
+    };
+
+    let forwarded = entries("forwarded");
+    if !forwarded.is_empty() {
+        return forwarded
+            .into_iter()
+            .map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.split_once('='))
+                    .find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+                    .and_then(|(_, node)| parse_node(node.trim().trim_matches('"')))
+            })
+            .collect();
+    }
+    let forwarded_for = entries("x-forwarded-for");
+    if !forwarded_for.is_empty() {
+        return forwarded_for.into_iter().map(parse_node).collect();
+    }
+    entries("x-real-ip").into_iter().map(parse_node).collect()
+}
+
+/// Parses a forwarded node: an address with an optional port, IPv6 in
+/// brackets when it has one. Obfuscated and `unknown` nodes yield `None`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Ok(addr) = node.parse::<IpAddr>() {
+        return Some(addr.to_canonical());
+    }
+    if let Some(rest) = node.strip_prefix('[') {
+        let (addr, _) = rest.split_once(']')?;
+        return addr.parse::<Ipv6Addr>().ok().map(|addr| IpAddr::V6(addr).to_canonical());
+    }
+    node.parse::<SocketAddr>().ok().map(|addr| addr.ip().to_canonical())
+}
+
+/// Drops the host part of an address: IPv4 keeps its /24, IPv6 its /48.
+pub fn anonymize_ip(addr: IpAddr) -> String {
+    match addr {
+        IpAddr::V4(v4) => {
+            let [a, b, c, _] = v4.octets();
+            Ipv4Addr::new(a, b, c, 0).to_string()
+        }
+        IpAddr::V6(v6) => {
+            let s = v6.segments();
+            Ipv6Addr::new(s[0], s[1], s[2], 0, 0, 0, 0, 0).to_string()
+        }
+    }
+}
+
+/// A list of addresses and networks, parsed from comma-separated entries
+/// like `10.0.0.0/8,203.0.113.7,2001:db8::/32`.
+#[derive(Debug, Clone, Default)]
+pub struct IpList(Vec<IpNet>);
+
+impl IpList {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns whether the address is in one of the listed networks.
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        self.0.iter().any(|net| net.contains(&addr))
+    }
+}
+
+impl FromStr for IpList {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                entry
+                    .parse::<IpNet>()
+                    .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from))
+                    .map_err(|_| format!("'{}' is not an IP address or CIDR network", entry))
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+impl fmt::Display for IpList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entries: Vec<String> = self.0.iter().map(IpNet::to_string).collect();
+        f.write_str(&entries.join(","))
+    }
+}
+
+/// Why a request was refused because of its address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refusal {
+    /// The address is on the denylist, or not on a configured allowlist
+    Denied,
+    /// The address has used up its requests; retry after the given time
+    RateLimited(Duration),
+}
+
+/// Allowlist and denylist of client addresses.
+///
+/// The denylist wins over the allowlist. With an allowlist configured,
+/// requests whose address is unknown are refused.
+#[derive(Debug, Clone, Default)]
+pub struct IpFilter {
+    pub allow: IpList,
+    pub deny: IpList,
+}
+
+impl IpFilter {
+    /// Checks an address against the lists.
+    pub fn check(&self, addr: Option<IpAddr>) -> Result<(), Refusal> {
+        match addr {
+            Some(addr) if self.deny.contains(addr) => Err(Refusal::Denied),
+            Some(addr) if self.allow.is_empty() || self.allow.contains(addr) => Ok(()),
+            None if self.allow.is_empty() => Ok(()),
+            _ => Err(Refusal::Denied),
+        }
+    }
+}
+
+/// Most buckets kept. At the cap, full buckets are dropped first, since a
+/// full bucket is the same as none, and then the one unused the longest.
+const MAX_BUCKETS: usize = 10_000;
+
+/// Tokens left to a client address and when they were last counted.
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token-bucket rate limiter keyed by client address.
+///
+/// Each address may send `burst` requests at once and then `per_minute`
+/// requests a minute. IPv6 addresses share the bucket of their /64, since a
+/// single client usually has a whole /64 to rotate through.
+pub struct IpRateLimiter {
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+    /// Tokens added per second
+    rate: f64,
+    /// Most tokens a bucket holds
+    burst: f64,
+}
+
+impl IpRateLimiter {
+    /// Creates a limiter with every bucket full.
+    ///
+    /// # Arguments
+    /// * `per_minute` - Requests an address may send per minute
+    /// * `burst` - Requests an address may send at once
+    pub fn new(per_minute: u32, burst: u32) -> Self {
+        Self {
+            buckets: Mutex::new(HashMap::new()),
+            rate: f64::from(per_minute) / 60.0,
+            burst: f64::from(burst),
+        }
+    }
+
+    /// Takes a token from the address's bucket.
+    ///
+    /// # Returns
+    /// * `Ok(())` - If the request may proceed
+    /// * `Err(Refusal::RateLimited)` - With the time until a token is available
+    pub fn check(&self, addr: IpAddr) -> Result<(), Refusal> {
+        let key = match addr {
+            IpAddr::V6(v6) => {
+                let s = v6.segments();
+                IpAddr::V6(Ipv6Addr::new(s[0], s[1], s[2], s[3], 0, 0, 0, 0))
+            }
+            v4 => v4,
+        };
+        let now = Instant::now();
+        // A panic while holding the lock cannot leave a bucket inconsistent
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+
+        if buckets.len() >= MAX_BUCKETS && !buckets.contains_key(&key) {
+            buckets.retain(|_, bucket| self.refilled(bucket, now) < self.burst);
+            if buckets.len() >= MAX_BUCKETS {
+                let oldest = buckets
+                    .iter()
+                    .min_by_key(|(_, bucket)| bucket.updated)
+                    .map(|(addr, _)| *addr);
+                if let Some(oldest) = oldest {
+                    buckets.remove(&oldest);
+                }
+            }
+        }
+        let bucket = buckets.entry(key).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        bucket.tokens = self.refilled(bucket, now);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            let wait = (1.0 - bucket.tokens) / self.rate;
+            Err(Refusal::RateLimited(Duration::from_secs_f64(wait)))
+        }
+    }
+
+    /// Tokens in a bucket at `now`, capped at `burst`.
+    fn refilled(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.rate).min(self.burst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_limit_buckets_are_capped() {
+        let limiter = IpRateLimiter::new(1, 1);
+        let addr = |i: u32| IpAddr::V4(Ipv4Addr::from(0x0a00_0000 + i));
+        for i in 0..MAX_BUCKETS as u32 {
+            assert!(limiter.check(addr(i)).is_ok());
+        }
+
+        // Every bucket is in use, so the longest unused one makes room
+        let newcomer = addr(MAX_BUCKETS as u32);
+        assert!(limiter.check(newcomer).is_ok());
+        assert!(matches!(limiter.check(newcomer), Err(Refusal::RateLimited(_))));
+        assert_eq!(limiter.buckets.lock().unwrap().len(), MAX_BUCKETS);
+    }
+
+    #[test]
+    fn full_buckets_are_dropped_first() {
+        // A token a millisecond refills a bucket right away
+        let limiter = IpRateLimiter::new(60_000, 1);
+        let addr = |i: u32| IpAddr::V4(Ipv4Addr::from(0x0a00_0000 + i));
+        for i in 0..MAX_BUCKETS as u32 {
+            assert!(limiter.check(addr(i)).is_ok());
+        }
+        std::thread::sleep(Duration::from_millis(5));
+
+        assert!(limiter.check(addr(MAX_BUCKETS as u32)).is_ok());
+        assert_eq!(limiter.buckets.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,123 @@
+pub mod openai;
+pub mod qdrant;
+
+use anyhow::{Context, Result};
+use qdrant_client::qdrant::{
+    collections_server::CollectionsServer, points_server::PointsServer, qdrant_server::QdrantServer,
+};
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tonic::transport::server::TcpIncoming;
+
+use self::qdrant::DemoQdrant;
+
+/// Addresses of the stand-ins started for `DEMO_MODE`.
+pub struct DemoUpstreams {
+    /// Base URL of the stub OpenAI API, used instead of api.openai.com
+    pub openai_base: String,
+    /// URL of the in-memory Qdrant, used instead of `QDRANT_URL`
+    pub qdrant_url: String,
+}
+
+/// Starts a stub OpenAI API and an in-memory Qdrant on loopback ports.
+///
+/// Both run as tasks of this process and stop with it. The rest of the
+/// service talks to them over HTTP and gRPC like to the real upstreams, so
+/// every endpoint works without credentials, network access or a Qdrant
+/// container.
+///
+/// # Returns
+/// * `Ok(DemoUpstreams)` - Where the stand-ins listen
+/// * `Err(anyhow::Error)` - If a loopback port cannot be bound
+pub async fn start() -> Result<DemoUpstreams> {
+    let openai_listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+        .context("binding the demo OpenAI listener failed")?;
+    let openai_base = format!("http://{}/v1", openai_listener.local_addr()?);
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(openai_listener, openai::router()).await {
+            tracing::error!("Demo OpenAI stub stopped: {}", e);
+        }
+    });
+
+    let qdrant_listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+        .context("binding the demo Qdrant listener failed")?;
+    let qdrant_url = format!("http://{}", qdrant_listener.local_addr()?);
+    let store = Arc::new(DemoQdrant::default());
+    let qdrant = tonic::transport::Server::builder()
+        .add_service(QdrantServer::from_arc(store.clone()))
+        .add_service(CollectionsServer::from_arc(store.clone()))
+        .add_service(PointsServer::from_arc(store));
+    tokio::spawn(async move {
+        if let Err(e) = qdrant.serve_with_incoming(TcpIncoming::from(qdrant_listener)).await {
+            tracing::error!("Demo Qdrant stopped: {}", e);
+        }
+    });
+
+    Ok(DemoUpstreams { openai_base, qdrant_url })
+}
+
+/// Warns at startup that nothing this instance does is real.
+pub fn log_banner() {
+    tracing::warn!("==================================================================");
+    tracing::warn!(" DEMO MODE");
+    tracing::warn!(" OpenAI is replaced by a stub: embeddings are hashed from the");
+    tracing::warn!(" words of each text and chat answers echo the prompt.");
+    tracing::warn!(" Qdrant is replaced by an in-memory store: nothing is persisted.");
+    tracing::warn!(" Every response is marked with \"demo\": true.");
+    tracing::warn!("==================================================================");
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+
+    use crate::middleware::DEMO_HEADER;
+    use crate::test_support;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn search_returns_the_nearest_document() {
+        let app = test_support::app(&[]).await;
+        for text in [
+            "Rust is a systems programming language",
+            "Bananas are rich in potassium",
+            "The Eiffel tower is in Paris",
+        ] {
+            let stored = app.post("/api/documents", &json!({ "text": text })).await;
+            assert_eq!(stored.status, StatusCode::OK, "{}", stored.text);
+        }
+        let count = app.state.qdrant_service.count_documents(false, None).await.expect("count");
+        assert_eq!(count, 3);
+
+        let found = app
+            .post("/api/search", &json!({ "query": "which programming language is Rust", "limit": 3 }))
+            .await;
+        assert_eq!(found.status, StatusCode::OK, "{}", found.text);
+        let hits = found.body["data"]["hits"].as_array().expect("hits");
+        assert_eq!(hits.len(), 3);
+        assert_eq!(hits[0]["text"], "Rust is a systems programming language");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn responses_are_labelled_as_demo() {
+        let app = test_support::app(&[]).await;
+
+        let ok = app.post("/api/tokenize", &json!({ "text": "hello" })).await;
+        assert_eq!(ok.body["demo"], true);
+        assert_eq!(ok.headers[DEMO_HEADER], "true");
+
+        // Refusals by the middleware are labelled too
+        let refused = app.call(Method::POST, "/api/tokenize", None).await;
+        assert_eq!(refused.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(refused.body["demo"], true);
+
+        // Streamed bodies pass through unlabelled
+        let export = app.get("/api/documents/export").await;
+        assert_eq!(export.status, StatusCode::OK);
+        assert_eq!(export.headers[DEMO_HEADER], "true");
+        assert!(!export.text.contains("\"demo\""));
+    }
+}
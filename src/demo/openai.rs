@@ -0,0 +1,172 @@
+use axum::{extract::DefaultBodyLimit, routing::post, Json, Router};
+use serde_json::{json, Value};
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+use crate::{
+    services::{openai::models, tokenizer},
+    vector_math,
+};
+
+/// Dimension of embeddings for models whose size is unknown.
+const DEFAULT_DIMENSION: usize = 1536;
+
+/// Weight of a whole word in a pseudo-embedding; its trigrams get the rest.
+const WORD_WEIGHT: f32 = 1.0;
+const TRIGRAM_WEIGHT: f32 = 0.5;
+
+/// Routes of the OpenAI API this service calls, answered locally.
+pub fn router() -> Router {
+    Router::new()
+        .route("/v1/embeddings", post(embeddings))
+        .route("/v1/chat/completions", post(chat_completions))
+        // Like the real API, take messages with several large inline images
+        .layer(DefaultBodyLimit::disable())
+}
+
+/// Returns a deterministic pseudo-embedding for each input.
+///
+/// Honours `dimensions` and `encoding_format: base64` like the real API.
+async fn embeddings(Json(request): Json<Value>) -> Json<Value> {
+    let model = request["model"].as_str().unwrap_or(models::EMBEDDING_MODEL);
+    let dimension = request["dimensions"]
+        .as_u64()
+        .or_else(|| models::embedding_dimension(model))
+        .map_or(DEFAULT_DIMENSION, |dimension| dimension as usize);
+    let inputs: Vec<String> = match &request["input"] {
+        Value::String(text) => vec![text.clone()],
+        Value::Array(items) => items
+            .iter()
+            .map(|item| item.as_str().map_or_else(|| item.to_string(), str::to_string))
+            .collect(),
+        other => vec![other.to_string()],
+    };
+    let base64 = request["encoding_format"] == "base64";
+
+    let mut tokens = 0;
+    let data: Vec<Value> = inputs
+        .iter()
+        .enumerate()
+        .map(|(index, text)| {
+            tokens += count_tokens(model, text);
+            let vector = pseudo_embedding(text, dimension);
+            let embedding = if base64 {
+                Value::from(vector_math::encode_base64(&vector))
+            } else {
+                json!(vector)
+            };
+            json!({ "object": "embedding", "index": index, "embedding": embedding })
+        })
+        .collect();
+    Json(json!({
+        "object": "list",
+        "data": data,
+        "model": model,
+        "usage": { "prompt_tokens": tokens, "total_tokens": tokens },
+    }))
+}
+
+/// Answers with the last user message echoed back.
+///
+/// For `/api/ask` that is the prompt with the retrieved context, which shows
+/// what a real model would have been given. Answers longer than
+/// `max_completion_tokens` (or `max_tokens`) words are cut off there with
+/// `finish_reason: length`.
+async fn chat_completions(Json(request): Json<Value>) -> Json<Value> {
+    let model = request["model"].as_str().unwrap_or(models::CHAT_MODEL);
+    let messages = request["messages"].as_array().map(Vec::as_slice).unwrap_or_default();
+    let prompt_tokens: usize = messages
+        .iter()
+        .map(|message| count_tokens(model, &message_text(message)))
+        .sum();
+    let question = messages
+        .iter()
+        .rev()
+        .find(|message| message["role"] == "user")
+        .map(message_text)
+        .unwrap_or_default();
+
+    let mut answer = format!("[demo] {}", question.trim());
+    let mut finish_reason = "stop";
+    let limit = request["max_completion_tokens"]
+        .as_u64()
+        .or_else(|| request["max_tokens"].as_u64());
+    if let Some(limit) = limit {
+        let words: Vec<&str> = answer.split_whitespace().collect();
+        if words.len() as u64 > limit {
+            answer = words[..limit as usize].join(" ");
+            finish_reason = "length";
+        }
+    }
+
+    let completion_tokens = count_tokens(model, &answer);
+    let created = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+    Json(json!({
+        "id": format!("chatcmpl-demo-{}", Uuid::new_v4().simple()),
+        "object": "chat.completion",
+        "created": created,
+        "model": model,
+        "choices": [{
+            "index": 0,
+            "message": { "role": "assistant", "content": answer },
+            "finish_reason": finish_reason,
+            "logprobs": null,
+        }],
+        "usage": {
+            "prompt_tokens": prompt_tokens,
+            "completion_tokens": completion_tokens,
+            "total_tokens": prompt_tokens + completion_tokens,
+        },
+    }))
+}
+
+/// Text of a chat message, whether its content is a string or a list of parts.
+fn message_text(message: &Value) -> String {
+    match &message["content"] {
+        Value::String(text) => text.clone(),
+        Value::Array(parts) => parts
+            .iter()
+            .filter_map(|part| part["text"].as_str())
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+fn count_tokens(model: &str, text: &str) -> usize {
+    tokenizer::count_tokens(model, text).unwrap_or_else(|| text.split_whitespace().count())
+}
+
+/// Hashes the words of a text and their character trigrams into a unit vector.
+///
+/// Texts sharing words end up close together, and so do texts with words
+/// sharing a stem through their trigrams, which is enough for nearest
+/// neighbours to look sensible. The same text always gives the same vector.
+fn pseudo_embedding(text: &str, dimension: usize) -> Vec<f32> {
+    let mut vector = vec![0.0; dimension.max(1)];
+    let lowercase = text.to_lowercase();
+    for word in lowercase.split(|c: char| !c.is_alphanumeric()).filter(|word| !word.is_empty()) {
+        add_feature(&mut vector, word.as_bytes(), WORD_WEIGHT);
+        let padded: Vec<char> = format!(" {} ", word).chars().collect();
+        for trigram in padded.windows(3) {
+            add_feature(&mut vector, trigram.iter().collect::<String>().as_bytes(), TRIGRAM_WEIGHT);
+        }
+    }
+    if vector_math::normalize_in_place(&mut vector).is_err() {
+        // No words at all; any fixed direction will do
+        vector[0] = 1.0;
+    }
+    vector
+}
+
+/// Adds `weight` to the component a feature hashes to, with a hashed sign
+/// so that unrelated features cancel out rather than pile up.
+fn add_feature(vector: &mut [f32], feature: &[u8], weight: f32) {
+    // FNV-1a
+    let hash = feature
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3));
+    let index = (hash % vector.len() as u64) as usize;
+    let sign = if hash >> 63 == 1 { -1.0 } else { 1.0 };
+    vector[index] += sign * weight;
+}
@@ -0,0 +1,1109 @@
+use qdrant_client::qdrant::{
+    alias_operations::Action, collections_server::Collections, condition::ConditionOneOf, group_id,
+    point_id::PointIdOptions, points_selector::PointsSelectorOneOf, points_server::Points,
+    qdrant_server::Qdrant, r#match::MatchValue, value::Kind, vector, vector_output, vectors,
+    vectors_config, vectors_output, with_payload_selector, with_vectors_selector, AliasDescription,
+    BatchResult, ChangeAliases, ClearPayloadPoints, CollectionClusterInfoRequest,
+    CollectionClusterInfoResponse, CollectionConfig, CollectionDescription, CollectionExists,
+    CollectionExistsRequest, CollectionExistsResponse, CollectionInfo, CollectionOperationResponse,
+    CollectionParams, CollectionStatus, Condition, CountPoints, CountResponse, CountResult,
+    CreateCollection, CreateFieldIndexCollection, CreateShardKeyRequest, CreateShardKeyResponse,
+    CreateVectorNameRequest, DeleteCollection, DeleteFieldIndexCollection, DeletePayloadPoints,
+    DeletePointVectors, DeletePoints, DeleteShardKeyRequest, DeleteShardKeyResponse,
+    DeleteVectorNameRequest, DenseVector, DiscoverBatchPoints, DiscoverBatchResponse, DiscoverPoints,
+    DiscoverResponse, Distance, FacetCounts, FacetResponse, FieldCondition, FieldType, Filter,
+    GetCollectionInfoRequest, GetCollectionInfoResponse, GetPoints, GetResponse, GroupId,
+    GroupsResult, HealthCheckReply, HealthCheckRequest, ListAliasesRequest, ListAliasesResponse,
+    ListCollectionAliasesRequest, ListCollectionsRequest, ListCollectionsResponse,
+    ListShardKeysRequest, ListShardKeysResponse, Match, PayloadSchemaInfo, PointGroup, PointId,
+    PointStruct, PointsOperationResponse, PointsSelector, QueryBatchPoints, QueryBatchResponse,
+    QueryGroupsResponse, QueryPointGroups, QueryPoints, QueryResponse, Range, RecommendBatchPoints,
+    RecommendBatchResponse, RecommendGroupsResponse, RecommendPointGroups, RecommendPoints,
+    RecommendResponse, RetrievedPoint, ScoredPoint, ScrollPoints, ScrollResponse,
+    SearchBatchPoints, SearchBatchResponse, SearchGroupsResponse, SearchMatrixOffsetsResponse,
+    SearchMatrixPairsResponse, SearchMatrixPoints, SearchPointGroups, SearchPoints, SearchResponse,
+    SetPayloadPoints, UpdateBatchPoints, UpdateBatchResponse, UpdateCollection,
+    UpdateCollectionClusterSetupRequest, UpdateCollectionClusterSetupResponse, UpdatePointVectors,
+    UpdateMode, UpdateResult, UpdateStatus, UpsertPoints, Value, VectorOutput, VectorParams, VectorsConfig,
+    VectorsOutput, WithPayloadSelector, WithVectorsSelector,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Mutex, MutexGuard};
+use tonic::{Request, Response, Status};
+use uuid::Uuid;
+
+use crate::vector_math;
+
+/// Server version reported to the client's compatibility check.
+const VERSION: &str = "1.19.0";
+
+/// Points returned by a scroll without a limit, as in Qdrant.
+const DEFAULT_SCROLL_LIMIT: u32 = 10;
+
+/// Qdrant's gRPC API over collections kept in memory.
+///
+/// Covers the calls this service makes: collections and aliases, point
+/// writes and reads, conditional upserts, filtered search, batch and
+/// grouped search, count and field indexes. Filters support matches,
+/// ranges, emptiness and nested conditions; geo and datetime conditions,
+/// named vectors and custom sharding are refused as unimplemented. Search compares the query with
+/// every stored vector, which is fine for the few thousand points a demo
+/// holds. Nothing is persisted.
+#[derive(Default)]
+pub struct DemoQdrant {
+    store: Mutex<Store>,
+}
+
+#[derive(Default)]
+struct Store {
+    collections: BTreeMap<String, Collection>,
+    /// Alias name to collection name
+    aliases: BTreeMap<String, String>,
+    /// Last operation id handed out
+    operations: u64,
+}
+
+struct Collection {
+    params: VectorParams,
+    sharding_method: Option<i32>,
+    points: BTreeMap<Key, StoredPoint>,
+    /// Indexed payload fields and their `FieldType`
+    indexes: HashMap<String, i32>,
+}
+
+/// Point id in a form that orders like Qdrant's: numbers before UUIDs.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Key {
+    Num(u64),
+    Uuid(String),
+}
+
+impl Key {
+    fn parse(id: &PointId) -> Result<Self, Status> {
+        match &id.point_id_options {
+            Some(PointIdOptions::Num(num)) => Ok(Key::Num(*num)),
+            Some(PointIdOptions::Uuid(uuid)) => Uuid::parse_str(uuid)
+                .map(|uuid| Key::Uuid(uuid.to_string()))
+                .map_err(|_| Status::invalid_argument(format!("Wrong input: invalid point id '{}'", uuid))),
+            None => Err(Status::invalid_argument("Wrong input: point id is missing")),
+        }
+    }
+
+    fn to_point_id(&self) -> PointId {
+        match self {
+            Key::Num(num) => PointId::from(*num),
+            Key::Uuid(uuid) => PointId::from(uuid.as_str()),
+        }
+    }
+}
+
+struct StoredPoint {
+    vector: Vec<f32>,
+    payload: HashMap<String, Value>,
+    /// Operation that last changed the point
+    version: u64,
+}
+
+impl DemoQdrant {
+    fn lock(&self) -> MutexGuard<'_, Store> {
+        self.store.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Store {
+    /// Resolves an alias to its collection; other names are returned as is.
+    fn resolve<'a>(&'a self, name: &'a str) -> &'a str {
+        self.aliases.get(name).map_or(name, String::as_str)
+    }
+
+    fn collection(&self, name: &str) -> Result<&Collection, Status> {
+        self.collections.get(self.resolve(name)).ok_or_else(|| missing_collection(name))
+    }
+
+    fn collection_mut(&mut self, name: &str) -> Result<&mut Collection, Status> {
+        let resolved = self.resolve(name).to_string();
+        self.collections.get_mut(&resolved).ok_or_else(|| missing_collection(name))
+    }
+
+    fn next_operation(&mut self) -> u64 {
+        self.operations += 1;
+        self.operations
+    }
+
+    /// Applies `apply` to every selected point of a collection under a new
+    /// operation id, failing before any change if a listed point is missing.
+    fn update_points(
+        &mut self,
+        name: &str,
+        selector: Option<&PointsSelector>,
+        mut apply: impl FnMut(&mut StoredPoint),
+    ) -> Result<Response<PointsOperationResponse>, Status> {
+        let operation = self.operations + 1;
+        let collection = self.collection_mut(name)?;
+        let keys = collection.select(selector)?;
+        if let Some(key) = keys.iter().find(|key| !collection.points.contains_key(key)) {
+            return Err(missing_point(key));
+        }
+        for key in keys {
+            if let Some(point) = collection.points.get_mut(&key) {
+                apply(point);
+                point.version = operation;
+            }
+        }
+        self.operations = operation;
+        Ok(completed(operation))
+    }
+}
+
+impl Collection {
+    fn distance(&self) -> Distance {
+        Distance::try_from(self.params.distance).unwrap_or(Distance::Cosine)
+    }
+
+    /// Returns the ids picked by a points selector.
+    fn select(&self, selector: Option<&PointsSelector>) -> Result<Vec<Key>, Status> {
+        match selector.and_then(|selector| selector.points_selector_one_of.as_ref()) {
+            Some(PointsSelectorOneOf::Points(list)) => list.ids.iter().map(Key::parse).collect(),
+            Some(PointsSelectorOneOf::Filter(filter)) => self.filtered(Some(filter)),
+            None => Err(Status::invalid_argument("Wrong input: points selector is missing")),
+        }
+    }
+
+    /// Returns the ids of the points matching a filter, in id order.
+    fn filtered(&self, filter: Option<&Filter>) -> Result<Vec<Key>, Status> {
+        let mut keys = Vec::new();
+        for (key, point) in &self.points {
+            if self.admits(filter, key, point)? {
+                keys.push(key.clone());
+            }
+        }
+        Ok(keys)
+    }
+
+    fn admits(&self, filter: Option<&Filter>, key: &Key, point: &StoredPoint) -> Result<bool, Status> {
+        match filter {
+            Some(filter) => check_filter(filter, Some(key), &point.payload, &self.indexes),
+            None => Ok(true),
+        }
+    }
+
+    /// Checks a vector's size and prepares it for storage or comparison.
+    ///
+    /// Cosine collections store and compare unit vectors, as Qdrant does.
+    fn prepare(&self, mut vector: Vec<f32>) -> Result<Vec<f32>, Status> {
+        if vector.len() as u64 != self.params.size {
+            return Err(Status::invalid_argument(format!(
+                "Wrong input: Vector dimension error: expected dim: {}, got {}",
+                self.params.size,
+                vector.len()
+            )));
+        }
+        if self.distance() == Distance::Cosine {
+            // A zero vector has no direction; it is kept as is and scores 0
+            let _ = vector_math::normalize_in_place(&mut vector);
+        }
+        Ok(vector)
+    }
+
+    /// Scores every point passing the filter against `vector`, best first.
+    fn rank(
+        &self,
+        vector: Vec<f32>,
+        filter: Option<&Filter>,
+        threshold: Option<f32>,
+    ) -> Result<Vec<(&Key, &StoredPoint, f32)>, Status> {
+        let query = self.prepare(vector)?;
+        let distance = self.distance();
+        let similarity = !matches!(distance, Distance::Euclid | Distance::Manhattan);
+        let mut ranked = Vec::new();
+        for (key, point) in &self.points {
+            if !self.admits(filter, key, point)? {
+                continue;
+            }
+            let score = match distance {
+                Distance::Euclid => vector_math::euclidean_distance(&query, &point.vector),
+                Distance::Manhattan => query.iter().zip(&point.vector).map(|(a, b)| (a - b).abs()).sum(),
+                _ => vector_math::dot(&query, &point.vector),
+            };
+            let passes = match threshold {
+                Some(threshold) if similarity => score >= threshold,
+                Some(threshold) => score <= threshold,
+                None => true,
+            };
+            if passes {
+                ranked.push((key, point, score));
+            }
+        }
+        if similarity {
+            ranked.sort_by(|a, b| b.2.total_cmp(&a.2).then_with(|| a.0.cmp(b.0)));
+        } else {
+            ranked.sort_by(|a, b| a.2.total_cmp(&b.2).then_with(|| a.0.cmp(b.0)));
+        }
+        Ok(ranked)
+    }
+
+    fn search(&self, request: SearchPoints) -> Result<Vec<ScoredPoint>, Status> {
+        if request.vector_name.as_deref().is_some_and(|name| !name.is_empty()) {
+            return Err(unavailable("named vectors"));
+        }
+        let ranked = self.rank(request.vector, request.filter.as_ref(), request.score_threshold)?;
+        Ok(ranked
+            .into_iter()
+            .skip(request.offset.unwrap_or(0) as usize)
+            .take(request.limit as usize)
+            .map(|(key, point, score)| {
+                scored(key, point, score, request.with_payload.as_ref(), request.with_vectors.as_ref())
+            })
+            .collect())
+    }
+
+    fn info(&self) -> CollectionInfo {
+        let payload_schema = self
+            .indexes
+            .iter()
+            .map(|(field, field_type)| {
+                let info = PayloadSchemaInfo {
+                    // PayloadSchemaType counts from UnknownType, FieldType from Keyword
+                    data_type: field_type + 1,
+                    params: None,
+                    points: Some(self.points.len() as u64),
+                };
+                (field.clone(), info)
+            })
+            .collect();
+        CollectionInfo {
+            status: CollectionStatus::Green as i32,
+            segments_count: 1,
+            config: Some(CollectionConfig {
+                params: Some(CollectionParams {
+                    shard_number: 1,
+                    vectors_config: Some(VectorsConfig {
+                        config: Some(vectors_config::Config::Params(self.params)),
+                    }),
+                    replication_factor: Some(1),
+                    write_consistency_factor: Some(1),
+                    sharding_method: self.sharding_method,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            payload_schema,
+            points_count: Some(self.points.len() as u64),
+            indexed_vectors_count: Some(0),
+            ..Default::default()
+        }
+    }
+}
+
+fn missing_collection(name: &str) -> Status {
+    // The wording matches Qdrant's, which `qdrant::is_collection_missing` looks for
+    Status::not_found(format!("Not found: Collection `{}` doesn't exist!", name))
+}
+
+fn missing_point(key: &Key) -> Status {
+    match key {
+        Key::Num(num) => Status::not_found(format!("Not found: No point with id {} found", num)),
+        Key::Uuid(uuid) => Status::not_found(format!("Not found: No point with id {} found", uuid)),
+    }
+}
+
+fn unavailable(what: &str) -> Status {
+    Status::unimplemented(format!("{} is not available in demo mode", what))
+}
+
+fn completed(operation: u64) -> Response<PointsOperationResponse> {
+    Response::new(PointsOperationResponse {
+        result: Some(UpdateResult {
+            operation_id: Some(operation),
+            status: UpdateStatus::Completed as i32,
+        }),
+        time: 0.0,
+        usage: None,
+    })
+}
+
+fn collection_changed(result: bool) -> Response<CollectionOperationResponse> {
+    Response::new(CollectionOperationResponse { result, time: 0.0 })
+}
+
+/// Takes the dense vector out of a point written by the client.
+#[allow(deprecated)]
+fn dense_vector(point: &PointStruct) -> Result<Vec<f32>, Status> {
+    match point.vectors.as_ref().and_then(|vectors| vectors.vectors_options.as_ref()) {
+        Some(vectors::VectorsOptions::Vector(vector)) => match &vector.vector {
+            Some(vector::Vector::Dense(dense)) => Ok(dense.data.clone()),
+            None if !vector.data.is_empty() => Ok(vector.data.clone()),
+            _ => Err(unavailable("Non-dense vectors")),
+        },
+        Some(vectors::VectorsOptions::Vectors(_)) => Err(unavailable("Named vectors")),
+        None => Err(Status::invalid_argument("Wrong input: point has no vector")),
+    }
+}
+
+/// Returns the part of a payload a selector asks for; none by default.
+fn select_payload(
+    payload: &HashMap<String, Value>,
+    selector: Option<&WithPayloadSelector>,
+) -> HashMap<String, Value> {
+    let top_level = |field: &String| field.split('.').next().unwrap_or_default().to_string();
+    match selector.and_then(|selector| selector.selector_options.as_ref()) {
+        Some(with_payload_selector::SelectorOptions::Enable(true)) => payload.clone(),
+        Some(with_payload_selector::SelectorOptions::Include(include)) => {
+            let fields: Vec<String> = include.fields.iter().map(top_level).collect();
+            payload
+                .iter()
+                .filter(|(key, _)| fields.contains(key))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect()
+        }
+        Some(with_payload_selector::SelectorOptions::Exclude(exclude)) => payload
+            .iter()
+            .filter(|(key, _)| !exclude.fields.contains(key))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect(),
+        _ => HashMap::new(),
+    }
+}
+
+/// Returns the vector if a selector asks for it; it is left out by default.
+fn select_vector(vector: &[f32], selector: Option<&WithVectorsSelector>) -> Option<VectorsOutput> {
+    let wanted = match selector.and_then(|selector| selector.selector_options.as_ref()) {
+        Some(with_vectors_selector::SelectorOptions::Enable(enabled)) => *enabled,
+        Some(with_vectors_selector::SelectorOptions::Include(_)) => true,
+        None => false,
+    };
+    wanted.then(|| VectorsOutput {
+        vectors_options: Some(vectors_output::VectorsOptions::Vector(VectorOutput {
+            vector: Some(vector_output::Vector::Dense(DenseVector { data: vector.to_vec() })),
+            ..Default::default()
+        })),
+    })
+}
+
+fn scored(
+    key: &Key,
+    point: &StoredPoint,
+    score: f32,
+    with_payload: Option<&WithPayloadSelector>,
+    with_vectors: Option<&WithVectorsSelector>,
+) -> ScoredPoint {
+    ScoredPoint {
+        id: Some(key.to_point_id()),
+        payload: select_payload(&point.payload, with_payload),
+        score,
+        version: point.version,
+        vectors: select_vector(&point.vector, with_vectors),
+        ..Default::default()
+    }
+}
+
+fn retrieved(
+    key: &Key,
+    point: &StoredPoint,
+    with_payload: Option<&WithPayloadSelector>,
+    with_vectors: Option<&WithVectorsSelector>,
+) -> RetrievedPoint {
+    RetrievedPoint {
+        id: Some(key.to_point_id()),
+        payload: select_payload(&point.payload, with_payload),
+        vectors: select_vector(&point.vector, with_vectors),
+        ..Default::default()
+    }
+}
+
+/// Collects the values at a dotted payload path.
+///
+/// Arrays are looked through at every step, so `metadata.tags` yields each
+/// tag and `items.name` the name of every item, as in Qdrant's filters.
+fn values_at<'a>(payload: &'a HashMap<String, Value>, key: &str) -> Vec<&'a Value> {
+    let mut segments = key.split('.').map(|segment| segment.trim_end_matches("[]"));
+    let mut current: Vec<&Value> = segments
+        .next()
+        .and_then(|first| payload.get(first))
+        .into_iter()
+        .collect();
+    for segment in segments {
+        current = flatten(current)
+            .into_iter()
+            .filter_map(|value| match &value.kind {
+                Some(Kind::StructValue(fields)) => fields.fields.get(segment),
+                _ => None,
+            })
+            .collect();
+    }
+    flatten(current)
+}
+
+fn flatten(values: Vec<&Value>) -> Vec<&Value> {
+    values
+        .into_iter()
+        .flat_map(|value| match &value.kind {
+            Some(Kind::ListValue(list)) => list.values.iter().collect(),
+            _ => vec![value],
+        })
+        .collect()
+}
+
+fn is_null(value: &Value) -> bool {
+    matches!(value.kind, None | Some(Kind::NullValue(_)))
+}
+
+fn as_str(value: &Value) -> Option<&str> {
+    match &value.kind {
+        Some(Kind::StringValue(s)) => Some(s),
+        _ => None,
+    }
+}
+
+fn as_i64(value: &Value) -> Option<i64> {
+    match value.kind {
+        Some(Kind::IntegerValue(i)) => Some(i),
+        _ => None,
+    }
+}
+
+fn as_f64(value: &Value) -> Option<f64> {
+    match value.kind {
+        Some(Kind::IntegerValue(i)) => Some(i as f64),
+        Some(Kind::DoubleValue(f)) => Some(f),
+        _ => None,
+    }
+}
+
+/// Lowercased words of a text, as a full-text index would split it.
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+fn check_filter(
+    filter: &Filter,
+    key: Option<&Key>,
+    payload: &HashMap<String, Value>,
+    indexes: &HashMap<String, i32>,
+) -> Result<bool, Status> {
+    let check = |condition: &Condition| check_condition(condition, key, payload, indexes);
+    for condition in &filter.must {
+        if !check(condition)? {
+            return Ok(false);
+        }
+    }
+    for condition in &filter.must_not {
+        if check(condition)? {
+            return Ok(false);
+        }
+    }
+    if !filter.should.is_empty() {
+        let mut any = false;
+        for condition in &filter.should {
+            if check(condition)? {
+                any = true;
+                break;
+            }
+        }
+        if !any {
+            return Ok(false);
+        }
+    }
+    if let Some(min_should) = &filter.min_should {
+        let mut matched = 0;
+        for condition in &min_should.conditions {
+            if check(condition)? {
+                matched += 1;
+            }
+        }
+        if matched < min_should.min_count {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+fn check_condition(
+    condition: &Condition,
+    key: Option<&Key>,
+    payload: &HashMap<String, Value>,
+    indexes: &HashMap<String, i32>,
+) -> Result<bool, Status> {
+    match &condition.condition_one_of {
+        Some(ConditionOneOf::Field(field)) => check_field(field, payload, indexes),
+        Some(ConditionOneOf::IsEmpty(is_empty)) => {
+            Ok(values_at(payload, &is_empty.key).into_iter().all(is_null))
+        }
+        Some(ConditionOneOf::IsNull(is_null_condition)) => {
+            Ok(values_at(payload, &is_null_condition.key).into_iter().any(is_null))
+        }
+        Some(ConditionOneOf::HasId(has_id)) => {
+            let ids = has_id.has_id.iter().map(Key::parse).collect::<Result<Vec<_>, _>>()?;
+            Ok(key.is_some_and(|key| ids.contains(key)))
+        }
+        Some(ConditionOneOf::Filter(filter)) => check_filter(filter, key, payload, indexes),
+        Some(ConditionOneOf::Nested(nested)) => {
+            let Some(filter) = &nested.filter else {
+                return Ok(true);
+            };
+            for value in values_at(payload, &nested.key) {
+                if let Some(Kind::StructValue(object)) = &value.kind {
+                    if check_filter(filter, None, &object.fields, indexes)? {
+                        return Ok(true);
+                    }
+                }
+            }
+            Ok(false)
+        }
+        Some(ConditionOneOf::HasVector(_)) => Ok(true),
+        _ => Err(unavailable("This filter condition")),
+    }
+}
+
+fn check_field(
+    field: &FieldCondition,
+    payload: &HashMap<String, Value>,
+    indexes: &HashMap<String, i32>,
+) -> Result<bool, Status> {
+    if field.geo_bounding_box.is_some()
+        || field.geo_radius.is_some()
+        || field.geo_polygon.is_some()
+        || field.values_count.is_some()
+        || field.datetime_range.is_some()
+    {
+        return Err(unavailable("This filter condition"));
+    }
+    let values = values_at(payload, &field.key);
+    if let Some(expected) = &field.r#match {
+        let text_indexed = indexes.get(&field.key) == Some(&(FieldType::Text as i32));
+        if !check_match(expected, &values, text_indexed) {
+            return Ok(false);
+        }
+    }
+    if let Some(range) = &field.range {
+        if !values.iter().filter_map(|value| as_f64(value)).any(|number| in_range(range, number)) {
+            return Ok(false);
+        }
+    }
+    if let Some(is_empty) = field.is_empty {
+        if values.iter().all(|value| is_null(value)) != is_empty {
+            return Ok(false);
+        }
+    }
+    if let Some(null) = field.is_null {
+        if values.iter().any(|value| is_null(value)) != null {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Checks a match against the values of a field; any value may satisfy it.
+///
+/// Text matches need every word of the query in an indexed field, and a
+/// substring otherwise, like Qdrant with and without a full-text index.
+fn check_match(expected: &Match, values: &[&Value], text_indexed: bool) -> bool {
+    let strings = || values.iter().filter_map(|value| as_str(value));
+    let integers = || values.iter().filter_map(|value| as_i64(value));
+    match &expected.match_value {
+        Some(MatchValue::Keyword(keyword)) => strings().any(|s| s == keyword),
+        Some(MatchValue::Integer(integer)) => integers().any(|i| i == *integer),
+        Some(MatchValue::Boolean(boolean)) => values
+            .iter()
+            .any(|value| matches!(value.kind, Some(Kind::BoolValue(b)) if b == *boolean)),
+        Some(MatchValue::Text(text)) if text_indexed => {
+            let wanted = words(text);
+            strings().any(|s| {
+                let present = words(s);
+                wanted.iter().all(|word| present.contains(word))
+            })
+        }
+        Some(MatchValue::Text(text)) => strings().any(|s| s.contains(text.as_str())),
+        Some(MatchValue::TextAny(text)) => {
+            let wanted = words(text);
+            strings().any(|s| words(s).iter().any(|word| wanted.contains(word)))
+        }
+        Some(MatchValue::Phrase(phrase)) => {
+            let phrase = words(phrase).join(" ");
+            strings().any(|s| words(s).join(" ").contains(&phrase))
+        }
+        Some(MatchValue::Prefix(prefix)) => {
+            let prefix = prefix.to_lowercase();
+            strings().any(|s| words(s).iter().any(|word| word.starts_with(&prefix)))
+        }
+        Some(MatchValue::Keywords(keywords)) => strings().any(|s| keywords.strings.iter().any(|k| k == s)),
+        Some(MatchValue::Integers(list)) => integers().any(|i| list.integers.contains(&i)),
+        Some(MatchValue::ExceptKeywords(keywords)) => !strings().any(|s| keywords.strings.iter().any(|k| k == s)),
+        Some(MatchValue::ExceptIntegers(list)) => !integers().any(|i| list.integers.contains(&i)),
+        None => true,
+    }
+}
+
+fn in_range(range: &Range, number: f64) -> bool {
+    range.lt.is_none_or(|lt| number < lt)
+        && range.gt.is_none_or(|gt| number > gt)
+        && range.lte.is_none_or(|lte| number <= lte)
+        && range.gte.is_none_or(|gte| number >= gte)
+}
+
+/// Group ids a point belongs to: each string or integer at the `group_by` path.
+fn group_ids(payload: &HashMap<String, Value>, group_by: &str) -> Vec<group_id::Kind> {
+    values_at(payload, group_by)
+        .into_iter()
+        .filter_map(|value| match &value.kind {
+            Some(Kind::StringValue(s)) => Some(group_id::Kind::StringValue(s.clone())),
+            Some(Kind::IntegerValue(i)) if *i >= 0 => Some(group_id::Kind::UnsignedValue(*i as u64)),
+            Some(Kind::IntegerValue(i)) => Some(group_id::Kind::IntegerValue(*i)),
+            _ => None,
+        })
+        .collect()
+}
+
+#[tonic::async_trait]
+impl Qdrant for DemoQdrant {
+    async fn health_check(&self, _: Request<HealthCheckRequest>) -> Result<Response<HealthCheckReply>, Status> {
+        Ok(Response::new(HealthCheckReply {
+            title: "qdrant - vector search engine (demo)".to_string(),
+            version: VERSION.to_string(),
+            commit: None,
+        }))
+    }
+}
+
+#[tonic::async_trait]
+impl Collections for DemoQdrant {
+    async fn get(
+        &self,
+        request: Request<GetCollectionInfoRequest>,
+    ) -> Result<Response<GetCollectionInfoResponse>, Status> {
+        let store = self.lock();
+        let info = store.collection(&request.get_ref().collection_name)?.info();
+        Ok(Response::new(GetCollectionInfoResponse { result: Some(info), time: 0.0 }))
+    }
+
+    async fn list(&self, _: Request<ListCollectionsRequest>) -> Result<Response<ListCollectionsResponse>, Status> {
+        let collections = self
+            .lock()
+            .collections
+            .keys()
+            .map(|name| CollectionDescription { name: name.clone() })
+            .collect();
+        Ok(Response::new(ListCollectionsResponse { collections, time: 0.0 }))
+    }
+
+    async fn create(&self, request: Request<CreateCollection>) -> Result<Response<CollectionOperationResponse>, Status> {
+        let request = request.into_inner();
+        let params = match request.vectors_config.and_then(|config| config.config) {
+            Some(vectors_config::Config::Params(params)) => params,
+            Some(vectors_config::Config::ParamsMap(_)) => return Err(unavailable("Named vectors")),
+            None => return Err(Status::invalid_argument("Wrong input: vectors config is missing")),
+        };
+        let mut store = self.lock();
+        let name = request.collection_name;
+        if store.collections.contains_key(&name) || store.aliases.contains_key(&name) {
+            return Err(Status::already_exists(format!("Wrong input: Collection `{}` already exists!", name)));
+        }
+        let collection = Collection {
+            params,
+            sharding_method: request.sharding_method,
+            points: BTreeMap::new(),
+            indexes: HashMap::new(),
+        };
+        store.collections.insert(name, collection);
+        Ok(collection_changed(true))
+    }
+
+    async fn update(&self, _: Request<UpdateCollection>) -> Result<Response<CollectionOperationResponse>, Status> {
+        Err(unavailable("Updating a collection"))
+    }
+
+    async fn delete(&self, request: Request<DeleteCollection>) -> Result<Response<CollectionOperationResponse>, Status> {
+        let name = request.into_inner().collection_name;
+        let mut store = self.lock();
+        let existed = store.collections.remove(&name).is_some();
+        store.aliases.retain(|_, collection| *collection != name);
+        Ok(collection_changed(existed))
+    }
+
+    async fn update_aliases(&self, request: Request<ChangeAliases>) -> Result<Response<CollectionOperationResponse>, Status> {
+        let mut store = self.lock();
+        for action in request.into_inner().actions.into_iter().filter_map(|operation| operation.action) {
+            match action {
+                Action::CreateAlias(create) => {
+                    if !store.collections.contains_key(&create.collection_name) {
+                        return Err(missing_collection(&create.collection_name));
+                    }
+                    store.aliases.insert(create.alias_name, create.collection_name);
+                }
+                Action::RenameAlias(rename) => {
+                    let collection = store.aliases.remove(&rename.old_alias_name).ok_or_else(|| {
+                        Status::not_found(format!("Not found: Alias {} does not exists!", rename.old_alias_name))
+                    })?;
+                    store.aliases.insert(rename.new_alias_name, collection);
+                }
+                Action::DeleteAlias(delete) => {
+                    store.aliases.remove(&delete.alias_name).ok_or_else(|| {
+                        Status::not_found(format!("Not found: Alias {} does not exists!", delete.alias_name))
+                    })?;
+                }
+            }
+        }
+        Ok(collection_changed(true))
+    }
+
+    async fn list_collection_aliases(
+        &self,
+        request: Request<ListCollectionAliasesRequest>,
+    ) -> Result<Response<ListAliasesResponse>, Status> {
+        let name = &request.get_ref().collection_name;
+        let aliases = self
+            .lock()
+            .aliases
+            .iter()
+            .filter(|(_, collection)| *collection == name)
+            .map(|(alias, collection)| AliasDescription {
+                alias_name: alias.clone(),
+                collection_name: collection.clone(),
+            })
+            .collect();
+        Ok(Response::new(ListAliasesResponse { aliases, time: 0.0 }))
+    }
+
+    async fn list_aliases(&self, _: Request<ListAliasesRequest>) -> Result<Response<ListAliasesResponse>, Status> {
+        let aliases = self
+            .lock()
+            .aliases
+            .iter()
+            .map(|(alias, collection)| AliasDescription {
+                alias_name: alias.clone(),
+                collection_name: collection.clone(),
+            })
+            .collect();
+        Ok(Response::new(ListAliasesResponse { aliases, time: 0.0 }))
+    }
+
+    async fn collection_cluster_info(
+        &self,
+        _: Request<CollectionClusterInfoRequest>,
+    ) -> Result<Response<CollectionClusterInfoResponse>, Status> {
+        Err(unavailable("Cluster info"))
+    }
+
+    async fn collection_exists(
+        &self,
+        request: Request<CollectionExistsRequest>,
+    ) -> Result<Response<CollectionExistsResponse>, Status> {
+        let exists = self.lock().collections.contains_key(&request.get_ref().collection_name);
+        Ok(Response::new(CollectionExistsResponse {
+            result: Some(CollectionExists { exists }),
+            time: 0.0,
+        }))
+    }
+
+    async fn update_collection_cluster_setup(
+        &self,
+        _: Request<UpdateCollectionClusterSetupRequest>,
+    ) -> Result<Response<UpdateCollectionClusterSetupResponse>, Status> {
+        Err(unavailable("Cluster setup"))
+    }
+
+    async fn create_shard_key(&self, _: Request<CreateShardKeyRequest>) -> Result<Response<CreateShardKeyResponse>, Status> {
+        Err(unavailable("Custom sharding"))
+    }
+
+    async fn delete_shard_key(&self, _: Request<DeleteShardKeyRequest>) -> Result<Response<DeleteShardKeyResponse>, Status> {
+        Err(unavailable("Custom sharding"))
+    }
+
+    async fn list_shard_keys(&self, _: Request<ListShardKeysRequest>) -> Result<Response<ListShardKeysResponse>, Status> {
+        Err(unavailable("Custom sharding"))
+    }
+}
+
+#[tonic::async_trait]
+impl Points for DemoQdrant {
+    async fn upsert(&self, request: Request<UpsertPoints>) -> Result<Response<PointsOperationResponse>, Status> {
+        let request = request.into_inner();
+        let mode = match request.update_mode {
+            Some(mode) => UpdateMode::try_from(mode).map_err(|_| Status::invalid_argument("Wrong input: unknown update mode"))?,
+            None => UpdateMode::Upsert,
+        };
+        let mut store = self.lock();
+        let operation = store.operations + 1;
+        let collection = store.collection_mut(&request.collection_name)?;
+        // Check every point first so a bad one leaves the collection unchanged
+        let mut prepared = Vec::with_capacity(request.points.len());
+        for point in &request.points {
+            let key = Key::parse(point.id.as_ref().unwrap_or(&PointId::default()))?;
+            prepared.push((key, collection.prepare(dense_vector(point)?)?));
+        }
+        for ((key, vector), point) in prepared.into_iter().zip(request.points) {
+            // Existing points are only replaced when they match the update filter
+            let write = match collection.points.get(&key) {
+                Some(_) if mode == UpdateMode::InsertOnly => false,
+                Some(existing) => collection.admits(request.update_filter.as_ref(), &key, existing)?,
+                None => mode != UpdateMode::UpdateOnly,
+            };
+            if write {
+                let stored = StoredPoint { vector, payload: point.payload, version: operation };
+                collection.points.insert(key, stored);
+            }
+        }
+        store.operations = operation;
+        Ok(completed(operation))
+    }
+
+    async fn delete(&self, request: Request<DeletePoints>) -> Result<Response<PointsOperationResponse>, Status> {
+        let request = request.into_inner();
+        let mut store = self.lock();
+        let collection = store.collection_mut(&request.collection_name)?;
+        for key in collection.select(request.points.as_ref())? {
+            collection.points.remove(&key);
+        }
+        Ok(completed(store.next_operation()))
+    }
+
+    async fn get(&self, request: Request<GetPoints>) -> Result<Response<GetResponse>, Status> {
+        let request = request.into_inner();
+        let store = self.lock();
+        let collection = store.collection(&request.collection_name)?;
+        let mut result = Vec::new();
+        for id in &request.ids {
+            let key = Key::parse(id)?;
+            if let Some(point) = collection.points.get(&key) {
+                result.push(retrieved(&key, point, request.with_payload.as_ref(), request.with_vectors.as_ref()));
+            }
+        }
+        Ok(Response::new(GetResponse { result, time: 0.0, usage: None }))
+    }
+
+    async fn update_vectors(&self, _: Request<UpdatePointVectors>) -> Result<Response<PointsOperationResponse>, Status> {
+        Err(unavailable("Updating vectors"))
+    }
+
+    async fn delete_vectors(&self, _: Request<DeletePointVectors>) -> Result<Response<PointsOperationResponse>, Status> {
+        Err(unavailable("Deleting vectors"))
+    }
+
+    async fn set_payload(&self, request: Request<SetPayloadPoints>) -> Result<Response<PointsOperationResponse>, Status> {
+        let request = request.into_inner();
+        if request.key.is_some() {
+            return Err(unavailable("Setting a nested payload key"));
+        }
+        self.lock()
+            .update_points(&request.collection_name, request.points_selector.as_ref(), |point| {
+                point.payload.extend(request.payload.clone());
+            })
+    }
+
+    async fn overwrite_payload(&self, request: Request<SetPayloadPoints>) -> Result<Response<PointsOperationResponse>, Status> {
+        let request = request.into_inner();
+        if request.key.is_some() {
+            return Err(unavailable("Setting a nested payload key"));
+        }
+        self.lock()
+            .update_points(&request.collection_name, request.points_selector.as_ref(), |point| {
+                point.payload = request.payload.clone();
+            })
+    }
+
+    async fn delete_payload(&self, request: Request<DeletePayloadPoints>) -> Result<Response<PointsOperationResponse>, Status> {
+        let request = request.into_inner();
+        self.lock()
+            .update_points(&request.collection_name, request.points_selector.as_ref(), |point| {
+                for key in &request.keys {
+                    point.payload.remove(key);
+                }
+            })
+    }
+
+    async fn clear_payload(&self, request: Request<ClearPayloadPoints>) -> Result<Response<PointsOperationResponse>, Status> {
+        let request = request.into_inner();
+        self.lock()
+            .update_points(&request.collection_name, request.points.as_ref(), |point| point.payload.clear())
+    }
+
+    async fn create_field_index(
+        &self,
+        request: Request<CreateFieldIndexCollection>,
+    ) -> Result<Response<PointsOperationResponse>, Status> {
+        let request = request.into_inner();
+        let mut store = self.lock();
+        let collection = store.collection_mut(&request.collection_name)?;
+        let field_type = request.field_type.unwrap_or(FieldType::Keyword as i32);
+        collection.indexes.insert(request.field_name, field_type);
+        Ok(completed(store.next_operation()))
+    }
+
+    async fn delete_field_index(
+        &self,
+        request: Request<DeleteFieldIndexCollection>,
+    ) -> Result<Response<PointsOperationResponse>, Status> {
+        let request = request.into_inner();
+        let mut store = self.lock();
+        store.collection_mut(&request.collection_name)?.indexes.remove(&request.field_name);
+        Ok(completed(store.next_operation()))
+    }
+
+    async fn create_vector_name(&self, _: Request<CreateVectorNameRequest>) -> Result<Response<PointsOperationResponse>, Status> {
+        Err(unavailable("Named vectors"))
+    }
+
+    async fn delete_vector_name(&self, _: Request<DeleteVectorNameRequest>) -> Result<Response<PointsOperationResponse>, Status> {
+        Err(unavailable("Named vectors"))
+    }
+
+    async fn search(&self, request: Request<SearchPoints>) -> Result<Response<SearchResponse>, Status> {
+        let request = request.into_inner();
+        let store = self.lock();
+        let result = store.collection(&request.collection_name)?.search(request)?;
+        Ok(Response::new(SearchResponse { result, time: 0.0, usage: None }))
+    }
+
+    async fn search_batch(&self, request: Request<SearchBatchPoints>) -> Result<Response<SearchBatchResponse>, Status> {
+        let request = request.into_inner();
+        let store = self.lock();
+        let collection = store.collection(&request.collection_name)?;
+        let result = request
+            .search_points
+            .into_iter()
+            .map(|search| collection.search(search).map(|result| BatchResult { result }))
+            .collect::<Result<_, _>>()?;
+        Ok(Response::new(SearchBatchResponse { result, time: 0.0, usage: None }))
+    }
+
+    async fn search_groups(&self, request: Request<SearchPointGroups>) -> Result<Response<SearchGroupsResponse>, Status> {
+        let request = request.into_inner();
+        if request.with_lookup.is_some() {
+            return Err(unavailable("Group lookups"));
+        }
+        let store = self.lock();
+        let collection = store.collection(&request.collection_name)?;
+        let ranked = collection.rank(request.vector, request.filter.as_ref(), request.score_threshold)?;
+
+        // Groups are ordered by their best hit, which is the first one seen
+        let mut groups: Vec<PointGroup> = Vec::new();
+        for (key, point, score) in ranked {
+            for id in group_ids(&point.payload, &request.group_by) {
+                let position = groups
+                    .iter()
+                    .position(|group| group.id.as_ref().and_then(|id| id.kind.as_ref()) == Some(&id));
+                let group = match position {
+                    Some(position) => &mut groups[position],
+                    None if groups.len() < request.limit as usize => {
+                        groups.push(PointGroup {
+                            id: Some(GroupId { kind: Some(id) }),
+                            hits: Vec::new(),
+                            lookup: None,
+                        });
+                        groups.last_mut().expect("just pushed")
+                    }
+                    None => continue,
+                };
+                if group.hits.len() < request.group_size as usize {
+                    group.hits.push(scored(
+                        key,
+                        point,
+                        score,
+                        request.with_payload.as_ref(),
+                        request.with_vectors.as_ref(),
+                    ));
+                }
+            }
+        }
+        Ok(Response::new(SearchGroupsResponse {
+            result: Some(GroupsResult { groups }),
+            time: 0.0,
+            usage: None,
+        }))
+    }
+
+    async fn scroll(&self, request: Request<ScrollPoints>) -> Result<Response<ScrollResponse>, Status> {
+        let request = request.into_inner();
+        if request.order_by.is_some() {
+            return Err(unavailable("Ordered scrolling"));
+        }
+        let store = self.lock();
+        let collection = store.collection(&request.collection_name)?;
+        let offset = request.offset.as_ref().map(Key::parse).transpose()?;
+        let limit = request.limit.unwrap_or(DEFAULT_SCROLL_LIMIT) as usize;
+
+        let mut result = Vec::new();
+        let mut next_page_offset = None;
+        let points = match &offset {
+            Some(offset) => collection.points.range(offset.clone()..),
+            None => collection.points.range(..),
+        };
+        for (key, point) in points {
+            if !collection.admits(request.filter.as_ref(), key, point)? {
+                continue;
+            }
+            if result.len() == limit {
+                next_page_offset = Some(key.to_point_id());
+                break;
+            }
+            result.push(retrieved(key, point, request.with_payload.as_ref(), request.with_vectors.as_ref()));
+        }
+        Ok(Response::new(ScrollResponse { next_page_offset, result, time: 0.0, usage: None }))
+    }
+
+    async fn recommend(&self, _: Request<RecommendPoints>) -> Result<Response<RecommendResponse>, Status> {
+        Err(unavailable("Recommendations"))
+    }
+
+    async fn recommend_batch(&self, _: Request<RecommendBatchPoints>) -> Result<Response<RecommendBatchResponse>, Status> {
+        Err(unavailable("Recommendations"))
+    }
+
+    async fn recommend_groups(&self, _: Request<RecommendPointGroups>) -> Result<Response<RecommendGroupsResponse>, Status> {
+        Err(unavailable("Recommendations"))
+    }
+
+    async fn discover(&self, _: Request<DiscoverPoints>) -> Result<Response<DiscoverResponse>, Status> {
+        Err(unavailable("Discovery"))
+    }
+
+    async fn discover_batch(&self, _: Request<DiscoverBatchPoints>) -> Result<Response<DiscoverBatchResponse>, Status> {
+        Err(unavailable("Discovery"))
+    }
+
+    async fn count(&self, request: Request<CountPoints>) -> Result<Response<CountResponse>, Status> {
+        let request = request.into_inner();
+        let store = self.lock();
+        let count = store.collection(&request.collection_name)?.filtered(request.filter.as_ref())?.len() as u64;
+        Ok(Response::new(CountResponse {
+            result: Some(CountResult { count }),
+            time: 0.0,
+            usage: None,
+        }))
+    }
+
+    async fn update_batch(&self, _: Request<UpdateBatchPoints>) -> Result<Response<UpdateBatchResponse>, Status> {
+        Err(unavailable("Batch updates"))
+    }
+
+    async fn query(&self, _: Request<QueryPoints>) -> Result<Response<QueryResponse>, Status> {
+        Err(unavailable("The query API"))
+    }
+
+    async fn query_batch(&self, _: Request<QueryBatchPoints>) -> Result<Response<QueryBatchResponse>, Status> {
+        Err(unavailable("The query API"))
+    }
+
+    async fn query_groups(&self, _: Request<QueryPointGroups>) -> Result<Response<QueryGroupsResponse>, Status> {
+        Err(unavailable("The query API"))
+    }
+
+    async fn facet(&self, _: Request<FacetCounts>) -> Result<Response<FacetResponse>, Status> {
+        Err(unavailable("Facets"))
+    }
+
+    async fn search_matrix_pairs(&self, _: Request<SearchMatrixPoints>) -> Result<Response<SearchMatrixPairsResponse>, Status> {
+        Err(unavailable("Distance matrices"))
+    }
+
+    async fn search_matrix_offsets(
+        &self,
+        _: Request<SearchMatrixPoints>,
+    ) -> Result<Response<SearchMatrixOffsetsResponse>, Status> {
+        Err(unavailable("Distance matrices"))
+    }
+}
@@ -0,0 +1,235 @@
+use anyhow::{anyhow, Result};
+use axum::http::Method;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::budget::{self, TokenKind};
+use crate::config::Config;
+use crate::keys::{KeyRole, KeySet};
+use crate::paths;
+use crate::services::openai::models;
+
+/// What a single API key may use. Omitted fields are unrestricted.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Entitlement {
+    /// OpenAI models the key's requests may call
+    pub models: Option<Vec<String>>,
+    /// Route paths the key may call, as written in the router (e.g. `/api/documents/:id`)
+    pub routes: Option<Vec<String>>,
+    /// OpenAI tokens the key may consume per calendar month (UTC)
+    pub monthly_tokens: Option<u64>,
+    /// Tenant the key belongs to; with `SHARDING=custom` it is the shard key
+    /// of the key's requests that don't name one
+    pub tenant: Option<String>,
+}
+
+/// Why a request was refused by its key's entitlement.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    /// The route is not in the key's `routes`
+    Route(String),
+    /// The request would call a model not in the key's `models`
+    Model(String),
+    /// The key has used up its `monthly_tokens`
+    Quota(u64),
+}
+
+impl Violation {
+    /// Machine-readable code returned as `error_code`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Route(_) => "route_not_entitled",
+            Self::Model(_) => "model_not_entitled",
+            Self::Quota(_) => "quota_exceeded",
+        }
+    }
+
+    /// Human-readable explanation returned as `error`.
+    pub fn message(&self) -> String {
+        match self {
+            Self::Route(route) => format!("This API key may not call {}", route),
+            Self::Model(model) => format!("This API key may not use model '{}'", model),
+            Self::Quota(quota) => format!("This API key has used its monthly quota of {} tokens", quota),
+        }
+    }
+}
+
+impl Entitlement {
+    /// Checks a route and the models it calls against this entitlement.
+    pub fn check_request(&self, route: &str, route_models: &[&str]) -> Result<(), Violation> {
+        if let Some(routes) = &self.routes {
+            if !routes.iter().any(|r| r == route) {
+                return Err(Violation::Route(route.to_string()));
+            }
+        }
+        route_models.iter().try_for_each(|model| self.check_model(model))
+    }
+
+    /// Checks a single model against this entitlement.
+    pub fn check_model(&self, model: &str) -> Result<(), Violation> {
+        match &self.models {
+            Some(models) if !models.iter().any(|m| m == model) => {
+                Err(Violation::Model(model.to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks the tokens already used this month against the quota.
+    pub fn check_quota(&self, used: u64) -> Result<(), Violation> {
+        match self.monthly_tokens {
+            Some(quota) if used >= quota => Err(Violation::Quota(quota)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Entitlements per API key fingerprint, parsed from `KEY_ENTITLEMENTS`.
+///
+/// The value is a JSON object keyed by fingerprint as listed by
+/// `/api/admin/keys`, e.g.
+/// `{"sha256:8254c329a92850f6": {"models": ["text-embedding-3-large"], "routes": ["/api/search"], "monthly_tokens": 1000000}}`.
+/// Keys without an entry are unrestricted, and so are admin keys.
+#[derive(Debug, Clone, Default)]
+pub struct Entitlements(HashMap<String, Entitlement>);
+
+impl FromStr for Entitlements {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s).map(Self).map_err(|e| e.to_string())
+    }
+}
+
+impl Entitlements {
+    /// Returns the entitlement of the key with the given fingerprint, if restricted.
+    pub fn get(&self, fingerprint: &str) -> Option<&Entitlement> {
+        self.0.get(fingerprint)
+    }
+
+    /// Tenants of the keys that belong to one, by fingerprint.
+    pub fn tenants(&self) -> HashMap<String, String> {
+        self.0
+            .iter()
+            .filter_map(|(fingerprint, entitlement)| {
+                let tenant = entitlement.tenant.as_ref()?;
+                Some((fingerprint.clone(), tenant.clone()))
+            })
+            .collect()
+    }
+
+    /// Rejects entries that don't match a configured user key, which are
+    /// most likely mistyped fingerprints, and routes that don't exist.
+    pub fn validate(&self, keys: &KeySet) -> Result<()> {
+        let mut routes = self.0.values().filter_map(|entitlement| entitlement.routes.as_ref()).flatten();
+        if let Some(route) = routes.find(|route| !paths::ALL.contains(&route.as_str())) {
+            return Err(anyhow!("{} is not a route", route));
+        }
+
+        let user_keys: Vec<String> = keys
+            .describe()
+            .into_iter()
+            .filter(|key| key.role == KeyRole::User)
+            .map(|key| key.fingerprint)
+            .collect();
+        match self.0.keys().find(|fingerprint| !user_keys.contains(fingerprint)) {
+            Some(fingerprint) => Err(anyhow!(
+                "{} is not the fingerprint of a key in API_KEY",
+                fingerprint
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+/// OpenAI models a route calls, so model entitlements can be checked
+/// before the request runs.
+///
+/// Follows the token kinds of `budget::route_kinds`, except for
+/// `/api/embed/compare-models`, which takes its models from the request
+/// body and checks them in the handler.
+pub fn route_models<'a>(config: &'a Config, method: &Method, route: &str) -> Vec<&'a str> {
+    if route == paths::COMPARE_MODELS {
+        return Vec::new();
+    }
+    budget::route_kinds(method, route)
+        .iter()
+        .map(|kind| match kind {
+            TokenKind::Embedding => config.embedding_model.as_str(),
+            TokenKind::Chat => models::CHAT_MODEL,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+    use serde_json::json;
+
+    use crate::test_support;
+
+    fn user_fingerprint() -> String {
+        let keys = test_support::config(&[]).api_keys.describe();
+        keys.into_iter().find(|key| key.role == KeyRole::User).expect("a user key").fingerprint
+    }
+
+    #[test]
+    fn entitlements_name_configured_keys_and_existing_routes() {
+        let fingerprint = user_fingerprint();
+        let routes = |routes: &[&str]| json!({ &fingerprint: { "routes": routes } }).to_string();
+
+        let config = test_support::config(&[("KEY_ENTITLEMENTS", &routes(&[paths::SEARCH, paths::DOCUMENT]))]);
+        assert!(config.key_entitlements.get(&fingerprint).is_some());
+
+        let unknown = test_support::try_config(&[("KEY_ENTITLEMENTS", &routes(&["/api/documents/1"]))]);
+        let error = unknown.err().expect("an unknown route is refused").to_string();
+        assert!(error.contains("/api/documents/1 is not a route"), "{}", error);
+        let stranger = json!({ "sha256:0000000000000000": {} }).to_string();
+        assert!(test_support::try_config(&[("KEY_ENTITLEMENTS", &stranger)]).is_err());
+    }
+
+    #[test]
+    fn keys_name_their_tenant() {
+        let fingerprint = user_fingerprint();
+        let entitlements = json!({ &fingerprint: { "tenant": "tenant-a" } }).to_string();
+        let config = test_support::config(&[("KEY_ENTITLEMENTS", &entitlements)]);
+        assert_eq!(config.key_entitlements.tenants(), HashMap::from([(fingerprint, "tenant-a".to_string())]));
+        assert!(test_support::config(&[]).key_entitlements.tenants().is_empty());
+    }
+
+    #[test]
+    fn routes_call_the_models_of_their_token_kinds() {
+        let config = test_support::config(&[]);
+        let embedding = config.embedding_model.as_str();
+        assert_eq!(route_models(&config, &Method::POST, paths::ASK), [embedding, models::CHAT_MODEL]);
+        assert_eq!(route_models(&config, &Method::POST, paths::DOCUMENTS), [embedding]);
+        assert!(route_models(&config, &Method::GET, paths::DOCUMENTS).is_empty());
+        // Checked in the handler against the models in the body
+        assert!(route_models(&config, &Method::POST, paths::COMPARE_MODELS).is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn quota_usage_survives_a_restart() {
+        let path = std::env::temp_dir().join(format!("rust-qdrant-usage-{}.json", uuid::Uuid::new_v4()));
+        let fingerprint = user_fingerprint();
+        let entitlements = json!({ &fingerprint: { "monthly_tokens": 1 } }).to_string();
+        let vars = [("TOKEN_USAGE_FILE", path.to_str().unwrap()), ("KEY_ENTITLEMENTS", &entitlements)];
+        let embed = json!({ "text": "Rust is fast" });
+
+        let app = test_support::app(&vars).await;
+        assert_eq!(app.post(paths::EMBED, &embed).await.status, StatusCode::OK);
+        let used = app.state.metrics.key_tokens_this_month(&fingerprint);
+        assert!(used > 0);
+        app.state.metrics.token_budget().persist().await.unwrap();
+
+        let restarted = test_support::app(&vars).await;
+        assert_eq!(restarted.state.metrics.key_tokens_this_month(&fingerprint), used);
+        let refused = restarted.post(paths::EMBED, &embed).await;
+        tokio::fs::remove_file(&path).await.ok();
+        assert_eq!(refused.status, StatusCode::FORBIDDEN);
+        assert_eq!(refused.body["error_code"], "quota_exceeded");
+    }
+}
@@ -0,0 +1,620 @@
+//! Admin handlers for managing Qdrant collections and the collection alias,
+//! inspecting API keys and quotas, managing the global token budgets,
+//! purging the document trash, flagging documents for re-embedding and
+//! echoing the running configuration.
+//!
+//! These routes are only served when `ADMIN_API_KEY` is set, and require
+//! that key instead of the regular API key.
+
+use axum::{
+    extract::State,
+    Json,
+};
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info};
+use validator::Validate;
+
+use crate::{
+    audit::{AuditContext, AuditEntry},
+    build_info,
+    keys::{unix_now, year_month, KeyRole},
+    services::qdrant,
+    state::AppState,
+    types::{
+        ApiError, ApiJson, ApiPath, ApiQuery, ApiResponse, CreateCollectionRequest, DeleteCollectionRequest,
+        MarkStaleRequest, PurgeTrashQuery, SwitchAliasRequest,
+    },
+};
+
+/// Handles collection creation requests.
+///
+/// Distance and on-disk storage default to the server's own collection
+/// settings; the configured sharding method is always used.
+///
+/// # Arguments
+/// * `state` - Application state containing service instances
+/// * `payload` - JSON payload describing the new collection
+///
+/// # Returns
+/// * `Ok(Json<ApiResponse<Value>>)` - The created collection's settings
+/// * `Err(ApiError)` - 400 for an invalid name or size, 409 if it already exists
+///
+/// # Example Request
+/// ```json
+/// {
+///     "name": "tenant-b",
+///     "vector_size": 1536,
+///     "distance": "cosine",
+///     "on_disk": false
+/// }
+/// ```
+pub async fn handle_create_collection(
+    State(state): State<Arc<AppState>>,
+    ApiJson(payload): ApiJson<CreateCollectionRequest>,
+) -> Result<Json<ApiResponse<Value>>, ApiError> {
+    payload
+        .validate()
+        .map_err(|e| ApiError::Validation(e.to_string()))?;
+    check_collection_name(&payload.name)?;
+
+    let qdrant = &state.qdrant_service;
+    if qdrant.collection_exists(&payload.name).await.map_err(qdrant_failed)? {
+        return Err(ApiError::Conflict(format!(
+            "Collection '{}' already exists",
+            payload.name
+        )));
+    }
+
+    let distance = payload.distance.unwrap_or(state.config.qdrant_distance);
+    let on_disk = payload.on_disk.unwrap_or(state.config.qdrant_on_disk);
+    qdrant
+        .create_collection(&payload.name, payload.vector_size, distance, on_disk)
+        .await
+        .map_err(qdrant_failed)?;
+
+    info!("Created collection '{}'", payload.name);
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "name": payload.name,
+        "vector_size": payload.vector_size,
+        "distance": distance,
+        "on_disk": on_disk
+    }))))
+}
+
+/// Handles collection listing requests.
+///
+/// # Returns
+/// * `Ok(Json<ApiResponse<Value>>)` - Each collection's name, point count and status
+/// * `Err(ApiError)` - 500 if Qdrant cannot be queried
+pub async fn handle_list_collections(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<ApiResponse<Value>>, ApiError> {
+    let collections = state
+        .qdrant_service
+        .list_collections()
+        .await
+        .map_err(qdrant_failed)?;
+
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "collections": collections
+    }))))
+}
+
+/// Handles collection deletion requests.
+///
+/// The body must repeat the collection name in `confirm`. The collection
+/// currently serving document operations can never be deleted: the one
+/// `QDRANT_ALIAS` points at, or `COLLECTION_NAME` without an alias. Once the
+/// alias has moved on, the collection it used to point at can be deleted.
+///
+/// # Arguments
+/// * `state` - Application state containing service instances
+/// * `name` - Name of the collection to delete
+/// * `payload` - JSON payload with the confirmation
+///
+/// # Returns
+/// * `Ok(Json<ApiResponse<Value>>)` - Confirmation of the deletion
+/// * `Err(ApiError)` - 400 without a matching confirmation or for the serving
+///   collection, 404 if the collection does not exist
+///
+/// # Example Request
+/// ```json
+/// { "confirm": "tenant-b" }
+/// ```
+pub async fn handle_delete_collection(
+    State(state): State<Arc<AppState>>,
+    audit: AuditContext,
+    ApiPath(name): ApiPath<String>,
+    ApiJson(payload): ApiJson<DeleteCollectionRequest>,
+) -> Result<Json<ApiResponse<Value>>, ApiError> {
+    if payload.confirm != name {
+        return Err(ApiError::Validation(
+            "confirm must repeat the collection name".into(),
+        ));
+    }
+    let qdrant = &state.qdrant_service;
+    if name == qdrant.serving_collection().await.map_err(qdrant_failed)? {
+        return Err(ApiError::Validation(format!(
+            "Collection '{}' serves the API through '{}' and cannot be deleted",
+            name,
+            qdrant.collection()
+        )));
+    }
+    if !qdrant.collection_exists(&name).await.map_err(qdrant_failed)? {
+        return Err(ApiError::NotFound(format!("Collection '{}' does not exist", name)));
+    }
+
+    // Audit the attempt whether or not it succeeded
+    let entry = state.audit.begin(
+        AuditEntry::new(&audit, "delete_collection")
+            .target(serde_json::json!({ "collection": name })),
+    );
+    match qdrant.delete_collection(&name).await {
+        Ok(()) => entry.succeeded(None),
+        Err(e) => {
+            entry.failed(&e);
+            return Err(qdrant_failed(e));
+        }
+    }
+
+    info!("Deleted collection '{}'", name);
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "message": format!("Collection '{}' deleted", name)
+    }))))
+}
+
+/// Handles alias lookup requests.
+///
+/// # Returns
+/// * `Ok(Json<ApiResponse<Value>>)` - The configured alias (null without
+///   `QDRANT_ALIAS`) and the collection that currently serves the API
+/// * `Err(ApiError)` - 500 if Qdrant cannot be queried
+pub async fn handle_get_alias(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<ApiResponse<Value>>, ApiError> {
+    let qdrant = &state.qdrant_service;
+    let collection = qdrant.serving_collection().await.map_err(qdrant_failed)?;
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "alias": qdrant.alias(),
+        "collection": collection
+    }))))
+}
+
+/// Handles alias switch requests.
+///
+/// Points `QDRANT_ALIAS` at another collection in a single Qdrant
+/// operation, so requests move over without downtime. The previous
+/// collection is kept and can be deleted once nothing needs it.
+///
+/// # Arguments
+/// * `state` - Application state containing service instances
+/// * `payload` - JSON payload naming the collection to switch to
+///
+/// # Returns
+/// * `Ok(Json<ApiResponse<Value>>)` - The alias with its previous and new collection
+/// * `Err(ApiError)` - 400 without `QDRANT_ALIAS` or if the collection's vector
+///   size differs from the embedding model's, 404 if the collection does not exist
+///
+/// # Example Request
+/// ```json
+/// { "collection": "documents-v2" }
+/// ```
+pub async fn handle_switch_alias(
+    State(state): State<Arc<AppState>>,
+    audit: AuditContext,
+    ApiJson(payload): ApiJson<SwitchAliasRequest>,
+) -> Result<Json<ApiResponse<Value>>, ApiError> {
+    let qdrant = &state.qdrant_service;
+    let Some(alias) = qdrant.alias() else {
+        return Err(ApiError::Validation(
+            "No alias is configured; set QDRANT_ALIAS to switch collections".into(),
+        ));
+    };
+    let target = payload.collection;
+    if !qdrant.collection_exists(&target).await.map_err(qdrant_failed)? {
+        return Err(ApiError::NotFound(format!("Collection '{}' does not exist", target)));
+    }
+
+    // Every vector the API writes or searches with has the model's dimension
+    let size = qdrant.collection_vector_size(&target).await.map_err(qdrant_failed)?;
+    if let (Some(expected), Some(size)) = (qdrant.vector_size(), size) {
+        if size != expected {
+            return Err(ApiError::Validation(format!(
+                "Collection '{}' stores {}-dimensional vectors but the embedding model produces {}",
+                target, size, expected
+            )));
+        }
+    }
+
+    let previous = qdrant.alias_target(alias).await.map_err(qdrant_failed)?;
+    // Audit the attempt whether or not it succeeded
+    let entry = state.audit.begin(
+        AuditEntry::new(&audit, "switch_alias").target(serde_json::json!({
+            "alias": alias,
+            "from": previous,
+            "to": target
+        })),
+    );
+    match qdrant.switch_alias(alias, &target).await {
+        Ok(()) => entry.succeeded(None),
+        Err(e) => {
+            entry.failed(&e);
+            return Err(qdrant_failed(e));
+        }
+    }
+
+    info!("Alias '{}' now points at '{}' (was {:?})", alias, target, previous);
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "alias": alias,
+        "previous_collection": previous,
+        "collection": target
+    }))))
+}
+
+/// Handles API key listing requests.
+///
+/// Keys are identified by fingerprint; key material is never returned.
+///
+/// # Returns
+/// * `Ok(Json<ApiResponse<Value>>)` - Each key's fingerprint, role, expiry and whether it expired
+pub async fn handle_list_keys(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<ApiResponse<Value>>, ApiError> {
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "keys": state.config.api_keys.describe()
+    }))))
+}
+
+/// Handles configuration echo requests.
+///
+/// Reports the build and the effective settings of this instance, so
+/// operators can check what a deployment is actually running. Settings go
+/// through `Config::redacted`, which never includes secrets.
+///
+/// # Returns
+/// * `Ok(Json<ApiResponse<Value>>)` - The crate version, git SHA and redacted settings
+pub async fn handle_config(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<ApiResponse<Value>>, ApiError> {
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "version": build_info::VERSION,
+        "git_sha": build_info::GIT_SHA,
+        "config": state.config.redacted()
+    }))))
+}
+
+/// Handles quota listing requests.
+///
+/// Lists every regular API key with the tokens it used this calendar month
+/// (UTC) and what remains of its `KEY_ENTITLEMENTS` quota. Usage is saved
+/// with the token budget totals, so it survives a restart.
+///
+/// # Returns
+/// * `Ok(Json<ApiResponse<Value>>)` - The month and each key's quota, usage and remainder
+pub async fn handle_list_quotas(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<ApiResponse<Value>>, ApiError> {
+    let now = unix_now();
+    let quotas: Vec<Value> = state
+        .config
+        .api_keys
+        .describe()
+        .into_iter()
+        .filter(|key| key.role == KeyRole::User)
+        .map(|key| {
+            let used = state.metrics.key_tokens_this_month(&key.fingerprint);
+            let quota = state
+                .config
+                .key_entitlements
+                .get(&key.fingerprint)
+                .and_then(|entitlement| entitlement.monthly_tokens);
+            serde_json::json!({
+                "fingerprint": key.fingerprint,
+                "monthly_tokens": quota,
+                "used_tokens": used,
+                "remaining_tokens": quota.map(|quota| quota.saturating_sub(used))
+            })
+        })
+        .collect();
+
+    let (year, month) = year_month(now);
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "month": format!("{:04}-{:02}", year, month),
+        "keys": quotas
+    }))))
+}
+
+/// Handles token budget requests.
+///
+/// # Returns
+/// * `Ok(Json<ApiResponse<Value>>)` - Tokens used, limit and remainder per kind
+///   for the current day and month, with the time each period resets
+pub async fn handle_token_budget(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<ApiResponse<Value>>, ApiError> {
+    Ok(Json(ApiResponse::success(
+        state.metrics.token_budget().status(unix_now()),
+    )))
+}
+
+/// Handles token budget reset requests.
+///
+/// Clears the daily and monthly totals of every kind, e.g. after raising a
+/// budget that was used up by mistake. Per-key quota usage is not affected.
+///
+/// # Returns
+/// * `Ok(Json<ApiResponse<Value>>)` - The budget status after the reset
+pub async fn handle_reset_token_budget(
+    State(state): State<Arc<AppState>>,
+    audit: AuditContext,
+) -> Result<Json<ApiResponse<Value>>, ApiError> {
+    let now = unix_now();
+    let budget = state.metrics.token_budget();
+    let before = budget.status(now);
+    budget.reset(now);
+    state.audit.record(AuditEntry::new(&audit, "reset_token_budget").target(before));
+    if let Err(e) = budget.persist().await {
+        error!("Failed to save token usage after reset: {:#}", e);
+    }
+
+    info!("Token budget totals reset");
+    Ok(Json(ApiResponse::success(budget.status(now))))
+}
+
+/// Handles trash purge requests.
+///
+/// Permanently removes the soft-deleted documents that have been in the
+/// trash longer than `TRASH_RETENTION_SECS`, or `older_than_secs` when given.
+///
+/// # Arguments
+/// * `state` - Application state containing service instances
+/// * `params` - Query parameters (`older_than_secs`, `shard_key`)
+///
+/// # Returns
+/// * `Ok(Json<ApiResponse<Value>>)` - `{purged, older_than_secs}`
+/// * `Err(ApiError)` - 400 for an invalid shard key, 500 if the delete fails
+///
+/// # Example Request
+/// ```text
+/// POST /api/admin/trash/purge?older_than_secs=86400
+/// ```
+pub async fn handle_purge_trash(
+    State(state): State<Arc<AppState>>,
+    audit: AuditContext,
+    ApiQuery(params): ApiQuery<PurgeTrashQuery>,
+) -> Result<Json<ApiResponse<Value>>, ApiError> {
+    let shard_key = params.shard_key.as_deref();
+    state
+        .qdrant_service
+        .shard_key_selector(shard_key)
+        .map_err(|e| ApiError::Validation(e.to_string()))?;
+    let older_than_secs = params.older_than_secs.unwrap_or(state.config.trash_retention_secs);
+
+    let entry = state.audit.begin(
+        AuditEntry::new(&audit, "purge_trash")
+            .tenant(shard_key)
+            .target(serde_json::json!({ "older_than_secs": older_than_secs })),
+    );
+    let result = state
+        .qdrant_service
+        .purge_trash(Duration::from_secs(older_than_secs), shard_key)
+        .await;
+    let purged = match result {
+        Ok(purged) => {
+            entry.succeeded(Some(purged));
+            purged
+        }
+        Err(e) => {
+            entry.failed(&e);
+            return Err(qdrant_failed(e));
+        }
+    };
+
+    info!("Purged {} documents from the trash", purged);
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "purged": purged,
+        "older_than_secs": older_than_secs
+    }))))
+}
+
+/// Handles requests to flag documents for re-embedding.
+///
+/// Sets the `stale` flag on every document matching the filter, trashed
+/// ones included. The flagged documents are re-embedded a batch at a time
+/// by the background worker, which only runs when
+/// `STALE_REEMBED_INTERVAL_SECS` is set. A filter without conditions is
+/// refused; every document is flagged with `"all": true` instead.
+///
+/// # Arguments
+/// * `state` - Application state containing service instances
+/// * `payload` - JSON payload with the filter or `all`, write ordering and shard key
+///
+/// # Returns
+/// * `Ok(Json<ApiResponse<Value>>)` - `{marked}`, the number of documents flagged
+/// * `Err(ApiError)` - 400 for malformed JSON, a missing or empty filter without `all`,
+///   a filter with `all` or an invalid shard key, 422 for an invalid filter, 500 if the
+///   update fails
+///
+/// # Example Request
+/// ```json
+/// {
+///     "filter": {
+///         "must": [{ "key": "metadata.source", "match": "wiki" }]
+///     }
+/// }
+/// ```
+pub async fn handle_mark_stale(
+    State(state): State<Arc<AppState>>,
+    audit: AuditContext,
+    ApiJson(payload): ApiJson<MarkStaleRequest>,
+) -> Result<Json<ApiResponse<Value>>, ApiError> {
+    // Flagging everything is costly enough to have to be asked for by name
+    let filter = match (&payload.filter, payload.all) {
+        (Value::Null, true) => qdrant::DocumentFilter::default(),
+        (Value::Null, false) => {
+            return Err(ApiError::Validation(
+                "A filter is required; send \"all\": true to flag every document".into(),
+            ))
+        }
+        (_, true) => return Err(ApiError::Validation("\"all\" cannot be combined with a filter".into())),
+        (filter, false) => qdrant::DocumentFilter::parse(filter).map_err(ApiError::InvalidFilter)?,
+    };
+    if filter.is_empty() && !payload.all {
+        return Err(ApiError::Validation(
+            "The filter has no conditions; send \"all\": true to flag every document".into(),
+        ));
+    }
+    let shard_key = payload.shard_key.as_deref();
+    state
+        .qdrant_service
+        .shard_key_selector(shard_key)
+        .map_err(|e| ApiError::Validation(e.to_string()))?;
+
+    let entry = state.audit.begin(
+        AuditEntry::new(&audit, "mark_stale")
+            .tenant(shard_key)
+            .target(serde_json::json!({ "filter": filter })),
+    );
+    let result = state
+        .qdrant_service
+        .mark_stale_by_filter(filter.into(), payload.write_ordering, shard_key)
+        .await;
+    let marked = match result {
+        Ok(marked) => {
+            entry.succeeded(Some(marked));
+            marked
+        }
+        Err(e) => {
+            entry.failed(&e);
+            return Err(qdrant_failed(e));
+        }
+    };
+
+    info!("Flagged {} documents as stale", marked);
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "marked": marked
+    }))))
+}
+
+/// Rejects names Qdrant would not accept as part of a URL path.
+fn check_collection_name(name: &str) -> Result<(), ApiError> {
+    let valid = name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(ApiError::Validation(
+            "Name may only contain letters, digits, '-', '_' and '.'".into(),
+        ))
+    }
+}
+
+/// Logs a Qdrant failure and hides its details, other than the gRPC
+/// status, from the client.
+fn qdrant_failed(e: anyhow::Error) -> ApiError {
+    error!("Collection admin operation failed: {:#}", e);
+    match qdrant::status_code(&e) {
+        Some(code) => ApiError::Qdrant(format!("Collection operation failed (Qdrant status: {:?})", code)),
+        None => ApiError::Qdrant("Collection operation failed".into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+
+    use crate::handlers::start_stale_reembed;
+    use crate::paths;
+    use crate::test_support::{self, TestApp, ADMIN_KEY};
+
+    async fn store(app: &TestApp, text: &str, source: &str) -> u64 {
+        let stored = app
+            .post(paths::DOCUMENTS, &json!({ "text": text, "metadata": { "source": source } }))
+            .await;
+        stored.body["data"]["id"].as_u64().expect("stored document id")
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn mark_stale_needs_a_filter_or_all() {
+        let app = test_support::app(&[]).await;
+        store(&app, "Rust is fast", "wiki").await;
+
+        let missing_body = app.call(Method::POST, paths::ADMIN_MARK_STALE, Some(ADMIN_KEY)).await;
+        assert_eq!(missing_body.status, StatusCode::BAD_REQUEST);
+        for body in [
+            json!({}),
+            json!({ "filter": {} }),
+            json!({ "filter": { "must": [] } }),
+            json!({ "all": true, "filter": { "must": [{ "key": "metadata.source", "match": "wiki" }] } }),
+        ] {
+            let refused = app.admin_post(paths::ADMIN_MARK_STALE, &body).await;
+            assert_eq!(refused.status, StatusCode::BAD_REQUEST, "{}", body);
+        }
+        let malformed = app.admin_post(paths::ADMIN_MARK_STALE, &json!({ "all": "yes" })).await;
+        assert_eq!(malformed.status, StatusCode::BAD_REQUEST);
+        assert_eq!(app.state.qdrant_service.count_stale(None).await.unwrap(), 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn mark_stale_flags_matches_or_everything() {
+        let app = test_support::app(&[]).await;
+        store(&app, "Rust is fast", "wiki").await;
+        store(&app, "Go is simple", "blog").await;
+
+        let filter = json!({ "filter": { "must": [{ "key": "metadata.source", "match": "wiki" }] } });
+        let marked = app.admin_post(paths::ADMIN_MARK_STALE, &filter).await;
+        assert_eq!(marked.status, StatusCode::OK, "{}", marked.text);
+        assert_eq!(marked.body["data"]["marked"], 1);
+
+        let marked = app.admin_post(paths::ADMIN_MARK_STALE, &json!({ "all": true })).await;
+        assert_eq!(marked.body["data"]["marked"], 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn stale_documents_are_reembedded() {
+        let app = test_support::app(&[]).await;
+        let id = store(&app, "Rust is fast", "wiki").await;
+        app.admin_post(paths::ADMIN_MARK_STALE, &json!({ "all": true })).await;
+        let qdrant = &app.state.qdrant_service;
+        assert_eq!(qdrant.count_stale(None).await.unwrap(), 1);
+
+        let job = start_stale_reembed(&app.state).await.unwrap().expect("job started");
+        let job = app.finished_job(&job).await;
+        assert_eq!(job.processed, 1, "{:?}", job.error);
+        assert_eq!(qdrant.count_stale(None).await.unwrap(), 0);
+        let document = qdrant.get_document(id, false, None).await.unwrap().unwrap();
+        assert_eq!(document.metadata["source"], "wiki");
+    }
+
+    async fn delete(app: &TestApp, name: &str) -> test_support::TestResponse {
+        let uri = paths::ADMIN_COLLECTION.replace(":name", name);
+        app.call_json(Method::DELETE, &uri, Some(ADMIN_KEY), &json!({ "confirm": name })).await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn only_the_serving_collection_is_protected() {
+        let app = test_support::app(&[("QDRANT_ALIAS", "live")]).await;
+        let qdrant = &app.state.qdrant_service;
+        let vector_size = qdrant.vector_size().expect("demo vector size");
+
+        let created = app
+            .admin_post(paths::ADMIN_COLLECTIONS, &json!({ "name": "documents-v2", "vector_size": vector_size }))
+            .await;
+        assert_eq!(created.status, StatusCode::OK, "{}", created.text);
+        assert_eq!(delete(&app, "documents").await.status, StatusCode::BAD_REQUEST);
+
+        let switched = app
+            .call_json(Method::PUT, paths::ADMIN_ALIAS, Some(ADMIN_KEY), &json!({ "collection": "documents-v2" }))
+            .await;
+        assert_eq!(switched.status, StatusCode::OK, "{}", switched.text);
+        assert_eq!(delete(&app, "documents-v2").await.status, StatusCode::BAD_REQUEST);
+        let deleted = delete(&app, "documents").await;
+        assert_eq!(deleted.status, StatusCode::OK, "{}", deleted.text);
+        assert!(!qdrant.collection_exists("documents").await.unwrap());
+        assert_eq!(qdrant.serving_collection().await.unwrap(), "documents-v2");
+    }
+}
@@ -1,89 +1,686 @@
-use axum::{extract::State, http::StatusCode, Json};
+//! Request handlers for the API endpoints.
+//!
+//! Handlers await every OpenAI and Qdrant call and, apart from the
+//! `/api/admin/reindex` job, never spawn detached tasks; OpenAI calls run on the `OpenAIQueue` workers, which drop a call
+//! once nobody awaits its result. When a client disconnects, axum drops the
+//! request future, which cancels any upstream call still in flight instead
+//! of letting it run to completion and consume quota.
+
+pub mod admin;
+
+use axum::{
+    body::{Body, Bytes},
+    extract::State,
+    http::{header, HeaderMap, Method, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use futures::{StreamExt, TryStreamExt};
+use qdrant_client::qdrant::point_id::PointIdOptions;
 use serde_json::Value;
 use std::sync::Arc;
-use tracing::{error, info};
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, warn};
+use validator::Validate;
 
 use crate::{
+    audit::{AuditContext, AuditEntry},
+    build_info::BuildInfo,
+    budget::TokenKind,
+    jobs::{JobHandle, JobId, JobKind, StageTimings},
+    keys::{unix_now, Authenticated},
+    models::Document,
     state::AppState,
-    types::{ApiResponse, EmbeddingRequest, MessageRequest},
+    services::{
+        conversations,
+        openai::{models, ChatTurn, CompletionOptions, CompletionResponse, Usage},
+        qdrant::{
+            self, Connectivity, DistanceMetric, DocumentFilter, QdrantRejection, ReadConsistencyLevel, SearchScope,
+            WriteConflict, WriteOrderingLevel,
+        },
+        tokenizer, QueueError,
+    },
+    vector_math::{self, ZeroVector},
+    types::{
+        ApiError, ApiJson, ApiPath, ApiQuery, ApiResponse, ApiVersion, OptionalApiJson, AskRequest, CompareModelsRequest, DeleteByFilterRequest, DeleteByIdsRequest, DeleteDocumentQuery, DocumentQuery, DocumentRequest, EmbedQuery, EmbeddingFormat, EmbeddingRequest, ImportQuery, ImportRecord,
+        EmbeddingResponse, EncodedEmbedding, ExportQuery, GetDocumentsRequest, ListDocumentsQuery, MessageRequest, NoContextBehavior, RawDocumentRequest,
+        ReindexRequest, RestoreDocumentQuery, StatsQuery, UpdateDocumentRequest,
+        ResetRequest, ScoreDistributionRequest, SearchHit, SearchMode, SearchRequest, SearchResults, SimilarityRequest, TokenizeRequest, ValidateFilterRequest, VersionQuery, MAX_DELETE_IDS, MAX_GET_IDS, MAX_SIMILARITY_PAIRS,
+    },
 };
 
 /// Handles requests to generate embeddings from text input.
 /// 
+/// Accepts either a single `text` or a batch of `texts`; batches are
+/// embedded with a single OpenAI call and returned in input order.
+/// With `?format=base64`, vectors are returned as base64-encoded
+/// little-endian f32 buffers along with their dimension count. Inputs are
+/// counted locally before anything is sent to OpenAI.
+/// 
 /// # Arguments
 /// * `state` - Application state containing service instances
-/// * `payload` - JSON payload containing the text to embed
+/// * `params` - Query parameters selecting the response format
+/// * `payload` - JSON payload containing the text(s) to embed
 /// 
 /// # Returns
-/// * `Ok(Json<ApiResponse<Vec<f32>>>)` - Vector of floating point numbers representing the embedding
-/// * `Err(StatusCode)` - Error status code if the request fails
+/// * `Ok(Json<ApiResponse<EmbeddingResponse>>)` - A vector, or a matrix for batch input,
+///   with the tokens consumed in `usage` and each input's token count in `tokens`
+/// * `Err(ApiError)` - 422 for a text over the model's token limit, 503 if the
+///   OpenAI queue is full, 500 if the request fails
 /// 
-/// # Example Request
+/// # Example Requests
 /// ```json
 /// {
 ///     "text": "Your text to embed"
 /// }
 /// ```
+/// ```json
+/// {
+///     "texts": ["First text", "Second text"]
+/// }
+/// ```
 pub async fn handle_embed(
     State(state): State<Arc<AppState>>,
-    Json(payload): Json<EmbeddingRequest>,
-) -> Result<Json<ApiResponse<Vec<f32>>>, StatusCode> {
-    // Validate that the input text is not empty
-    if payload.text.trim().is_empty() {
-        error!("Empty text provided for embedding");
-        return Ok(Json(ApiResponse::<Vec<f32>>::error("Text cannot be empty".into())));
+    ApiQuery(params): ApiQuery<EmbedQuery>,
+    ApiJson(payload): ApiJson<EmbeddingRequest>,
+) -> Result<Json<ApiResponse<EmbeddingResponse>>, ApiError> {
+    // Validate that the input text(s) are not empty
+    if let Err(message) = payload.validate_inputs() {
+        error!("Invalid embedding request: {}", message);
+        return Err(ApiError::Validation(message));
     }
 
-    // Call OpenAI service to generate embedding
-    let embedding = state
-        .openai_service
-        .get_embedding(&payload.text)
-        .await
+    // OpenAI answers over-long inputs with an unhelpful 400, so count first
+    let texts = match &payload {
+        EmbeddingRequest::Single { text } => vec![text.clone()],
+        EmbeddingRequest::Batch { texts } => texts.clone(),
+    };
+    let tokens = embedding_tokens(&state, texts).await?;
+
+    let (response, usage) = match payload {
+        EmbeddingRequest::Single { text } => {
+            // Call OpenAI service to generate embedding
+            let input = text.clone();
+            let (mut embedding, usage) = state
+                .openai
+                .submit(move |openai| async move { openai.get_embedding_with_usage(&input, None).await })
+                .await?
+                .map_err(|e| {
+                    error!("Failed to generate embedding: {}", e);
+                    ApiError::OpenAI("Failed to generate embedding".into())
+                })?;
+            if state.config.normalize_embeddings {
+                normalize_embedding(&mut embedding)?;
+            }
+
+            info!(
+                "Successfully generated embedding for text length {} using {} tokens",
+                text.len(),
+                usage.total_tokens
+            );
+            let response = match params.format {
+                EmbeddingFormat::Float => EmbeddingResponse::Single(embedding),
+                EmbeddingFormat::Base64 => EmbeddingResponse::EncodedSingle(EncodedEmbedding::new(&embedding)),
+            };
+            (response, usage)
+        }
+        EmbeddingRequest::Batch { texts } => {
+            // Embed all texts in a single OpenAI call
+            let mut embeddings = state
+                .openai
+                .submit(move |openai| async move { openai.get_embeddings(&texts).await })
+                .await?
+                .map_err(|e| {
+                    error!("Failed to generate batch embeddings: {}", e);
+                    ApiError::OpenAI("Failed to generate embeddings".into())
+                })?;
+            if state.config.normalize_embeddings {
+                embeddings.vectors.iter_mut().try_for_each(|e| normalize_embedding(e))?;
+            }
+
+            info!(
+                "Successfully generated {} embeddings using {} tokens",
+                embeddings.vectors.len(),
+                embeddings.usage.total_tokens
+            );
+            let response = match params.format {
+                EmbeddingFormat::Float => EmbeddingResponse::Batch(embeddings.vectors),
+                EmbeddingFormat::Base64 => EmbeddingResponse::EncodedBatch(
+                    embeddings.vectors.iter().map(|e| EncodedEmbedding::new(e)).collect(),
+                ),
+            };
+            (response, embeddings.usage)
+        }
+    };
+
+    Ok(Json(ApiResponse::success(response).with_usage(usage).with_tokens(tokens)))
+}
+
+/// Counts the tokens of each text under `EMBEDDING_MODEL` and rejects texts
+/// the model cannot take.
+/// 
+/// # Returns
+/// * `Ok(Some(Vec<usize>))` - The token count of each text
+/// * `Ok(None)` - If the model's encoding is unknown, so nothing was checked
+/// * `Err(ApiError)` - 422 with the count and the limit for the first text over it
+async fn embedding_tokens(state: &AppState, texts: Vec<String>) -> Result<Option<Vec<usize>>, ApiError> {
+    let model = state.config.embedding_model.clone();
+    let max_tokens = tokenizer::context_window(&model);
+    let batch = texts.len() > 1;
+    let normalization = state.config.text_normalization;
+    // Encoding a large body takes a while; keep it off the async workers.
+    // Texts are counted as they will be sent, i.e. normalized
+    let (model, counts) = tokio::task::spawn_blocking(move || {
+        let counts: Option<Vec<usize>> = texts
+            .iter()
+            .map(|text| tokenizer::count_tokens(&model, &normalization.apply(text)))
+            .collect();
+        (model, counts)
+    })
+    .await
+    .map_err(|e| ApiError::Internal(format!("Tokenizer task failed: {}", e)))?;
+
+    let over_limit = counts
+        .iter()
+        .flatten()
+        .enumerate()
+        .find(|(_, tokens)| **tokens > max_tokens);
+    if let Some((index, tokens)) = over_limit {
+        let subject = if batch { format!("Text {}", index) } else { "Text".to_string() };
+        return Err(ApiError::Unprocessable(format!(
+            "{} has {} tokens, but {} accepts at most {}",
+            subject, tokens, model, max_tokens
+        )));
+    }
+    Ok(counts)
+}
+
+/// Cuts each text longer than `EMBEDDING_MODEL` accepts into chunks that fit.
+/// 
+/// Texts are cut as they are stored, so a text's chunks put back together
+/// give the text. A text that fits, or whose model's encoding is unknown,
+/// comes back as a single chunk.
+async fn chunk_texts(state: &AppState, texts: Vec<String>) -> Result<Vec<Vec<String>>, ApiError> {
+    let model = state.config.embedding_model.clone();
+    // Encoding a large body takes a while; keep it off the async workers
+    tokio::task::spawn_blocking(move || {
+        let max_tokens = tokenizer::context_window(&model);
+        texts
+            .into_iter()
+            .map(|text| match tokenizer::split(&model, &text, max_tokens) {
+                Some(pieces) if pieces.len() > 1 => pieces.into_iter().map(|(piece, _)| piece.to_string()).collect(),
+                _ => vec![text],
+            })
+            .collect()
+    })
+    .await
+    .map_err(|e| ApiError::Internal(format!("Tokenizer task failed: {}", e)))
+}
+
+/// Scales an embedding returned by `/api/embed` to unit length, matching
+/// what is stored when `NORMALIZE_EMBEDDINGS` is enabled.
+fn normalize_embedding(embedding: &mut [f32]) -> Result<(), ApiError> {
+    vector_math::normalize_in_place(embedding).map_err(|e| {
+        error!("Failed to normalize embedding: {}", e);
+        ApiError::Internal("Failed to normalize embedding".into())
+    })
+}
+
+/// Handles text similarity requests.
+/// 
+/// Embeds every text with a single OpenAI call and compares the vectors.
+/// Qdrant is not involved, which makes this handy for checking how the
+/// embedding model scores pairs of texts while debugging retrieval.
+/// Vectors are normalized first when `NORMALIZE_EMBEDDINGS` is enabled, and
+/// `score` uses the collection's distance unless `metric` says otherwise,
+/// so it is comparable to search scores and `SCORE_THRESHOLD`.
+/// 
+/// # Arguments
+/// * `state` - Application state containing service instances
+/// * `payload` - JSON payload containing one pair of texts or a list of `pairs`
+/// 
+/// # Returns
+/// * `Ok(Json<ApiResponse<Value>>)` - The model, the metric and the score of each pair, along
+///   with its cosine similarity, dot product and Euclidean distance
+/// * `Err(ApiError)` - 400 for empty texts or a malformed request, 422 above `MAX_SIMILARITY_PAIRS`
+///   or for a zero vector under cosine, 500 if embedding fails
+/// 
+/// # Example Request
+/// ```json
+/// {
+///     "pairs": [
+///         { "text_a": "How do I reset my password?", "text_b": "Password recovery steps" },
+///         { "text_a": "How do I reset my password?", "text_b": "Shipping rates" }
+///     ],
+///     "metric": "dot"
+/// }
+/// ```
+pub async fn handle_similarity(
+    State(state): State<Arc<AppState>>,
+    ApiJson(payload): ApiJson<SimilarityRequest>,
+) -> Result<Json<ApiResponse<Value>>, ApiError> {
+    payload
+        .validate()
+        .map_err(|e| ApiError::Validation(e.to_string()))?;
+    let (pairs, single) = match (payload.text_a, payload.text_b, payload.pairs) {
+        (Some(text_a), Some(text_b), None) => (vec![(text_a, text_b)], true),
+        (None, None, Some(pairs)) => {
+            (pairs.into_iter().map(|pair| (pair.text_a, pair.text_b)).collect::<Vec<_>>(), false)
+        }
+        _ => {
+            return Err(ApiError::Validation(
+                "Provide either text_a and text_b, or pairs".into(),
+            ))
+        }
+    };
+    if pairs.is_empty() {
+        return Err(ApiError::Validation("pairs must contain at least one pair".into()));
+    }
+    if pairs.len() > MAX_SIMILARITY_PAIRS {
+        return Err(ApiError::Unprocessable(format!(
+            "pairs may contain at most {} pairs, got {}",
+            MAX_SIMILARITY_PAIRS,
+            pairs.len()
+        )));
+    }
+
+    let metric = payload.metric.unwrap_or(state.config.qdrant_distance);
+    let count = pairs.len() * 2;
+    let texts: Vec<String> = pairs.into_iter().flat_map(|(a, b)| [a, b]).collect();
+    let mut embeddings = state
+        .openai
+        .submit(move |openai| async move { openai.get_embeddings(&texts).await })
+        .await?
         .map_err(|e| {
-            error!("Failed to generate embedding: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            error!("Failed to generate embeddings for similarity: {}", e);
+            ApiError::OpenAI("Failed to generate embeddings".into())
         })?;
+    if embeddings.vectors.len() != count {
+        error!("Expected {} embeddings, got {}", count, embeddings.vectors.len());
+        return Err(ApiError::Internal("Failed to generate embeddings".into()));
+    }
+    if state.config.normalize_embeddings {
+        embeddings.vectors.iter_mut().try_for_each(|e| normalize_embedding(e))?;
+    }
+
+    let mut results = embeddings
+        .vectors
+        .chunks_exact(2)
+        .enumerate()
+        .map(|(index, pair)| {
+            let (a, b) = (&pair[0], &pair[1]);
+            let zero_vector = |e: ZeroVector| {
+                let subject = if single { String::new() } else { format!(" in pair {}", index) };
+                ApiError::Unprocessable(format!("{}{}", e, subject))
+            };
+            let similarity = vector_math::cosine_similarity(a, b).map_err(zero_vector)?;
+            let score = metric.score(a, b).map_err(zero_vector)?;
+            Ok(serde_json::json!({
+                "score": score,
+                "similarity": similarity,
+                "dot": vector_math::dot(a, b),
+                "euclidean_distance": vector_math::euclidean_distance(a, b)
+            }))
+        })
+        .collect::<Result<Vec<Value>, ApiError>>()?;
+
+    let model = state.config.embedding_model.clone();
+    let response = if single {
+        let mut response = results.remove(0);
+        response["model"] = Value::String(model);
+        response["metric"] = serde_json::json!(metric);
+        response["higher_is_closer"] = Value::Bool(metric.higher_is_closer());
+        response
+    } else {
+        serde_json::json!({
+            "model": model,
+            "metric": metric,
+            "higher_is_closer": metric.higher_is_closer(),
+            "results": results
+        })
+    };
+
+    Ok(Json(ApiResponse::success(response).with_usage(embeddings.usage)))
+}
+
+/// Handles token counting requests.
+/// 
+/// Counts tokens locally with the model's tokenizer, without calling
+/// OpenAI, so clients can check a text against context and budget limits
+/// before sending it.
+/// 
+/// # Arguments
+/// * `payload` - JSON payload containing the text and an optional model
+/// 
+/// # Returns
+/// * `Ok(Json<ApiResponse<Value>>)` - The model, the token count and the model's context window
+/// * `Err(ApiError)` - 400 for empty text or a model with unknown encoding
+/// 
+/// # Example Request
+/// ```json
+/// {
+///     "text": "How many tokens is this?",
+///     "model": "text-embedding-3-small"
+/// }
+/// ```
+pub async fn handle_tokenize(
+    ApiJson(payload): ApiJson<TokenizeRequest>,
+) -> Result<Json<ApiResponse<Value>>, ApiError> {
+    payload
+        .validate()
+        .map_err(|e| ApiError::Validation(e.to_string()))?;
 
-    // Log success and return the embedding
-    info!("Successfully generated embedding for text length: {}", payload.text.len());
-    Ok(Json(ApiResponse::success(embedding)))
+    let model = payload.model.unwrap_or_else(|| models::CHAT_MODEL.to_string());
+    let text = payload.text;
+    // Encoding a large body takes a while; keep it off the async workers
+    let (model, tokens) = tokio::task::spawn_blocking(move || {
+        let tokens = tokenizer::count_tokens(&model, &text);
+        (model, tokens)
+    })
+    .await
+    .map_err(|e| ApiError::Internal(format!("Tokenizer task failed: {}", e)))?;
+    let Some(tokens) = tokens else {
+        return Err(ApiError::Validation(format!("Unknown tokenizer for model '{}'", model)));
+    };
+
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "model": model,
+        "tokens": tokens,
+        "max_context_tokens": tokenizer::context_window(&model)
+    }))))
+}
+
+/// Handles embedding model comparison requests.
+/// 
+/// Embeds `text` with each selected model and reports the dimension and
+/// norm of every vector. With `candidates`, each model also ranks them by
+/// their cosine similarity to `text`, and every pair of models is compared
+/// on those rankings. Vectors of different models live in unrelated spaces,
+/// so scores are only compared within a model; across models only the order
+/// counts.
+/// 
+/// # Arguments
+/// * `state` - Application state containing service instances
+/// * `key` - The API key the request was authenticated with
+/// * `payload` - JSON payload with the text, and optionally the candidates and the models to compare
+/// 
+/// # Returns
+/// * `Ok(Json<ApiResponse<Value>>)` - Each model's dimension and norm, and with candidates
+///   its ranking and the rank agreement of each pair of models
+/// * `Err(ApiError)` - 400 for an empty text, fewer than 2 or more than `MAX_COMPARE_CANDIDATES`
+///   candidates or models not in `COMPARE_EMBEDDING_MODELS`, 403 for models the API key is
+///   not entitled to, 422 for a zero vector, 500 if embedding fails
+/// 
+/// # Example Request
+/// ```json
+/// {
+///     "text": "How do I reset my password?",
+///     "candidates": ["Password recovery steps", "Shipping rates", "Account settings"],
+///     "models": ["text-embedding-3-small", "text-embedding-3-large"]
+/// }
+/// ```
+pub async fn handle_compare_models(
+    State(state): State<Arc<AppState>>,
+    Extension(key): Extension<Authenticated>,
+    ApiJson(payload): ApiJson<CompareModelsRequest>,
+) -> Result<Json<ApiResponse<Value>>, ApiError> {
+    payload
+        .validate()
+        .map_err(|e| ApiError::Validation(e.to_string()))?;
+    if payload.text.trim().is_empty() {
+        return Err(ApiError::Validation("Text cannot be empty".into()));
+    }
+    let candidates = payload.candidates.as_deref().unwrap_or_default();
+    if let Some(index) = candidates.iter().position(|text| text.trim().is_empty()) {
+        return Err(ApiError::Validation(format!("Candidate {} cannot be empty", index)));
+    }
+
+    let allowed = &state.config.compare_embedding_models;
+    let mut selected: Vec<&str> = Vec::new();
+    for model in payload.models.as_deref().unwrap_or(allowed) {
+        if !allowed.contains(model) {
+            return Err(ApiError::Validation(format!(
+                "Model '{}' is not one of COMPARE_EMBEDDING_MODELS ({})",
+                model,
+                allowed.join(", ")
+            )));
+        }
+        if !selected.contains(&model.as_str()) {
+            selected.push(model);
+        }
+    }
+    if selected.len() < 2 {
+        return Err(ApiError::Validation("At least two distinct models are required".into()));
+    }
+    // Models come from the body, so entitlements can't check them up front
+    if let Some(entitlement) = state.config.key_entitlements.get(&key.fingerprint) {
+        for model in &selected {
+            entitlement.check_model(model)?;
+        }
+    }
+
+    // The query first, then any candidates, in one call per model
+    let texts: Vec<String> = std::iter::once(payload.text.clone())
+        .chain(candidates.iter().cloned())
+        .collect();
+    // Awaited together, so a disconnect drops every result and cancels the calls
+    let openai = &state.openai;
+    let embeddings = futures::future::try_join_all(selected.iter().map(|model| {
+        let (texts, model) = (texts.clone(), model.to_string());
+        async move {
+            openai
+                .submit(move |openai| async move { openai.get_model_embeddings(&texts, &model).await })
+                .await?
+                .map_err(|e| {
+                    error!("Failed to generate embeddings for model comparison: {}", e);
+                    ApiError::OpenAI("Failed to generate embeddings".into())
+                })
+        }
+    }))
+    .await?;
+
+    let mut rankings = Vec::with_capacity(selected.len());
+    for embeddings in &embeddings {
+        if embeddings.vectors.len() != texts.len() {
+            error!("Expected {} embeddings, got {}", texts.len(), embeddings.vectors.len());
+            return Err(ApiError::Internal("Failed to generate embeddings".into()));
+        }
+        let (query, candidates) = (&embeddings.vectors[0], &embeddings.vectors[1..]);
+        let scores = candidates
+            .iter()
+            .map(|candidate| vector_math::cosine_similarity(query, candidate))
+            .collect::<Result<Vec<f32>, ZeroVector>>()
+            .map_err(|e| ApiError::Unprocessable(e.to_string()))?;
+        let mut order: Vec<usize> = (0..scores.len()).collect();
+        order.sort_by(|&a, &b| scores[b].total_cmp(&scores[a]));
+        rankings.push((query.len(), vector_math::l2_norm(query), order, scores));
+    }
+
+    info!("Compared {} embedding models on {} candidates", selected.len(), candidates.len());
+    let models = selected
+        .iter()
+        .zip(&rankings)
+        .map(|(model, (dimension, norm, _, _))| serde_json::json!({ "model": model, "dimension": dimension, "norm": norm }))
+        .collect::<Vec<_>>();
+    // Without candidates there is nothing to rank
+    if candidates.is_empty() {
+        return Ok(Json(ApiResponse::success(serde_json::json!({ "models": models }))));
+    }
+
+    let mut agreements = Vec::new();
+    for (i, (_, _, a, _)) in rankings.iter().enumerate() {
+        for (j, (_, _, b, _)) in rankings.iter().enumerate().skip(i + 1) {
+            agreements.push(serde_json::json!({
+                "model_a": selected[i],
+                "model_b": selected[j],
+                "rank_correlation": vector_math::rank_correlation(a, b),
+                "same_top": a[0] == b[0]
+            }));
+        }
+    }
+    let models = models
+        .into_iter()
+        .zip(&rankings)
+        .map(|(mut entry, (_, _, order, scores))| {
+            entry["ranking"] = order
+                .iter()
+                .map(|&index| serde_json::json!({ "index": index, "similarity": scores[index] }))
+                .collect();
+            entry
+        })
+        .collect::<Vec<_>>();
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "models": models,
+        "agreements": agreements
+    }))))
 }
 
 /// Handles chat message requests to generate AI responses.
 /// 
+/// When a `conversation_id` is given, prior turns of that conversation are
+/// sent along with the message and the new exchange is stored afterwards,
+/// so clients only need to send their latest message.
+/// 
+/// Messages with `images` are answered by `VISION_MODEL`. Only the text is
+/// stored in the conversation, so later messages no longer see the images.
+/// A request may pick another model from `CHAT_MODELS` and its own
+/// temperature; the model must accept images when images are sent.
+/// 
 /// # Arguments
 /// * `state` - Application state containing service instances
+/// * `key` - The API key the request was authenticated with
 /// * `payload` - JSON payload containing the message to process
 /// 
 /// # Returns
 /// * `Ok(Json<ApiResponse<Value>>)` - JSON response containing the AI-generated message
-/// * `Err(StatusCode)` - Error status code if the request fails
+/// * `Err(ApiError)` - 400 for an empty message or conversation id or invalid
+///   completion options, 422 for images without a vision model, 503 if the
+///   OpenAI queue is full, 500 if the request fails
 /// 
 /// # Example Request
 /// ```json
 /// {
-///     "message": "What is the capital of France?"
+///     "message": "What is the capital of France?",
+///     "conversation_id": "3f2b8c1e-7d4a-4e0b-9c5f-2a6d8e1b4c7f",
+///     "max_tokens": 200,
+///     "temperature": 0.2,
+///     "seed": 42,
+///     "top_logprobs": 3,
+///     "images": [{ "url": "https://example.com/screenshot.png" }]
 /// }
 /// ```
 pub async fn handle_message(
     State(state): State<Arc<AppState>>,
-    Json(payload): Json<MessageRequest>,
-) -> Result<Json<ApiResponse<Value>>, StatusCode> {
-    // Validate that the input message is not empty
-    if payload.message.trim().is_empty() {
-        error!("Empty message provided");
-        return Ok(Json(ApiResponse::<Value>::error("Message cannot be empty".into())));
+    Extension(key): Extension<Authenticated>,
+    ApiJson(payload): ApiJson<MessageRequest>,
+) -> Result<Json<ApiResponse<Value>>, ApiError> {
+    // Validate that the message and conversation id are not empty
+    payload.validate().map_err(|e| {
+        error!("Invalid message: {}", e);
+        ApiError::Validation(e.to_string())
+    })?;
+    let options = completion_options(&state, payload.max_tokens, payload.stop.clone())?
+        .with_sampling(payload.seed, payload.logprobs, payload.top_logprobs)
+        .and_then(|options| {
+            options.with_model(payload.model.clone(), payload.temperature, &state.config.chat_models)
+        })
+        .map_err(|message| {
+            error!("Invalid completion options: {}", message);
+            ApiError::Validation(message)
+        })?;
+    if !payload.images.is_empty() {
+        let Some(model) = options.model.as_ref().or(state.config.vision_model.as_ref()) else {
+            return Err(ApiError::Unprocessable(format!(
+                "model '{}' does not accept images and VISION_MODEL is unset; models that do: {}",
+                models::CHAT_MODEL,
+                models::VISION_MODELS.join(", ")
+            )));
+        };
+        if !models::supports_vision(model) {
+            return Err(ApiError::Unprocessable(format!(
+                "model '{}' does not accept images; models that do: {}",
+                model,
+                models::VISION_MODELS.join(", ")
+            )));
+        }
+        if payload.images.len() > state.config.max_chat_images {
+            error!("Too many images provided: {}", payload.images.len());
+            return Err(ApiError::Validation(format!(
+                "At most {} images may be sent per message",
+                state.config.max_chat_images
+            )));
+        }
+        if let Some(rejection) = payload
+            .images
+            .iter()
+            .find_map(|image| image.validate(state.config.max_image_bytes).err())
+        {
+            error!("Invalid image: {}", rejection);
+            return Err(rejection.into());
+        }
+        // The middleware only checks the chat model
+        if let Some(entitlement) = state.config.key_entitlements.get(&key.fingerprint) {
+            entitlement.check_model(model)?;
+        }
+    } else if let Some(model) = &options.model {
+        if let Some(entitlement) = state.config.key_entitlements.get(&key.fingerprint) {
+            entitlement.check_model(model)?;
+        }
     }
 
-    // Call OpenAI service to generate completion
-    let response = state
-        .openai_service
-        .generate_completion(&payload.message)
-        .await
-        .map_err(|e| {
-            error!("Failed to generate completion: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
+    // Call OpenAI service to generate completion, including prior turns if any
+    let mut summary_usage = None;
+    let result = match &payload.conversation_id {
+        Some(id) => {
+            let turns = state.conversations.history(id).await.map_err(|e| {
+                error!("Failed to load conversation: {:#}", e);
+                ApiError::Qdrant("Failed to load conversation".into())
+            })?;
+            let (mut turns, usage) = summarize_history(&state, id, turns).await?;
+            summary_usage = usage;
+            turns.push(ChatTurn::user(payload.message.as_str()));
+            let (images, options) = (payload.images.clone(), options.clone());
+            state
+                .openai
+                .submit(move |openai| async move {
+                    openai.generate_chat_completion(&turns, &images, &options).await
+                })
+                .await?
+        }
+        None => {
+            let (message, images, options) =
+                (payload.message.clone(), payload.images.clone(), options.clone());
+            state
+                .openai
+                .submit(move |openai| async move {
+                    openai.generate_completion(&message, &images, &options).await
+                })
+                .await?
+        }
+    };
+    let response = result.map_err(|e| {
+        error!("Failed to generate completion: {}", e);
+        ApiError::OpenAI("Failed to generate completion".into())
+    })?;
+    if response.empty {
+        warn!(
+            "Chat completion came back empty (finish_reason: {:?})",
+            response.finish_reason
+        );
+    }
+
+    // Persist the new exchange only once the model has replied. The reply is
+    // returned even if saving fails, since it has already been paid for. An
+    // empty reply is not stored, so the user can simply ask again.
+    if let Some(id) = payload.conversation_id.as_ref().filter(|_| !response.empty) {
+        let turns = vec![
+            ChatTurn::user(payload.message.as_str()),
+            ChatTurn::assistant(response.response.as_str()),
+        ];
+        if let Err(e) = state.conversations.append(id, turns).await {
+            error!("Failed to save conversation: {:#}", e);
+        }
+    }
 
     // Log success with token usage
     info!(
@@ -91,42 +688,3444 @@ pub async fn handle_message(
         response.usage.total_tokens
     );
 
+    let model = match (&options.model, payload.images.is_empty()) {
+        (Some(model), _) => Some(model.as_str()),
+        (None, true) => Some(models::CHAT_MODEL),
+        (None, false) => state.config.vision_model.as_deref(),
+    };
+
+    // A seed only makes answers reproducible if the model reports the
+    // backend configuration it ran with
+    let deterministic = options.seed.is_some() && response.system_fingerprint.is_some();
+
     // Return the formatted response
     Ok(Json(ApiResponse::success(serde_json::json!({
         "message": response.response,
-        "usage": response.usage
+        "finish_reason": response.finish_reason,
+        "empty": response.empty,
+        "refusal": response.refusal,
+        "cached": response.cached,
+        "model": model,
+        "temperature": options.temperature.unwrap_or(models::TEMPERATURE),
+        "seed": options.seed,
+        "system_fingerprint": response.system_fingerprint,
+        "deterministic": deterministic,
+        "logprobs": response.logprobs,
+        "usage": response.usage,
+        "summary_usage": summary_usage,
+        "conversation_id": payload.conversation_id
     }))))
 }
 
-/// Handles database reset requests.
+/// Folds the early turns of a long conversation into a summary.
 /// 
-/// This endpoint clears all data from the Qdrant collection,
-/// effectively resetting the database to its initial state.
+/// Once a history exceeds `HISTORY_SUMMARY_THRESHOLD` turns or
+/// `HISTORY_SUMMARY_TOKENS` tokens, everything but the latest
+/// `HISTORY_SUMMARY_KEEP_TURNS` (fewer if they are long) is summarized by the
+/// chat model into a single system turn at the head, and the shortened
+/// history is stored. A failed summary is logged and the history is used
+/// as-is.
+/// 
+/// # Returns
+/// * `Ok((turns, usage))` - The history to send to the model, and the summary's
+///   token usage if one was made
+/// * `Err(QueueError)` - If the OpenAI queue is full
+async fn summarize_history(
+    state: &AppState,
+    id: &str,
+    turns: Vec<ChatTurn>,
+) -> Result<(Vec<ChatTurn>, Option<Usage>), QueueError> {
+    let Some(split) = conversations::summary_split(
+        &turns,
+        state.config.history_summary_threshold,
+        state.config.history_summary_tokens,
+        state.config.history_summary_keep_turns,
+    ) else {
+        return Ok((turns, None));
+    };
+
+    let early = turns[..split].to_vec();
+    let summary = state
+        .openai
+        .submit(move |openai| async move { openai.summarize_turns(&early).await })
+        .await?;
+    let summary = match summary {
+        Ok(summary) => summary,
+        Err(e) => {
+            warn!("Failed to summarize conversation, keeping the full history: {:#}", e);
+            return Ok((turns, None));
+        }
+    };
+    let mut summarized = Vec::with_capacity(turns.len() - split + 1);
+    summarized.push(ChatTurn::system(format!(
+        "{}{}",
+        conversations::SUMMARY_PREFIX,
+        summary.response
+    )));
+    summarized.extend_from_slice(&turns[split..]);
+
+    if let Err(e) = state.conversations.replace(id, summarized.clone()).await {
+        error!("Failed to save summarized conversation: {:#}", e);
+    }
+    info!(
+        "Summarized {} turns of a conversation using {} tokens",
+        split, summary.usage.total_tokens
+    );
+    Ok((summarized, Some(summary.usage)))
+}
+
+/// Handles document ingestion requests.
+/// 
+/// Stores a document in the Qdrant collection. The text is embedded with
+/// OpenAI unless the request supplies a pre-computed `embedding`, which is
+/// validated (finite values, collection vector size) and stored directly.
+/// An `If-Match` header makes the write conditional on the stored document's ETag.
+/// A text longer than the embedding model accepts is stored as several
+/// chunks sharing a `parent_id`, see `Document::into_chunks`.
 /// 
 /// # Arguments
 /// * `state` - Application state containing service instances
+/// * `headers` - Request headers, checked for `If-Match`
+/// * `payload` - JSON payload containing the document
 /// 
 /// # Returns
-/// * `Ok(Json<ApiResponse<Value>>)` - Success message
-/// * `Err(StatusCode)` - Error status code if the reset fails
-pub async fn handle_reset(
+/// * `Ok(Json<ApiResponse<Value>>)` - The stored id and the embedding source, plus
+///   `chunk_ids` for a text stored as chunks
+/// * `Err(ApiError)` - 400 for empty text, 412 if `If-Match` fails,
+///   422 for an invalid embedding or a chunked text with non-object metadata, 500 otherwise
+/// 
+/// # Example Request
+/// ```json
+/// {
+///     "text": "Rust is a systems programming language",
+///     "metadata": { "category": "Programming" },
+///     "embedding": [0.1, 0.2, ...]
+/// }
+/// ```
+pub async fn handle_store_document(
     State(state): State<Arc<AppState>>,
-) -> Result<Json<ApiResponse<Value>>, StatusCode> {
-    // Delete all points from the collection
+    headers: HeaderMap,
+    ApiJson(payload): ApiJson<DocumentRequest>,
+) -> Result<Json<ApiResponse<Value>>, ApiError> {
+    // Validate that the document text is not empty
+    let normalized = state.config.text_normalization.apply(&payload.text);
+    if normalized.trim().is_empty() {
+        error!("Empty text provided for document");
+        return Err(ApiError::Validation("Text cannot be empty".into()));
+    }
+
+    // Reject shard keys that don't match the collection's sharding method
+    state
+        .qdrant_service
+        .shard_key_selector(payload.shard_key.as_deref())
+        .map_err(|e| ApiError::Validation(e.to_string()))?;
+
+    // Check preconditions before paying for an embedding; the id is derived
+    // from the normalized text, so variants of the same text share it
+    let id = payload.id.unwrap_or_else(|| Document::id_for_text(&normalized));
+    check_if_match(&state, &headers, id, payload.shard_key.as_deref()).await?;
+
+    let provided = payload.embedding.is_some();
+    let document = Document {
+        id,
+        text: if state.config.store_raw_text { payload.text } else { normalized },
+        embedding: Vec::new(),
+        metadata: payload.metadata,
+        updated_at: None,
+        deleted_at: None,
+        version: None,
+        stale: false,
+    };
+    let documents = match payload.embedding {
+        Some(embedding) => {
+            validate_embedding(&state, &embedding)?;
+            vec![Document { embedding, ..document }]
+        }
+        None => {
+            // Texts longer than the model accepts are stored as chunks
+            let chunks = chunk_texts(&state, vec![document.text.clone()]).await?.pop().unwrap_or_default();
+            let mut documents = document.into_chunks(chunks).map_err(ApiError::Unprocessable)?;
+            let texts: Vec<String> = documents.iter().map(|document| document.text.clone()).collect();
+            let embeddings = state
+                .openai
+                .submit(move |openai| async move { openai.get_document_embeddings(&texts).await })
+                .await?
+                .map_err(|e| {
+                    error!("Failed to generate embedding: {}", e);
+                    ApiError::OpenAI("Failed to generate embedding".into())
+                })?;
+            for (document, embedding) in documents.iter_mut().zip(embeddings.vectors) {
+                document.embedding = embedding;
+            }
+            documents
+        }
+    };
+    let version = match documents.as_slice() {
+        [document] => store_document(&state, document, payload.write_ordering, payload.shard_key.as_deref()).await?,
+        _ => store_chunks(&state, &documents, payload.write_ordering, payload.shard_key.as_deref()).await?,
+    };
+
+    info!(
+        "Stored document {} in {} chunks (embedding provided: {})",
+        id,
+        documents.len(),
+        provided
+    );
+    let mut response = serde_json::json!({
+        "id": id,
+        "embedding_source": if provided { "provided" } else { "generated" },
+        "version": version
+    });
+    if documents.len() > 1 {
+        response["chunk_ids"] = documents.iter().map(|document| document.id).collect();
+    }
+    Ok(Json(ApiResponse::success(response)))
+}
+
+/// Handles raw document upserts with a pre-computed embedding.
+/// 
+/// Stores the document exactly as supplied without calling OpenAI, which is
+/// useful for bulk-loading precomputed datasets or running without an OpenAI key.
+/// Honors `If-Match` like the regular ingestion endpoint.
+/// 
+/// # Arguments
+/// * `state` - Application state containing service instances
+/// * `headers` - Request headers, checked for `If-Match`
+/// * `payload` - JSON payload containing the id, text and embedding
+/// 
+/// # Returns
+/// * `Ok(Json<ApiResponse<Value>>)` - The stored document id
+/// * `Err(ApiError)` - 400 for empty text, 412 if `If-Match` fails,
+///   422 for an invalid embedding, 500 otherwise
+/// 
+/// # Example Request
+/// ```json
+/// {
+///     "id": 42,
+///     "text": "Rust is a systems programming language",
+///     "embedding": [0.1, 0.2, ...]
+/// }
+/// ```
+pub async fn handle_store_raw_document(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    ApiJson(payload): ApiJson<RawDocumentRequest>,
+) -> Result<Json<ApiResponse<Value>>, ApiError> {
+    // Validate that the document text is not empty
+    if payload.text.trim().is_empty() {
+        error!("Empty text provided for raw document");
+        return Err(ApiError::Validation("Text cannot be empty".into()));
+    }
+
+    // Reject shard keys that don't match the collection's sharding method
+    state
+        .qdrant_service
+        .shard_key_selector(payload.shard_key.as_deref())
+        .map_err(|e| ApiError::Validation(e.to_string()))?;
+
+    validate_embedding(&state, &payload.embedding)?;
+    check_if_match(&state, &headers, payload.id, payload.shard_key.as_deref()).await?;
+
+    let document = Document {
+        id: payload.id,
+        text: payload.text,
+        embedding: payload.embedding,
+        metadata: payload.metadata,
+        updated_at: None,
+        deleted_at: None,
+        version: None,
+        stale: false,
+    };
+    let version = store_document(&state, &document, payload.write_ordering, payload.shard_key.as_deref()).await?;
+
+    info!("Stored raw document {}", document.id);
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "id": document.id,
+        "version": version
+    }))))
+}
+
+/// Validates a caller-supplied embedding before it reaches Qdrant.
+/// 
+/// Rejects non-finite values and vectors whose length doesn't match the
+/// collection, and warns when the dimension is shared by several known models.
+fn validate_embedding(state: &AppState, embedding: &[f32]) -> Result<(), ApiError> {
+    if embedding.iter().any(|v| !v.is_finite()) {
+        return Err(ApiError::Unprocessable(
+            "Embedding must only contain finite values".into(),
+        ));
+    }
+    state
+        .qdrant_service
+        .check_vector(embedding)
+        .map_err(|e| ApiError::Unprocessable(e.to_string()))?;
+
+    let candidates = models::models_with_dimension(embedding.len() as u64);
+    if candidates.len() > 1 {
+        warn!(
+            "Provided {}-dimensional embedding matches several models ({}); \
+             make sure it was produced by the collection's model",
+            embedding.len(),
+            candidates.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Upserts a document, mapping vector validation failures to 422.
+/// 
+/// Returns the document's version number when versioning is on.
+async fn store_document(
+    state: &AppState,
+    document: &Document,
+    ordering: Option<WriteOrderingLevel>,
+    shard_key: Option<&str>,
+) -> Result<Option<u64>, ApiError> {
     state
         .qdrant_service
-        .delete_all_points()
+        .upsert_document(document, ordering, shard_key)
+        .await
+        .map_err(|e| {
+            error!("Failed to store document {}: {:#}", document.id, e);
+            if e.is::<ZeroVector>() {
+                ApiError::Unprocessable(e.to_string())
+            } else {
+                qdrant_error(state, &e, "Failed to store document")
+            }
+        })
+}
+
+/// Upserts the chunks of a long document in one request.
+/// 
+/// Returns the version number of the first chunk when versioning is on.
+async fn store_chunks(
+    state: &AppState,
+    chunks: &[Document],
+    ordering: Option<WriteOrderingLevel>,
+    shard_key: Option<&str>,
+) -> Result<Option<u64>, ApiError> {
+    let versions = state
+        .qdrant_service
+        .upsert_documents(chunks, ordering, shard_key)
         .await
         .map_err(|e| {
-            error!("Failed to reset database: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            error!("Failed to store the chunks of document {}: {:#}", chunks[0].id, e);
+            if e.is::<ZeroVector>() {
+                ApiError::Unprocessable(e.to_string())
+            } else {
+                qdrant_error(state, &e, "Failed to store document")
+            }
         })?;
+    Ok(versions.into_iter().next().flatten())
+}
 
-    // Log success
-    info!("Database reset successfully");
+/// Turns a failed Qdrant call into the error returned to the client.
+/// 
+/// Failures the client can act on, such as a missing collection, a
+/// vector of the wrong size or documents other writers kept changing, are
+/// reported as what they are, after `message`. Anything else is a 500 with `message` and the gRPC status
+/// Qdrant answered with, if it answered.
+fn qdrant_error(state: &AppState, error: &anyhow::Error, message: &str) -> ApiError {
+    if let Some(conflict) = error.downcast_ref::<WriteConflict>() {
+        return ApiError::Conflict(format!("{}: {}", message, conflict));
+    }
+    let Some(rejection) = state.qdrant_service.translate_error(error) else {
+        return match qdrant::status_code(error) {
+            Some(code) => ApiError::Qdrant(format!("{} (Qdrant status: {:?})", message, code)),
+            None => ApiError::Qdrant(message.to_string()),
+        };
+    };
+    let detail = format!("{}: {}", message, rejection);
+    match rejection {
+        QdrantRejection::CollectionMissing { .. } | QdrantRejection::NotFound(_) => ApiError::NotFound(detail),
+        QdrantRejection::DimensionMismatch(_) => ApiError::Unprocessable(detail),
+        QdrantRejection::InvalidPointId(_) | QdrantRejection::InvalidArgument(_) => ApiError::Validation(detail),
+        QdrantRejection::PayloadTooLarge(_) => ApiError::PayloadTooLarge(detail),
+        QdrantRejection::AlreadyExists(_) => ApiError::Conflict(detail),
+    }
+}
 
-    // Return success message
+/// Checks an `If-Match` precondition against the stored document.
+/// 
+/// `*` requires the document to exist; otherwise one of the listed strong
+/// ETags must equal the stored document's. The check and the following
+/// write are not atomic, so concurrent writers can still race.
+async fn check_if_match(
+    state: &AppState,
+    headers: &HeaderMap,
+    id: u64,
+    shard_key: Option<&str>,
+) -> Result<(), ApiError> {
+    let Some(if_match) = headers.get(header::IF_MATCH) else {
+        return Ok(());
+    };
+    let if_match = if_match
+        .to_str()
+        .map_err(|_| ApiError::Validation("Invalid If-Match header".into()))?;
+
+    let current = load_document(state, id, false, shard_key).await?;
+    let matches = match &current {
+        Some(document) => etag_matches(if_match, &document.etag(), false),
+        None => false,
+    };
+    if !matches {
+        warn!("If-Match precondition failed for document {}", id);
+        return Err(ApiError::PreconditionFailed(format!(
+            "Document {} does not match If-Match",
+            id
+        )));
+    }
+    Ok(())
+}
+
+/// Fetches a document, mapping Qdrant failures to 500.
+async fn load_document(
+    state: &AppState,
+    id: u64,
+    with_vectors: bool,
+    shard_key: Option<&str>,
+) -> Result<Option<Document>, ApiError> {
+    state
+        .qdrant_service
+        .get_document(id, with_vectors, shard_key)
+        .await
+        .map_err(|e| {
+            error!("Failed to fetch document {}: {:#}", id, e);
+            qdrant_error(state, &e, "Failed to fetch document")
+        })
+}
+
+/// Returns whether a conditional header value matches `etag`.
+/// 
+/// `If-None-Match` uses weak comparison (the `W/` prefix is ignored),
+/// while `If-Match` only matches strong ETags.
+fn etag_matches(header_value: &str, etag: &str, weak: bool) -> bool {
+    let strip = |tag: &str| tag.strip_prefix("W/").map(str::to_owned);
+    header_value.split(',').map(str::trim).any(|candidate| {
+        if candidate == "*" {
+            return true;
+        }
+        if weak {
+            let candidate = strip(candidate).unwrap_or_else(|| candidate.to_owned());
+            let etag = strip(etag).unwrap_or_else(|| etag.to_owned());
+            candidate == etag
+        } else {
+            !candidate.starts_with("W/") && !etag.starts_with("W/") && candidate == etag
+        }
+    })
+}
+
+/// Returns whether the request's `If-None-Match` header matches `etag`.
+fn is_not_modified(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| etag_matches(v, etag, true))
+}
+
+/// Handles single document reads.
+/// 
+/// Responds with a strong `ETag` derived from the document's last write time
+/// and content hash, and with 304 Not Modified when `If-None-Match` matches.
+/// Documents in the trash are reported as missing.
+/// 
+/// # Arguments
+/// * `state` - Application state containing service instances
+/// * `id` - Document id from the path
+/// * `params` - Query parameters (`with_vectors`, `shard_key`)
+/// * `headers` - Request headers, checked for `If-None-Match`
+/// 
+/// # Returns
+/// * `Ok(Response)` - The document, or 304 if unchanged
+/// * `Err(ApiError)` - 400 for an invalid shard key, 404 if missing, 500 otherwise
+/// 
+/// # Example Request
+/// ```text
+/// GET /api/documents/42
+/// If-None-Match: "1718000000000-9f86d081884c7d65"
+/// ```
+pub async fn handle_get_document(
+    State(state): State<Arc<AppState>>,
+    ApiPath(id): ApiPath<u64>,
+    ApiQuery(params): ApiQuery<DocumentQuery>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    // Reject shard keys that don't match the collection's sharding method
+    state
+        .qdrant_service
+        .shard_key_selector(params.shard_key.as_deref())
+        .map_err(|e| ApiError::Validation(e.to_string()))?;
+
+    let document = load_document(&state, id, params.with_vectors, params.shard_key.as_deref())
+        .await?
+        .filter(|document| document.deleted_at.is_none())
+        .ok_or_else(|| ApiError::NotFound(format!("Document {} does not exist", id)))?;
+
+    let etag = document.etag();
+    if is_not_modified(&headers, &etag) {
+        return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response());
+    }
+    Ok(([(header::ETAG, etag)], Json(ApiResponse::success(document))).into_response())
+}
+
+/// Handles batch document reads by id.
+/// 
+/// All ids are fetched with a single Qdrant request. The response lists one
+/// entry per requested id, in request order, with `null` for ids that don't
+/// exist or are in the trash; those ids are also listed in `missing`.
+/// 
+/// # Arguments
+/// * `state` - Application state containing service instances
+/// * `payload` - JSON payload containing the ids
+/// 
+/// # Returns
+/// * `Ok(Json<ApiResponse<Value>>)` - `{documents, found, missing}`
+/// * `Err(ApiError)` - 400 for an empty list or invalid shard key, 422 above `MAX_GET_IDS`
+///   ids, 500 otherwise
+/// 
+/// # Example Request
+/// ```json
+/// { "ids": [1712345678901, 1712345678902], "include_vectors": false }
+/// ```
+pub async fn handle_get_documents(
+    State(state): State<Arc<AppState>>,
+    ApiJson(payload): ApiJson<GetDocumentsRequest>,
+) -> Result<Json<ApiResponse<Value>>, ApiError> {
+    if payload.ids.is_empty() {
+        return Err(ApiError::Validation("ids must contain at least one id".into()));
+    }
+    if payload.ids.len() > MAX_GET_IDS {
+        return Err(ApiError::Unprocessable(format!(
+            "ids may contain at most {} ids, got {}",
+            MAX_GET_IDS,
+            payload.ids.len()
+        )));
+    }
+
+    state
+        .qdrant_service
+        .shard_key_selector(payload.shard_key.as_deref())
+        .map_err(|e| ApiError::Validation(e.to_string()))?;
+
+    let documents = state
+        .qdrant_service
+        .get_documents(&payload.ids, payload.include_vectors, payload.shard_key.as_deref())
+        .await
+        .map_err(|e| {
+            error!("Failed to fetch documents: {:#}", e);
+            qdrant_error(&state, &e, "Failed to fetch documents")
+        })?;
+    let documents: Vec<Option<Document>> = documents
+        .into_iter()
+        .map(|document| document.filter(|document| document.deleted_at.is_none()))
+        .collect();
+
+    let missing: Vec<u64> = payload
+        .ids
+        .iter()
+        .zip(&documents)
+        .filter(|(_, document)| document.is_none())
+        .map(|(id, _)| *id)
+        .collect();
     Ok(Json(ApiResponse::success(serde_json::json!({
-        "message": "Database reset successfully"
+        "found": documents.len() - missing.len(),
+        "missing": missing,
+        "documents": documents
     }))))
-} 
\ No newline at end of file
+}
+
+/// Default number of documents per page when listing.
+const DEFAULT_LIST_LIMIT: u32 = 100;
+
+/// Largest page a list request may ask for.
+const MAX_LIST_LIMIT: u32 = 1000;
+
+/// Handles paginated document listing.
+/// 
+/// Responds with a weak `ETag` derived from the collection's point count and
+/// the service's write counter, and with 304 Not Modified when `If-None-Match`
+/// matches. The check happens before the page is read.
+/// 
+/// # Arguments
+/// * `state` - Application state containing service instances
+/// * `params` - Query parameters (`limit`, `offset`, `with_vectors`, `shard_key`)
+/// * `headers` - Request headers, checked for `If-None-Match`
+/// 
+/// # Returns
+/// * `Ok(Response)` - `{documents, next_offset}`, or 304 if unchanged
+/// * `Err(ApiError)` - 400 for an invalid shard key or limit, 500 otherwise
+/// 
+/// # Example Request
+/// ```text
+/// GET /api/documents?limit=50&offset=1234
+/// ```
+pub async fn handle_list_documents(
+    State(state): State<Arc<AppState>>,
+    ApiQuery(params): ApiQuery<ListDocumentsQuery>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let shard_key = params.shard_key.as_deref();
+    state
+        .qdrant_service
+        .shard_key_selector(shard_key)
+        .map_err(|e| ApiError::Validation(e.to_string()))?;
+
+    let limit = params.limit.unwrap_or(DEFAULT_LIST_LIMIT);
+    if limit == 0 || limit > MAX_LIST_LIMIT {
+        return Err(ApiError::Validation(format!(
+            "Limit must be between 1 and {}",
+            MAX_LIST_LIMIT
+        )));
+    }
+
+    let count = state.qdrant_service.count_documents(false, shard_key).await.map_err(|e| {
+        error!("Failed to count documents: {:#}", e);
+        qdrant_error(&state, &e, "Failed to list documents")
+    })?;
+    let etag = format!("W/\"{}-{}\"", count, state.qdrant_service.version());
+    if is_not_modified(&headers, &etag) {
+        return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response());
+    }
+
+    let (documents, next_offset) = state
+        .qdrant_service
+        .scroll_documents(params.offset.map(Into::into), limit, params.with_vectors, false, shard_key)
+        .await
+        .map_err(|e| {
+            error!("Failed to list documents: {:#}", e);
+            qdrant_error(&state, &e, "Failed to list documents")
+        })?;
+    let next_offset = next_offset.and_then(|id| match id.point_id_options {
+        Some(PointIdOptions::Num(num)) => Some(num),
+        _ => None,
+    });
+
+    let body = Json(ApiResponse::success(serde_json::json!({
+        "documents": documents,
+        "next_offset": next_offset
+    })));
+    Ok(([(header::ETAG, etag)], body).into_response())
+}
+
+/// Handles filter validation requests.
+/// 
+/// Checks a payload filter with `DocumentFilter::parse`, as `/api/search`
+/// and the filtering endpoints do, without running it, so query builders
+/// can point at mistakes as they are made.
+/// 
+/// # Arguments
+/// * `payload` - JSON payload containing the filter
+/// 
+/// # Returns
+/// * `Ok(Json<ApiResponse<Value>>)` - `{"valid": true, "conditions": n}`
+/// * `Err(ApiError)` - 422 listing each problem with its path in `details`
+/// 
+/// # Example Request
+/// ```json
+/// {
+///     "filter": {
+///         "must": [{ "key": "metadata.source", "mach": "wiki" }]
+///     }
+/// }
+/// ```
+pub async fn handle_validate_filter(
+    ApiJson(payload): ApiJson<ValidateFilterRequest>,
+) -> Result<Json<ApiResponse<Value>>, ApiError> {
+    let filter = DocumentFilter::parse(&payload.filter).map_err(ApiError::InvalidFilter)?;
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "valid": true,
+        "conditions": filter.must.len() + filter.should.len() + filter.must_not.len()
+    }))))
+}
+
+/// Handles bulk deletes of the documents matching a payload filter.
+/// 
+/// # Arguments
+/// * `state` - Application state containing service instances
+/// * `payload` - JSON payload containing the filter
+/// 
+/// # Returns
+/// * `Ok(Json<ApiResponse<Value>>)` - The number of deleted documents
+/// * `Err(ApiError)` - 400 for an empty filter or invalid shard key, 422 listing the
+///   problems of an invalid filter, 500 otherwise
+/// 
+/// # Example Request
+/// ```json
+/// {
+///     "filter": {
+///         "must": [{ "key": "metadata.source", "match": "stale" }]
+///     }
+/// }
+/// ```
+pub async fn handle_delete_by_filter(
+    State(state): State<Arc<AppState>>,
+    audit: AuditContext,
+    ApiJson(payload): ApiJson<DeleteByFilterRequest>,
+) -> Result<Json<ApiResponse<Value>>, ApiError> {
+    let filter = DocumentFilter::parse(&payload.filter).map_err(ApiError::InvalidFilter)?;
+    // An empty filter matches everything; /api/reset exists for that
+    if filter.is_empty() {
+        return Err(ApiError::Validation("Filter must contain at least one condition".into()));
+    }
+
+    state
+        .qdrant_service
+        .shard_key_selector(payload.shard_key.as_deref())
+        .map_err(|e| ApiError::Validation(e.to_string()))?;
+
+    let entry = state.audit.begin(
+        AuditEntry::new(&audit, "delete_by_filter")
+            .tenant(payload.shard_key.as_deref())
+            .target(serde_json::json!({ "filter": filter })),
+    );
+    let result = state
+        .qdrant_service
+        .delete_by_filter(filter.into(), payload.write_ordering, payload.shard_key.as_deref())
+        .await;
+    let deleted = match result {
+        Ok(deleted) => {
+            entry.succeeded(Some(deleted));
+            deleted
+        }
+        Err(e) => {
+            entry.failed(&e);
+            error!("Failed to delete documents by filter: {:#}", e);
+            return Err(qdrant_error(&state, &e, "Failed to delete documents"));
+        }
+    };
+
+    info!("Deleted {} documents by filter", deleted);
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "deleted": deleted
+    }))))
+}
+
+/// Handles deletes of documents by id.
+/// 
+/// All ids are removed with a single Qdrant request; duplicates are
+/// dropped and ids that don't exist are ignored.
+/// 
+/// # Arguments
+/// * `state` - Application state containing service instances
+/// * `payload` - JSON payload containing the ids
+/// 
+/// # Returns
+/// * `Ok(Json<ApiResponse<Value>>)` - The number of distinct ids sent to Qdrant
+/// * `Err(ApiError)` - 400 for an empty or too long list or an invalid shard key, 500 otherwise
+/// 
+/// # Example Request
+/// ```json
+/// { "ids": [1712345678901, 1712345678902] }
+/// ```
+pub async fn handle_delete_by_ids(
+    State(state): State<Arc<AppState>>,
+    audit: AuditContext,
+    ApiJson(payload): ApiJson<DeleteByIdsRequest>,
+) -> Result<Json<ApiResponse<Value>>, ApiError> {
+    if payload.ids.is_empty() {
+        return Err(ApiError::Validation("ids must contain at least one id".into()));
+    }
+    if payload.ids.len() > MAX_DELETE_IDS {
+        return Err(ApiError::Validation(format!(
+            "ids may contain at most {} ids",
+            MAX_DELETE_IDS
+        )));
+    }
+
+    state
+        .qdrant_service
+        .shard_key_selector(payload.shard_key.as_deref())
+        .map_err(|e| ApiError::Validation(e.to_string()))?;
+
+    let mut ids = payload.ids;
+    ids.sort_unstable();
+    ids.dedup();
+    let count = ids.len() as u64;
+
+    let entry = state.audit.begin(
+        AuditEntry::new(&audit, "delete_by_ids")
+            .tenant(payload.shard_key.as_deref())
+            .target(serde_json::json!({ "ids": ids })),
+    );
+    let result = state
+        .qdrant_service
+        .delete_points(ids, payload.write_ordering, payload.shard_key.as_deref())
+        .await;
+    match result {
+        Ok(()) => entry.succeeded(Some(count)),
+        Err(e) => {
+            entry.failed(&e);
+            error!("Failed to delete documents by id: {:#}", e);
+            return Err(qdrant_error(&state, &e, "Failed to delete documents"));
+        }
+    }
+
+    info!("Deleted up to {} documents by id", count);
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "requested": count
+    }))))
+}
+
+/// Handles deletes of a single document.
+/// 
+/// By default the point is removed. With `?soft=true` it is moved to the
+/// trash instead: it stays stored but no longer shows up in searches,
+/// listings or counts, and can be brought back with
+/// `POST /api/documents/:id/restore` until it is purged. Soft-deleting a
+/// document that is already in the trash keeps its original deletion time.
+/// 
+/// # Arguments
+/// * `state` - Application state containing service instances
+/// * `id` - Document id from the path
+/// * `params` - Query parameters (`soft`, `write_ordering`, `shard_key`)
+/// 
+/// # Returns
+/// * `Ok(Json<ApiResponse<Value>>)` - `{id, soft, deleted_at}`
+/// * `Err(ApiError)` - 400 for an invalid shard key, 404 if missing, 500 otherwise
+/// 
+/// # Example Request
+/// ```text
+/// DELETE /api/documents/42?soft=true
+/// ```
+pub async fn handle_delete_document(
+    State(state): State<Arc<AppState>>,
+    audit: AuditContext,
+    ApiPath(id): ApiPath<u64>,
+    ApiQuery(params): ApiQuery<DeleteDocumentQuery>,
+) -> Result<Json<ApiResponse<Value>>, ApiError> {
+    let shard_key = params.shard_key.as_deref();
+    state
+        .qdrant_service
+        .shard_key_selector(shard_key)
+        .map_err(|e| ApiError::Validation(e.to_string()))?;
+
+    let document = load_document(&state, id, false, shard_key)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("Document {} does not exist", id)))?;
+    if params.soft {
+        if let Some(deleted_at) = document.deleted_at {
+            return Ok(Json(ApiResponse::success(serde_json::json!({
+                "id": id,
+                "soft": true,
+                "deleted_at": deleted_at
+            }))));
+        }
+    }
+
+    let operation = if params.soft { "soft_delete_document" } else { "delete_document" };
+    let entry = state.audit.begin(
+        AuditEntry::new(&audit, operation)
+            .tenant(shard_key)
+            .target(serde_json::json!({ "id": id })),
+    );
+    let result = if params.soft {
+        state
+            .qdrant_service
+            .soft_delete_document(id, params.write_ordering, shard_key)
+            .await
+            .map(Some)
+    } else {
+        state
+            .qdrant_service
+            .delete_points(vec![id], params.write_ordering, shard_key)
+            .await
+            .map(|()| None)
+    };
+    let deleted_at = match result {
+        Ok(deleted_at) => {
+            entry.succeeded(Some(1));
+            deleted_at
+        }
+        Err(e) => {
+            entry.failed(&e);
+            error!("Failed to delete document {}: {:#}", id, e);
+            return Err(qdrant_error(&state, &e, "Failed to delete document"));
+        }
+    };
+
+    if params.soft {
+        info!("Moved document {} to the trash", id);
+    } else {
+        info!("Deleted document {}", id);
+    }
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "id": id,
+        "soft": params.soft,
+        "deleted_at": deleted_at
+    }))))
+}
+
+/// Handles restoring a soft-deleted document from the trash.
+/// 
+/// # Arguments
+/// * `state` - Application state containing service instances
+/// * `id` - Document id from the path
+/// * `params` - Query parameters (`write_ordering`, `shard_key`)
+/// 
+/// # Returns
+/// * `Ok(Json<ApiResponse<Value>>)` - `{id, restored}`
+/// * `Err(ApiError)` - 400 for an invalid shard key, 404 if missing or already
+///   purged, 409 if the document is not in the trash, 500 otherwise
+/// 
+/// # Example Request
+/// ```text
+/// POST /api/documents/42/restore
+/// ```
+pub async fn handle_restore_document(
+    State(state): State<Arc<AppState>>,
+    audit: AuditContext,
+    ApiPath(id): ApiPath<u64>,
+    ApiQuery(params): ApiQuery<RestoreDocumentQuery>,
+) -> Result<Json<ApiResponse<Value>>, ApiError> {
+    let shard_key = params.shard_key.as_deref();
+    state
+        .qdrant_service
+        .shard_key_selector(shard_key)
+        .map_err(|e| ApiError::Validation(e.to_string()))?;
+
+    let document = load_document(&state, id, false, shard_key)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("Document {} does not exist", id)))?;
+    if document.deleted_at.is_none() {
+        return Err(ApiError::Conflict(format!("Document {} is not in the trash", id)));
+    }
+
+    let entry = state.audit.begin(
+        AuditEntry::new(&audit, "restore_document")
+            .tenant(shard_key)
+            .target(serde_json::json!({ "id": id })),
+    );
+    let result = state
+        .qdrant_service
+        .restore_document(id, params.write_ordering, shard_key)
+        .await;
+    match result {
+        Ok(()) => entry.succeeded(Some(1)),
+        Err(e) => {
+            entry.failed(&e);
+            error!("Failed to restore document {}: {:#}", id, e);
+            return Err(qdrant_error(&state, &e, "Failed to restore document"));
+        }
+    }
+
+    info!("Restored document {} from the trash", id);
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "id": id,
+        "restored": true
+    }))))
+}
+
+/// Handles updates of a document's flags.
+/// 
+/// Only the `stale` flag can be changed. Stale documents are re-embedded a
+/// batch at a time by the background worker when
+/// `STALE_REEMBED_INTERVAL_SECS` is set; flagging a document in the trash
+/// is allowed.
+/// 
+/// # Arguments
+/// * `state` - Application state containing service instances
+/// * `id` - Document id from the path
+/// * `payload` - JSON payload with the new flag value
+/// 
+/// # Returns
+/// * `Ok(Json<ApiResponse<Value>>)` - `{id, stale}`
+/// * `Err(ApiError)` - 400 for an invalid shard key, 404 if missing, 500 otherwise
+/// 
+/// # Example Request
+/// ```json
+/// { "stale": true }
+/// ```
+pub async fn handle_update_document(
+    State(state): State<Arc<AppState>>,
+    audit: AuditContext,
+    ApiPath(id): ApiPath<u64>,
+    ApiJson(payload): ApiJson<UpdateDocumentRequest>,
+) -> Result<Json<ApiResponse<Value>>, ApiError> {
+    let shard_key = payload.shard_key.as_deref();
+    state
+        .qdrant_service
+        .shard_key_selector(shard_key)
+        .map_err(|e| ApiError::Validation(e.to_string()))?;
+
+    if load_document(&state, id, false, shard_key).await?.is_none() {
+        return Err(ApiError::NotFound(format!("Document {} does not exist", id)));
+    }
+
+    let entry = state.audit.begin(
+        AuditEntry::new(&audit, "mark_stale")
+            .tenant(shard_key)
+            .target(serde_json::json!({ "id": id, "stale": payload.stale })),
+    );
+    let result = state
+        .qdrant_service
+        .mark_stale(id, payload.stale, payload.write_ordering, shard_key)
+        .await;
+    match result {
+        Ok(()) => entry.succeeded(Some(1)),
+        Err(e) => {
+            entry.failed(&e);
+            error!("Failed to update document {}: {:#}", id, e);
+            return Err(qdrant_error(&state, &e, "Failed to update document"));
+        }
+    }
+
+    info!("Set the stale flag of document {} to {}", id, payload.stale);
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "id": id,
+        "stale": payload.stale
+    }))))
+}
+
+/// Handles listing the earlier versions of a document.
+/// 
+/// Versions are only recorded while `DOCUMENT_VERSIONS` is set; each write
+/// that changes a document's text or metadata archives the previous one.
+/// 
+/// # Arguments
+/// * `state` - Application state containing service instances
+/// * `id` - Document id from the path
+/// * `params` - Query parameters (`shard_key`)
+/// 
+/// # Returns
+/// * `Ok(Json<ApiResponse<Value>>)` - `{id, version, versions}`, newest version first
+/// * `Err(ApiError)` - 400 for an invalid shard key, 404 if the document doesn't exist,
+///   500 otherwise
+/// 
+/// # Example Request
+/// ```text
+/// GET /api/documents/42/versions
+/// ```
+pub async fn handle_list_versions(
+    State(state): State<Arc<AppState>>,
+    ApiPath(id): ApiPath<u64>,
+    ApiQuery(params): ApiQuery<VersionQuery>,
+) -> Result<Json<ApiResponse<Value>>, ApiError> {
+    let shard_key = params.shard_key.as_deref();
+    state
+        .qdrant_service
+        .shard_key_selector(shard_key)
+        .map_err(|e| ApiError::Validation(e.to_string()))?;
+
+    let document = load_document(&state, id, false, shard_key)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("Document {} does not exist", id)))?;
+    let versions = state
+        .qdrant_service
+        .list_versions(id, shard_key)
+        .await
+        .map_err(|e| {
+            error!("Failed to list versions of document {}: {:#}", id, e);
+            qdrant_error(&state, &e, "Failed to list versions")
+        })?;
+
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "id": id,
+        "version": document.version,
+        "versions": versions
+    }))))
+}
+
+/// Handles restoring an earlier version of a document.
+/// 
+/// The version's text is embedded again and written as the document's new
+/// head, so the version being replaced is archived like on any other write
+/// and a restore can itself be undone.
+/// 
+/// # Arguments
+/// * `state` - Application state containing service instances
+/// * `id` - Document id and version number from the path
+/// * `params` - Query parameters (`write_ordering`, `shard_key`)
+/// 
+/// # Returns
+/// * `Ok(Json<ApiResponse<Value>>)` - `{id, restored_version, version}`
+/// * `Err(ApiError)` - 400 for an invalid shard key, 404 if the document or version
+///   doesn't exist, 409 if versioning is off or the document is in the trash, 500 otherwise
+/// 
+/// # Example Request
+/// ```text
+/// POST /api/documents/42/versions/3/restore
+/// ```
+pub async fn handle_restore_version(
+    State(state): State<Arc<AppState>>,
+    ApiPath((id, version)): ApiPath<(u64, u64)>,
+    ApiQuery(params): ApiQuery<RestoreDocumentQuery>,
+) -> Result<Json<ApiResponse<Value>>, ApiError> {
+    if state.config.document_versions == 0 {
+        return Err(ApiError::Conflict(
+            "Versioning is off; set DOCUMENT_VERSIONS to restore versions".into(),
+        ));
+    }
+    let shard_key = params.shard_key.as_deref();
+    state
+        .qdrant_service
+        .shard_key_selector(shard_key)
+        .map_err(|e| ApiError::Validation(e.to_string()))?;
+
+    let document = load_document(&state, id, false, shard_key)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("Document {} does not exist", id)))?;
+    if document.deleted_at.is_some() {
+        return Err(ApiError::Conflict(format!(
+            "Document {} is in the trash; restore it first",
+            id
+        )));
+    }
+    let archived = state
+        .qdrant_service
+        .get_version(id, version, shard_key)
+        .await
+        .map_err(|e| {
+            error!("Failed to fetch version {} of document {}: {:#}", version, id, e);
+            qdrant_error(&state, &e, "Failed to fetch version")
+        })?
+        .ok_or_else(|| ApiError::NotFound(format!("Document {} has no version {}", id, version)))?;
+
+    // Embed again, as the embedding model may have changed since
+    let text = archived.text.clone();
+    let embedding = state
+        .openai
+        .submit(move |openai| async move { openai.get_document_embedding(&text).await })
+        .await?
+        .map_err(|e| {
+            error!("Failed to generate embedding: {}", e);
+            ApiError::OpenAI("Failed to generate embedding".into())
+        })?;
+    let restored = Document {
+        id,
+        text: archived.text,
+        embedding,
+        metadata: archived.metadata,
+        updated_at: None,
+        deleted_at: None,
+        version: None,
+        stale: false,
+    };
+    let head = store_document(&state, &restored, params.write_ordering, shard_key).await?;
+
+    info!("Restored version {} of document {}", version, id);
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "id": id,
+        "restored_version": version,
+        "version": head
+    }))))
+}
+
+/// Number of points read from Qdrant per scroll page during export.
+const EXPORT_PAGE_SIZE: u32 = 256;
+
+/// Handles collection export requests.
+/// 
+/// Streams every document in the collection as newline-delimited JSON,
+/// one `Document` per line. Pages are read with the scroll API and written
+/// as they arrive, so the collection is never buffered in memory.
+/// 
+/// # Arguments
+/// * `state` - Application state containing service instances
+/// * `params` - Query parameters (`with_vectors`, `shard_key`)
+/// 
+/// # Returns
+/// * `Ok(Response)` - An `application/x-ndjson` streaming response
+/// * `Err(ApiError)` - 400 if the shard key doesn't fit the sharding method
+/// 
+/// # Example Request
+/// ```text
+/// GET /api/documents/export?with_vectors=true
+/// ```
+pub async fn handle_export(
+    State(state): State<Arc<AppState>>,
+    ApiQuery(params): ApiQuery<ExportQuery>,
+) -> Result<Response, ApiError> {
+    // Reject shard keys that don't match the collection's sharding method
+    state
+        .qdrant_service
+        .shard_key_selector(params.shard_key.as_deref())
+        .map_err(|e| ApiError::Validation(e.to_string()))?;
+
+    // The cursor is `None` once the last page has been written
+    let stream = futures::stream::unfold(Some(None), move |cursor| {
+        let state = state.clone();
+        let shard_key = params.shard_key.clone();
+        async move {
+            let offset = cursor?;
+            let page = state
+                .qdrant_service
+                .scroll_documents(offset, EXPORT_PAGE_SIZE, params.with_vectors, true, shard_key.as_deref())
+                .await;
+
+            match page {
+                Ok((documents, next_offset)) => {
+                    let mut chunk = Vec::new();
+                    for document in &documents {
+                        if let Err(e) = serde_json::to_writer(&mut chunk, document) {
+                            error!("Failed to serialize document {}: {}", document.id, e);
+                            continue;
+                        }
+                        chunk.push(b'\n');
+                    }
+                    Some((Ok(Bytes::from(chunk)), next_offset.map(Some)))
+                }
+                Err(e) => {
+                    // Abort the stream; the client sees a truncated response
+                    error!("Failed to export documents: {:#}", e);
+                    Some((Err(std::io::Error::other(e.to_string())), None))
+                }
+            }
+        }
+    });
+
+    info!("Starting collection export (with_vectors: {})", params.with_vectors);
+    Response::builder()
+        .header(header::CONTENT_TYPE, "application/x-ndjson")
+        // The compression layer may poll the body again after its end
+        .body(Body::from_stream(stream.fuse()))
+        .map_err(|e| ApiError::Internal(e.to_string()))
+}
+
+/// Documents embedded and upserted together during an import.
+const IMPORT_WINDOW: usize = 100;
+
+/// Longest line the import endpoint accepts, in bytes.
+const MAX_IMPORT_LINE_BYTES: usize = 1024 * 1024;
+
+/// Invalid lines listed in an import response; further ones are only counted.
+const MAX_IMPORT_ERRORS: usize = 100;
+
+/// Handles document import requests.
+/// 
+/// Reads newline-delimited JSON records, as written by the export endpoint,
+/// while the body is still arriving. Records are collected into windows of
+/// `IMPORT_WINDOW`; each window's texts without an embedding are embedded
+/// with one OpenAI call, the window is upserted, and its records are dropped
+/// before the next window is read. Memory use is therefore bounded by the
+/// window and `MAX_IMPORT_LINE_BYTES`, not by the size of the body.
+/// 
+/// Invalid lines are skipped and reported by line number. A failed embedding
+/// or upsert stops the import; the windows before it stay stored.
+/// 
+/// # Arguments
+/// * `state` - Application state containing service instances
+/// * `params` - Query parameters (`write_ordering`, `shard_key`)
+/// * `body` - The NDJSON request body
+/// 
+/// # Returns
+/// * `Ok(Json<ApiResponse<Value>>)` - Imported and skipped counts, errors by line, and token usage
+/// * `Err(ApiError)` - 400 for an invalid shard key, an unreadable body or an overlong line,
+///   503 if the OpenAI queue is full, 500 if embedding or storing fails
+/// 
+/// # Example Request
+/// ```text
+/// POST /api/documents/import
+/// {"id": 1, "text": "First document", "metadata": {"category": "a"}}
+/// {"text": "Second document"}
+/// ```
+pub async fn handle_import(
+    State(state): State<Arc<AppState>>,
+    ApiQuery(params): ApiQuery<ImportQuery>,
+    body: Body,
+) -> Result<Json<ApiResponse<Value>>, ApiError> {
+    state
+        .qdrant_service
+        .shard_key_selector(params.shard_key.as_deref())
+        .map_err(|e| ApiError::Validation(e.to_string()))?;
+
+    let mut import = Import::new(&state, params);
+    let mut chunks = body.into_data_stream();
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut line = 0;
+    loop {
+        let chunk = chunks.next().await.transpose().map_err(|e| {
+            ApiError::Validation(format!(
+                "Failed to read the request body after line {} ({} documents imported): {}",
+                line, import.imported, e
+            ))
+        })?;
+        let end = chunk.is_none();
+        if let Some(chunk) = chunk {
+            buffer.extend_from_slice(&chunk);
+        }
+
+        // Handle every complete line, then keep only the unfinished one
+        let mut start = 0;
+        while let Some(length) = buffer[start..].iter().position(|&b| b == b'\n') {
+            line += 1;
+            check_import_line(line, length, import.imported)?;
+            import.add_line(line, &buffer[start..start + length]).await?;
+            start += length + 1;
+        }
+        buffer.drain(..start);
+        check_import_line(line + 1, buffer.len(), import.imported)?;
+
+        if end {
+            if !buffer.is_empty() {
+                line += 1;
+                import.add_line(line, &buffer).await?;
+            }
+            break;
+        }
+    }
+    import.flush().await?;
+
+    info!(
+        "Imported {} documents from {} lines ({} skipped)",
+        import.imported, line, import.skipped
+    );
+    Ok(Json(
+        ApiResponse::success(serde_json::json!({
+            "imported": import.imported,
+            "skipped": import.skipped,
+            "errors": import.errors
+        }))
+        .with_usage(import.usage),
+    ))
+}
+
+/// Rejects an import line longer than `MAX_IMPORT_LINE_BYTES`.
+fn check_import_line(line: usize, length: usize, imported: u64) -> Result<(), ApiError> {
+    if length > MAX_IMPORT_LINE_BYTES {
+        return Err(ApiError::Validation(format!(
+            "Line {} is longer than {} bytes ({} documents imported)",
+            line, MAX_IMPORT_LINE_BYTES, imported
+        )));
+    }
+    Ok(())
+}
+
+/// State of a running import.
+struct Import<'a> {
+    state: &'a AppState,
+    params: ImportQuery,
+    /// Parsed records waiting to be stored, with their line numbers
+    window: Vec<(usize, ImportRecord)>,
+    imported: u64,
+    skipped: u64,
+    /// The first `MAX_IMPORT_ERRORS` invalid lines
+    errors: Vec<Value>,
+    usage: Usage,
+}
+
+impl<'a> Import<'a> {
+    fn new(state: &'a AppState, params: ImportQuery) -> Self {
+        Self {
+            state,
+            params,
+            window: Vec::with_capacity(IMPORT_WINDOW),
+            imported: 0,
+            skipped: 0,
+            errors: Vec::new(),
+            usage: Usage::default(),
+        }
+    }
+
+    /// Parses and validates one line, storing the window once it is full.
+    async fn add_line(&mut self, line: usize, bytes: &[u8]) -> Result<(), ApiError> {
+        if bytes.iter().all(u8::is_ascii_whitespace) {
+            return Ok(());
+        }
+        let record = serde_json::from_slice::<ImportRecord>(bytes)
+            .map_err(|e| e.to_string())
+            .and_then(|record| self.validate(record));
+        match record {
+            Ok(record) => {
+                self.window.push((line, record));
+                if self.window.len() >= IMPORT_WINDOW {
+                    self.flush().await?;
+                }
+            }
+            Err(message) => self.skip(line, message),
+        }
+        Ok(())
+    }
+
+    fn validate(&self, record: ImportRecord) -> Result<ImportRecord, String> {
+        if record.text.trim().is_empty() {
+            return Err("Text cannot be empty".into());
+        }
+        if !record.embedding.is_empty() {
+            validate_embedding(self.state, &record.embedding).map_err(|e| e.to_string())?;
+        }
+        Ok(record)
+    }
+
+    /// Embeds and upserts the current window, then drops its records.
+    /// 
+    /// Records with a text longer than the model accepts are stored as
+    /// chunks; one whose metadata can't carry the chunk fields is skipped.
+    async fn flush(&mut self) -> Result<(), ApiError> {
+        let (Some((first, _)), Some((last, _))) = (self.window.first(), self.window.last()) else {
+            return Ok(());
+        };
+        let lines = format!("lines {}-{}", first, last);
+
+        let mut records = Vec::with_capacity(self.window.len());
+        for (line, record) in std::mem::take(&mut self.window) {
+            let normalized = self.state.config.text_normalization.apply(&record.text);
+            let document = Document {
+                id: record.id.unwrap_or_else(|| Document::id_for_text(&normalized)),
+                text: if self.state.config.store_raw_text { record.text } else { normalized },
+                embedding: record.embedding,
+                metadata: record.metadata,
+                updated_at: None,
+                deleted_at: record.deleted_at,
+                version: None,
+                stale: record.stale,
+            };
+            records.push((line, document));
+        }
+
+        // Texts longer than the model accepts are stored as chunks
+        let texts: Vec<String> = records
+            .iter()
+            .filter(|(_, document)| document.embedding.is_empty())
+            .map(|(_, document)| document.text.clone())
+            .collect();
+        let mut chunks = chunk_texts(self.state, texts).await?.into_iter();
+        let mut documents = Vec::with_capacity(records.len());
+        let mut stored = 0;
+        for (line, document) in records {
+            if !document.embedding.is_empty() {
+                documents.push(document);
+                stored += 1;
+                continue;
+            }
+            match document.into_chunks(chunks.next().unwrap_or_default()) {
+                Ok(chunks) => {
+                    documents.extend(chunks);
+                    stored += 1;
+                }
+                Err(message) => self.skip(line, message),
+            }
+        }
+
+        let texts: Vec<String> = documents
+            .iter()
+            .filter(|document| document.embedding.is_empty())
+            .map(|document| document.text.clone())
+            .collect();
+        if !texts.is_empty() {
+            let embeddings = self
+                .state
+                .openai
+                .submit_background(move |openai| async move { openai.get_document_embeddings(&texts).await })
+                .await
+                .map_err(|e| {
+                    ApiError::ServiceUnavailable(format!(
+                        "{} at {} ({} documents imported)",
+                        e, lines, self.imported
+                    ))
+                })?
+                .map_err(|e| {
+                    error!("Failed to embed import {}: {:#}", lines, e);
+                    ApiError::OpenAI(format!(
+                        "Failed to embed {} ({} documents imported)",
+                        lines, self.imported
+                    ))
+                })?;
+            self.usage += embeddings.usage;
+            let mut vectors = embeddings.vectors.into_iter();
+            for document in documents.iter_mut().filter(|document| document.embedding.is_empty()) {
+                document.embedding = vectors.next().ok_or_else(|| {
+                    ApiError::Internal(format!("Missing embeddings for {}", lines))
+                })?;
+            }
+        }
+
+        if documents.is_empty() {
+            return Ok(());
+        }
+        self.state
+            .qdrant_service
+            .upsert_documents(&documents, self.params.write_ordering, self.params.shard_key.as_deref())
+            .await
+            .map_err(|e| {
+                error!("Failed to store import {}: {:#}", lines, e);
+                qdrant_error(
+                    self.state,
+                    &e,
+                    &format!("Failed to store {} ({} documents imported)", lines, self.imported),
+                )
+            })?;
+        self.imported += stored;
+        Ok(())
+    }
+
+    /// Counts an invalid line, listing it among the first `MAX_IMPORT_ERRORS`.
+    fn skip(&mut self, line: usize, message: String) {
+        self.skipped += 1;
+        if self.errors.len() < MAX_IMPORT_ERRORS {
+            self.errors.push(serde_json::json!({ "line": line, "error": message }));
+        }
+    }
+}
+
+/// Documents re-embedded per OpenAI call during a reindex, unless the request overrides it.
+const REINDEX_BATCH_SIZE: u32 = 100;
+
+/// Most inputs OpenAI accepts in a single embedding request.
+const MAX_REINDEX_BATCH_SIZE: u32 = 2048;
+
+/// How long a reindex waits before retrying a call the OpenAI queue rejected.
+const REINDEX_QUEUE_RETRY: Duration = Duration::from_secs(1);
+
+/// Handles reindex requests.
+/// 
+/// Starts a background job that scrolls through the collection, embeds each
+/// document's text again with the current `EMBEDDING_MODEL` in batches, and
+/// upserts the new vectors with the existing ids and metadata, with up to
+/// `EMBED_CONCURRENCY` batches in flight. The job's progress and per-stage
+/// timings are polled with `GET /api/jobs/:id`. Only one reindex runs at a time.
+/// Served on the admin router, since it rewrites every document.
+/// 
+/// # Arguments
+/// * `state` - Application state containing service instances
+/// * `audit` - Caller details for the audit log
+/// * `payload` - JSON payload with the batch size, write ordering and shard key;
+///   an empty body uses the defaults
+/// 
+/// # Returns
+/// * `Ok((StatusCode, Json<ApiResponse<Value>>))` - 202 with the job id
+/// * `Err(ApiError)` - 400 for malformed JSON, an out-of-range batch size or invalid
+///   shard key, 409 if a reindex is already running, 503 with `DISABLE_OPENAI`
+/// 
+/// # Example Request
+/// ```json
+/// { "batch_size": 200 }
+/// ```
+pub async fn handle_reindex(
+    State(state): State<Arc<AppState>>,
+    audit: AuditContext,
+    OptionalApiJson(payload): OptionalApiJson<ReindexRequest>,
+) -> Result<(StatusCode, Json<ApiResponse<Value>>), ApiError> {
+    let batch_size = payload.batch_size.unwrap_or(REINDEX_BATCH_SIZE);
+    if !(1..=MAX_REINDEX_BATCH_SIZE).contains(&batch_size) {
+        return Err(ApiError::Validation(format!(
+            "Batch size must be between 1 and {}",
+            MAX_REINDEX_BATCH_SIZE
+        )));
+    }
+    state
+        .qdrant_service
+        .shard_key_selector(payload.shard_key.as_deref())
+        .map_err(|e| ApiError::Validation(e.to_string()))?;
+    // Every batch would be rejected, so don't start a job that can only fail
+    if state.openai.is_disabled() {
+        return Err(QueueError::Disabled.into());
+    }
+
+    // The total only drives the progress report, so a failed count is not fatal
+    let total = match state.qdrant_service.count_documents(true, payload.shard_key.as_deref()).await {
+        Ok(total) => Some(total),
+        Err(e) => {
+            warn!("Failed to count documents before reindexing: {:#}", e);
+            None
+        }
+    };
+    let (ordering, shard_key) = (payload.write_ordering, payload.shard_key);
+    let job_id = state
+        .jobs
+        .spawn(JobKind::Reindex, total, |job| {
+            let entry = state.audit.begin(
+                AuditEntry::new(&audit, "reindex")
+                    .tenant(shard_key.as_deref())
+                    .target(serde_json::json!({
+                        "collection": state.qdrant_service.collection(),
+                        "model": state.config.embedding_model,
+                        "job_id": job.id()
+                    })),
+            );
+            let state = state.clone();
+            async move {
+                let result = reindex(state.clone(), &job, batch_size, ordering, shard_key).await;
+                match &result {
+                    Ok(processed) => entry.succeeded(Some(*processed)),
+                    Err(e) => entry.failed(e),
+                }
+                result
+            }
+        })
+        .map_err(|running| ApiError::Conflict(format!("Reindex job {} is still running", running)))?;
+    info!("Started reindex job {} (batch size {})", job_id, batch_size);
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(ApiResponse::success(serde_json::json!({
+            "job_id": job_id,
+            "total": total
+        }))),
+    ))
+}
+
+/// Re-embeds and upserts every document, one scroll page per batch.
+/// 
+/// Pages are read one after another, since each scroll returns the offset
+/// of the next page, but up to `EMBED_CONCURRENCY` batches are embedded and
+/// upserted at once. Each batch is written as soon as its embeddings arrive.
+/// 
+/// # Returns
+/// * `Ok(u64)` - The number of documents reindexed
+/// * `Err(anyhow::Error)` - The first scroll, embedding or upsert error
+async fn reindex(
+    state: Arc<AppState>,
+    job: &JobHandle,
+    batch_size: u32,
+    ordering: Option<WriteOrderingLevel>,
+    shard_key: Option<String>,
+) -> anyhow::Result<u64> {
+    // The cursor is `None` once the last page has been read
+    let pages = futures::stream::try_unfold(Some(None), {
+        let (state, shard_key) = (state.clone(), shard_key.clone());
+        move |cursor| {
+            let (state, shard_key) = (state.clone(), shard_key.clone());
+            async move {
+                let Some(offset) = cursor else {
+                    return Ok(None);
+                };
+                let started = Instant::now();
+                let (documents, next_offset) = state
+                    .qdrant_service
+                    .scroll_documents(offset, batch_size, false, true, shard_key.as_deref())
+                    .await?;
+                Ok::<_, anyhow::Error>(Some(((documents, started.elapsed()), next_offset.map(Some))))
+            }
+        }
+    });
+
+    let mut batches = std::pin::pin!(pages
+        .map_ok(|(documents, scroll)| {
+            reindex_batch(state.clone(), documents, scroll, ordering, shard_key.clone())
+        })
+        .try_buffer_unordered(state.config.embed_concurrency.get()));
+    let mut processed = 0;
+    while let Some((count, tokens, timings)) = batches.try_next().await? {
+        processed += count;
+        job.progress(count, tokens, timings);
+    }
+    Ok(processed)
+}
+
+/// Re-embeds one page of documents and upserts them.
+/// 
+/// Documents another writer changed since the page was read are left as
+/// that writer stored them.
+/// 
+/// # Returns
+/// * `Ok((documents, tokens, timings))` - The documents written, the tokens used and how
+///   long each stage took
+/// * `Err(anyhow::Error)` - If embedding or upserting fails
+async fn reindex_batch(
+    state: Arc<AppState>,
+    mut documents: Vec<Document>,
+    scroll: Duration,
+    ordering: Option<WriteOrderingLevel>,
+    shard_key: Option<String>,
+) -> anyhow::Result<(u64, u64, StageTimings)> {
+    if documents.is_empty() {
+        return Ok((0, 0, StageTimings::new(scroll, Duration::ZERO, Duration::ZERO)));
+    }
+
+    let started = Instant::now();
+    let texts: Vec<String> = documents.iter().map(|document| document.text.clone()).collect();
+    // A background job can wait for room in the queue instead of failing
+    let embeddings = loop {
+        let texts = texts.clone();
+        match state
+            .openai
+            .submit_background(move |openai| async move { openai.get_document_embeddings(&texts).await })
+            .await
+        {
+            Err(QueueError::Full) => tokio::time::sleep(REINDEX_QUEUE_RETRY).await,
+            result => break result??,
+        }
+    };
+    let embed = started.elapsed();
+    if embeddings.vectors.len() != documents.len() {
+        anyhow::bail!(
+            "OpenAI returned {} embeddings for {} documents",
+            embeddings.vectors.len(),
+            documents.len()
+        );
+    }
+    // Vectors come back in input order, so each stays with its document's id
+    for (document, embedding) in documents.iter_mut().zip(embeddings.vectors) {
+        document.embedding = embedding;
+    }
+
+    // Documents changed since the scroll keep the newer write
+    let started = Instant::now();
+    let written = state
+        .qdrant_service
+        .replace_embeddings(&documents, ordering, shard_key.as_deref())
+        .await?;
+    let upsert = started.elapsed();
+
+    Ok((
+        written,
+        u64::from(embeddings.usage.total_tokens),
+        StageTimings::new(scroll, embed, upsert),
+    ))
+}
+
+/// Starts a job re-embedding the documents flagged as stale.
+/// 
+/// Called on every `STALE_REEMBED_INTERVAL_SECS` tick. Nothing is started
+/// when no document is stale, the previous job is still running, or the
+/// embedding token budget is spent. The count of stale documents is
+/// published as the `stale_documents` gauge either way.
+/// 
+/// # Returns
+/// * `Ok(Some(JobId))` - The id of the job started
+/// * `Ok(None)` - If there was nothing to start
+/// * `Err(anyhow::Error)` - If the stale documents cannot be counted
+pub async fn start_stale_reembed(state: &Arc<AppState>) -> anyhow::Result<Option<JobId>> {
+    let stale = state.qdrant_service.count_stale(None).await?;
+    state.metrics.set_stale_documents(stale);
+    if stale == 0 {
+        return Ok(None);
+    }
+    if let Err(exceeded) = state.metrics.token_budget().check(&[TokenKind::Embedding], unix_now()) {
+        warn!("Not re-embedding {} stale documents: {}", stale, exceeded.message());
+        return Ok(None);
+    }
+
+    let job_state = state.clone();
+    let started = state.jobs.spawn(JobKind::Reembed, Some(stale), |job| async move {
+        reembed_stale(job_state, &job, stale).await
+    });
+    Ok(started.ok())
+}
+
+/// Re-embeds stale documents one batch at a time until none are left.
+/// 
+/// Each batch goes through the OpenAI queue like any other call. The job
+/// stops early, leaving the rest for the next tick, once Qdrant becomes
+/// unreachable, the embedding token budget runs out, or a whole batch was
+/// rewritten by other writers while it was being embedded.
+/// 
+/// # Returns
+/// * `Ok(u64)` - The number of documents re-embedded
+/// * `Err(anyhow::Error)` - The first scroll, embedding or upsert error
+async fn reembed_stale(state: Arc<AppState>, job: &JobHandle, total: u64) -> anyhow::Result<u64> {
+    let mut processed = 0;
+    loop {
+        if state.qdrant_service.connectivity() != Connectivity::Connected {
+            info!("Qdrant is unavailable, pausing stale document re-embedding");
+            break;
+        }
+        if let Err(exceeded) = state.metrics.token_budget().check(&[TokenKind::Embedding], unix_now()) {
+            warn!("Pausing stale document re-embedding: {}", exceeded.message());
+            break;
+        }
+
+        let started = Instant::now();
+        let documents = state
+            .qdrant_service
+            .stale_documents(state.config.stale_reembed_batch_size, None)
+            .await?;
+        if documents.is_empty() {
+            break;
+        }
+        // Writing the new embedding clears the flag
+        let (count, tokens, timings) = reindex_batch(state.clone(), documents, started.elapsed(), None, None).await?;
+        processed += count;
+        job.progress(count, tokens, timings);
+        state.metrics.set_stale_documents(total.saturating_sub(processed));
+        // Documents rewritten in the meantime are picked up on the next tick
+        if count == 0 {
+            break;
+        }
+    }
+    Ok(processed)
+}
+
+/// Handles background job listing requests.
+/// 
+/// Lists running jobs and the most recent finished ones, newest first.
+/// 
+/// # Arguments
+/// * `state` - Application state containing the job registry
+/// 
+/// # Returns
+/// * `Ok(Json<ApiResponse<Value>>)` - The `JobStatus` of every known job
+/// * `Err(ApiError)` - 500 if the statuses cannot be serialized
+/// 
+/// # Example Request
+/// ```text
+/// GET /api/jobs
+/// ```
+pub async fn handle_list_jobs(State(state): State<Arc<AppState>>) -> Result<Json<ApiResponse<Value>>, ApiError> {
+    let jobs = serde_json::to_value(state.jobs.list()).map_err(|e| ApiError::Internal(e.to_string()))?;
+    Ok(Json(ApiResponse::success(serde_json::json!({ "jobs": jobs }))))
+}
+
+/// Handles background job status requests.
+/// 
+/// Jobs are kept in memory, so they are forgotten on restart, and only the
+/// most recent finished jobs can be polled.
+/// 
+/// # Arguments
+/// * `state` - Application state containing the job registry
+/// * `id` - Job id returned when the job was started
+/// 
+/// # Returns
+/// * `Ok(Json<ApiResponse<Value>>)` - The job's `JobStatus`
+/// * `Err(ApiError)` - 404 if the job is unknown
+/// 
+/// # Example Request
+/// ```text
+/// GET /api/jobs/6f1c2a9e-0b4d-4c3e-9a57-2d8e1f3b7c40
+/// ```
+pub async fn handle_get_job(
+    State(state): State<Arc<AppState>>,
+    ApiPath(id): ApiPath<String>,
+) -> Result<Json<ApiResponse<Value>>, ApiError> {
+    let job = state
+        .jobs
+        .get(&id)
+        .ok_or_else(|| ApiError::NotFound(format!("Job {} not found", id)))?;
+    let job = serde_json::to_value(job).map_err(|e| ApiError::Internal(e.to_string()))?;
+    Ok(Json(ApiResponse::success(job)))
+}
+
+/// Handles health check requests.
+/// 
+/// Reports whether Qdrant can be reached, as observed by recent calls,
+/// without calling it, so probes stay cheap and never hang. The current
+/// load is reported alongside, so operators can see how close the server
+/// is to shedding requests. With `WARMUP`, the start-up calls' latencies
+/// and errors are reported under `warmup`, as they complete.
+/// 
+/// # Arguments
+/// * `state` - Application state containing the Qdrant service
+/// 
+/// # Returns
+/// * `200` - `{"qdrant": "connected", "openai": "enabled", "load": {...}, "warmup": {...}}`
+/// * `503` - An error naming the state while Qdrant is `connecting` or
+///   `unavailable`, with `{"qdrant", "ready", "warmup"}` as its `data` so a
+///   failing probe shows how far start-up got
+/// 
+/// # Example Request
+/// ```text
+/// GET /health
+/// ```
+pub async fn handle_health(State(state): State<Arc<AppState>>) -> Response {
+    let connectivity = state.qdrant_service.connectivity();
+    let message = match connectivity {
+        Connectivity::Connected => return Json(ApiResponse::success(serde_json::json!({
+            "qdrant": Connectivity::Connected,
+            "openai": if state.openai.is_disabled() { "disabled" } else { "enabled" },
+            "load": {
+                "in_flight_requests": state.in_flight(),
+                "openai_queued": state.openai.queued(),
+                "avg_service_ms": state.service_time.average().as_millis() as u64,
+                "shed_high_water_mark": state.config.shed_high_water_mark,
+            },
+            "warmup": state.readiness.report()
+        })))
+        .into_response(),
+        Connectivity::Connecting => "qdrant is connecting: startup checks have not succeeded yet",
+        Connectivity::Unavailable => "qdrant is unavailable: recent calls could not reach it",
+    };
+
+    let error = ApiError::ServiceUnavailable(message.into());
+    let mut body = ApiResponse::<Value>::error(error.code(), error.to_string());
+    body.data = serde_json::json!({
+        "qdrant": connectivity,
+        "ready": state.readiness.is_ready(),
+        "warmup": state.readiness.report()
+    });
+    (error.status_code(), Json(body)).into_response()
+}
+
+/// Answers requests for paths no route serves, with the error envelope.
+/// 
+/// # Returns
+/// * `ApiError` - Always 404
+pub async fn handle_not_found(method: Method, uri: Uri) -> ApiError {
+    ApiError::NotFound(format!("no route for {} {}", method, uri.path()))
+}
+
+/// Answers requests whose path exists but doesn't take their method.
+/// 
+/// # Returns
+/// * `ApiError` - Always 405
+pub async fn handle_method_not_allowed(method: Method, uri: Uri) -> ApiError {
+    ApiError::MethodNotAllowed(format!("{} does not accept {}", uri.path(), method))
+}
+
+/// Handles version requests.
+/// 
+/// Served without an API key, so clients can check what an instance
+/// supports before using it. Features reflect the running configuration:
+/// one switched off in the environment is reported as `false`.
+/// 
+/// # Arguments
+/// * `state` - Application state holding the configuration
+/// 
+/// # Returns
+/// * `Ok(Json<ApiResponse<Value>>)` - `{"version", "git_sha", "build_timestamp", "features": {...}, "models": {...}}`
+/// * `Err(ApiError)` - 500 if the build information cannot be serialized
+/// 
+/// # Example Request
+/// ```text
+/// GET /api/version
+/// ```
+pub async fn handle_version(State(state): State<Arc<AppState>>) -> Result<Json<ApiResponse<Value>>, ApiError> {
+    let info = BuildInfo::new(&state.config);
+    let info = serde_json::to_value(info).map_err(|e| ApiError::Internal(e.to_string()))?;
+    Ok(Json(ApiResponse::success(info)))
+}
+
+/// Handles readiness probes.
+/// 
+/// The server is ready once the `WARMUP` calls have completed or timed
+/// out, or right after binding when warm-up is off. Unlike `/health`, it
+/// stays ready while Qdrant is unreachable, so a blip doesn't take every
+/// replica out of the load balancer.
+/// 
+/// # Arguments
+/// * `state` - Application state holding the readiness
+/// 
+/// # Returns
+/// * `Ok(Json<ApiResponse<Value>>)` - `{"ready": true, "warmup": {...}}`, with a null
+///   `warmup` when it is off
+/// * `Err(ApiError)` - 503 while warming up
+/// 
+/// # Example Request
+/// ```text
+/// GET /ready
+/// ```
+pub async fn handle_ready(State(state): State<Arc<AppState>>) -> Result<Json<ApiResponse<Value>>, ApiError> {
+    if !state.readiness.is_ready() {
+        return Err(ApiError::ServiceUnavailable("warming up, not ready yet".into()));
+    }
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "ready": true,
+        "warmup": state.readiness.report()
+    }))))
+}
+
+/// Handles Prometheus scrape requests.
+/// 
+/// Renders OpenAI latency, token and estimated cost metrics along with
+/// Qdrant latency metrics in the Prometheus text exposition format.
+/// 
+/// # Arguments
+/// * `state` - Application state containing the metrics registry
+/// 
+/// # Returns
+/// * `Ok(Response)` - A `text/plain; version=0.0.4` response
+/// * `Err(ApiError)` - 500 if the metrics cannot be encoded
+/// 
+/// # Example Request
+/// ```text
+/// GET /metrics
+/// ```
+pub async fn handle_metrics(State(state): State<Arc<AppState>>) -> Result<Response, ApiError> {
+    state.metrics.set_load(state.in_flight(), state.openai.queued());
+    let body = state.metrics.render().map_err(|e| {
+        error!("Failed to encode metrics: {}", e);
+        ApiError::Internal("Failed to encode metrics".into())
+    })?;
+
+    Response::builder()
+        .header(header::CONTENT_TYPE, "text/plain; version=0.0.4")
+        .body(Body::from(body))
+        .map_err(|e| ApiError::Internal(e.to_string()))
+}
+
+/// Handles runtime stats requests.
+/// 
+/// Returns request counts, error counts and average latency, overall and
+/// by route, with the OpenAI tokens used and the chat cache hit rate, as
+/// counted since startup. A quick check for deployments without a metrics
+/// scraper; `/metrics` has the full picture. With `?reset=true` the counts
+/// start over after this read, so that polling it gives per-interval figures.
+/// Once `ADMIN_API_KEY` is set, only admin keys can read or reset the counts.
+/// 
+/// # Arguments
+/// * `state` - Application state containing the counters
+/// * `params` - Whether to reset the counters
+/// 
+/// # Returns
+/// * `Ok(Json<ApiResponse<Value>>)` - The counts and the time they were counted from
+/// * `Err(ApiError)` - 500 if the counts cannot be serialized
+/// 
+/// # Example Request
+/// ```text
+/// GET /api/stats?reset=true
+/// ```
+pub async fn handle_stats(
+    State(state): State<Arc<AppState>>,
+    ApiQuery(params): ApiQuery<StatsQuery>,
+) -> Result<Json<ApiResponse<Value>>, ApiError> {
+    if params.reset {
+        info!("Runtime stats read and reset");
+    }
+    let stats = serde_json::to_value(state.stats.snapshot(params.reset))
+        .map_err(|e| ApiError::Internal(e.to_string()))?;
+    Ok(Json(ApiResponse::success(stats)))
+}
+
+/// Handles semantic search requests.
+/// 
+/// The query text is embedded with OpenAI and the nearest documents
+/// are looked up in the Qdrant collection.
+/// 
+/// With `mode: "hybrid"`, documents whose text contains the keywords (the
+/// query unless `keywords` is given) are ranked as well, and both rankings
+/// are merged with reciprocal rank fusion weighted by `keyword_weight`.
+/// 
+/// With `group_by`, results are grouped by the value of that payload field
+/// and each group carries its best `group_size` hits, so one document split
+/// into many points cannot fill the whole result list.
+/// 
+/// With `filter`, only documents matching that payload filter are returned.
+/// 
+/// With `SEARCH_COALESCING`, an ungrouped search arriving while an identical
+/// one runs waits for it and shares its hits.
+/// 
+/// # Arguments
+/// * `state` - Application state containing service instances
+/// * `version` - Envelope version of the request; version 1 gets the hits under `results`
+/// * `payload` - JSON payload containing the search query
+/// 
+/// # Returns
+/// * `Ok(Json<ApiResponse<Value>>)` - Matching documents with their scores, or
+///   groups of them with `group_by`
+/// * `Err(ApiError)` - 400 for an empty query, out-of-range limit, keyword weight or
+///   group size, hybrid options in dense mode or grouping in hybrid mode, 422 listing
+///   the problems of an invalid filter, 500 otherwise
+/// 
+/// # Example Request
+/// ```json
+/// {
+///     "query": "What is Rust?",
+///     "limit": 5,
+///     "mode": "hybrid",
+///     "keywords": "ownership",
+///     "keyword_weight": 0.3
+/// }
+/// ```
+pub async fn handle_search(
+    State(state): State<Arc<AppState>>,
+    version: Option<Extension<ApiVersion>>,
+    ApiJson(payload): ApiJson<SearchRequest>,
+) -> Result<Json<ApiResponse<Value>>, ApiError> {
+    // Validate that the query is not empty
+    if payload.query.trim().is_empty() {
+        error!("Empty query provided for search");
+        return Err(ApiError::Validation("Query cannot be empty".into()));
+    }
+    let version = version.map_or(state.config.api_version, |Extension(version)| version);
+
+    // Apply the default limit and enforce the configured maximum
+    let limit = search_limit(&state, payload.limit)?;
+
+    // Keyword options only mean something in hybrid mode
+    let hybrid = match payload.mode {
+        SearchMode::Dense => {
+            if payload.keywords.is_some() || payload.keyword_weight.is_some() {
+                return Err(ApiError::Validation(
+                    "keywords and keyword_weight require mode 'hybrid'".into(),
+                ));
+            }
+            None
+        }
+        SearchMode::Hybrid => {
+            let keywords = payload.keywords.clone().unwrap_or_else(|| payload.query.clone());
+            if keywords.trim().is_empty() {
+                return Err(ApiError::Validation("Keywords cannot be empty".into()));
+            }
+            let weight = payload.keyword_weight.unwrap_or(state.config.hybrid_keyword_weight);
+            if !(0.0..=1.0).contains(&weight) {
+                return Err(ApiError::Validation("keyword_weight must be between 0 and 1".into()));
+            }
+            Some((keywords, weight))
+        }
+    };
+
+    if payload.score_threshold.is_some_and(|threshold| !threshold.is_finite()) {
+        return Err(ApiError::Validation("Score threshold must be a finite number".into()));
+    }
+    let min_results = payload.min_results.unwrap_or(0);
+    if min_results > 0 && payload.score_threshold.is_none() {
+        return Err(ApiError::Validation("min_results requires score_threshold".into()));
+    }
+    if min_results > limit {
+        return Err(ApiError::Validation(format!("min_results must not exceed the limit of {}", limit)));
+    }
+
+    // Grouping only applies to the dense ranking
+    let group_by = match (&payload.group_by, payload.group_size) {
+        (None, Some(_)) => return Err(ApiError::Validation("group_size requires group_by".into())),
+        (None, None) => None,
+        (Some(field), _) if field.trim().is_empty() => {
+            return Err(ApiError::Validation("group_by cannot be empty".into()))
+        }
+        (Some(_), _) if hybrid.is_some() => {
+            return Err(ApiError::Validation("group_by is not supported in mode 'hybrid'".into()))
+        }
+        (Some(_), _) if min_results > 0 => {
+            return Err(ApiError::Validation("min_results cannot be combined with group_by".into()))
+        }
+        (Some(field), size) => {
+            let size = size.unwrap_or(1);
+            if size == 0 || u64::from(size) > state.config.max_search_limit {
+                return Err(ApiError::Validation(format!(
+                    "group_size must be between 1 and {}",
+                    state.config.max_search_limit
+                )));
+            }
+            Some((field.clone(), size))
+        }
+    };
+
+    // Reject shard keys that don't match the collection's sharding method
+    state
+        .qdrant_service
+        .shard_key_selector(payload.shard_key.as_deref())
+        .map_err(|e| ApiError::Validation(e.to_string()))?;
+    // The same check as /api/search/validate, so a filter it accepts is searchable
+    let filter = payload
+        .filter
+        .as_ref()
+        .map(|filter| DocumentFilter::parse(filter).map_err(ApiError::InvalidFilter))
+        .transpose()?;
+
+    if let Some((field, size)) = group_by {
+        let vector = embed_query(&state, &payload.query).await?;
+        return search_groups(&state, &payload, filter.as_ref(), vector, (&field, size), limit, version).await;
+    }
+
+    // Embed the query and rank the documents, fusing in keyword matches in
+    // hybrid mode; identical searches running at the same time share the work
+    let key = SearchKey {
+        query: state.config.text_normalization.apply(&payload.query),
+        limit,
+        hybrid: hybrid.as_ref().map(|(keywords, weight)| (keywords.clone(), weight.to_bits())),
+        read_consistency: payload.read_consistency,
+        shard_key: payload.shard_key.clone(),
+        filter,
+    };
+    let (mut hits, query_time_ms) = if state.config.search_coalescing {
+        let (ranked, shared) = state
+            .search_flights
+            .run(key.clone(), rank_documents(state.clone(), key))
+            .await;
+        if shared {
+            debug!("Search shared the result of an identical one in flight");
+            state.metrics.record_search_coalesced();
+        }
+        ranked
+    } else {
+        rank_documents(state.clone(), key).await
+    }?;
+    let total_candidates = hits.len();
+
+    // Keep the hits that clear the threshold, or fall back to the nearest
+    // `min_results` when too few do
+    let mut low_confidence = false;
+    if let Some(threshold) = payload.score_threshold {
+        let distance = state.config.qdrant_distance;
+        // The fused hybrid score isn't on the metric's scale
+        let clears = |hit: &SearchHit| {
+            let score = if hybrid.is_some() { hit.vector_score } else { Some(hit.score) };
+            score.is_some_and(|score| distance.clears(score, threshold))
+        };
+        let passing = hits.iter().filter(|hit| clears(hit)).count() as u64;
+        if passing >= min_results {
+            hits.retain(clears);
+        } else {
+            info!(
+                "Only {} of {} results cleared the score threshold, returning the nearest {}",
+                passing,
+                hits.len(),
+                min_results
+            );
+            low_confidence = true;
+            hits.truncate(min_results as usize);
+        }
+        for hit in &mut hits {
+            hit.low_confidence = Some(!clears(hit));
+        }
+    }
+
+    info!("Search returned {} results in {} ms", hits.len(), query_time_ms);
+    let results = SearchResults {
+        hits,
+        query_time_ms,
+        total_candidates,
+        score_threshold: payload.score_threshold,
+        low_confidence,
+    };
+    Ok(Json(ApiResponse::success(results.render(version))))
+}
+
+/// What an ungrouped `/api/search` ranks; concurrent searches with equal
+/// keys share one embedding and one Qdrant search.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SearchKey {
+    /// The query as normalized before embedding
+    query: String,
+    limit: u64,
+    /// Keywords and the bits of the keyword weight, in hybrid mode
+    hybrid: Option<(String, u32)>,
+    read_consistency: Option<ReadConsistencyLevel>,
+    shard_key: Option<String>,
+    filter: Option<DocumentFilter>,
+}
+
+/// Output of `rank_documents`: the hits and the milliseconds spent in Qdrant.
+pub type RankedDocuments = Result<(Vec<SearchHit>, u64), ApiError>;
+
+/// Embeds the query of a search and ranks the documents against it.
+async fn rank_documents(state: Arc<AppState>, key: SearchKey) -> RankedDocuments {
+    let vector = embed_query(&state, &key.query).await?;
+    let started = Instant::now();
+    let scope = SearchScope {
+        filter: key.filter.as_ref(),
+        read_consistency: key.read_consistency,
+        shard_key: key.shard_key.as_deref(),
+    };
+    let hits = match &key.hybrid {
+        Some((keywords, weight)) => {
+            state
+                .qdrant_service
+                .hybrid_search(vector, keywords, key.limit, f32::from_bits(*weight), scope)
+                .await
+        }
+        None => state.qdrant_service.search(vector, key.limit, scope).await,
+    }
+    .map_err(|e| {
+        error!("Failed to search documents: {:#}", e);
+        if e.is::<ZeroVector>() {
+            ApiError::Unprocessable(e.to_string())
+        } else {
+            qdrant_error(&state, &e, "Failed to search documents")
+        }
+    })?;
+    Ok((hits, started.elapsed().as_millis() as u64))
+}
+
+/// Embeds a search query.
+async fn embed_query(state: &AppState, query: &str) -> Result<Vec<f32>, ApiError> {
+    let query = query.to_string();
+    state
+        .openai
+        .submit(move |openai| async move { openai.get_embedding(&query, None).await })
+        .await?
+        .map_err(|e| {
+            error!("Failed to generate query embedding: {}", e);
+            ApiError::OpenAI("Failed to generate query embedding".into())
+        })
+}
+
+/// Runs the grouped variant of `/api/search`.
+/// 
+/// Hits below `score_threshold` are dropped from their group, and groups
+/// left without hits are dropped altogether.
+async fn search_groups(
+    state: &AppState,
+    payload: &SearchRequest,
+    filter: Option<&DocumentFilter>,
+    vector: Vec<f32>,
+    (group_by, group_size): (&str, u32),
+    limit: u64,
+    version: ApiVersion,
+) -> Result<Json<ApiResponse<Value>>, ApiError> {
+    let scope = SearchScope {
+        filter,
+        read_consistency: payload.read_consistency,
+        shard_key: payload.shard_key.as_deref(),
+    };
+    let mut groups = state
+        .qdrant_service
+        .search_groups(vector, group_by, group_size, limit, scope)
+        .await
+        .map_err(|e| {
+            error!("Failed to search document groups: {:#}", e);
+            if e.is::<ZeroVector>() {
+                ApiError::Unprocessable(e.to_string())
+            } else {
+                qdrant_error(state, &e, "Failed to search documents")
+            }
+        })?;
+
+    if let Some(threshold) = payload.score_threshold {
+        let distance = state.config.qdrant_distance;
+        for group in &mut groups {
+            group.hits.retain(|hit| distance.clears(hit.score, threshold));
+        }
+        groups.retain(|group| !group.hits.is_empty());
+    }
+
+    info!("Grouped search by '{}' returned {} groups", group_by, groups.len());
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "groups": groups.into_iter().map(|group| group.render(version)).collect::<Vec<_>>(),
+        "group_by": group_by,
+        "score_threshold": payload.score_threshold
+    }))))
+}
+
+/// Handles score distribution requests, a tool for tuning `score_threshold`.
+/// 
+/// Embeds the query, runs a dense search for its nearest `limit` documents
+/// without fetching their payloads, and summarizes their scores. The
+/// statistics only cover those nearest documents, not the whole collection.
+/// 
+/// # Arguments
+/// * `state` - Application state containing service instances
+/// * `payload` - JSON payload containing the query and an optional limit
+/// 
+/// # Returns
+/// * `Ok(Json<ApiResponse<Value>>)` - The distance metric and the count, minimum, maximum,
+///   mean, standard deviation and percentiles of the scores
+/// * `Err(ApiError)` - 400 for an empty query or out-of-range limit, 503 if the OpenAI
+///   queue is full, 500 otherwise
+/// 
+/// # Example Request
+/// ```json
+/// {
+///     "query": "What is Rust?",
+///     "limit": 500
+/// }
+/// ```
+pub async fn handle_score_distribution(
+    State(state): State<Arc<AppState>>,
+    ApiJson(payload): ApiJson<ScoreDistributionRequest>,
+) -> Result<Json<ApiResponse<Value>>, ApiError> {
+    if payload.query.trim().is_empty() {
+        error!("Empty query provided for score distribution");
+        return Err(ApiError::Validation("Query cannot be empty".into()));
+    }
+
+    let max = state.config.score_distribution_limit;
+    let limit = match payload.limit {
+        None => max,
+        Some(0) => return Err(ApiError::Validation("Limit must be at least 1".into())),
+        Some(limit) if limit > max => {
+            return Err(ApiError::Validation(format!(
+                "Limit {} exceeds the maximum of {}",
+                limit, max
+            )))
+        }
+        Some(limit) => limit,
+    };
+
+    state
+        .qdrant_service
+        .shard_key_selector(payload.shard_key.as_deref())
+        .map_err(|e| ApiError::Validation(e.to_string()))?;
+
+    let query = payload.query.clone();
+    let vector = state
+        .openai
+        .submit(move |openai| async move { openai.get_embedding(&query, None).await })
+        .await?
+        .map_err(|e| {
+            error!("Failed to generate query embedding: {}", e);
+            ApiError::OpenAI("Failed to generate query embedding".into())
+        })?;
+
+    let scores = state
+        .qdrant_service
+        .search_scores(
+            vector,
+            limit,
+            SearchScope {
+                read_consistency: payload.read_consistency,
+                shard_key: payload.shard_key.as_deref(),
+                ..Default::default()
+            },
+        )
+        .await
+        .map_err(|e| {
+            error!("Failed to search documents: {:#}", e);
+            if e.is::<ZeroVector>() {
+                ApiError::Unprocessable(e.to_string())
+            } else {
+                qdrant_error(&state, &e, "Failed to search documents")
+            }
+        })?;
+
+    info!("Summarized the scores of {} nearest documents", scores.len());
+    let distance = state.config.qdrant_distance;
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "limit": limit,
+        "distance": distance,
+        "higher_is_closer": distance.higher_is_closer(),
+        "scores": score_summary(&scores)
+    }))))
+}
+
+/// Summarizes search scores: count, minimum, maximum, mean, population
+/// standard deviation and percentiles interpolated between the nearest
+/// ranks. Every statistic but the count is null when there are no scores.
+fn score_summary(scores: &[f32]) -> Value {
+    let mut sorted: Vec<f64> = scores.iter().map(|&score| f64::from(score)).collect();
+    sorted.sort_by(f64::total_cmp);
+    let (Some(&min), Some(&max)) = (sorted.first(), sorted.last()) else {
+        return serde_json::json!({
+            "count": 0,
+            "min": null,
+            "max": null,
+            "mean": null,
+            "std_dev": null,
+            "percentiles": null
+        });
+    };
+
+    let count = sorted.len() as f64;
+    let mean = sorted.iter().sum::<f64>() / count;
+    let variance = sorted.iter().map(|score| (score - mean).powi(2)).sum::<f64>() / count;
+    let percentile = |p: f64| {
+        let rank = p / 100.0 * (count - 1.0);
+        let (low, high) = (sorted[rank.floor() as usize], sorted[rank.ceil() as usize]);
+        low + (high - low) * rank.fract()
+    };
+
+    serde_json::json!({
+        "count": sorted.len(),
+        "min": min,
+        "max": max,
+        "mean": mean,
+        "std_dev": variance.sqrt(),
+        "percentiles": {
+            "p5": percentile(5.0),
+            "p25": percentile(25.0),
+            "p50": percentile(50.0),
+            "p75": percentile(75.0),
+            "p90": percentile(90.0),
+            "p95": percentile(95.0),
+            "p99": percentile(99.0)
+        }
+    })
+}
+
+/// Handles question answering requests over the stored documents.
+/// 
+/// The question is embedded, the nearest documents are retrieved and their
+/// texts are numbered and inserted into `RAG_PROMPT_TEMPLATE` together with
+/// the question before asking the chat model.
+/// 
+/// With a `conversation_id`, prior turns are sent along and the question and
+/// answer are stored, as in `/api/chat`. A follow-up question is first
+/// rewritten by `REWRITE_MODEL` into a standalone search query, which is
+/// embedded instead of the question, unless the request sets `rewrite: false`.
+/// 
+/// Only documents whose score clears `RAG_SCORE_THRESHOLD` are used as
+/// context. When none does, `NO_CONTEXT_BEHAVIOR` decides whether to answer
+/// without context, refuse without calling the chat model, or relax the
+/// threshold once.
+/// 
+/// # Arguments
+/// * `state` - Application state containing service instances
+/// * `key` - The API key the request was authenticated with
+/// * `payload` - JSON payload containing the question
+/// 
+/// # Returns
+/// * `Ok(Json<ApiResponse<Value>>)` - The answer and why it ended, the ids and scores of the
+///   documents used, retrieval statistics, the rewritten query, and token usage including
+///   the rewrite
+/// * `Err(ApiError)` - 400 for an empty question, out-of-range limit, non-finite score
+///   threshold or invalid completion options, 503 if the OpenAI queue is full, 500 otherwise
+/// 
+/// # Example Request
+/// ```json
+/// {
+///     "question": "What about the second one?",
+///     "conversation_id": "3f2b8c1e-7d4a-4e0b-9c5f-2a6d8e1b4c7f",
+///     "limit": 5
+/// }
+/// ```
+pub async fn handle_ask(
+    State(state): State<Arc<AppState>>,
+    Extension(key): Extension<Authenticated>,
+    ApiJson(payload): ApiJson<AskRequest>,
+) -> Result<Json<ApiResponse<Value>>, ApiError> {
+    if payload.question.trim().is_empty() {
+        return Err(ApiError::Validation("Question cannot be empty".into()));
+    }
+    if matches!(&payload.conversation_id, Some(id) if id.trim().is_empty()) {
+        return Err(ApiError::Validation("Conversation id cannot be empty".into()));
+    }
+    let limit = search_limit(&state, payload.limit)?;
+    let options = completion_options(&state, payload.max_tokens, payload.stop)?;
+    state
+        .qdrant_service
+        .shard_key_selector(payload.shard_key.as_deref())
+        .map_err(|e| ApiError::Validation(e.to_string()))?;
+    if payload.score_threshold.is_some_and(|threshold| !threshold.is_finite()) {
+        return Err(ApiError::Validation("Score threshold must be a finite number".into()));
+    }
+    let score_threshold = payload.score_threshold.or(state.config.rag_score_threshold);
+    let behavior = payload.no_context_behavior.unwrap_or(state.config.no_context_behavior);
+
+    let (history, summary_usage) = match &payload.conversation_id {
+        Some(id) => {
+            let turns = state.conversations.history(id).await.map_err(|e| {
+                error!("Failed to load conversation: {:#}", e);
+                ApiError::Qdrant("Failed to load conversation".into())
+            })?;
+            summarize_history(&state, id, turns).await?
+        }
+        None => (Vec::new(), None),
+    };
+
+    // A follow-up makes a poor search query on its own
+    let (rewritten_query, rewrite_usage) = if payload.rewrite.unwrap_or(true) && !history.is_empty() {
+        if let Some(entitlement) = state.config.key_entitlements.get(&key.fingerprint) {
+            entitlement.check_model(&state.config.rewrite_model)?;
+        }
+        rewrite_query(&state, &history, &payload.question).await?
+    } else {
+        (None, None)
+    };
+
+    let query = rewritten_query.clone().unwrap_or_else(|| payload.question.clone());
+    let vector = state
+        .openai
+        .submit(move |openai| async move { openai.get_embedding(&query, None).await })
+        .await?
+        .map_err(|e| {
+            error!("Failed to generate question embedding: {}", e);
+            ApiError::OpenAI("Failed to generate question embedding".into())
+        })?;
+    let candidates = state
+        .qdrant_service
+        .search(
+            vector,
+            limit,
+            SearchScope {
+                shard_key: payload.shard_key.as_deref(),
+                ..Default::default()
+            },
+        )
+        .await
+        .map_err(|e| {
+            error!("Failed to retrieve context documents: {:#}", e);
+            if e.is::<ZeroVector>() {
+                ApiError::Unprocessable(e.to_string())
+            } else {
+                qdrant_error(&state, &e, "Failed to retrieve context documents")
+            }
+        })?;
+
+    let distance = state.config.qdrant_distance;
+    let mut applied_threshold = score_threshold;
+    let mut results = clearing_threshold(&candidates, applied_threshold, distance);
+    let relaxed = results.is_empty() && behavior == NoContextBehavior::Relax && applied_threshold.is_some();
+    if relaxed {
+        // Searching again would return the same nearest documents, so the
+        // candidates are filtered again instead
+        applied_threshold = applied_threshold.map(|threshold| distance.relax(threshold));
+        results = clearing_threshold(&candidates, applied_threshold, distance);
+    }
+    let context_used = !results.is_empty();
+    let retrieval = serde_json::json!({
+        "candidates": candidates.len(),
+        "best_score": candidates.first().map(|hit| hit.score),
+        "score_threshold": applied_threshold,
+        "relaxed": relaxed,
+        "used": results.len()
+    });
+
+    let mut response = if !context_used && behavior == NoContextBehavior::Refuse {
+        info!("No context document cleared the score threshold, refusing to answer");
+        CompletionResponse {
+            response: state.config.no_context_message.clone(),
+            usage: Usage::default(),
+            finish_reason: None,
+            system_fingerprint: None,
+            logprobs: None,
+            empty: false,
+            refusal: None,
+            cached: false,
+        }
+    } else {
+        let context = results
+            .iter()
+            .map(|hit| hit.text.as_str())
+            .filter(|text| !text.is_empty())
+            .enumerate()
+            .map(|(i, text)| format!("[{}] {}", i + 1, text))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        let prompt = state
+            .config
+            .rag_prompt_template
+            .render(&context, &payload.question);
+
+        let mut turns = history;
+        turns.push(ChatTurn::user(prompt));
+        state
+            .openai
+            .submit(move |openai| async move { openai.generate_chat_completion(&turns, &[], &options).await })
+            .await?
+            .map_err(|e| {
+                error!("Failed to generate answer: {}", e);
+                ApiError::OpenAI("Failed to generate answer".into())
+            })?
+    };
+    if response.empty {
+        warn!("Answer came back empty (finish_reason: {:?})", response.finish_reason);
+    }
+
+    // Store the question rather than the prompt, so the retrieved context
+    // doesn't pile up in the history; empty answers are not stored
+    if let Some(id) = payload.conversation_id.as_ref().filter(|_| !response.empty) {
+        let turns = vec![
+            ChatTurn::user(payload.question.as_str()),
+            ChatTurn::assistant(response.response.as_str()),
+        ];
+        if let Err(e) = state.conversations.append(id, turns).await {
+            error!("Failed to save conversation: {:#}", e);
+        }
+    }
+
+    if let Some(rewrite_usage) = rewrite_usage {
+        response.usage += rewrite_usage;
+    }
+    info!(
+        "Answered question using {} of {} retrieved documents",
+        results.len(),
+        candidates.len()
+    );
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "answer": response.response,
+        "finish_reason": response.finish_reason,
+        "empty": response.empty,
+        "refusal": response.refusal,
+        "cached": response.cached,
+        "sources": results
+            .iter()
+            .map(|hit| serde_json::json!({ "id": hit.id, "score": hit.score }))
+            .collect::<Vec<_>>(),
+        "context_used": context_used,
+        "no_context_behavior": behavior,
+        "retrieval": retrieval,
+        "rewritten_query": rewritten_query,
+        "usage": response.usage,
+        "rewrite_usage": rewrite_usage,
+        "summary_usage": summary_usage,
+        "conversation_id": payload.conversation_id
+    }))))
+}
+
+/// Keeps the search hits whose score clears `threshold` under `distance`.
+/// 
+/// Every hit is kept when no threshold is set.
+fn clearing_threshold(
+    hits: &[SearchHit],
+    threshold: Option<f32>,
+    distance: DistanceMetric,
+) -> Vec<&SearchHit> {
+    hits.iter()
+        .filter(|hit| threshold.is_none_or(|threshold| distance.clears(hit.score, threshold)))
+        .collect()
+}
+
+/// Rewrites a follow-up question into a standalone search query.
+/// 
+/// A failed or empty rewrite is logged and the question is searched as-is.
+/// 
+/// # Returns
+/// * `Ok((query, usage))` - The rewritten query and its token usage, if the rewrite succeeded
+/// * `Err(QueueError)` - If the OpenAI queue is full
+async fn rewrite_query(
+    state: &AppState,
+    history: &[ChatTurn],
+    question: &str,
+) -> Result<(Option<String>, Option<Usage>), QueueError> {
+    let (history, question) = (history.to_vec(), question.to_string());
+    let rewrite = state
+        .openai
+        .submit(move |openai| async move { openai.rewrite_query(&history, &question).await })
+        .await?;
+    match rewrite {
+        Ok(rewrite) => {
+            let query = rewrite.response.trim().to_string();
+            debug!("Rewrote follow-up question into search query {:?}", query);
+            Ok((Some(query), Some(rewrite.usage)))
+        }
+        Err(e) => {
+            warn!("Failed to rewrite question, searching it as-is: {:#}", e);
+            Ok((None, None))
+        }
+    }
+}
+
+/// Resolves the completion limits for a request.
+/// 
+/// Starts from `MAX_COMPLETION_TOKENS` and `STOP_SEQUENCES` and applies the
+/// request's overrides, rejecting a `max_tokens` above the configured maximum.
+fn completion_options(
+    state: &AppState,
+    max_tokens: Option<u32>,
+    stop: Option<Vec<String>>,
+) -> Result<CompletionOptions, ApiError> {
+    state
+        .config
+        .completion_options
+        .with_overrides(max_tokens, stop)
+        .map_err(ApiError::Validation)
+}
+
+/// Resolves the result limit for a search request.
+/// 
+/// Uses `DEFAULT_SEARCH_LIMIT` when the request omits a limit and rejects
+/// limits of zero or above `MAX_SEARCH_LIMIT` with a validation error.
+fn search_limit(state: &AppState, requested: Option<u64>) -> Result<u64, ApiError> {
+    let max = state.config.max_search_limit;
+    match requested {
+        None => Ok(state.config.default_search_limit.min(max)),
+        Some(0) => Err(ApiError::Validation("Limit must be at least 1".into())),
+        Some(limit) if limit > max => Err(ApiError::Validation(format!(
+            "Limit {} exceeds the maximum of {}",
+            limit, max
+        ))),
+        Some(limit) => Ok(limit),
+    }
+}
+
+/// Handles database reset requests.
+/// 
+/// This endpoint clears all data from the Qdrant collection,
+/// effectively resetting the database to its initial state.
+/// An optional JSON body may name another collection to clear (it must be
+/// listed in `RESET_ALLOWED_COLLECTIONS`) and override the write ordering.
+/// Once `ADMIN_API_KEY` is set, only admin keys can reset.
+/// 
+/// # Arguments
+/// * `state` - Application state containing service instances
+/// * `payload` - Optional JSON payload with operation overrides
+/// 
+/// # Returns
+/// * `Ok(Json<ApiResponse<Value>>)` - Success message
+/// * `Err(ApiError)` - 400 for malformed JSON or a shard key the collection doesn't take,
+///   403 for a collection not on the allow-list, 500 if the reset fails
+/// 
+/// # Example Request
+/// ```json
+/// {
+///     "collection": "documents-staging",
+///     "write_ordering": "strong",
+///     "shard_key": "tenant-a"
+/// }
+/// ```
+pub async fn handle_reset(
+    State(state): State<Arc<AppState>>,
+    audit: AuditContext,
+    OptionalApiJson(payload): OptionalApiJson<ResetRequest>,
+) -> Result<Json<ApiResponse<Value>>, ApiError> {
+    let collection = payload
+        .collection
+        .as_deref()
+        .unwrap_or(state.qdrant_service.collection());
+
+    // Only collections on the allow-list may be wiped
+    if !state.config.is_resettable(collection) {
+        warn!("Rejected reset of collection '{}' (not in allow-list)", collection);
+        return Err(ApiError::Forbidden {
+            code: "collection_not_resettable",
+            message: format!("Collection '{}' may not be reset", collection),
+        });
+    }
+
+    // Reject shard keys that don't match the collection's sharding method
+    if let Err(e) = state.qdrant_service.shard_key_selector(payload.shard_key.as_deref()) {
+        error!("Invalid shard key for reset: {}", e);
+        return Err(ApiError::Validation(e.to_string()));
+    }
+
+    let ordering = state
+        .qdrant_service
+        .effective_write_ordering(payload.write_ordering);
+
+    // Audit the attempt whether or not it succeeded
+    let entry = state.audit.begin(
+        AuditEntry::new(&audit, "reset")
+            .tenant(payload.shard_key.as_deref())
+            .target(serde_json::json!({ "collection": collection })),
+    );
+
+    // Delete all points from the collection
+    let result = state
+        .qdrant_service
+        .delete_all_points(Some(collection), Some(ordering), payload.shard_key.as_deref())
+        .await;
+    let deleted = match result {
+        Ok(deleted) => {
+            entry.succeeded(Some(deleted));
+            deleted
+        }
+        Err(e) => {
+            entry.failed(&e);
+            error!("Failed to reset database: {:#}", e);
+            return Err(qdrant_error(&state, &e, "Failed to reset database"));
+        }
+    };
+
+    // Log success
+    info!("Database reset successfully (collection '{}', {} points)", collection, deleted);
+
+    // Return success message
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "message": "Database reset successfully",
+        "collection": collection,
+        "deleted": deleted,
+        "strong_ordering": ordering == WriteOrderingLevel::Strong
+    }))))
+} 
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        extract::Request,
+        http::{header, Method, StatusCode},
+        middleware::Next,
+    };
+    use futures::StreamExt;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::jobs::JobState;
+    use crate::models::Document;
+    use crate::services::qdrant::DistanceMetric;
+    use crate::paths;
+    use crate::test_support::{self, TestApp, ADMIN_KEY, USER_KEY};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn reset_without_a_body_clears_the_collection() {
+        let app = test_support::app(&[("ADMIN_API_KEY", "")]).await;
+        app.post(paths::DOCUMENTS, &json!({ "text": "Rust is fast" })).await;
+
+        let reset = app.call(Method::POST, paths::RESET, Some(USER_KEY)).await;
+        assert_eq!(reset.status, StatusCode::OK, "{}", reset.text);
+        assert_eq!(reset.body["data"]["deleted"], 1);
+        assert_eq!(reset.body["data"]["collection"], "documents");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn reset_and_stats_refuse_user_keys_once_an_admin_key_is_set() {
+        let app = test_support::app(&[]).await;
+        app.post(paths::DOCUMENTS, &json!({ "text": "Rust is fast" })).await;
+
+        let reset = app.call(Method::POST, paths::RESET, Some(USER_KEY)).await;
+        assert_eq!(reset.status, StatusCode::FORBIDDEN);
+        assert_eq!(reset.body["error_code"], "admin_key_required");
+        let stats = app.get(paths::STATS).await;
+        assert_eq!(stats.status, StatusCode::FORBIDDEN);
+        let stats = app.call(Method::GET, paths::STATS, Some("not-a-key")).await;
+        assert_eq!(stats.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(app.state.qdrant_service.count_documents(false, None).await.unwrap(), 1);
+
+        let stats = app.call(Method::GET, paths::STATS, Some(ADMIN_KEY)).await;
+        assert_eq!(stats.status, StatusCode::OK, "{}", stats.text);
+        assert!(stats.body["data"]["requests"].as_u64().unwrap() >= 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn reset_and_stats_take_user_keys_without_an_admin_key() {
+        let app = test_support::app(&[("ADMIN_API_KEY", "")]).await;
+        app.post(paths::DOCUMENTS, &json!({ "text": "Rust is fast" })).await;
+
+        let stats = app.get(paths::STATS).await;
+        assert_eq!(stats.status, StatusCode::OK, "{}", stats.text);
+        let reset = app.call(Method::POST, paths::RESET, Some(USER_KEY)).await;
+        assert_eq!(reset.status, StatusCode::OK, "{}", reset.text);
+        assert_eq!(reset.body["data"]["deleted"], 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn reset_can_be_switched_off() {
+        let app = test_support::app(&[("ENABLE_RESET", "false")]).await;
+        let reset = app.call(Method::POST, paths::RESET, Some(ADMIN_KEY)).await;
+        assert_eq!(reset.status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn reset_rejects_malformed_json() {
+        let app = test_support::app(&[("ADMIN_API_KEY", "")]).await;
+        let reset = app
+            .send(
+                test_support::request(Method::POST, paths::RESET, Some(USER_KEY))
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from("{\"collection\":"))
+                    .unwrap(),
+            )
+            .await;
+        assert_eq!(reset.status, StatusCode::BAD_REQUEST);
+        assert_eq!(reset.body["code"], "validation_failed");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn reset_refuses_collections_off_the_allow_list() {
+        let app = test_support::app(&[("ADMIN_API_KEY", ""), ("RESET_ALLOWED_COLLECTIONS", "staging")]).await;
+        app.post(paths::DOCUMENTS, &json!({ "text": "Rust is fast" })).await;
+
+        let reset = app.post(paths::RESET, &json!({ "collection": "someone-elses" })).await;
+        assert_eq!(reset.status, StatusCode::FORBIDDEN);
+        assert_eq!(reset.body["status"], "error");
+        assert_eq!(reset.body["error_code"], "collection_not_resettable");
+
+        let qdrant = &app.state.qdrant_service;
+        qdrant.create_collection("staging", 3, DistanceMetric::Cosine, false).await.unwrap();
+        let reset = app.post(paths::RESET, &json!({ "collection": "staging" })).await;
+        assert_eq!(reset.status, StatusCode::OK, "{}", reset.text);
+        assert_eq!(reset.body["data"]["collection"], "staging");
+        assert_eq!(qdrant.count_documents(false, None).await.unwrap(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn reindex_needs_the_admin_key_and_takes_an_empty_body() {
+        let app = test_support::app(&[]).await;
+        app.post(paths::DOCUMENTS, &json!({ "text": "Rust is fast" })).await;
+        app.post(paths::DOCUMENTS, &json!({ "text": "Go is simple" })).await;
+
+        let refused = app.call(Method::POST, paths::ADMIN_REINDEX, Some(USER_KEY)).await;
+        assert_eq!(refused.status, StatusCode::UNAUTHORIZED);
+
+        let malformed = app
+            .send(
+                test_support::request(Method::POST, paths::ADMIN_REINDEX, Some(ADMIN_KEY))
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from("{\"batch_size\":"))
+                    .unwrap(),
+            )
+            .await;
+        assert_eq!(malformed.status, StatusCode::BAD_REQUEST);
+        let out_of_range = app.admin_post(paths::ADMIN_REINDEX, &json!({ "batch_size": 0 })).await;
+        assert_eq!(out_of_range.status, StatusCode::BAD_REQUEST);
+
+        let started = app.call(Method::POST, paths::ADMIN_REINDEX, Some(ADMIN_KEY)).await;
+        assert_eq!(started.status, StatusCode::ACCEPTED, "{}", started.text);
+        let job = app.finished_job(started.body["data"]["job_id"].as_str().unwrap()).await;
+        assert_eq!(job.state, JobState::Succeeded, "{:?}", job.error);
+        assert_eq!(job.processed, 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn reindex_batches_keep_writes_made_while_embedding() {
+        let app = test_support::app(&[("DOCUMENT_VERSIONS", "3")]).await;
+        let qdrant = &app.state.qdrant_service;
+        let stored = app.post(paths::DOCUMENTS, &json!({ "text": "Rust is fast" })).await;
+        let id = stored.body["data"]["id"].as_u64().unwrap();
+        let read = qdrant.get_document(id, true, None).await.unwrap().unwrap();
+
+        let update = Document { text: "Rust is very fast".into(), ..read.clone() };
+        qdrant.upsert_document(&update, None, None).await.unwrap();
+        let (written, _, _) = super::reindex_batch(app.state.clone(), vec![read], Duration::ZERO, None, None)
+            .await
+            .unwrap();
+        assert_eq!(written, 0);
+        let current = qdrant.get_document(id, false, None).await.unwrap().unwrap();
+        assert_eq!(current.text, "Rust is very fast");
+        assert_eq!(current.version, Some(2));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn reindex_embeds_at_most_embed_concurrency_batches_at_once() {
+        // The demo OpenAI, slowed down so that batches overlap, counting the
+        // embedding requests in flight
+        let (running, peak) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let openai = crate::demo::openai::router().layer(axum::middleware::from_fn({
+            let (running, peak) = (running.clone(), peak.clone());
+            move |request: Request, next: Next| {
+                let (running, peak) = (running.clone(), peak.clone());
+                async move {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    let response = next.run(request).await;
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    response
+                }
+            }
+        }));
+        let app = TestApp::with_openai(test_support::config(&[("EMBED_CONCURRENCY", "2")]), openai).await;
+        for i in 0..12 {
+            app.post(paths::DOCUMENTS, &json!({ "text": format!("document {}", i) })).await;
+        }
+        peak.store(0, Ordering::SeqCst);
+
+        let started = app.admin_post(paths::ADMIN_REINDEX, &json!({ "batch_size": 2 })).await;
+        assert_eq!(started.status, StatusCode::ACCEPTED, "{}", started.text);
+        let job = app.finished_job(started.body["data"]["job_id"].as_str().unwrap()).await;
+        assert_eq!(job.state, JobState::Succeeded, "{:?}", job.error);
+        assert_eq!(job.processed, 12);
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+    }
+
+    /// Decrements the counter it holds when dropped.
+    struct Running(Arc<AtomicUsize>);
+
+    impl Drop for Running {
+        fn drop(&mut self) {
+            self.0.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn abandoned_requests_cancel_their_upstream_calls() {
+        // An OpenAI that takes a minute to answer, counting the calls made and still open
+        let (started, running) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let openai = crate::demo::openai::router().layer(axum::middleware::from_fn({
+            let (started, running) = (started.clone(), running.clone());
+            move |request: Request, next: Next| {
+                let (started, running) = (started.clone(), running.clone());
+                async move {
+                    started.fetch_add(1, Ordering::SeqCst);
+                    running.fetch_add(1, Ordering::SeqCst);
+                    let _running = Running(running);
+                    tokio::time::sleep(Duration::from_secs(60)).await;
+                    next.run(request).await
+                }
+            }
+        }));
+        let app = TestApp::with_openai(test_support::config(&[]), openai).await;
+
+        // The client gives up, which drops the request future like a disconnect does
+        let query = json!({ "query": "Rust" });
+        let search = app.post(paths::SEARCH, &query);
+        assert!(tokio::time::timeout(Duration::from_millis(300), search).await.is_err());
+        assert_eq!(started.load(Ordering::SeqCst), 1);
+
+        for _ in 0..200 {
+            if running.load(Ordering::SeqCst) == 0 {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("the embedding call outlived the request");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn embeddings_are_the_same_in_either_format() {
+        let floats = test_support::app(&[]).await;
+        let base64 = test_support::app(&[("OPENAI_BASE64_EMBEDDINGS", "true")]).await;
+        let batch = json!({ "texts": ["Rust is fast", "Go is simple"] });
+
+        let expected = floats.post(paths::EMBED, &batch).await;
+        let expected: Vec<Vec<f32>> = serde_json::from_value(expected.body["data"].clone()).unwrap();
+        // Decoding OpenAI's base64 vectors gives the float ones back
+        let decoded = base64.post(paths::EMBED, &batch).await;
+        assert_eq!(serde_json::from_value::<Vec<Vec<f32>>>(decoded.body["data"].clone()).unwrap(), expected);
+
+        let encoded = floats.post(&format!("{}?format=base64", paths::EMBED), &batch).await;
+        assert_eq!(encoded.status, StatusCode::OK, "{}", encoded.text);
+        let encoded = encoded.body["data"].as_array().expect("encoded vectors");
+        for (encoded, expected) in encoded.iter().zip(&expected) {
+            assert_eq!(encoded["dimensions"], expected.len());
+            let vector = crate::vector_math::decode_base64(encoded["embedding"].as_str().unwrap(), Some(expected.len()));
+            assert_eq!(&vector.unwrap(), expected);
+        }
+
+        let single = floats.post(&format!("{}?format=base64", paths::EMBED), &json!({ "text": "Rust is fast" })).await;
+        assert_eq!(single.body["data"]["dimensions"], expected[0].len());
+        let unknown = floats.post(&format!("{}?format=hex", paths::EMBED), &batch).await;
+        assert_eq!(unknown.status, StatusCode::BAD_REQUEST);
+    }
+
+    /// A text of about 12000 tokens, over what the embedding models accept.
+    fn long_text() -> String {
+        (0..12_000).map(|i| format!("w{} ", i % 7)).collect()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn long_documents_are_stored_as_chunks() {
+        let app = test_support::app(&[]).await;
+        let qdrant = &app.state.qdrant_service;
+        let text = long_text();
+        let stored = app.post(paths::DOCUMENTS, &json!({ "text": text, "metadata": { "source": "book" } })).await;
+        assert_eq!(stored.status, StatusCode::OK, "{}", stored.text);
+        let id = stored.body["data"]["id"].as_u64().unwrap();
+        let chunk_ids: Vec<u64> = stored.body["data"]["chunk_ids"]
+            .as_array()
+            .expect("chunk ids")
+            .iter()
+            .map(|id| id.as_u64().unwrap())
+            .collect();
+        assert!(chunk_ids.len() > 1);
+        assert_eq!(chunk_ids[0], id);
+
+        let mut joined = String::new();
+        for (chunk, chunk_id) in chunk_ids.iter().enumerate() {
+            let document = qdrant.get_document(*chunk_id, false, None).await.unwrap().expect("stored chunk");
+            assert_eq!(document.metadata["parent_id"], id);
+            assert_eq!(document.metadata["chunk"], chunk);
+            assert_eq!(document.metadata["source"], "book");
+            joined.push_str(&document.text);
+        }
+        assert_eq!(joined, text);
+
+        let short = app.post(paths::DOCUMENTS, &json!({ "text": "Rust is fast" })).await;
+        assert!(short.body["data"].get("chunk_ids").is_none());
+        let tagged = app.post(paths::DOCUMENTS, &json!({ "text": text, "metadata": "book" })).await;
+        assert_eq!(tagged.status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn imports_store_long_records_as_chunks() {
+        let app = test_support::app(&[]).await;
+        let lines = [
+            json!({ "id": 1, "text": long_text() }),
+            json!({ "id": 2, "text": "Rust is fast" }),
+            json!({ "id": 3, "text": long_text(), "metadata": "book" }),
+        ];
+        let body: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+        let imported = app
+            .send(
+                test_support::request(Method::POST, paths::IMPORT, Some(USER_KEY))
+                    .header(header::CONTENT_TYPE, "application/x-ndjson")
+                    .body(Body::from(body))
+                    .unwrap(),
+            )
+            .await;
+        assert_eq!(imported.status, StatusCode::OK, "{}", imported.text);
+        assert_eq!(imported.body["data"]["imported"], 2);
+        assert_eq!(imported.body["data"]["skipped"], 1);
+        assert_eq!(imported.body["data"]["errors"][0]["line"], 3);
+
+        let qdrant = &app.state.qdrant_service;
+        let second = qdrant.get_document(Document::chunk_id(1, 1), false, None).await.unwrap().expect("second chunk");
+        assert_eq!(second.metadata["parent_id"], 1);
+        assert!(qdrant.get_document(2, false, None).await.unwrap().is_some());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn imports_are_stored_window_by_window_while_the_body_streams() {
+        const LINES: usize = 5000;
+        // Every 997th line is not JSON
+        let invalid = |line: usize| line.is_multiple_of(997);
+
+        let embeddings = Arc::new(AtomicUsize::new(0));
+        let openai = crate::demo::openai::router().layer(axum::middleware::from_fn({
+            let embeddings = embeddings.clone();
+            move |request: Request, next: Next| {
+                let embeddings = embeddings.clone();
+                async move {
+                    let response = next.run(request).await;
+                    embeddings.fetch_add(1, Ordering::SeqCst);
+                    response
+                }
+            }
+        }));
+        let config = test_support::config(&[("EMBEDDING_DIMENSIONS", "8")]);
+        let app = TestApp::with_openai(config, openai).await;
+
+        // A body sent one line at a time. Before each line goes out, every
+        // full window of the lines before it must already be embedded, so
+        // no more than a window of records is ever held at once.
+        let lines = futures::stream::iter(1..=LINES).map({
+            let embeddings = embeddings.clone();
+            move |line| {
+                let valid = (1..line).filter(|&line| !invalid(line)).count();
+                let flushed = embeddings.load(Ordering::SeqCst);
+                assert!(
+                    flushed >= valid / super::IMPORT_WINDOW,
+                    "{} records read but only {} windows embedded at line {}",
+                    valid,
+                    flushed,
+                    line
+                );
+                let record = if invalid(line) {
+                    "not json\n".to_string()
+                } else {
+                    format!("{}\n", json!({ "id": line, "text": format!("record {}", line) }))
+                };
+                Ok::<_, std::convert::Infallible>(record)
+            }
+        });
+        let imported = app
+            .send(
+                test_support::request(Method::POST, paths::IMPORT, Some(USER_KEY))
+                    .header(header::CONTENT_TYPE, "application/x-ndjson")
+                    .body(Body::from_stream(lines))
+                    .unwrap(),
+            )
+            .await;
+        assert_eq!(imported.status, StatusCode::OK, "{}", imported.text);
+        let skipped: Vec<usize> = (1..=LINES).filter(|&line| invalid(line)).collect();
+        assert_eq!(imported.body["data"]["imported"], LINES - skipped.len());
+        assert_eq!(imported.body["data"]["skipped"], skipped.len());
+        let errors: Vec<u64> = imported.body["data"]["errors"]
+            .as_array()
+            .expect("errors")
+            .iter()
+            .map(|error| error["line"].as_u64().unwrap())
+            .collect();
+        assert_eq!(errors, skipped.iter().map(|&line| line as u64).collect::<Vec<_>>());
+        let count = app.state.qdrant_service.count_documents(false, None).await.unwrap();
+        assert_eq!(count, (LINES - skipped.len()) as u64);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn chat_rejects_blank_messages_and_conversation_ids() {
+        let app = test_support::app(&[]).await;
+
+        let blank = app.post(paths::CHAT, &json!({ "message": " \n" })).await;
+        assert_eq!(blank.status, StatusCode::BAD_REQUEST);
+        assert!(blank.text.contains("Message cannot be empty"), "{}", blank.text);
+
+        let blank_id = app.post(paths::CHAT, &json!({ "message": "hello", "conversation_id": " " })).await;
+        assert_eq!(blank_id.status, StatusCode::BAD_REQUEST);
+        assert!(blank_id.text.contains("Conversation id cannot be empty"), "{}", blank_id.text);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn models_are_compared_on_their_rankings() {
+        let app = test_support::app(&[]).await;
+        let compare = json!({
+            "text": "How do I reset my password?",
+            "candidates": ["Shipping rates", "How do I reset my password?", "Account settings"]
+        });
+
+        let compared = app.post(paths::COMPARE_MODELS, &compare).await;
+        assert_eq!(compared.status, StatusCode::OK, "{}", compared.text);
+        let models = compared.body["data"]["models"].as_array().expect("models");
+        assert_eq!(models.len(), 2);
+        let dimensions: Vec<u64> = models.iter().map(|model| model["dimension"].as_u64().unwrap()).collect();
+        assert_eq!(dimensions, [1536, 3072]);
+        for model in models {
+            let ranking = model["ranking"].as_array().expect("ranking");
+            assert_eq!(ranking.len(), 3);
+            // The candidate equal to the text comes first in every model
+            assert_eq!(ranking[0]["index"], 1);
+            assert!(ranking[0]["similarity"].as_f64().unwrap() > 0.999);
+        }
+        let agreement = &compared.body["data"]["agreements"][0];
+        assert_eq!(agreement["same_top"], true);
+        let correlation = agreement["rank_correlation"].as_f64().unwrap();
+        assert!((-1.0..=1.0).contains(&correlation));
+        // No score is compared across models
+        assert!(!compared.text.contains("cosine_similarity"));
+
+        let single = app.post(paths::COMPARE_MODELS, &json!({ "text": "reset", "candidates": ["one"] })).await;
+        assert_eq!(single.status, StatusCode::BAD_REQUEST);
+        let blank = app.post(paths::COMPARE_MODELS, &json!({ "text": "reset", "candidates": ["one", " "] })).await;
+        assert!(blank.text.contains("Candidate 1 cannot be empty"), "{}", blank.text);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn a_text_alone_is_compared_on_dimensions_and_norms() {
+        let app = test_support::app(&[]).await;
+        let compared = app.post(paths::COMPARE_MODELS, &json!({ "text": "How do I reset my password?" })).await;
+        assert_eq!(compared.status, StatusCode::OK, "{}", compared.text);
+
+        let models = compared.body["data"]["models"].as_array().expect("models");
+        let dimensions: Vec<u64> = models.iter().map(|model| model["dimension"].as_u64().unwrap()).collect();
+        assert_eq!(dimensions, [1536, 3072]);
+        for model in models {
+            assert!(model["norm"].as_f64().unwrap() > 0.0, "{}", model);
+            assert!(model.get("ranking").is_none(), "{}", model);
+        }
+        assert!(compared.body["data"].get("agreements").is_none(), "{}", compared.text);
+
+        let empty = app.post(paths::COMPARE_MODELS, &json!({ "text": "reset", "candidates": [] })).await;
+        assert_eq!(empty.status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn a_few_long_turns_are_summarized() {
+        let app = test_support::app(&[("HISTORY_SUMMARY_TOKENS", "2000")]).await;
+        let message = json!({ "message": "word ".repeat(600), "conversation_id": "long-turns" });
+
+        // Two exchanges of about 600 tokens a turn fit, far below the turn threshold
+        for _ in 0..2 {
+            let reply = app.post(paths::CHAT, &message).await;
+            assert_eq!(reply.status, StatusCode::OK, "{}", reply.text);
+            assert!(reply.body["data"]["summary_usage"].is_null());
+        }
+        let reply = app.post(paths::CHAT, &message).await;
+        assert!(reply.body["data"]["summary_usage"]["total_tokens"].as_u64().unwrap() > 0);
+
+        let turns = app.state.conversations.history("long-turns").await.unwrap();
+        assert!(turns[0].content.starts_with(crate::services::conversations::SUMMARY_PREFIX));
+        // The summary, the last reply kept verbatim, and the new exchange
+        assert_eq!(turns.len(), 4);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn chat_answers_are_only_capped_when_configured() {
+        let message = "word ".repeat(1500);
+
+        let app = test_support::app(&[]).await;
+        let uncapped = app.post(paths::CHAT, &json!({ "message": message })).await;
+        assert_eq!(uncapped.status, StatusCode::OK, "{}", uncapped.text);
+        assert_eq!(uncapped.body["data"]["finish_reason"], "stop");
+        let lowered = app.post(paths::CHAT, &json!({ "message": message, "max_tokens": 5 })).await;
+        assert_eq!(lowered.body["data"]["finish_reason"], "length");
+
+        let app = test_support::app(&[("MAX_COMPLETION_TOKENS", "10")]).await;
+        let capped = app.post(paths::CHAT, &json!({ "message": message })).await;
+        assert_eq!(capped.body["data"]["finish_reason"], "length");
+        let above = app.post(paths::CHAT, &json!({ "message": message, "max_tokens": 11 })).await;
+        assert_eq!(above.status, StatusCode::BAD_REQUEST);
+        assert!(above.text.contains("max_tokens must not exceed 10"), "{}", above.text);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn search_and_validate_agree_on_filters() {
+        let app = test_support::app(&[]).await;
+        let filters = [
+            json!({ "must": [{ "key": "metadata.lang", "match": "en" }] }),
+            json!({ "should": [{ "key": "metadata.year", "match": [2023, 2024] }] }),
+            json!({}),
+            json!({ "must": [{ "key": "metadata.lang", "mach": "en" }] }),
+            json!({ "must_not": [{ "key": "metadata.year", "match": 1.5 }] }),
+            json!({ "muts": [] }),
+            json!(["not", "an", "object"]),
+        ];
+        for filter in filters {
+            let validated = app.post(paths::VALIDATE_FILTER, &json!({ "filter": filter })).await;
+            let searched = app.post(paths::SEARCH, &json!({ "query": "rust", "filter": filter })).await;
+            assert_eq!(validated.status, searched.status, "{} / {}", validated.text, searched.text);
+            assert_eq!(validated.body["details"], searched.body["details"], "{}", filter);
+            if validated.status == StatusCode::UNPROCESSABLE_ENTITY {
+                assert_eq!(searched.body["error_code"], "invalid_filter");
+            } else {
+                assert_eq!(validated.status, StatusCode::OK, "{}", validated.text);
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn search_filters_limit_the_results() {
+        let app = test_support::app(&[]).await;
+        for (text, lang) in [("Rust is fast", "en"), ("Rust ist schnell", "de")] {
+            let stored = app.post(paths::DOCUMENTS, &json!({ "text": text, "metadata": { "lang": lang } })).await;
+            assert_eq!(stored.status, StatusCode::OK, "{}", stored.text);
+        }
+        let german = json!({ "must": [{ "key": "metadata.lang", "match": "de" }] });
+
+        for mode in ["dense", "hybrid"] {
+            let search = json!({ "query": "Rust", "mode": mode, "filter": german });
+            let found = app.post(paths::SEARCH, &search).await;
+            assert_eq!(found.status, StatusCode::OK, "{}", found.text);
+            let hits = found.body["data"]["hits"].as_array().unwrap();
+            assert_eq!(hits.len(), 1, "{}", found.text);
+            assert_eq!(hits[0]["text"], "Rust ist schnell");
+        }
+
+        let grouped = json!({ "query": "Rust", "group_by": "metadata.lang", "filter": german });
+        let groups = app.post(paths::SEARCH, &grouped).await;
+        assert_eq!(groups.status, StatusCode::OK, "{}", groups.text);
+        assert_eq!(groups.body["data"]["groups"].as_array().unwrap().len(), 1, "{}", groups.text);
+    }
+
+    /// Ids of the documents an `/api/search` with `body` returned, groups flattened.
+    async fn searched_ids(app: &TestApp, body: serde_json::Value) -> Vec<u64> {
+        let found = app.post(paths::SEARCH, &body).await;
+        assert_eq!(found.status, StatusCode::OK, "{}", found.text);
+        let data = &found.body["data"];
+        let hits: Vec<&serde_json::Value> = match data["groups"].as_array() {
+            Some(groups) => groups.iter().flat_map(|group| group["hits"].as_array().unwrap()).collect(),
+            None => data["hits"].as_array().unwrap().iter().collect(),
+        };
+        hits.iter().map(|hit| hit["id"].as_u64().unwrap()).collect()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn soft_deleted_documents_never_appear_in_results() {
+        let app = test_support::app(&[]).await;
+        let mut ids = Vec::new();
+        for text in ["Rust is fast", "Rust is safe"] {
+            let stored = app.post(paths::DOCUMENTS, &json!({ "text": text, "metadata": { "lang": "en" } })).await;
+            ids.push(stored.body["data"]["id"].as_u64().unwrap());
+        }
+        let (trashed, kept) = (ids[0], ids[1]);
+        let deleted = app.call(Method::DELETE, &format!("/api/documents/{}?soft=true", trashed), Some(USER_KEY)).await;
+        assert_eq!(deleted.status, StatusCode::OK, "{}", deleted.text);
+
+        let filter = json!({ "must": [{ "key": "metadata.lang", "match": "en" }] });
+        let searches = [
+            json!({ "query": "Rust is fast" }),
+            json!({ "query": "Rust is fast", "mode": "hybrid" }),
+            json!({ "query": "Rust is fast", "group_by": "metadata.lang", "group_size": 5 }),
+            json!({ "query": "Rust is fast", "filter": filter }),
+        ];
+        for search in &searches {
+            assert_eq!(searched_ids(&app, search.clone()).await, [kept], "{}", search);
+        }
+        let asked = app.post(paths::ASK, &json!({ "question": "Rust is fast" })).await;
+        let sources: Vec<u64> =
+            asked.body["data"]["sources"].as_array().unwrap().iter().map(|s| s["id"].as_u64().unwrap()).collect();
+        assert_eq!(sources, [kept], "{}", asked.text);
+        let listed = app.get(paths::DOCUMENTS).await;
+        assert_eq!(listed.body["data"]["documents"].as_array().unwrap().len(), 1, "{}", listed.text);
+        assert_eq!(app.get(&format!("/api/documents/{}", trashed)).await.status, StatusCode::NOT_FOUND);
+
+        let restored = app.call(Method::POST, &format!("/api/documents/{}/restore", trashed), Some(USER_KEY)).await;
+        assert_eq!(restored.status, StatusCode::OK, "{}", restored.text);
+        assert!(searched_ids(&app, searches[0].clone()).await.contains(&trashed));
+
+        app.call(Method::DELETE, &format!("/api/documents/{}?soft=true", trashed), Some(USER_KEY)).await;
+        let purged = app.call(Method::POST, &format!("{}?older_than_secs=0", paths::ADMIN_PURGE_TRASH), Some(ADMIN_KEY)).await;
+        assert_eq!(purged.body["data"]["purged"], 1, "{}", purged.text);
+        let gone = app.call(Method::POST, &format!("/api/documents/{}/restore", trashed), Some(USER_KEY)).await;
+        assert_eq!(gone.status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn qdrant_failures_map_to_statuses() {
+        use tonic::Code;
+
+        let app = test_support::app(&[]).await;
+        let failed = |code, message: &str| {
+            let error = qdrant_client::QdrantError::ResponseError { status: tonic::Status::new(code, message) };
+            super::qdrant_error(&app.state, &anyhow::Error::from(error), "Failed").status_code()
+        };
+        assert_eq!(failed(Code::NotFound, "Collection `x` doesn't exist!"), StatusCode::NOT_FOUND);
+        assert_eq!(failed(Code::NotFound, "Shard key 7 not found"), StatusCode::NOT_FOUND);
+        assert_eq!(failed(Code::InvalidArgument, "Vector dimension error: expected dim: 4, got 3"), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(failed(Code::InvalidArgument, "Bad request"), StatusCode::BAD_REQUEST);
+        assert_eq!(failed(Code::ResourceExhausted, "message too large"), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(failed(Code::AlreadyExists, "exists"), StatusCode::CONFLICT);
+        assert_eq!(failed(Code::Internal, "boom"), StatusCode::INTERNAL_SERVER_ERROR);
+        let conflict = anyhow::Error::from(crate::services::qdrant::WriteConflict { ids: vec![1], attempts: 5 });
+        assert_eq!(super::qdrant_error(&app.state, &conflict, "Failed").status_code(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn an_unhealthy_server_reports_how_far_start_up_got() {
+        let app = test_support::app(&[]).await;
+        app.state.qdrant_service.mark_connecting();
+
+        let health = app.get(paths::HEALTH).await;
+        assert_eq!(health.status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(health.body["code"], "unavailable");
+        assert_eq!(health.body["data"], json!({ "qdrant": "connecting", "ready": true, "warmup": null }));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn similarity_texts_must_not_be_blank() {
+        let app = test_support::app(&[]).await;
+        let compared = app.post(paths::SIMILARITY, &json!({ "text_a": "Rust", "text_b": "Go" })).await;
+        assert_eq!(compared.status, StatusCode::OK, "{}", compared.text);
+
+        let blank = app.post(paths::SIMILARITY, &json!({ "text_a": "Rust", "text_b": "  " })).await;
+        assert_eq!(blank.status, StatusCode::BAD_REQUEST);
+        assert!(blank.text.contains("Both texts must be non-empty"), "{}", blank.text);
+        let pairs = json!({ "pairs": [{ "text_a": "Rust", "text_b": "Go" }, { "text_a": "", "text_b": "Go" }] });
+        let blank_pair = app.post(paths::SIMILARITY, &pairs).await;
+        assert_eq!(blank_pair.status, StatusCode::BAD_REQUEST);
+        assert!(blank_pair.text.contains("pairs[1].text_a"), "{}", blank_pair.text);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn tokenize_rejects_blank_text() {
+        let app = test_support::app(&[]).await;
+        let counted = app.post(paths::TOKENIZE, &json!({ "text": "How many tokens is this?" })).await;
+        assert_eq!(counted.status, StatusCode::OK, "{}", counted.text);
+        assert!(counted.body["data"]["tokens"].as_u64().unwrap() > 0);
+
+        for text in ["", " \n"] {
+            let blank = app.post(paths::TOKENIZE, &json!({ "text": text })).await;
+            assert_eq!(blank.status, StatusCode::BAD_REQUEST);
+            assert!(blank.text.contains("Text cannot be empty"), "{}", blank.text);
+        }
+    }
+}
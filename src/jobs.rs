@@ -0,0 +1,348 @@
+use futures::FutureExt;
+use serde::Serialize;
+use std::any::Any;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::ops::AddAssign;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+
+use crate::keys::{format_rfc3339, unix_now};
+use crate::metrics::RequestContext;
+
+/// Identifier of a background job (a random UUID).
+pub type JobId = String;
+
+/// Finished jobs kept for polling; older ones are forgotten.
+const MAX_FINISHED_JOBS: usize = 100;
+
+/// Kind of work a background job does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobKind {
+    /// Re-embeds every stored document (`POST /api/admin/reindex`)
+    Reindex,
+    /// Re-embeds the documents flagged as stale (`STALE_REEMBED_INTERVAL_SECS`)
+    Reembed,
+}
+
+/// Where a background job is in its lifecycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    /// Still working
+    Running,
+    /// Finished without errors
+    Succeeded,
+    /// Stopped at the error in `JobStatus::error`
+    Failed,
+}
+
+/// Time a job spent in each stage, summed over its batches.
+///
+/// Batches run concurrently, so the stages can add up to more than the
+/// job's `elapsed_ms`.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct StageTimings {
+    /// Reading documents from Qdrant
+    pub scroll_ms: u64,
+    /// Waiting for OpenAI embeddings, including time queued for a worker
+    pub embed_ms: u64,
+    /// Writing the new vectors to Qdrant
+    pub upsert_ms: u64,
+}
+
+impl StageTimings {
+    /// Builds the timings of one batch.
+    pub fn new(scroll: Duration, embed: Duration, upsert: Duration) -> Self {
+        Self {
+            scroll_ms: scroll.as_millis() as u64,
+            embed_ms: embed.as_millis() as u64,
+            upsert_ms: upsert.as_millis() as u64,
+        }
+    }
+}
+
+impl AddAssign for StageTimings {
+    fn add_assign(&mut self, other: Self) {
+        self.scroll_ms += other.scroll_ms;
+        self.embed_ms += other.embed_ms;
+        self.upsert_ms += other.upsert_ms;
+    }
+}
+
+/// Progress of a background job, as returned by `GET /api/jobs/:id`.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobStatus {
+    pub id: JobId,
+    pub kind: JobKind,
+    pub state: JobState,
+    /// Documents processed so far
+    pub processed: u64,
+    /// Documents in the collection when the job started; approximate
+    pub total: Option<u64>,
+    /// OpenAI tokens used so far
+    pub tokens: u64,
+    /// Start time as an RFC 3339 UTC timestamp
+    pub started_at: String,
+    /// End time as an RFC 3339 UTC timestamp, once the job has stopped
+    pub finished_at: Option<String>,
+    /// Wall time from start until now, or until the job stopped
+    pub elapsed_ms: u64,
+    /// Time spent per stage
+    pub timings: StageTimings,
+    /// Why the job failed
+    pub error: Option<String>,
+    #[serde(skip)]
+    started: Instant,
+}
+
+/// In-memory registry of background jobs.
+///
+/// Jobs don't survive a restart; a job interrupted by one simply
+/// disappears and can be started again.
+#[derive(Debug, Default)]
+pub struct JobRegistry {
+    inner: Mutex<Jobs>,
+}
+
+#[derive(Debug, Default)]
+struct Jobs {
+    by_id: HashMap<JobId, JobStatus>,
+    /// Ids of finished jobs, oldest first
+    finished: VecDeque<JobId>,
+}
+
+/// Lets a job's task report its progress.
+#[derive(Debug, Clone)]
+pub struct JobHandle {
+    id: JobId,
+    registry: Arc<JobRegistry>,
+}
+
+impl JobHandle {
+    /// The job's id.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Adds a finished batch's items, tokens and stage timings to the job.
+    pub fn progress(&self, processed: u64, tokens: u64, timings: StageTimings) {
+        self.registry.progress(&self.id, processed, tokens, timings);
+    }
+}
+
+impl JobRegistry {
+    /// Registers a new running job.
+    ///
+    /// Only one job of each kind runs at a time.
+    ///
+    /// # Arguments
+    /// * `kind` - What the job does
+    /// * `total` - Number of items the job expects to process, if known
+    ///
+    /// # Returns
+    /// * `Ok(JobId)` - The new job's id
+    /// * `Err(JobId)` - The id of the job of this kind that is still running
+    fn start(&self, kind: JobKind, total: Option<u64>) -> Result<JobId, JobId> {
+        let mut jobs = self.lock();
+        if let Some(running) = jobs
+            .by_id
+            .values()
+            .find(|job| job.kind == kind && job.state == JobState::Running)
+        {
+            return Err(running.id.clone());
+        }
+        let id = uuid::Uuid::new_v4().to_string();
+        jobs.by_id.insert(
+            id.clone(),
+            JobStatus {
+                id: id.clone(),
+                kind,
+                state: JobState::Running,
+                processed: 0,
+                total,
+                tokens: 0,
+                started_at: format_rfc3339(unix_now()),
+                finished_at: None,
+                elapsed_ms: 0,
+                timings: StageTimings::default(),
+                error: None,
+                started: Instant::now(),
+            },
+        );
+        Ok(id)
+    }
+
+    /// Registers a job and runs it on a tokio task, marking it finished when
+    /// `work` completes. A job that panics is marked failed with the panic
+    /// message, so it doesn't stay running and block the next job of its kind.
+    ///
+    /// The task runs with the calling request's metrics context, so the
+    /// OpenAI tokens it uses count against the key that started it.
+    ///
+    /// # Arguments
+    /// * `kind` - What the job does; only one job of each kind runs at a time
+    /// * `total` - Number of items the job expects to process, if known
+    /// * `work` - Builds the job from its handle; resolves to the number of items processed
+    ///
+    /// # Returns
+    /// * `Ok(JobId)` - The new job's id
+    /// * `Err(JobId)` - The id of the job of this kind that is still running
+    pub fn spawn<F, Fut>(self: &Arc<Self>, kind: JobKind, total: Option<u64>, work: F) -> Result<JobId, JobId>
+    where
+        F: FnOnce(JobHandle) -> Fut,
+        Fut: Future<Output = anyhow::Result<u64>> + Send + 'static,
+    {
+        let id = self.start(kind, total)?;
+        let handle = JobHandle {
+            id: id.clone(),
+            registry: self.clone(),
+        };
+        let job = work(handle.clone());
+        let context = RequestContext::capture();
+        tokio::spawn(context.scope(async move {
+            // The registry's state is only touched through its lock, which
+            // tolerates poisoning, so unwinding out of the job leaves it usable
+            let outcome = match AssertUnwindSafe(job).catch_unwind().await {
+                Ok(outcome) => outcome,
+                Err(panic) => Err(anyhow::anyhow!("job panicked: {}", panic_message(&*panic))),
+            };
+            match outcome {
+                Ok(processed) => {
+                    info!("{:?} job {} finished after {} items", kind, handle.id, processed);
+                    handle.registry.finish(&handle.id, None);
+                }
+                Err(e) => {
+                    error!("{:?} job {} failed: {:#}", kind, handle.id, e);
+                    handle.registry.finish(&handle.id, Some(format!("{:#}", e)));
+                }
+            }
+        }));
+        Ok(id)
+    }
+
+    /// Adds a finished batch's items, tokens and stage timings to a running job.
+    fn progress(&self, id: &str, processed: u64, tokens: u64, timings: StageTimings) {
+        if let Some(job) = self.lock().by_id.get_mut(id) {
+            job.processed += processed;
+            job.tokens += tokens;
+            job.timings += timings;
+        }
+    }
+
+    /// Marks a job as finished, failed if `error` is set.
+    fn finish(&self, id: &str, error: Option<String>) {
+        let mut jobs = self.lock();
+        let Some(job) = jobs.by_id.get_mut(id) else {
+            return;
+        };
+        job.state = if error.is_some() { JobState::Failed } else { JobState::Succeeded };
+        job.finished_at = Some(format_rfc3339(unix_now()));
+        job.elapsed_ms = job.started.elapsed().as_millis() as u64;
+        job.error = error;
+
+        jobs.finished.push_back(id.to_string());
+        while jobs.finished.len() > MAX_FINISHED_JOBS {
+            if let Some(oldest) = jobs.finished.pop_front() {
+                jobs.by_id.remove(&oldest);
+            }
+        }
+    }
+
+    /// Returns the status of a job, if it is known.
+    pub fn get(&self, id: &str) -> Option<JobStatus> {
+        self.lock().by_id.get(id).cloned().map(Self::refreshed)
+    }
+
+    /// Returns every known job, most recently started first.
+    pub fn list(&self) -> Vec<JobStatus> {
+        let mut jobs: Vec<JobStatus> = self.lock().by_id.values().cloned().map(Self::refreshed).collect();
+        jobs.sort_by_key(|job| std::cmp::Reverse(job.started));
+        jobs
+    }
+
+    /// Brings a running job's elapsed time up to date.
+    fn refreshed(mut job: JobStatus) -> JobStatus {
+        if job.state == JobState::Running {
+            job.elapsed_ms = job.started.elapsed().as_millis() as u64;
+        }
+        job
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Jobs> {
+        // A panic while holding the lock leaves the map consistent
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// The message a panic was raised with, for the payloads `panic!` produces.
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Waits for a job to stop running.
+    async fn finished(registry: &JobRegistry, id: &str) -> JobStatus {
+        for _ in 0..500 {
+            let job = registry.get(id).expect("job is known");
+            if job.state != JobState::Running {
+                return job;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("job {} still running", id);
+    }
+
+    #[tokio::test]
+    async fn panicking_jobs_are_marked_failed() {
+        let registry = Arc::new(JobRegistry::default());
+        let id = registry
+            .spawn(JobKind::Reindex, None, |_| async {
+                if true {
+                    panic!("batch {} has no text", 3);
+                }
+                Ok(0)
+            })
+            .unwrap();
+
+        let job = finished(&registry, &id).await;
+        assert_eq!(job.state, JobState::Failed);
+        assert_eq!(job.error.as_deref(), Some("job panicked: batch 3 has no text"));
+        assert!(job.finished_at.is_some());
+
+        // The failed job no longer blocks the next one of its kind
+        let next = registry.spawn(JobKind::Reindex, None, |_| async { Ok(2) }).unwrap();
+        assert_eq!(finished(&registry, &next).await.state, JobState::Succeeded);
+    }
+
+    #[tokio::test]
+    async fn one_job_of_each_kind_runs_at_a_time() {
+        let registry = Arc::new(JobRegistry::default());
+        let (release, wait) = tokio::sync::oneshot::channel::<()>();
+        let running = registry
+            .spawn(JobKind::Reindex, Some(10), |handle| async move {
+                handle.progress(4, 100, StageTimings::default());
+                wait.await.ok();
+                Ok(4)
+            })
+            .unwrap();
+
+        assert_eq!(registry.spawn(JobKind::Reindex, None, |_| async { Ok(0) }), Err(running.clone()));
+        let other = registry.spawn(JobKind::Reembed, None, |_| async { Ok(0) }).unwrap();
+        assert_eq!(finished(&registry, &other).await.state, JobState::Succeeded);
+
+        release.send(()).unwrap();
+        let job = finished(&registry, &running).await;
+        assert_eq!((job.state, job.processed, job.tokens, job.total), (JobState::Succeeded, 4, 100, Some(10)));
+    }
+}
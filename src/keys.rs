@@ -0,0 +1,339 @@
+use anyhow::{anyhow, bail, Result};
+use serde::Serialize;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use subtle::ConstantTimeEq;
+use time::{format_description::well_known::Rfc3339, Date, Month, OffsetDateTime, Time};
+
+/// What a key grants access to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyRole {
+    /// The regular API routes (`API_KEY`)
+    User,
+    /// The `/api/admin` routes (`ADMIN_API_KEY`)
+    Admin,
+}
+
+/// A configured API key with its role and optional expiry.
+#[derive(Debug, Clone)]
+pub struct ApiKey {
+    /// Key material; never logged or returned
+    secret: String,
+    /// Routes the key grants access to
+    pub role: KeyRole,
+    /// Unix time in seconds after which the key is rejected
+    pub expires_at: Option<u64>,
+}
+
+impl ApiKey {
+    /// Short SHA-256 based identifier that is safe to log and display.
+    pub fn fingerprint(&self) -> String {
+        let digest = aws_lc_rs::digest::digest(&aws_lc_rs::digest::SHA256, self.secret.as_bytes());
+        let hex: String = digest.as_ref()[..8].iter().map(|b| format!("{:02x}", b)).collect();
+        format!("sha256:{}", hex)
+    }
+
+    /// Returns whether the key has expired at `now` (unix seconds).
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| now >= expires_at)
+    }
+}
+
+/// The key a request was authenticated with, stored in the request extensions.
+#[derive(Debug, Clone)]
+pub struct Authenticated {
+    /// See `ApiKey::fingerprint`
+    pub fingerprint: String,
+    /// Role the key was accepted for
+    pub role: KeyRole,
+}
+
+/// Outcome of checking a presented key.
+#[derive(Debug)]
+pub enum KeyCheck<'a> {
+    /// The key is configured for the role and still valid
+    Valid(&'a ApiKey),
+    /// The key is configured for the role but has expired
+    Expired(&'a ApiKey),
+    /// No key with this value is configured for the role
+    Unknown,
+}
+
+/// Fingerprint, role and expiry of a key, as listed by the admin API.
+#[derive(Debug, Serialize)]
+pub struct KeyInfo {
+    /// See `ApiKey::fingerprint`
+    pub fingerprint: String,
+    /// Routes the key grants access to
+    pub role: KeyRole,
+    /// Expiry as an RFC 3339 UTC timestamp, if any
+    pub expires_at: Option<String>,
+    /// Whether the key has already expired
+    pub expired: bool,
+}
+
+/// Source of the current time that key expiry is checked against.
+pub trait Clock: Send + Sync {
+    /// Current unix time in seconds.
+    fn now(&self) -> u64;
+}
+
+/// The system's wall clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        unix_now()
+    }
+}
+
+/// All configured API keys.
+///
+/// Several keys per role may be valid at once, so a new key can be rolled
+/// out to clients before the old one expires.
+#[derive(Clone)]
+pub struct KeySet {
+    keys: Vec<ApiKey>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for KeySet {
+    fn default() -> Self {
+        Self {
+            keys: Vec::new(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+}
+
+impl fmt::Debug for KeySet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeySet").field("keys", &self.keys).finish_non_exhaustive()
+    }
+}
+
+impl KeySet {
+    /// Parses the user and admin key lists.
+    ///
+    /// Each list is comma-separated; every entry is a key optionally
+    /// followed by `@` and an RFC 3339 expiry, e.g.
+    /// `old-key@2025-12-31T00:00:00Z,new-key`. Keys therefore cannot
+    /// contain `,` or `@`.
+    pub fn parse(user: &str, admin: Option<&str>) -> Result<Self> {
+        let mut keys = parse_list(user, KeyRole::User)?;
+        if keys.is_empty() {
+            bail!("API_KEY must contain at least one key");
+        }
+        if let Some(admin) = admin {
+            keys.extend(parse_list(admin, KeyRole::Admin)?);
+        }
+        Ok(Self { keys, ..Self::default() })
+    }
+
+    /// Checks expiry against `clock` instead of the system clock.
+    #[cfg(test)]
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Current unix time in seconds, as seen by the set's clock.
+    pub fn now(&self) -> u64 {
+        self.clock.now()
+    }
+
+    /// Looks up a presented key for a role.
+    ///
+    /// Every key is compared in constant time, so the response time says
+    /// nothing about how much of a key was guessed right.
+    pub fn check(&self, provided: &str, role: KeyRole) -> KeyCheck<'_> {
+        let found = self.keys.iter().fold(None, |found, key| {
+            let matches = key.secret.as_bytes().ct_eq(provided.as_bytes());
+            if bool::from(matches) && key.role == role {
+                Some(key)
+            } else {
+                found
+            }
+        });
+        match found {
+            Some(key) if key.is_expired(self.now()) => KeyCheck::Expired(key),
+            Some(key) => KeyCheck::Valid(key),
+            None => KeyCheck::Unknown,
+        }
+    }
+
+    /// Returns whether any key is configured for `role`.
+    pub fn has_role(&self, role: KeyRole) -> bool {
+        self.keys.iter().any(|k| k.role == role)
+    }
+
+    /// Returns the keys that expire within `window_secs` of `now`,
+    /// including keys that have already expired.
+    pub fn expiring(&self, now: u64, window_secs: u64) -> impl Iterator<Item = &ApiKey> {
+        let deadline = now.saturating_add(window_secs);
+        self.keys
+            .iter()
+            .filter(move |k| k.expires_at.is_some_and(|expires_at| expires_at <= deadline))
+    }
+
+    /// Describes every key without its key material.
+    pub fn describe(&self) -> Vec<KeyInfo> {
+        let now = self.now();
+        self.keys
+            .iter()
+            .map(|key| KeyInfo {
+                fingerprint: key.fingerprint(),
+                role: key.role,
+                expires_at: key.expires_at.map(format_rfc3339),
+                expired: key.is_expired(now),
+            })
+            .collect()
+    }
+}
+
+/// Current unix time in seconds.
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Logs a warning for every key that expires within `window_days`.
+pub fn warn_expiring(keys: &KeySet, window_days: u64) {
+    let now = keys.now();
+    for key in keys.expiring(now, window_days * 24 * 60 * 60) {
+        let expires_at = key.expires_at.map(format_rfc3339).unwrap_or_default();
+        if key.is_expired(now) {
+            tracing::warn!(fingerprint = %key.fingerprint(), role = ?key.role, %expires_at, "API key has expired");
+        } else {
+            tracing::warn!(fingerprint = %key.fingerprint(), role = ?key.role, %expires_at, "API key expires soon");
+        }
+    }
+}
+
+fn parse_list(list: &str, role: KeyRole) -> Result<Vec<ApiKey>> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (secret, expires_at) = match entry.split_once('@') {
+                Some((secret, expiry)) => (secret, Some(parse_rfc3339(expiry)?)),
+                None => (entry, None),
+            };
+            if secret.is_empty() {
+                bail!("empty key before expiry '{}'", entry.trim_start_matches('@'));
+            }
+            Ok(ApiKey {
+                secret: secret.to_string(),
+                role,
+                expires_at,
+            })
+        })
+        .collect()
+}
+
+/// Parses an RFC 3339 timestamp into unix seconds.
+fn parse_rfc3339(s: &str) -> Result<u64> {
+    let invalid = || anyhow!("invalid expiry '{}', expected e.g. 2025-12-31T00:00:00Z", s);
+    let timestamp = OffsetDateTime::parse(s, &Rfc3339).map_err(|_| invalid())?;
+    u64::try_from(timestamp.unix_timestamp()).map_err(|_| invalid())
+}
+
+/// Formats unix seconds as an RFC 3339 UTC timestamp.
+pub fn format_rfc3339(secs: u64) -> String {
+    utc(secs)
+        .format(&Rfc3339)
+        .expect("every unix time fits RFC 3339")
+}
+
+/// Calendar year and month (UTC) of unix seconds.
+pub fn year_month(secs: u64) -> (i64, i64) {
+    let date = utc(secs);
+    (i64::from(date.year()), i64::from(u8::from(date.month())))
+}
+
+/// UTC date and time of unix seconds, clamped to the years RFC 3339 can write.
+fn utc(secs: u64) -> OffsetDateTime {
+    let latest = OffsetDateTime::new_utc(
+        Date::from_calendar_date(9999, Month::December, 31).expect("valid date"),
+        Time::from_hms(23, 59, 59).expect("valid time"),
+    );
+    i64::try_from(secs)
+        .ok()
+        .and_then(|secs| OffsetDateTime::from_unix_timestamp(secs).ok())
+        .map_or(latest, |date| date.min(latest))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    use super::*;
+    use crate::paths;
+    use crate::test_support::{self, TestApp, ADMIN_KEY};
+
+    /// 2025-12-31T00:00:00Z
+    const NEW_YEARS_EVE: u64 = 1_767_139_200;
+
+    /// A clock that only moves when told to.
+    struct ManualClock(AtomicU64);
+
+    impl Clock for ManualClock {
+        fn now(&self) -> u64 {
+            self.0.load(Ordering::SeqCst)
+        }
+    }
+
+    #[test]
+    fn expiries_are_rfc_3339_timestamps() {
+        assert_eq!(parse_rfc3339("2025-12-31T00:00:00Z").unwrap(), NEW_YEARS_EVE);
+        assert_eq!(parse_rfc3339("2025-12-31T02:00:00+02:00").unwrap(), NEW_YEARS_EVE);
+        assert_eq!(parse_rfc3339("2025-12-30T19:00:00.5-05:00").unwrap(), NEW_YEARS_EVE);
+        for invalid in ["2025-02-30T00:00:00Z", "2025-12-31", "2025-12-31T00:00:00", "1969-12-31T23:59:59Z", "soon"] {
+            assert!(parse_rfc3339(invalid).is_err(), "{}", invalid);
+        }
+        assert_eq!(format_rfc3339(NEW_YEARS_EVE), "2025-12-31T00:00:00Z");
+        assert_eq!(format_rfc3339(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_rfc3339(u64::MAX), "9999-12-31T23:59:59Z");
+        assert_eq!(year_month(NEW_YEARS_EVE), (2025, 12));
+    }
+
+    #[test]
+    fn keys_are_matched_by_value_and_role() {
+        let keys = KeySet::parse("old@2025-12-31T00:00:00Z, new", Some("admin")).unwrap();
+        assert!(matches!(keys.check("new", KeyRole::User), KeyCheck::Valid(key) if key.expires_at.is_none()));
+        assert!(matches!(keys.check("new", KeyRole::Admin), KeyCheck::Unknown));
+        assert!(matches!(keys.check("admin", KeyRole::Admin), KeyCheck::Valid(_)));
+        assert!(matches!(keys.check("ne", KeyRole::User), KeyCheck::Unknown));
+        assert!(matches!(keys.check("new-ish", KeyRole::User), KeyCheck::Unknown));
+        assert!(matches!(keys.check("", KeyRole::User), KeyCheck::Unknown));
+        assert!(KeySet::parse("@2025-12-31T00:00:00Z", None).is_err());
+        assert!(KeySet::parse(" , ", None).is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn a_key_stops_working_when_it_expires() {
+        let clock = Arc::new(ManualClock(AtomicU64::new(NEW_YEARS_EVE - 60)));
+        let mut config = test_support::config(&[("API_KEY", "old-key@2025-12-31T00:00:00Z,new-key")]);
+        config.api_keys = config.api_keys.with_clock(clock.clone());
+        let app = TestApp::new(config).await;
+
+        let listed = app.call(Method::GET, paths::ADMIN_KEYS, Some(ADMIN_KEY)).await;
+        let old = &listed.body["data"]["keys"][0];
+        assert_eq!(old["expires_at"], "2025-12-31T00:00:00Z", "{}", listed.text);
+        assert_eq!(old["expired"], false);
+        assert!(!listed.text.contains("old-key"));
+        assert_eq!(app.call(Method::GET, paths::JOBS, Some("old-key")).await.status, StatusCode::OK);
+
+        clock.0.store(NEW_YEARS_EVE, Ordering::SeqCst);
+        assert_eq!(app.call(Method::GET, paths::JOBS, Some("old-key")).await.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(app.call(Method::GET, paths::JOBS, Some("new-key")).await.status, StatusCode::OK);
+        let listed = app.call(Method::GET, paths::ADMIN_KEYS, Some(ADMIN_KEY)).await;
+        assert_eq!(listed.body["data"]["keys"][0]["expired"], true);
+    }
+}
@@ -0,0 +1,459 @@
+use anyhow::{bail, Context, Result};
+use axum::Router;
+use futures::future::{BoxFuture, FutureExt};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::{conn::auto, graceful::GracefulShutdown},
+    service::TowerToHyperService,
+};
+use prometheus::IntGauge;
+use std::fmt;
+use std::future::Future;
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::task::{Context as TaskContext, Poll};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tower::Service;
+
+/// Address the server listens on, configured as `tcp://host:port` or
+/// `unix:///path/to.sock`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddr {
+    /// TCP socket address
+    Tcp(SocketAddr),
+    /// Path of a unix domain socket
+    Unix(PathBuf),
+}
+
+impl Default for ListenAddr {
+    fn default() -> Self {
+        Self::Tcp(SocketAddr::from(([127, 0, 0, 1], 3000)))
+    }
+}
+
+impl FromStr for ListenAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(addr) = s.strip_prefix("tcp://") {
+            addr.parse()
+                .map(Self::Tcp)
+                .map_err(|e| format!("invalid TCP address '{}': {}", addr, e))
+        } else if let Some(path) = s.strip_prefix("unix://") {
+            if path.is_empty() {
+                return Err("unix socket path must not be empty".to_string());
+            }
+            Ok(Self::Unix(path.into()))
+        } else {
+            Err("expected tcp://host:port or unix:///path/to.sock".to_string())
+        }
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "tcp://{}", addr),
+            Self::Unix(path) => write!(f, "unix://{}", path.display()),
+        }
+    }
+}
+
+/// Permission bits applied to a unix socket file, written in octal (e.g. `660`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SocketMode(pub u32);
+
+impl Default for SocketMode {
+    fn default() -> Self {
+        Self(0o660)
+    }
+}
+
+impl FromStr for SocketMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits = s.strip_prefix("0o").unwrap_or(s);
+        match u32::from_str_radix(digits, 8) {
+            Ok(mode) if mode <= 0o777 => Ok(Self(mode)),
+            _ => Err("expected octal permission bits such as 660".to_string()),
+        }
+    }
+}
+
+/// Cap on the connections served at once (`MAX_CONNECTIONS`).
+///
+/// A connection over the cap is accepted but not read from until an open
+/// one closes; while the listener waits, further clients queue in the
+/// kernel's accept backlog. Once `close` is called on shutdown, waiting
+/// connections are let through, so the accept loop gets back to watching
+/// the shutdown signal. Connections are counted in the
+/// `http_connections_open` gauge whether or not a cap is set.
+#[derive(Clone)]
+pub struct ConnectionLimit {
+    /// Free slots, or `None` without a cap
+    permits: Option<Arc<Semaphore>>,
+    open: IntGauge,
+}
+
+/// A slot taken by an open connection, given back when it closes.
+struct ConnectionSlot {
+    _permit: Option<OwnedSemaphorePermit>,
+    open: IntGauge,
+}
+
+impl ConnectionLimit {
+    /// Creates a limit of `max` connections; 0 leaves them unlimited.
+    pub fn new(max: usize, open: IntGauge) -> Self {
+        Self {
+            permits: (max > 0).then(|| Arc::new(Semaphore::new(max))),
+            open,
+        }
+    }
+
+    /// Stops holding back connections, including those already waiting
+    /// for a slot.
+    pub fn close(&self) {
+        if let Some(permits) = &self.permits {
+            permits.close();
+        }
+    }
+
+    /// Waits for a free slot for a new connection, or until the limit is
+    /// closed.
+    async fn acquire(&self) -> ConnectionSlot {
+        let permit = match &self.permits {
+            Some(permits) => {
+                if permits.available_permits() == 0 {
+                    tracing::debug!("MAX_CONNECTIONS reached, waiting for a connection to close");
+                }
+                // Fails once closed, letting the connection through
+                permits.clone().acquire_owned().await.ok()
+            }
+            None => None,
+        };
+        self.open.inc();
+        ConnectionSlot {
+            _permit: permit,
+            open: self.open.clone(),
+        }
+    }
+
+    /// Wraps a make-service so that each connection it serves takes a slot.
+    pub fn layer<M>(&self, make_service: M) -> LimitConnections<M> {
+        LimitConnections {
+            inner: make_service,
+            limit: self.clone(),
+        }
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.open.dec();
+    }
+}
+
+/// Make-service that holds back new connections while all slots are taken.
+#[derive(Clone)]
+pub struct LimitConnections<M> {
+    inner: M,
+    limit: ConnectionLimit,
+}
+
+impl<M, T> Service<T> for LimitConnections<M>
+where
+    M: Service<T>,
+    M::Future: Send + 'static,
+{
+    type Response = Limited<M::Response>;
+    type Error = M::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let service = self.inner.call(target);
+        let limit = self.limit.clone();
+        async move {
+            let slot = limit.acquire().await;
+            Ok(Limited {
+                inner: service.await?,
+                _slot: Arc::new(slot),
+            })
+        }
+        .boxed()
+    }
+}
+
+/// Service of one connection, holding its slot until the connection and
+/// every request it started are done.
+#[derive(Clone)]
+pub struct Limited<S> {
+    inner: S,
+    _slot: Arc<ConnectionSlot>,
+}
+
+impl<S, R> Service<R> for Limited<S>
+where
+    S: Service<R>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: R) -> Self::Future {
+        self.inner.call(request)
+    }
+}
+
+/// How long the unix socket listener waits after an accept error that is
+/// not specific to one connection, such as running out of file descriptors.
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Whether an accept error only concerns the connection being accepted.
+fn is_connection_error(error: &std::io::Error) -> bool {
+    matches!(
+        error.kind(),
+        ErrorKind::ConnectionRefused | ErrorKind::ConnectionAborted | ErrorKind::ConnectionReset
+    )
+}
+
+/// A bound unix socket whose file is removed when the guard is dropped.
+pub struct UnixSocket {
+    /// The listening socket
+    listener: tokio::net::UnixListener,
+    /// Location of the socket file
+    path: PathBuf,
+}
+
+impl UnixSocket {
+    /// Binds a unix socket at `path` whose file is created with `mode`.
+    ///
+    /// The process umask is narrowed to `mode` while binding, so the file
+    /// never exists with wider permissions. A socket file left behind by a
+    /// previous run is removed first. Any other kind of file at `path` is
+    /// left alone and binding fails.
+    #[cfg(unix)]
+    pub fn bind(path: &Path, mode: SocketMode) -> Result<Self> {
+        use std::os::unix::fs::FileTypeExt;
+
+        match std::fs::symlink_metadata(path) {
+            Ok(meta) if meta.file_type().is_socket() => {
+                std::fs::remove_file(path)
+                    .with_context(|| format!("failed to remove stale socket {}", path.display()))?;
+                tracing::info!("Removed stale socket file {}", path.display());
+            }
+            Ok(_) => bail!("{} exists and is not a socket", path.display()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to inspect {}", path.display()))
+            }
+        }
+
+        // SAFETY: umask only swaps the process file mode creation mask
+        let umask = unsafe { libc::umask(!mode.0 as libc::mode_t & 0o777) };
+        let listener = tokio::net::UnixListener::bind(path);
+        unsafe { libc::umask(umask) };
+        let listener = listener.with_context(|| format!("failed to bind unix socket {}", path.display()))?;
+        Ok(Self {
+            listener,
+            path: path.to_path_buf(),
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn bind(_path: &Path, _mode: SocketMode) -> Result<Self> {
+        bail!("unix socket listeners are only supported on Unix")
+    }
+
+    /// Serves `app` on the socket until `shutdown` resolves, then waits for
+    /// open connections to finish their in-flight requests. The socket file
+    /// is removed once serving stops.
+    ///
+    /// No connection is accepted while `limit` has no free slot.
+    pub async fn serve(
+        self,
+        app: Router,
+        limit: ConnectionLimit,
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<()> {
+        let builder = auto::Builder::new(TokioExecutor::new());
+        let graceful = GracefulShutdown::new();
+        tokio::pin!(shutdown);
+
+        loop {
+            let slot = tokio::select! {
+                slot = limit.acquire() => slot,
+                _ = &mut shutdown => break,
+            };
+            let stream = tokio::select! {
+                accepted = self.listener.accept() => match accepted {
+                    Ok((stream, _)) => stream,
+                    // Only that connection failed
+                    Err(e) if is_connection_error(&e) => continue,
+                    // E.g. out of file descriptors: accepting again right away would spin
+                    Err(e) => {
+                        tracing::warn!("Failed to accept unix socket connection: {}", e);
+                        tokio::select! {
+                            _ = tokio::time::sleep(ACCEPT_ERROR_BACKOFF) => continue,
+                            _ = &mut shutdown => break,
+                        }
+                    }
+                },
+                _ = &mut shutdown => break,
+            };
+
+            let service = TowerToHyperService::new(app.clone());
+            let connection = builder
+                .serve_connection_with_upgrades(TokioIo::new(stream), service)
+                .into_owned();
+            let connection = graceful.watch(connection);
+            tokio::spawn(async move {
+                let _slot = slot;
+                if let Err(e) = connection.await {
+                    tracing::debug!("Unix socket connection closed with error: {}", e);
+                }
+            });
+        }
+
+        graceful.shutdown().await;
+        Ok(())
+    }
+}
+
+impl Drop for UnixSocket {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            tracing::warn!("Failed to remove socket file {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    use super::*;
+    use crate::{paths, routes};
+    use crate::test_support::{self, USER_KEY};
+
+    /// A socket path of its own for each test.
+    fn socket_path() -> PathBuf {
+        std::env::temp_dir().join(format!("rust-qdrant-{}.sock", uuid::Uuid::new_v4()))
+    }
+
+    /// Sends a `GET` over a fresh connection to the socket at `path`.
+    async fn get(path: &Path, uri: &str) -> (StatusCode, String) {
+        let stream = tokio::net::UnixStream::connect(path).await.expect("socket accepts");
+        let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .expect("http handshake");
+        tokio::spawn(connection);
+        let request = Request::get(uri).header("x-api-key", USER_KEY).body(Body::empty()).unwrap();
+        let response = sender.send_request(request).await.expect("response");
+        let status = response.status();
+        let body = axum::body::to_bytes(Body::new(response.into_body()), usize::MAX).await.expect("body");
+        (status, String::from_utf8_lossy(&body).into_owned())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn requests_are_served_over_a_unix_socket() {
+        let app = test_support::app(&[]).await;
+        let path = socket_path();
+        let socket = UnixSocket::bind(&path, SocketMode(0o600)).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let limit = ConnectionLimit::new(0, app.state.metrics.connections_open());
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(socket.serve(routes::create_router(app.state.clone()), limit, async {
+            stopped.await.ok();
+        }));
+
+        let (status, body) = get(&path, paths::HEALTH).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let (status, body) = get(&path, paths::DOCUMENTS).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        stop.send(()).unwrap();
+        server.await.unwrap().unwrap();
+        assert!(!path.exists(), "the socket file is removed on shutdown");
+    }
+
+    #[tokio::test]
+    async fn only_stale_sockets_are_replaced() {
+        let path = socket_path();
+        let stale = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        drop(stale);
+        assert!(std::fs::symlink_metadata(&path).unwrap().file_type().is_socket());
+        let socket = UnixSocket::bind(&path, SocketMode::default()).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o660);
+        drop(socket);
+
+        std::fs::write(&path, "not a socket").unwrap();
+        let refused = UnixSocket::bind(&path, SocketMode::default()).err().expect("a regular file is kept");
+        assert!(refused.to_string().contains("is not a socket"), "{}", refused);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "not a socket");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// Sends a `GET` of `uri` on `stream` and reads the response head.
+    async fn exchange(stream: &mut tokio::net::TcpStream, uri: &str) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let request = format!("GET {} HTTP/1.1\r\nhost: localhost\r\nx-api-key: {}\r\n\r\n", uri, USER_KEY);
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut head = vec![0; 1024];
+        let read = stream.read(&mut head).await.unwrap();
+        String::from_utf8_lossy(&head[..read]).into_owned()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn shutdown_lets_through_connections_waiting_for_a_slot() {
+        let app = test_support::app(&[]).await;
+        let limit = ConnectionLimit::new(1, app.state.metrics.connections_open());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let make_service = limit.layer(routes::create_router(app.state.clone()).into_make_service());
+        let server = tokio::spawn(async move {
+            axum::serve(listener, make_service)
+                .with_graceful_shutdown(async {
+                    stopped.await.ok();
+                })
+                .await
+        });
+
+        // The first connection takes the only slot and stays open
+        let mut open = tokio::net::TcpStream::connect(addr).await.unwrap();
+        assert!(exchange(&mut open, paths::HEALTH).await.starts_with("HTTP/1.1 200"));
+        let mut waiting = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let answer = tokio::spawn(async move { exchange(&mut waiting, paths::HEALTH).await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!answer.is_finished(), "the second connection waits for a slot");
+
+        limit.close();
+        stop.send(()).unwrap();
+        let answer = tokio::time::timeout(Duration::from_secs(5), answer).await.expect("waiting connection is served");
+        assert!(answer.unwrap().starts_with("HTTP/1.1 200"));
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .expect("server shuts down")
+            .unwrap()
+            .unwrap();
+        drop(open);
+    }
+}
@@ -1,31 +1,247 @@
+/// Audit log of destructive operations
+mod audit;
+/// Global daily and monthly OpenAI token budgets
+mod budget;
+/// Version, commit and enabled features reported by /api/version
+mod build_info;
+/// Client address resolution, filtering and rate limiting
+mod client_ip;
 /// Configuration module for environment variables and settings
 mod config;
+/// Stub OpenAI and in-memory Qdrant for running without either
+mod demo;
+/// Per-key model, route and quota entitlements
+mod entitlements;
 /// Request handlers for API endpoints
 mod handlers;
+/// Background jobs such as reindexing
+mod jobs;
+/// TCP and unix socket listener configuration
+mod listen;
 /// Middleware for authentication and logging
 mod middleware;
+/// API keys with roles and expiry
+mod keys;
+/// Prometheus metrics for upstream calls
+mod metrics;
 /// Database models and schemas
 mod models;
+/// Unicode and whitespace normalization of texts
+mod normalize;
+/// API route paths and the route lists settings refer to
+mod paths;
 /// API route definitions
 mod routes;
 /// External service integrations
 mod services;
 /// Application state management
 mod state;
+/// Sharing one call between concurrent identical requests
+mod single_flight;
+/// Router and demo upstreams for tests
+#[cfg(test)]
+mod test_support;
+/// Runtime counters served by /api/stats
+mod stats;
+/// TLS certificate loading and reloading
+mod tls;
 /// Shared types and API contracts
 mod types;
+/// Vector math helpers for embeddings
+pub mod vector_math;
+/// Start-up calls that prime connections before the server reports ready
+mod warmup;
 
 use anyhow::Result;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::net::TcpListener;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 use crate::{
+    audit::AuditLog,
+    budget::TokenBudget,
     config::Config,
-    services::{OpenAIService, QdrantService},
+    listen::{ConnectionLimit, ListenAddr, UnixSocket},
+    metrics::Metrics,
+    services::{
+        qdrant,
+        ConversationStore, HttpPoolSettings, OpenAIQueue, OpenAIService, QdrantService, QdrantSessionStore,
+        SessionBackend, SessionStore,
+    },
     state::AppState,
+    tls::TlsPaths,
 };
 
+/// How often changed token totals are written to `TOKEN_USAGE_FILE`.
+const TOKEN_USAGE_PERSIST_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Creates or checks the collection, its payload indexes and, if enabled,
+/// its text index.
+///
+/// Runs again on every start and while retrying in the background, so an
+/// index that failed to be created is retried until it exists.
+async fn prepare_qdrant(qdrant: &QdrantService, vector_size: u64, text_index: bool) -> Result<()> {
+    qdrant.ensure_collection(vector_size).await?;
+    qdrant.ensure_payload_indexes().await?;
+    if text_index {
+        qdrant.ensure_text_index().await?;
+    }
+    Ok(())
+}
+
+/// Creates the services described by `config` and the state shared by the handlers.
+/// 
+/// Checks the collection on the way, unless Qdrant can't be reached and
+/// `QDRANT_OPTIONAL_AT_BOOT` is set.
+/// 
+/// # Arguments
+/// * `config` - Validated configuration
+/// * `openai_base` - Base URL of the OpenAI API, when not api.openai.com
+/// 
+/// # Returns
+/// * `Ok((Arc<AppState>, u64, bool))` - The state, the vector size, and whether
+///   the collection still has to be checked once Qdrant is reachable
+/// * `Err(anyhow::Error)` - If a service cannot be set up
+async fn build_state(config: Config, openai_base: Option<&str>) -> Result<(Arc<AppState>, u64, bool)> {
+    // Initialize external services
+    let token_budget = TokenBudget::load(
+        config.daily_token_budget,
+        config.monthly_token_budget,
+        config.token_budget_soft_percent,
+        config.token_usage_file.clone(),
+    )
+    .await?;
+    if token_budget.is_limited() {
+        tracing::info!(
+            daily = ?config.daily_token_budget,
+            monthly = ?config.monthly_token_budget,
+            "token budgets enabled"
+        );
+    }
+    let metrics = Arc::new(Metrics::new(config.openai_prices.clone()).with_token_budget(token_budget));
+    let openai_pool = HttpPoolSettings::new(
+        config.openai_pool_size,
+        Duration::from_millis(config.openai_connect_timeout_ms.get()),
+    );
+    tracing::info!(
+        max_idle_per_host = openai_pool.max_idle_per_host,
+        connect_timeout_ms = openai_pool.connect_timeout.as_millis() as u64,
+        idle_timeout_secs = openai_pool.idle_timeout.as_secs(),
+        tcp_keepalive_secs = openai_pool.tcp_keepalive.as_secs(),
+        http2 = "when offered by the server",
+        "OpenAI HTTP client configured"
+    );
+    let openai_service = OpenAIService::new(
+        &config.openai_api_key,
+        config.openai_org_id.as_deref(),
+        config.openai_project_id.as_deref(),
+    )
+        .with_api_base(openai_base)
+        .with_extra_headers(config.openai_extra_headers.clone())
+        .with_embedding_model(&config.embedding_model, config.embedding_dimensions)?
+        .with_base64_embeddings(config.openai_base64_embeddings)
+        .with_timeout(Duration::from_secs(config.openai_timeout_secs))
+        .with_vision_model(config.vision_model.as_deref())
+        .with_rewrite_model(&config.rewrite_model)
+        .with_text_normalization(config.text_normalization)
+        .with_embedding_coalescing(
+            Duration::from_millis(config.embed_coalesce_window_ms),
+            config.embed_coalesce_max_batch,
+        )
+        .with_metrics(metrics.clone())
+        .with_http_pool(openai_pool);
+    let openai_service = match config.chat_cache_ttl_secs {
+        0 => openai_service,
+        ttl => openai_service.with_response_cache(config.chat_cache_max_entries, Duration::from_secs(ttl)),
+    };
+    let vector_size = openai_service.embedding_dimension().ok_or_else(|| {
+        anyhow::anyhow!(
+            "unknown vector size for embedding model '{}'; set EMBEDDING_DIMENSIONS",
+            config.embedding_model
+        )
+    })?;
+    let qdrant_service = QdrantService::new(
+        &config.qdrant_url,
+        config.qdrant_api_key.as_deref(),
+        &config.collection_name,
+    )?
+    .with_alias(config.qdrant_alias.as_deref())
+    .with_read_consistency(config.qdrant_read_consistency)
+    .with_write_ordering(config.qdrant_write_ordering)
+    .with_sharding(config.sharding)
+    .with_tenants(config.key_entitlements.tenants())
+    .with_vector_size(vector_size)
+    .with_strict_vector_size(config.strict_vector_size)
+    .with_distance(config.qdrant_distance, config.normalize_embeddings)
+    .with_on_disk(config.qdrant_on_disk)
+    .with_replication(config.qdrant_shard_number, config.qdrant_replication_factor)
+    .with_payload_indexes(config.payload_indexes.clone())
+    .with_version_retention(config.document_versions)
+    .with_large_integers(config.large_integers)
+    .with_auto_create(config.auto_create_collection, config.qdrant_text_index)
+    .with_metrics(metrics.clone());
+
+    // Make sure the collection exists with the right vector size before serving requests.
+    // With QDRANT_OPTIONAL_AT_BOOT, an unreachable Qdrant is retried in the background instead.
+    let qdrant_pending = match prepare_qdrant(&qdrant_service, vector_size, config.qdrant_text_index).await {
+        Ok(()) => false,
+        Err(e) if config.qdrant_optional_at_boot && qdrant::is_unreachable(&e) => {
+            tracing::warn!("Qdrant not yet reachable, will retry: {:#}", e);
+            qdrant_service.mark_connecting();
+            true
+        }
+        Err(e) => return Err(e),
+    };
+
+    // Keep chat histories in memory, or in their own collection so they survive restarts
+    let conversation_ttl = Duration::from_secs(config.conversation_ttl_secs);
+    let conversations: Box<dyn SessionStore> = match config.session_store {
+        SessionBackend::Memory => {
+            Box::new(ConversationStore::new(config.max_conversations, conversation_ttl))
+        }
+        SessionBackend::Qdrant => {
+            let sessions = QdrantService::new(
+                &config.qdrant_url,
+                config.qdrant_api_key.as_deref(),
+                &config.session_collection,
+            )?
+            .with_write_ordering(config.qdrant_write_ordering)
+            .with_replication(config.qdrant_shard_number, config.qdrant_replication_factor)
+            .with_metrics(metrics.clone());
+            Box::new(QdrantSessionStore::open(sessions, conversation_ttl, config.qdrant_optional_at_boot).await?)
+        }
+    };
+    tracing::info!("session store: {:?}", config.session_store);
+
+    // Create shared application state
+    let audit = AuditLog::open(&config.audit_log, config.audit_log_max_bytes, config.audit_log_max_files).await?;
+    tracing::info!("audit log: {}", config.audit_log);
+    // Without OpenAI the service above only supplies the embedding dimension
+    let openai = if config.disable_openai {
+        tracing::warn!("OpenAI is disabled: endpoints that embed or chat will return 503");
+        OpenAIQueue::disabled(metrics.clone())
+    } else {
+        OpenAIQueue::start(
+            openai_service,
+            config.openai_workers,
+            config.openai_background_workers,
+            config.openai_queue_depth,
+            metrics.clone(),
+        )
+    };
+    let state = Arc::new(AppState::new(
+        config,
+        openai,
+        qdrant_service,
+        metrics,
+        conversations,
+        audit,
+    ));
+    Ok((state, vector_size, qdrant_pending))
+}
+
 /// Application entry point.
 /// 
 /// This function performs the following setup:
@@ -33,6 +249,7 @@ use crate::{
 /// 2. Loads environment variables
 /// 3. Creates service instances
 /// 4. Sets up the web server
+/// 5. Drains in-flight requests on shutdown
 /// 
 /// # Returns
 /// * `Result<()>` - Ok if server starts successfully, Err otherwise
@@ -49,30 +266,313 @@ async fn main() -> Result<()> {
     // Load environment variables from .env file
     dotenv::dotenv().ok();
     
-    // Load application configuration
-    let config = Config::from_env()?;
+    // Load application configuration, rejecting settings that can't work
+    let mut config = Config::from_env()?;
+    config.validate()?;
+
+    // In demo mode both upstreams are stand-ins served by this process
+    let demo = if config.demo_mode {
+        if config.sharding == qdrant::ShardingMode::Custom {
+            anyhow::bail!("DEMO_MODE does not support SHARDING=custom");
+        }
+        let upstreams = demo::start().await?;
+        config.qdrant_url = upstreams.qdrant_url.clone();
+        config.qdrant_api_key = None;
+        demo::log_banner();
+        Some(upstreams)
+    } else {
+        None
+    };
+    config.log_summary();
+
+    // Load the TLS certificate up front so a bad certificate fails startup
+    let tls = match TlsPaths::from_config(config.tls_cert_path.as_deref(), config.tls_key_path.as_deref())? {
+        Some(paths) => {
+            let tls_config = paths.load().await?;
+            Some((paths, tls_config))
+        }
+        None => None,
+    };
+    if tls.is_some() && matches!(config.listen, ListenAddr::Unix(_)) {
+        anyhow::bail!("TLS is not supported on unix socket listeners");
+    }
     
-    // Initialize external services
-    let openai_service = OpenAIService::new(&config.openai_api_key);
-    let qdrant_service = QdrantService::new(
-        &config.qdrant_url,
-        config.qdrant_api_key.as_deref(),
-        &config.collection_name,
-    )?;
+    let openai_base = demo.as_ref().map(|upstreams| upstreams.openai_base.clone());
+    let (state, vector_size, qdrant_pending) = build_state(config, openai_base.as_deref()).await?;
+    let shutdown_timeout = Duration::from_secs(state.config.shutdown_timeout_secs);
+    let summary_interval = Duration::from_secs(state.config.metrics_summary_interval_mins * 60);
+    let sweep_interval = Duration::from_secs(state.config.session_sweep_interval_secs.max(1));
 
-    // Create shared application state
-    let state = Arc::new(AppState::new(config, openai_service, qdrant_service));
+    // Keep retrying the startup checks until Qdrant answers; until then its routes get a 503
+    if qdrant_pending {
+        let qdrant_state = state.clone();
+        tokio::spawn(async move {
+            let qdrant = &qdrant_state.qdrant_service;
+            let text_index = qdrant_state.config.qdrant_text_index;
+            let mut backoff = qdrant::MIN_RECONNECT_BACKOFF;
+            loop {
+                tokio::time::sleep(backoff).await;
+                match prepare_qdrant(qdrant, vector_size, text_index).await {
+                    Ok(()) => {
+                        qdrant.mark_connected();
+                        tracing::info!("Qdrant reachable, collection ready");
+                        break;
+                    }
+                    Err(e) => {
+                        backoff = (backoff * 2).min(qdrant::MAX_RECONNECT_BACKOFF);
+                        tracing::warn!("Qdrant still not ready, retrying in {:?}: {:#}", backoff, e);
+                    }
+                }
+            }
+        });
+    }
+
+    // Periodically log a metrics summary when enabled
+    if !summary_interval.is_zero() {
+        let metrics = state.metrics.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(summary_interval);
+            // The first tick completes immediately
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                metrics.log_summary();
+            }
+        });
+    }
+
+    // Save the token totals regularly so a restart resumes counting from them
+    let budget_metrics = state.metrics.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(TOKEN_USAGE_PERSIST_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = budget_metrics.token_budget().persist().await {
+                tracing::warn!("Failed to save token usage: {:#}", e);
+            }
+        }
+    });
+
+    // Remove expired conversations so idle sessions don't accumulate, and
+    // with TRASH_AUTO_PURGE documents that outlived the trash retention
+    let sweep_state = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(sweep_interval);
+        loop {
+            interval.tick().await;
+            match sweep_state.conversations.sweep().await {
+                Ok(0) => {}
+                Ok(removed) => tracing::info!("Removed {} expired conversations", removed),
+                Err(e) => tracing::warn!("Failed to remove expired conversations: {:#}", e),
+            }
+            if !sweep_state.config.trash_auto_purge || !sweep_state.qdrant_service.admit() {
+                continue;
+            }
+            let retention = Duration::from_secs(sweep_state.config.trash_retention_secs);
+            match sweep_state.qdrant_service.purge_trash(retention, None).await {
+                Ok(0) => {}
+                Ok(purged) => tracing::info!("Purged {} documents from the trash", purged),
+                Err(e) => tracing::warn!("Failed to purge the trash: {:#}", e),
+            }
+        }
+    });
+
+    // Re-embed documents flagged as stale in the background
+    if state.config.stale_reembed_interval_secs > 0 {
+        let reembed_state = state.clone();
+        let reembed_interval = Duration::from_secs(state.config.stale_reembed_interval_secs);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(reembed_interval);
+            loop {
+                interval.tick().await;
+                if !reembed_state.qdrant_service.admit() {
+                    continue;
+                }
+                match handlers::start_stale_reembed(&reembed_state).await {
+                    Ok(Some(job_id)) => tracing::info!("Started re-embedding stale documents (job {})", job_id),
+                    Ok(None) => {}
+                    Err(e) => tracing::warn!("Failed to count stale documents: {:#}", e),
+                }
+            }
+        });
+    }
+
+    // Warn once a day about API keys that are about to expire
+    let key_state = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(24 * 60 * 60));
+        loop {
+            interval.tick().await;
+            keys::warn_expiring(&key_state.config.api_keys, key_state.config.key_expiry_warning_days);
+        }
+    });
     
     // Create router with all routes and middleware
-    let app = routes::create_router(state);
+    let app = routes::create_router(state.clone());
     
-    // Configure and start the server
-    let addr = std::net::SocketAddr::from(([127, 0, 0, 1], 3000));
-    tracing::info!("listening on {}", addr);
-    
-    // Start serving requests
-    let listener = TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    // Start serving requests until a shutdown is requested, holding back
+    // connections above MAX_CONNECTIONS
+    let limit = ConnectionLimit::new(state.config.max_connections, state.metrics.connections_open());
+    let listen = state.config.listen.clone();
+    let waiting = limit.clone();
+    let (server, stop): (_, Box<dyn FnOnce() + Send>) = match (listen, tls) {
+        (ListenAddr::Tcp(addr), Some((paths, tls_config))) => {
+            tls::reload_on_sighup(paths, tls_config.clone())?;
+            tracing::info!("listening on https://{} (tcp)", addr);
+
+            let handle = axum_server::Handle::new();
+            let server_handle = handle.clone();
+            let server = tokio::spawn(async move {
+                axum_server::bind_rustls(addr, tls_config)
+                    .handle(server_handle)
+                    .serve(limit.layer(app.into_make_service_with_connect_info::<SocketAddr>()))
+                    .await
+                    .map_err(anyhow::Error::from)
+            });
+            (server, Box::new(move || handle.graceful_shutdown(None)))
+        }
+        (ListenAddr::Tcp(addr), None) => {
+            let listener = TcpListener::bind(addr).await?;
+            tracing::info!("listening on http://{} (tcp)", addr);
+
+            let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+            let server = tokio::spawn(async move {
+                // The peer address is the client address unless TRUST_PROXY is on
+                axum::serve(listener, limit.layer(app.into_make_service_with_connect_info::<SocketAddr>()))
+                    .with_graceful_shutdown(async {
+                        stop_rx.await.ok();
+                    })
+                    .await
+                    .map_err(anyhow::Error::from)
+            });
+            (server, Box::new(move || {
+                stop_tx.send(()).ok();
+            }))
+        }
+        (ListenAddr::Unix(path), _) => {
+            let socket = UnixSocket::bind(&path, state.config.listen_socket_mode)?;
+            tracing::info!("listening on unix://{} (unix socket)", path.display());
+
+            let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+            let server = tokio::spawn(socket.serve(app, limit, async {
+                stop_rx.await.ok();
+            }));
+            (server, Box::new(move || {
+                stop_tx.send(()).ok();
+            }))
+        }
+    };
+    // The accept loop only sees the signal again once a connection waiting
+    // for a slot is let through
+    let stop = move || {
+        waiting.close();
+        stop();
+    };
+
+    // With WARMUP, call every upstream once before reporting ready. A
+    // shutdown signal during warm-up skips straight to draining.
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    let interrupted = if state.config.warmup {
+        let timeout = Duration::from_secs(state.config.warmup_timeout_secs);
+        tracing::info!(?timeout, "Warming up");
+        match warmup::run(&state, timeout, shutdown.as_mut()).await {
+            Some(report) => {
+                for step in &report.steps {
+                    match &step.error {
+                        None => tracing::info!(call = step.name, latency_ms = step.latency_ms, "Warm-up call completed"),
+                        Some(e) => tracing::warn!(
+                            call = step.name,
+                            latency_ms = step.latency_ms,
+                            error = %e,
+                            "Warm-up call failed"
+                        ),
+                    }
+                }
+                if report.timed_out {
+                    tracing::warn!(?timeout, "Warm-up timed out, marking ready anyway");
+                }
+                if report.failed() && state.config.warmup_strict {
+                    stop();
+                    anyhow::bail!("warm-up failed and WARMUP_STRICT is set");
+                }
+                state.readiness.mark_ready(Some(report));
+                false
+            }
+            None => true,
+        }
+    } else {
+        state.readiness.mark_ready(None);
+        false
+    };
+    if !interrupted {
+        tracing::info!("Ready to serve traffic");
+        shutdown.await;
+    }
+    tracing::info!(
+        in_flight = state.in_flight(),
+        timeout = ?shutdown_timeout,
+        "Shutdown signal received, draining in-flight requests"
+    );
+
+    // Stop accepting connections and wait for in-flight requests to finish
+    stop();
+    let drain_start = Instant::now();
+    let drained = tokio::time::timeout(shutdown_timeout, server).await;
+
+    // Write out any audit entries still buffered and the latest token totals
+    state.audit.flush().await;
+    if let Err(e) = state.metrics.token_budget().persist().await {
+        tracing::warn!("Failed to save token usage: {:#}", e);
+    }
+
+    match drained {
+        Ok(result) => {
+            result??;
+            tracing::info!(
+                duration = ?drain_start.elapsed(),
+                "All in-flight requests drained, shutdown complete"
+            );
+        }
+        Err(_) => {
+            tracing::warn!(
+                in_flight = state.in_flight(),
+                duration = ?drain_start.elapsed(),
+                "Shutdown timeout elapsed before all requests drained"
+            );
+        }
+    }
 
     Ok(())
 }
+
+/// Resolves when the process receives Ctrl+C or, on Unix, SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("Failed to listen for Ctrl+C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
@@ -0,0 +1,749 @@
+use anyhow::{anyhow, Result};
+use prometheus::{
+    proto::MetricFamily, CounterVec, Encoder, Histogram, HistogramOpts, HistogramVec,
+    IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::collections::HashMap;
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::budget::{TokenBudget, TokenKind};
+use crate::keys::unix_now;
+use crate::stats::RuntimeStats;
+
+tokio::task_local! {
+    /// Upstream timings of the request being processed on the current task.
+    static UPSTREAM_TIMINGS: Arc<UpstreamTimings>;
+    /// Fingerprint of the API key that made the request on the current task.
+    static REQUEST_KEY: String;
+}
+
+/// Latency histogram buckets in seconds, from 5ms up to a minute.
+const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+];
+
+/// Price of a model in USD per million tokens.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModelPrice {
+    /// Price of prompt (input) tokens
+    pub input: f64,
+    /// Price of completion (output) tokens
+    pub output: f64,
+}
+
+/// Per-model token prices used to estimate OpenAI spend.
+///
+/// Parsed from a comma-separated list of `model=input[:output]` entries
+/// (USD per million tokens), which override the built-in defaults.
+#[derive(Debug, Clone)]
+pub struct PriceTable(HashMap<String, ModelPrice>);
+
+impl PriceTable {
+    /// Returns the price of a model, if known.
+    pub fn get(&self, model: &str) -> Option<ModelPrice> {
+        self.0.get(model).copied()
+    }
+
+    /// Returns the model with the lowest combined input and output price.
+    ///
+    /// Models without a known price only win when none has one, in which
+    /// case the first is returned.
+    pub fn cheapest<'a>(&self, models: &'a [String]) -> Option<&'a str> {
+        models
+            .iter()
+            .min_by(|a, b| {
+                let price = |model: &str| self.get(model).map_or(f64::INFINITY, |p| p.input + p.output);
+                price(a).total_cmp(&price(b))
+            })
+            .map(String::as_str)
+    }
+}
+
+impl Default for PriceTable {
+    fn default() -> Self {
+        let prices = [
+            ("text-embedding-ada-002", 0.10, 0.0),
+            ("text-embedding-3-small", 0.02, 0.0),
+            ("text-embedding-3-large", 0.13, 0.0),
+            ("gpt-4", 30.0, 60.0),
+            ("gpt-4-turbo", 10.0, 30.0),
+            ("gpt-4o", 2.5, 10.0),
+            ("gpt-4o-mini", 0.15, 0.60),
+            ("gpt-3.5-turbo", 0.50, 1.50),
+        ];
+        Self(
+            prices
+                .into_iter()
+                .map(|(model, input, output)| (model.to_string(), ModelPrice { input, output }))
+                .collect(),
+        )
+    }
+}
+
+impl FromStr for PriceTable {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut table = Self::default();
+        for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (model, prices) = entry
+                .split_once('=')
+                .ok_or_else(|| anyhow!("expected 'model=input[:output]', got '{}'", entry))?;
+            let (input, output) = prices.split_once(':').unwrap_or((prices, "0"));
+            let parse = |v: &str| {
+                v.trim()
+                    .parse::<f64>()
+                    .map_err(|_| anyhow!("invalid price '{}' for model '{}'", v, model))
+            };
+            table.0.insert(
+                model.trim().to_string(),
+                ModelPrice {
+                    input: parse(input)?,
+                    output: parse(output)?,
+                },
+            );
+        }
+        Ok(table)
+    }
+}
+
+/// Prometheus metrics for upstream OpenAI and Qdrant calls.
+///
+/// OpenAI metrics are labeled `{operation, model}` and Qdrant metrics
+/// `{operation, collection}`. Everything is registered in a dedicated
+/// registry rendered by the `/metrics` endpoint.
+pub struct Metrics {
+    registry: Registry,
+    prices: PriceTable,
+    openai_latency: HistogramVec,
+    openai_tokens: IntCounterVec,
+    openai_cost: CounterVec,
+    qdrant_latency: HistogramVec,
+    upstream_cancelled: IntCounterVec,
+    requests_cancelled: IntCounterVec,
+    budget_crossings: IntCounterVec,
+    openai_queue_rejected: IntCounter,
+    searches_coalesced: IntCounter,
+    openai_connections: IntCounter,
+    chat_cache_lookups: IntCounterVec,
+    ip_refused: IntCounterVec,
+    requests_shed: IntCounterVec,
+    requests_in_flight: IntGauge,
+    connections_open: IntGauge,
+    openai_queue_depth: IntGauge,
+    openai_calls_in_flight: IntGaugeVec,
+    stale_documents: IntGauge,
+    budget: TokenBudget,
+    stats: Arc<RuntimeStats>,
+}
+
+impl Metrics {
+    /// Creates and registers all metrics.
+    ///
+    /// # Arguments
+    /// * `prices` - Token prices used to estimate spend
+    pub fn new(prices: PriceTable) -> Self {
+        let registry = Registry::new();
+
+        let openai_latency = HistogramVec::new(
+            HistogramOpts::new("openai_request_duration_seconds", "Latency of OpenAI API calls")
+                .buckets(LATENCY_BUCKETS.to_vec()),
+            &["operation", "model"],
+        )
+        .expect("valid metric definition");
+        let openai_tokens = IntCounterVec::new(
+            Opts::new("openai_tokens_total", "Tokens consumed by OpenAI API calls"),
+            &["operation", "model", "kind"],
+        )
+        .expect("valid metric definition");
+        let openai_cost = CounterVec::new(
+            Opts::new("openai_cost_usd_total", "Estimated OpenAI spend in USD"),
+            &["operation", "model"],
+        )
+        .expect("valid metric definition");
+        let qdrant_latency = HistogramVec::new(
+            HistogramOpts::new("qdrant_request_duration_seconds", "Latency of Qdrant calls")
+                .buckets(LATENCY_BUCKETS.to_vec()),
+            &["operation", "collection"],
+        )
+        .expect("valid metric definition");
+        let upstream_cancelled = IntCounterVec::new(
+            Opts::new(
+                "upstream_calls_cancelled_total",
+                "Upstream calls abandoned before completion, e.g. after a client disconnect",
+            ),
+            &["upstream", "operation"],
+        )
+        .expect("valid metric definition");
+        let requests_cancelled = IntCounterVec::new(
+            Opts::new(
+                "http_requests_cancelled_total",
+                "Requests dropped before a response was produced",
+            ),
+            &["route"],
+        )
+        .expect("valid metric definition");
+        let budget_crossings = IntCounterVec::new(
+            Opts::new(
+                "token_budget_thresholds_crossed_total",
+                "Times token usage crossed the soft threshold or hard limit of a budget",
+            ),
+            &["kind", "period", "threshold"],
+        )
+        .expect("valid metric definition");
+        let openai_queue_rejected = IntCounter::new(
+            "openai_queue_rejected_total",
+            "OpenAI calls rejected because the request queue was full",
+        )
+        .expect("valid metric definition");
+        let searches_coalesced = IntCounter::new(
+            "searches_coalesced_total",
+            "Searches answered by an identical search already running instead of their own",
+        )
+        .expect("valid metric definition");
+        let openai_connections = IntCounter::new(
+            "openai_connections_opened_total",
+            "Connections opened to the OpenAI API; requests beyond this count reused a pooled connection",
+        )
+        .expect("valid metric definition");
+        let chat_cache_lookups = IntCounterVec::new(
+            Opts::new(
+                "chat_cache_lookups_total",
+                "Repeatable chat completions looked up in the response cache, by hit or miss",
+            ),
+            &["result"],
+        )
+        .expect("valid metric definition");
+        let ip_refused = IntCounterVec::new(
+            Opts::new(
+                "ip_requests_refused_total",
+                "Requests refused because of their client address, by denied or rate_limited",
+            ),
+            &["reason"],
+        )
+        .expect("valid metric definition");
+        let requests_shed = IntCounterVec::new(
+            Opts::new(
+                "requests_shed_total",
+                "Requests refused with 503 because in-flight requests were above SHED_HIGH_WATER_MARK, by route",
+            ),
+            &["route"],
+        )
+        .expect("valid metric definition");
+        let requests_in_flight = IntGauge::new(
+            "http_requests_in_flight",
+            "Requests being processed, as of the last scrape",
+        )
+        .expect("valid metric definition");
+        let connections_open = IntGauge::new(
+            "http_connections_open",
+            "Client connections being served, excluding ones waiting for a MAX_CONNECTIONS slot",
+        )
+        .expect("valid metric definition");
+        let openai_queue_depth = IntGauge::new(
+            "openai_queue_depth",
+            "OpenAI calls waiting for a free worker, as of the last scrape",
+        )
+        .expect("valid metric definition");
+        let openai_calls_in_flight = IntGaugeVec::new(
+            Opts::new(
+                "openai_calls_in_flight",
+                "OpenAI calls being run, by worker pool and by the priority they were submitted with",
+            ),
+            &["pool", "priority"],
+        )
+        .expect("valid metric definition");
+        let stale_documents = IntGauge::new(
+            "stale_documents",
+            "Documents flagged as stale and waiting to be re-embedded, as last counted",
+        )
+        .expect("valid metric definition");
+
+        for collector in [
+            Box::new(openai_latency.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(openai_tokens.clone()),
+            Box::new(openai_cost.clone()),
+            Box::new(qdrant_latency.clone()),
+            Box::new(upstream_cancelled.clone()),
+            Box::new(requests_cancelled.clone()),
+            Box::new(budget_crossings.clone()),
+            Box::new(openai_queue_rejected.clone()),
+            Box::new(searches_coalesced.clone()),
+            Box::new(openai_connections.clone()),
+            Box::new(chat_cache_lookups.clone()),
+            Box::new(ip_refused.clone()),
+            Box::new(requests_shed.clone()),
+            Box::new(requests_in_flight.clone()),
+            Box::new(connections_open.clone()),
+            Box::new(openai_queue_depth.clone()),
+            Box::new(openai_calls_in_flight.clone()),
+            Box::new(stale_documents.clone()),
+        ] {
+            registry
+                .register(collector)
+                .expect("metric names are unique");
+        }
+
+        Self {
+            registry,
+            prices,
+            openai_latency,
+            openai_tokens,
+            openai_cost,
+            qdrant_latency,
+            upstream_cancelled,
+            requests_cancelled,
+            budget_crossings,
+            openai_queue_rejected,
+            searches_coalesced,
+            openai_connections,
+            chat_cache_lookups,
+            ip_refused,
+            requests_shed,
+            requests_in_flight,
+            connections_open,
+            openai_queue_depth,
+            openai_calls_in_flight,
+            stale_documents,
+            budget: TokenBudget::default(),
+            stats: Arc::new(RuntimeStats::default()),
+        }
+    }
+
+    /// Sets the global token budgets usage is counted against.
+    pub fn with_token_budget(mut self, budget: TokenBudget) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Returns the global token budgets.
+    pub fn token_budget(&self) -> &TokenBudget {
+        &self.budget
+    }
+
+    /// Returns the counters behind `/api/stats`, which token usage and chat
+    /// cache lookups are also counted in.
+    pub fn runtime_stats(&self) -> Arc<RuntimeStats> {
+        self.stats.clone()
+    }
+
+    /// Starts a latency timer for an OpenAI call; the duration is recorded on drop.
+    pub fn openai_timer(&self, operation: OpenAIOperation, model: &str) -> UpstreamTimer {
+        UpstreamTimer::start(
+            self.openai_latency.with_label_values(&[operation.as_str(), model]),
+            self.upstream_cancelled.with_label_values(&["openai", operation.as_str()]),
+            Upstream::OpenAI,
+        )
+    }
+
+    /// Records token usage of an OpenAI call and the estimated cost.
+    pub fn record_openai_usage(
+        &self,
+        operation: OpenAIOperation,
+        model: &str,
+        prompt_tokens: u32,
+        completion_tokens: u32,
+    ) {
+        self.openai_tokens
+            .with_label_values(&[operation.as_str(), model, "prompt"])
+            .inc_by(u64::from(prompt_tokens));
+        self.openai_tokens
+            .with_label_values(&[operation.as_str(), model, "completion"])
+            .inc_by(u64::from(completion_tokens));
+
+        self.stats.record_tokens(prompt_tokens, completion_tokens);
+
+        let tokens = u64::from(prompt_tokens) + u64::from(completion_tokens);
+        let now = unix_now();
+        let _ = REQUEST_KEY.try_with(|fingerprint| self.budget.record_key(fingerprint, tokens, now));
+        for crossing in self.budget.record(operation.token_kind(), tokens, now) {
+            let threshold = if crossing.hard { "hard" } else { "soft" };
+            self.budget_crossings
+                .with_label_values(&[crossing.kind.as_str(), crossing.period.as_str(), threshold])
+                .inc();
+            warn!(
+                kind = crossing.kind.as_str(),
+                period = crossing.period.as_str(),
+                threshold,
+                used = crossing.used,
+                limit = crossing.limit,
+                "Token budget threshold crossed"
+            );
+        }
+
+        if let Some(price) = self.prices.get(model) {
+            let cost = (f64::from(prompt_tokens) * price.input
+                + f64::from(completion_tokens) * price.output)
+                / 1_000_000.0;
+            self.openai_cost
+                .with_label_values(&[operation.as_str(), model])
+                .inc_by(cost);
+        }
+    }
+
+    /// Starts a latency timer for a Qdrant call; the duration is recorded on drop.
+    pub fn qdrant_timer(&self, operation: &str, collection: &str) -> UpstreamTimer {
+        UpstreamTimer::start(
+            self.qdrant_latency.with_label_values(&[operation, collection]),
+            self.upstream_cancelled.with_label_values(&["qdrant", operation]),
+            Upstream::Qdrant,
+        )
+    }
+
+    /// Returns the tokens used by an API key in the current calendar month (UTC).
+    pub fn key_tokens_this_month(&self, fingerprint: &str) -> u64 {
+        self.budget.key_used(fingerprint, unix_now())
+    }
+
+    /// Counts a request that was dropped before producing a response.
+    pub fn record_request_cancelled(&self, route: &str) {
+        self.requests_cancelled.with_label_values(&[route]).inc();
+    }
+
+    /// Counts an OpenAI call rejected because the request queue was full.
+    pub fn record_openai_queue_rejected(&self) {
+        self.openai_queue_rejected.inc();
+    }
+
+    /// Counts a search that shared the result of an identical one already running.
+    pub fn record_search_coalesced(&self) {
+        self.searches_coalesced.inc();
+    }
+
+    /// Counts a new connection to the OpenAI API.
+    pub fn record_openai_connection(&self) {
+        self.openai_connections.inc();
+    }
+
+    /// Counts a response cache lookup of a repeatable chat completion.
+    pub fn record_chat_cache(&self, hit: bool) {
+        let result = if hit { "hit" } else { "miss" };
+        self.chat_cache_lookups.with_label_values(&[result]).inc();
+        self.stats.record_chat_cache(hit);
+    }
+
+    /// Counts a request refused by the IP filter or the IP rate limit.
+    pub fn record_ip_refused(&self, reason: &str) {
+        self.ip_refused.with_label_values(&[reason]).inc();
+    }
+
+    /// Counts a request shed because too many were in flight.
+    pub fn record_request_shed(&self, route: &str) {
+        self.requests_shed.with_label_values(&[route]).inc();
+    }
+
+    /// Records the requests in flight and the OpenAI calls waiting for a worker.
+    pub fn set_load(&self, in_flight: usize, openai_queued: usize) {
+        self.requests_in_flight.set(in_flight as i64);
+        self.openai_queue_depth.set(openai_queued as i64);
+    }
+
+    /// Returns the gauge of open client connections.
+    pub fn connections_open(&self) -> IntGauge {
+        self.connections_open.clone()
+    }
+
+    /// Returns the gauge of OpenAI calls running on `pool` workers with `priority`.
+    pub fn openai_calls_in_flight(&self, pool: &str, priority: &str) -> IntGauge {
+        self.openai_calls_in_flight.with_label_values(&[pool, priority])
+    }
+
+    /// Records how many documents are waiting to be re-embedded.
+    pub fn set_stale_documents(&self, count: u64) {
+        self.stale_documents.set(count as i64);
+    }
+
+    /// Renders all metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> Result<String> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+
+    /// Logs latency percentiles and estimated spend at info level.
+    ///
+    /// Intended for deployments without a Prometheus scraper; percentiles
+    /// are estimated from the histogram buckets.
+    pub fn log_summary(&self) {
+        for family in self.registry.gather() {
+            match family.get_name() {
+                "openai_request_duration_seconds" | "qdrant_request_duration_seconds" => {
+                    log_latency_family(&family)
+                }
+                "openai_cost_usd_total" => {
+                    for metric in family.get_metric() {
+                        info!(
+                            labels = %format_labels(metric),
+                            cost_usd = metric.get_counter().get_value(),
+                            "OpenAI estimated spend"
+                        );
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new(PriceTable::default())
+    }
+}
+
+/// Runs `future` with the OpenAI tokens it consumes charged to the key
+/// with the given fingerprint.
+pub async fn charge_to_key<F: Future>(fingerprint: String, future: F) -> F::Output {
+    REQUEST_KEY.scope(fingerprint, future).await
+}
+
+/// Fingerprint of the API key that made the request on the current task, if any.
+pub fn request_key() -> Option<String> {
+    REQUEST_KEY.try_with(String::clone).ok()
+}
+
+/// The per-request metrics context of the current task: its upstream
+/// timings and the API key its tokens are charged to.
+///
+/// Captured where a request is handled and re-entered on another task, so
+/// work handed off to it is still attributed to the request.
+#[derive(Debug, Clone, Default)]
+pub struct RequestContext {
+    timings: Option<Arc<UpstreamTimings>>,
+    key: Option<String>,
+}
+
+impl RequestContext {
+    /// Captures the context of the current task, if it has one.
+    pub fn capture() -> Self {
+        Self {
+            timings: UPSTREAM_TIMINGS.try_with(Arc::clone).ok(),
+            key: REQUEST_KEY.try_with(String::clone).ok(),
+        }
+    }
+
+    /// Runs `future` within the captured context.
+    pub async fn scope<F: Future>(self, future: F) -> F::Output {
+        let future = async move {
+            match self.key {
+                Some(key) => REQUEST_KEY.scope(key, future).await,
+                None => future.await,
+            }
+        };
+        match self.timings {
+            Some(timings) => UPSTREAM_TIMINGS.scope(timings, future).await,
+            None => future.await,
+        }
+    }
+}
+
+/// OpenAI operation a call is made for, labeled `operation` in the metrics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenAIOperation {
+    /// Embedding texts
+    Embed,
+    /// Chat completions, including `/api/ask` answers
+    Chat,
+    /// Summarizing the early turns of a long conversation
+    Summarize,
+    /// Rewriting a follow-up question into a search query
+    Rewrite,
+}
+
+impl OpenAIOperation {
+    /// Name used as the metrics label.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Embed => "embed",
+            Self::Chat => "chat",
+            Self::Summarize => "summarize",
+            Self::Rewrite => "rewrite",
+        }
+    }
+
+    /// Kind of the tokens the operation uses, for the token budgets.
+    pub fn token_kind(self) -> TokenKind {
+        match self {
+            Self::Embed => TokenKind::Embedding,
+            Self::Chat | Self::Summarize | Self::Rewrite => TokenKind::Chat,
+        }
+    }
+}
+
+/// Upstream service an outgoing call is made to.
+#[derive(Debug, Clone, Copy)]
+enum Upstream {
+    OpenAI,
+    Qdrant,
+}
+
+/// Time a single request spent waiting on upstream services.
+///
+/// An instance is created per request by the logging middleware and stored in
+/// the request extensions; service calls made while the request is processed
+/// add their durations to it.
+#[derive(Debug, Default)]
+pub struct UpstreamTimings {
+    openai_micros: AtomicU64,
+    qdrant_micros: AtomicU64,
+}
+
+impl UpstreamTimings {
+    /// Runs `future` with these timings receiving all upstream durations it records.
+    pub async fn scope<F: Future>(self: Arc<Self>, future: F) -> F::Output {
+        UPSTREAM_TIMINGS.scope(self, future).await
+    }
+
+    /// Total time spent in OpenAI calls.
+    pub fn openai(&self) -> Duration {
+        Duration::from_micros(self.openai_micros.load(Ordering::Relaxed))
+    }
+
+    /// Total time spent in Qdrant calls.
+    pub fn qdrant(&self) -> Duration {
+        Duration::from_micros(self.qdrant_micros.load(Ordering::Relaxed))
+    }
+
+    fn record(&self, upstream: Upstream, elapsed: Duration) {
+        let counter = match upstream {
+            Upstream::OpenAI => &self.openai_micros,
+            Upstream::Qdrant => &self.qdrant_micros,
+        };
+        counter.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Latency timer for an upstream call.
+///
+/// Calling `finish` once the call returns observes the elapsed time in the
+/// histogram. Either way, the time is added to the current request's
+/// `UpstreamTimings`, if any. A timer dropped without `finish` means the
+/// call's future was dropped mid-flight, which cancels the underlying HTTP
+/// request; it is counted and logged as a cancellation instead.
+pub struct UpstreamTimer {
+    histogram: Histogram,
+    cancelled: IntCounter,
+    upstream: Upstream,
+    start: Instant,
+    finished: bool,
+}
+
+impl UpstreamTimer {
+    fn start(histogram: Histogram, cancelled: IntCounter, upstream: Upstream) -> Self {
+        Self {
+            histogram,
+            cancelled,
+            upstream,
+            start: Instant::now(),
+            finished: false,
+        }
+    }
+
+    /// Marks the call as completed, successfully or not.
+    pub fn finish(mut self) {
+        self.finished = true;
+    }
+}
+
+impl Drop for UpstreamTimer {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
+        if self.finished {
+            self.histogram.observe(elapsed.as_secs_f64());
+        } else {
+            self.cancelled.inc();
+            warn!(
+                upstream = ?self.upstream,
+                elapsed = ?elapsed,
+                "Upstream call cancelled before completion"
+            );
+        }
+        // Calls made outside a request (e.g. at startup) have no timings to update
+        let _ = UPSTREAM_TIMINGS.try_with(|timings| timings.record(self.upstream, elapsed));
+    }
+}
+
+/// Logs count and p50/p95/p99 latency for every label set of a histogram family.
+fn log_latency_family(family: &MetricFamily) {
+    for metric in family.get_metric() {
+        let histogram = metric.get_histogram();
+        let count = histogram.get_sample_count();
+        if count == 0 {
+            continue;
+        }
+        let buckets: Vec<(f64, u64)> = histogram
+            .get_bucket()
+            .iter()
+            .map(|b| (b.get_upper_bound(), b.get_cumulative_count()))
+            .collect();
+
+        info!(
+            metric = family.get_name(),
+            labels = %format_labels(metric),
+            count,
+            p50_ms = estimate_quantile(&buckets, count, 0.50) * 1000.0,
+            p95_ms = estimate_quantile(&buckets, count, 0.95) * 1000.0,
+            p99_ms = estimate_quantile(&buckets, count, 0.99) * 1000.0,
+            "Latency summary"
+        );
+    }
+}
+
+/// Estimates a quantile from cumulative histogram buckets by linear
+/// interpolation within the bucket containing the target rank.
+fn estimate_quantile(buckets: &[(f64, u64)], count: u64, quantile: f64) -> f64 {
+    let rank = quantile * count as f64;
+    let mut lower_bound = 0.0;
+    let mut lower_count = 0u64;
+    for &(upper_bound, cumulative) in buckets {
+        if cumulative as f64 >= rank {
+            let in_bucket = (cumulative - lower_count) as f64;
+            if in_bucket == 0.0 {
+                return upper_bound;
+            }
+            let fraction = (rank - lower_count as f64) / in_bucket;
+            return lower_bound + (upper_bound - lower_bound) * fraction;
+        }
+        lower_bound = upper_bound;
+        lower_count = cumulative;
+    }
+    // The quantile falls in the +Inf bucket; report the largest finite bound
+    lower_bound
+}
+
+/// Formats a metric's labels as `name=value` pairs.
+fn format_labels(metric: &prometheus::proto::Metric) -> String {
+    metric
+        .get_label()
+        .iter()
+        .map(|l| format!("{}={}", l.get_name(), l.get_value()))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operations_charge_the_budget_of_their_token_kind() {
+        let metrics = Metrics::default();
+        metrics.record_openai_usage(OpenAIOperation::Embed, "text-embedding-3-small", 10, 0);
+        metrics.record_openai_usage(OpenAIOperation::Chat, "gpt-4", 4, 4);
+        metrics.record_openai_usage(OpenAIOperation::Summarize, "gpt-4", 3, 2);
+        metrics.record_openai_usage(OpenAIOperation::Rewrite, "gpt-4-turbo", 1, 1);
+
+        let daily = &metrics.token_budget().status(unix_now())["daily"]["kinds"];
+        assert_eq!(daily["embedding"]["used_tokens"], 10);
+        assert_eq!(daily["chat"]["used_tokens"], 15);
+        let rendered = metrics.render().expect("metrics render");
+        for operation in ["embed", "chat", "summarize", "rewrite"] {
+            assert!(rendered.contains(&format!("operation=\"{}\"", operation)), "{}", operation);
+        }
+    }
+}
@@ -1,93 +1,658 @@
 use axum::{
-    body::Body,
-    extract::State,
-    http::{Request, StatusCode},
+    body::{Body, Bytes},
+    extract::{MatchedPath, State},
+    http::{header, response::Parts, HeaderValue, Method, Request},
     middleware::Next,
-    response::Response,
+    response::{IntoResponse, Response},
 };
-use std::sync::Arc;
+use futures::StreamExt;
+use serde_json::Value;
+use std::sync::{atomic::Ordering, Arc};
+use std::time::{Duration, Instant};
 use tracing::{error, info, warn};
 
-use crate::state::AppState;
+use crate::{
+    budget, entitlements,
+    client_ip::{self, ClientIp, Refusal},
+    keys::{unix_now, Authenticated, KeyCheck, KeyRole},
+    paths,
+    services::conversations::SessionBackend,
+    metrics::{self, UpstreamTimings},
+    state::AppState,
+    types::{ApiError, ApiVersion},
+};
 
 /// Middleware that validates the API key in the request header.
 /// 
 /// This middleware checks for the presence of an 'x-api-key' header and validates
-/// its value against the configured API key. If the key is missing or invalid,
-/// the request is rejected with a 401 Unauthorized status.
+/// its value against the configured API keys. If the key is missing, unknown or
+/// expired, the request is rejected with a 401 Unauthorized status.
 /// 
 /// # Arguments
-/// * `state` - Application state containing the valid API key
+/// * `state` - Application state containing the valid API keys
 /// * `request` - The incoming HTTP request
 /// * `next` - The next middleware in the chain
 /// 
+/// Keys listed in `KEY_ENTITLEMENTS` are then checked against their allowed
+/// routes, models and monthly token quota, and requests that would call
+/// OpenAI against the global token budgets. OpenAI tokens used while the
+/// request runs are charged to the key.
+/// 
 /// # Returns
 /// * `Ok(Response)` - If authentication succeeds
-/// * `Err(StatusCode)` - If authentication fails
+/// * `Err(Response)` - 401 if authentication fails, 403 with an `error_code`
+///   if the key is not entitled to the request, 429 with an `error_code` if
+///   a token budget is used up
 pub async fn auth_middleware(
+    State(state): State<Arc<AppState>>,
+    mut request: Request<Body>,
+    next: Next,
+) -> Result<Response, Response> {
+    let fingerprint = authenticate(&state, &mut request, KeyRole::User)
+        .map_err(IntoResponse::into_response)?;
+
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or_else(|| request.uri().path(), |path| path.as_str());
+    if let Some(entitlement) = state.config.key_entitlements.get(&fingerprint) {
+        let models = entitlements::route_models(&state.config, request.method(), route);
+        let used = state.metrics.key_tokens_this_month(&fingerprint);
+        if let Err(violation) = entitlement
+            .check_request(route, &models)
+            .and_then(|()| entitlement.check_quota(used))
+        {
+            warn!(
+                %route,
+                %fingerprint,
+                code = violation.code(),
+                "Request refused by key entitlement"
+            );
+            return Err(ApiError::from(violation).into_response());
+        }
+    }
+
+    // Stop calling OpenAI once a global token budget is used up
+    let kinds = budget::route_kinds(request.method(), route);
+    if let Err(exceeded) = state.metrics.token_budget().check(kinds, unix_now()) {
+        warn!(
+            %route,
+            %fingerprint,
+            code = exceeded.code(),
+            "Request refused by token budget"
+        );
+        return Err(ApiError::from(exceeded).into_response());
+    }
+
+    // Continue processing the request
+    Ok(metrics::charge_to_key(fingerprint, next.run(request)).await)
+}
+
+/// Middleware that resolves the client address and refuses unwanted clients.
+/// 
+/// Runs before authentication, so a leaked key is of no use from a denied
+/// address. The address is stored in the request extensions as `ClientIp`
+/// for the logs and audit entries. Requests from addresses on
+/// `IP_DENYLIST`, or missing from a configured `IP_ALLOWLIST`, get a 403
+/// that doesn't say why; addresses over `IP_RATE_LIMIT_PER_MINUTE` get a
+/// 429 with a `Retry-After` header.
+/// 
+/// # Returns
+/// * `Ok(Response)` - If the address may send the request
+/// * `Err(Response)` - 403 or 429 with an `error_code` otherwise
+pub async fn client_ip_middleware(
+    State(state): State<Arc<AppState>>,
+    mut request: Request<Body>,
+    next: Next,
+) -> Result<Response, Response> {
+    let addr = client_ip::resolve(&request, &state.config.trusted_proxies);
+    let client = addr.map(|addr| ClientIp::new(addr, state.config.anonymize_ips));
+    let shown = client.as_ref().map_or("unknown", |client| client.shown.as_str());
+
+    let refusal = state.config.ip_filter.check(addr).and_then(|()| {
+        match (&state.ip_limiter, addr) {
+            (Some(limiter), Some(addr)) => limiter.check(addr),
+            _ => Ok(()),
+        }
+    });
+    match refusal {
+        Ok(()) => {}
+        Err(Refusal::Denied) => {
+            warn!(client_ip = %shown, uri = %request.uri(), "Request refused by IP filter");
+            state.metrics.record_ip_refused("denied");
+            return Err(ApiError::Forbidden {
+                code: "ip_denied",
+                message: "Access denied".into(),
+            }
+            .into_response());
+        }
+        Err(Refusal::RateLimited(retry_after)) => {
+            warn!(client_ip = %shown, uri = %request.uri(), "Request refused by IP rate limit");
+            state.metrics.record_ip_refused("rate_limited");
+            return Err(rate_limited(retry_after));
+        }
+    }
+
+    if let Some(client) = client {
+        request.extensions_mut().insert(client);
+    }
+    Ok(next.run(request).await)
+}
+
+/// Middleware that rate limits the routes served without a key by client
+/// address.
+/// 
+/// Applied to the `PUBLIC_PATHS` routes, where no key's quota applies, on
+/// top of any `IP_RATE_LIMIT_PER_MINUTE`. Relies on `client_ip_middleware`
+/// having resolved the address; requests whose address is unknown pass.
+/// 
+/// # Returns
+/// * `Ok(Response)` - If the address has requests left
+/// * `Err(Response)` - 429 with a `Retry-After` header otherwise
+pub async fn public_rate_limit_middleware(
+    State(state): State<Arc<AppState>>,
+    request: Request<Body>,
+    next: Next,
+) -> Result<Response, Response> {
+    if let (Some(limiter), Some(client)) = (&state.public_ip_limiter, request.extensions().get::<ClientIp>()) {
+        if let Err(Refusal::RateLimited(retry_after)) = limiter.check(client.addr) {
+            warn!(client_ip = %client.shown, uri = %request.uri(), "Request refused by public route rate limit");
+            state.metrics.record_ip_refused("public_rate_limited");
+            return Err(rate_limited(retry_after));
+        }
+    }
+    Ok(next.run(request).await)
+}
+
+/// Builds the 429 response of an address over its rate limit.
+fn rate_limited(retry_after: Duration) -> Response {
+    let mut response = ApiError::TooManyRequests {
+        code: "ip_rate_limited",
+        message: "Too many requests from this address, retry later".into(),
+    }
+    .into_response();
+    // Whole seconds, rounded up so a retry at that time succeeds
+    let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    response.headers_mut().insert(header::RETRY_AFTER, secs.into());
+    response
+}
+
+/// Middleware that sheds requests while too many are in flight.
+/// 
+/// Once more than `SHED_HIGH_WATER_MARK` requests are being processed, new
+/// ones get a 503 with a `Retry-After` of the recent average service time,
+/// roughly when a slot frees up, instead of queueing behind the others and
+/// letting latency climb. Routes in `paths::NEVER_SHED` are always served.
+/// The service time of admitted requests feeds that average.
+/// 
+/// # Returns
+/// * `Ok(Response)` - The handler's response
+/// * `Err(Response)` - 503 with a `Retry-After` header when shedding
+pub async fn shed_middleware(
     State(state): State<Arc<AppState>>,
     request: Request<Body>,
     next: Next,
-) -> Result<Response, StatusCode> {
-    // Extract and validate the API key from the request header
+) -> Result<Response, Response> {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or_else(|| request.uri().path().to_string(), |path| path.as_str().to_string());
+    if paths::NEVER_SHED.contains(&route.as_str()) {
+        return Ok(next.run(request).await);
+    }
+
+    // The in-flight count includes this request
+    let high_water_mark = state.config.shed_high_water_mark;
+    let in_flight = state.in_flight();
+    if high_water_mark > 0 && in_flight > high_water_mark {
+        let retry_after = state.service_time.average().as_secs_f64().ceil().clamp(1.0, 60.0) as u64;
+        warn!(%route, in_flight, high_water_mark, retry_after, "Shedding request");
+        state.metrics.record_request_shed(&route);
+        let mut response =
+            ApiError::ServiceUnavailable("Server is overloaded, retry later".into()).into_response();
+        response.headers_mut().insert(header::RETRY_AFTER, retry_after.into());
+        return Err(response);
+    }
+
+    let start = Instant::now();
+    let response = next.run(request).await;
+    state.service_time.record(start.elapsed());
+    Ok(response)
+}
+
+/// Middleware that turns away requests needing Qdrant while it is unreachable.
+/// 
+/// Routes in `paths::QDRANT_BACKED` get an immediate 503 while Qdrant is
+/// still being connected to at startup or after repeated calls failed to
+/// reach it, instead of each waiting for a connection timeout. So do the
+/// routes in `paths::QDRANT_SESSIONS` when sessions are stored in Qdrant.
+/// Other routes, such as `/api/embed`, keep working.
+/// 
+/// # Returns
+/// * `Ok(Response)` - If the route doesn't need Qdrant or Qdrant is reachable
+/// * `Err(ApiError)` - 503 otherwise
+pub async fn qdrant_availability_middleware(
+    State(state): State<Arc<AppState>>,
+    request: Request<Body>,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or_else(|| request.uri().path(), |path| path.as_str());
+    let needs_qdrant = paths::QDRANT_BACKED.contains(&route)
+        || (paths::QDRANT_SESSIONS.contains(&route) && state.config.session_store == SessionBackend::Qdrant);
+    if needs_qdrant && !state.qdrant_service.admit() {
+        warn!(%route, "Qdrant is unreachable, refusing request");
+        return Err(ApiError::ServiceUnavailable("Qdrant is unreachable, retry later".into()));
+    }
+    Ok(next.run(request).await)
+}
+
+/// Middleware that aborts requests running longer than their route's timeout.
+/// 
+/// The timeout comes from `ROUTE_TIMEOUTS`, or `REQUEST_TIMEOUT_SECS` for
+/// routes not listed there. It covers the time until the response starts,
+/// so a streamed body such as an export is not cut off once it is flowing.
+/// The handler is dropped on timeout, which aborts its upstream calls.
+/// 
+/// # Returns
+/// * `Ok(Response)` - The handler's response, if it finished in time
+/// * `Err(ApiError)` - 504 otherwise
+pub async fn timeout_middleware(
+    State(state): State<Arc<AppState>>,
+    request: Request<Body>,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or_else(|| request.uri().path().to_string(), |path| path.as_str().to_string());
+    let Some(timeout) = state.config.route_timeouts.get(&route, state.config.request_timeout_secs) else {
+        return Ok(next.run(request).await);
+    };
+    tokio::time::timeout(timeout, next.run(request)).await.map_err(|_| {
+        warn!(%route, "Request timed out after {:?}", timeout);
+        ApiError::Timeout(format!("{} did not finish within {}s", route, timeout.as_secs()))
+    })
+}
+
+/// Middleware that validates the API key for `/api/reset` and `/api/stats`.
+/// 
+/// Admin keys are always accepted. Once `ADMIN_API_KEY` is set, a regular
+/// key is refused with a 403, since these routes wipe data or describe every
+/// client's traffic; without one, regular keys are checked as by
+/// `auth_middleware`, entitlements included.
+/// 
+/// # Returns
+/// * `Ok(Response)` - If the key may use the route
+/// * `Err(Response)` - 401 if the key is missing, unknown or expired, 403
+///   with an `error_code` for a regular key while an admin key is configured
+pub async fn operator_auth_middleware(
+    State(state): State<Arc<AppState>>,
+    mut request: Request<Body>,
+    next: Next,
+) -> Result<Response, Response> {
+    let admin_key = request
+        .headers()
+        .get("x-api-key")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|key| !matches!(state.config.api_keys.check(key, KeyRole::Admin), KeyCheck::Unknown));
+    if admin_key {
+        authenticate(&state, &mut request, KeyRole::Admin).map_err(IntoResponse::into_response)?;
+        return Ok(next.run(request).await);
+    }
+    if !state.config.api_keys.has_role(KeyRole::Admin) {
+        return auth_middleware(State(state), request, next).await;
+    }
+
+    let fingerprint = authenticate(&state, &mut request, KeyRole::User).map_err(IntoResponse::into_response)?;
+    warn!(uri = %request.uri(), %fingerprint, "Regular API key refused on an admin-only route");
+    Err(ApiError::Forbidden {
+        code: "admin_key_required",
+        message: "This route requires the admin API key".into(),
+    }
+    .into_response())
+}
+
+/// Middleware that validates the admin API key for the `/api/admin` routes.
+/// 
+/// Works like `auth_middleware` but only accepts keys from `ADMIN_API_KEY`;
+/// the regular API keys do not grant admin access.
+/// 
+/// # Returns
+/// * `Ok(Response)` - If an admin key matches
+/// * `Err(ApiError)` - 401 if the key is missing, unknown or expired
+pub async fn admin_auth_middleware(
+    State(state): State<Arc<AppState>>,
+    mut request: Request<Body>,
+    next: Next,
+) -> Result<Response, ApiError> {
+    authenticate(&state, &mut request, KeyRole::Admin)?;
+    Ok(next.run(request).await)
+}
+
+/// Checks the request's 'x-api-key' header against the keys of `role` and
+/// records the accepted key in the request extensions.
+/// 
+/// # Returns
+/// The fingerprint of the accepted key
+fn authenticate(state: &AppState, request: &mut Request<Body>, role: KeyRole) -> Result<String, ApiError> {
+    // Extract the API key from the request header
     let api_key = request
         .headers()
         .get("x-api-key")
         .and_then(|v| v.to_str().ok())
         .ok_or_else(|| {
             warn!("Missing API key in request to {}", request.uri());
-            StatusCode::UNAUTHORIZED
+            ApiError::Auth("missing x-api-key header".into())
         })?;
 
-    // Check if the provided API key matches the configured one
-    if api_key != state.config.api_key {
-        warn!("Invalid API key provided for {}", request.uri());
-        return Err(StatusCode::UNAUTHORIZED);
+    // Check the key against the configured ones, including its expiry
+    match state.config.api_keys.check(api_key, role) {
+        KeyCheck::Valid(key) => {
+            // Log successful authentication with request details
+            info!(
+                method = %request.method(),
+                uri = %request.uri(),
+                fingerprint = %key.fingerprint(),
+                role = ?role,
+                "Request authenticated successfully"
+            );
+            let fingerprint = key.fingerprint();
+            request.extensions_mut().insert(Authenticated {
+                fingerprint: fingerprint.clone(),
+                role,
+            });
+            Ok(fingerprint)
+        }
+        KeyCheck::Expired(key) => {
+            warn!(
+                uri = %request.uri(),
+                fingerprint = %key.fingerprint(),
+                role = ?role,
+                "Expired API key rejected"
+            );
+            Err(ApiError::Auth("invalid API key".into()))
+        }
+        KeyCheck::Unknown => {
+            warn!("Invalid API key provided for {}", request.uri());
+            Err(ApiError::Auth("invalid API key".into()))
+        }
     }
+}
 
-    // Log successful authentication with request details
-    info!(
-        method = %request.method(),
-        uri = %request.uri(),
-        "Request authenticated successfully"
-    );
-    
-    // Continue processing the request
-    Ok(next.run(request).await)
+/// Header set on every response of an instance running with `DEMO_MODE`.
+pub const DEMO_HEADER: &str = "x-demo-mode";
+
+/// Middleware that marks every response as coming from a demo instance.
+/// 
+/// JSON object bodies get a `"demo": true` field; streamed, other and
+/// bodies above `MAX_REWRITTEN_BODY_BYTES` are left as they are. Every response carries the `x-demo-mode`
+/// header.
+/// 
+/// # Returns
+/// * `Ok(Response)` - The labelled response
+/// * `Err(ApiError)` - 500 if a JSON body cannot be read
+pub async fn demo_label_middleware(
+    request: Request<Body>,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let (mut parts, body) = next.run(request).await.into_parts();
+    parts.headers.insert(DEMO_HEADER, HeaderValue::from_static("true"));
+
+    let bytes = match buffer_json_body(&parts, body, "label it").await? {
+        BufferedBody::Json(bytes) => bytes,
+        BufferedBody::Other(body) => return Ok(Response::from_parts(parts, body)),
+    };
+    let bytes = match serde_json::from_slice::<Value>(&bytes) {
+        Ok(Value::Object(mut object)) => {
+            object.insert("demo".to_string(), Value::Bool(true));
+            parts.headers.remove(header::CONTENT_LENGTH);
+            Bytes::from(Value::Object(object).to_string())
+        }
+        _ => bytes,
+    };
+    Ok(Response::from_parts(parts, Body::from(bytes)))
 }
 
+/// Largest response body the middleware rewriting JSON envelopes buffer;
+/// larger bodies are passed on unchanged.
+const MAX_REWRITTEN_BODY_BYTES: usize = 16 * 1024 * 1024;
+
+/// A response body, read into memory when it is a JSON document that can be rewritten.
+enum BufferedBody {
+    Json(Bytes),
+    Other(Body),
+}
+
+/// Reads a JSON response body of up to `MAX_REWRITTEN_BODY_BYTES`.
+/// 
+/// Other content types, such as exports and event streams, are returned
+/// untouched. A body found to be larger while it is read is returned whole,
+/// the chunks read so far followed by the rest.
+/// 
+/// # Returns
+/// * `Ok(BufferedBody)` - The bytes of a JSON body, or the body as it was
+/// * `Err(ApiError)` - 500 if the body cannot be read
+async fn buffer_json_body(parts: &Parts, body: Body, purpose: &str) -> Result<BufferedBody, ApiError> {
+    let is_json = parts
+        .headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"));
+    if !is_json {
+        return Ok(BufferedBody::Other(body));
+    }
+
+    let mut stream = body.into_data_stream();
+    let mut chunks = Vec::new();
+    let mut size = 0;
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| {
+            error!("Failed to read response body to {}: {}", purpose, e);
+            ApiError::Internal("Failed to read response body".into())
+        })?;
+        size += chunk.len();
+        chunks.push(chunk);
+        if size > MAX_REWRITTEN_BODY_BYTES {
+            let read = futures::stream::iter(chunks.into_iter().map(Ok::<_, axum::Error>));
+            return Ok(BufferedBody::Other(Body::from_stream(read.chain(stream))));
+        }
+    }
+    Ok(BufferedBody::Json(chunks.concat().into()))
+}
+
+/// Header selecting the response envelope version, echoed on every response.
+pub const API_VERSION_HEADER: &str = "x-api-version";
+
+/// Top-level fields of the version 1 envelope.
+const V1_ENVELOPE_FIELDS: &[&str] = &["data", "status", "error"];
+
+/// Middleware that renders responses in the envelope version the client asked for.
+/// 
+/// The version comes from the `x-api-version` header, or `API_VERSION` when
+/// the header is absent. Version 2 responses pass through untouched. For
+/// version 1, JSON envelopes are cut down to `data`, `status` and
+/// `error`, so clients written against the first releases, including ones
+/// that reject unknown fields, keep working. Streamed and other bodies, and
+/// ones above `MAX_REWRITTEN_BODY_BYTES`, are the same in both versions.
+/// The version is added to the request's extensions for handlers whose
+/// `data` changed shape between versions.
+/// 
+/// # Returns
+/// * `Ok(Response)` - The response in the requested envelope
+/// * `Err(ApiError)` - 400 for an unsupported version, 500 if a JSON body cannot be read
+pub async fn api_version_middleware(
+    State(state): State<Arc<AppState>>,
+    mut request: Request<Body>,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let version = match request.headers().get(API_VERSION_HEADER) {
+        Some(value) => value
+            .to_str()
+            .map_err(|e| e.to_string())
+            .and_then(|value| value.parse::<ApiVersion>().map_err(|e| e.to_string()))
+            .map_err(|e| ApiError::Validation(format!("Unsupported {}: {}", API_VERSION_HEADER, e)))?,
+        None => state.config.api_version,
+    };
+    request.extensions_mut().insert(version);
+    let (mut parts, body) = next.run(request).await.into_parts();
+    parts
+        .headers
+        .insert(API_VERSION_HEADER, HeaderValue::from_static(version.as_str()));
+
+    if version != ApiVersion::V1 {
+        return Ok(Response::from_parts(parts, body));
+    }
+    let bytes = match buffer_json_body(&parts, body, "render it for version 1").await? {
+        BufferedBody::Json(bytes) => bytes,
+        BufferedBody::Other(body) => return Ok(Response::from_parts(parts, body)),
+    };
+    let bytes = match serde_json::from_slice::<Value>(&bytes) {
+        Ok(Value::Object(mut object)) if object.contains_key("status") => {
+            object.retain(|key, _| V1_ENVELOPE_FIELDS.contains(&key.as_str()));
+            parts.headers.remove(header::CONTENT_LENGTH);
+            Bytes::from(Value::Object(object).to_string())
+        }
+        _ => bytes,
+    };
+    Ok(Response::from_parts(parts, Body::from(bytes)))
+}
+
+/// Header naming the deployment a response came from, set from `ENVIRONMENT`.
+pub const ENVIRONMENT_HEADER: &str = "x-environment";
+
+/// Middleware that tags every response with the instance's `ENVIRONMENT`.
+/// 
+/// Lets clients and operators tell staging from production answers
+/// without asking the instance. Responses keep an `x-environment` header
+/// already set by a handler.
+/// 
+/// # Returns
+/// The response, tagged when `ENVIRONMENT` is set
+pub async fn environment_middleware(
+    State(state): State<Arc<AppState>>,
+    request: Request<Body>,
+    next: Next,
+) -> Response {
+    let mut response = next.run(request).await;
+    // Checked by Config::validate, so only an unset value is skipped
+    if let Some(value) = state
+        .config
+        .environment
+        .as_deref()
+        .and_then(|name| HeaderValue::from_str(name).ok())
+    {
+        response.headers_mut().entry(ENVIRONMENT_HEADER).or_insert(value);
+    }
+    response
+}
+
+/// Largest request body that will be buffered for logging.
+/// Bodies above this size (or without a content length) are never sampled.
+const MAX_SAMPLED_BODY_BYTES: usize = 1024 * 1024;
+
+/// Substrings of JSON keys whose values are replaced before a body is logged.
+const REDACTED_KEYS: &[&str] = &["key", "token", "secret", "password", "authorization"];
+
 /// Middleware that logs request and response details.
 /// 
 /// This middleware captures timing information and logs details about incoming
 /// requests and their corresponding responses. It includes HTTP method, URI,
-/// status code, and request duration.
+/// status code, and request duration. When `LOG_BODIES` is enabled, a truncated
+/// and redacted sample of the request body is logged as well.
+/// 
+/// When `SLOW_REQUEST_MS` is set (it is off by default), requests slower than
+/// it are logged at warn level with their route, the time spent in OpenAI and
+/// Qdrant calls, and for POSTs a redacted body snippet, so POST bodies are
+/// buffered while it is on. The per-request `UpstreamTimings` are stored in the request
+/// extensions and filled in by the services.
 /// 
 /// # Arguments
+/// * `state` - Application state containing the logging configuration
 /// * `request` - The incoming HTTP request
 /// * `next` - The next middleware in the chain
 /// 
 /// # Returns
 /// * `Ok(Response)` - The processed response
-/// * `Err(StatusCode)` - If an error occurs during processing
+/// * `Err(ApiError)` - 400 if a body to be logged cannot be read
 pub async fn logging_middleware(
+    State(state): State<Arc<AppState>>,
     request: Request<Body>,
     next: Next,
-) -> Result<Response, StatusCode> {
+) -> Result<Response, ApiError> {
     // Store request details and start timing
     let method = request.method().clone();
     let uri = request.uri().clone();
-    let start = std::time::Instant::now();
+    let start = Instant::now();
+
+    let client_ip = request
+        .extensions()
+        .get::<ClientIp>()
+        .map_or_else(|| "unknown".to_string(), |client| client.shown.clone());
 
     // Log incoming request details
     info!(
         method = %method,
         uri = %uri,
+        client_ip = %client_ip,
         "Incoming request"
     );
 
-    // Process the request and measure duration
-    let response = next.run(request).await;
+    // Buffer the body when it may be logged, rebuilding the request afterwards
+    let slow_threshold = (state.config.slow_request_ms > 0)
+        .then(|| Duration::from_millis(state.config.slow_request_ms));
+    let max_bytes = state.config.log_body_max_bytes;
+    let capture_body = state.config.log_bodies || (slow_threshold.is_some() && method == Method::POST);
+    let (mut request, body) = if capture_body {
+        buffer_request_body(request).await?
+    } else {
+        (request, None)
+    };
+    if let (true, Some(bytes)) = (state.config.log_bodies, &body) {
+        info!(
+            method = %method,
+            uri = %uri,
+            body = %redacted_body_sample(bytes, max_bytes),
+            "Request body sample"
+        );
+    }
+
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_owned())
+        .unwrap_or_else(|| uri.path().to_owned());
+    let timings = Arc::new(UpstreamTimings::default());
+    request.extensions_mut().insert(timings.clone());
+
+    // Process the request and measure duration. The guard tracks the request
+    // in the in-flight gauge; if the client disconnects, axum drops this future
+    // mid-await and the guard reports the abort.
+    let mut guard = RequestGuard::new(state.clone(), &method, &route, start);
+    let response = timings.clone().scope(next.run(request)).await;
+    guard.completed = true;
     let duration = start.elapsed();
+    state.stats.record_request(&route, response.status().as_u16(), duration);
+
+    if let Some(threshold) = slow_threshold.filter(|t| duration > *t) {
+        let snippet = match (&method, &body) {
+            (&Method::POST, Some(bytes)) => redacted_body_sample(bytes, max_bytes),
+            _ => String::new(),
+        };
+        warn!(
+            method = %method,
+            route = %route,
+            status = %response.status(),
+            duration = ?duration,
+            threshold = ?threshold,
+            openai = ?timings.openai(),
+            qdrant = ?timings.qdrant(),
+            body = %snippet,
+            "Slow request"
+        );
+    }
 
     // Log response details with appropriate level based on status
     if response.status().is_success() {
@@ -96,6 +661,7 @@ pub async fn logging_middleware(
             uri = %uri,
             status = %response.status(),
             duration = ?duration,
+            client_ip = %client_ip,
             "Request completed successfully"
         );
     } else {
@@ -104,9 +670,260 @@ pub async fn logging_middleware(
             uri = %uri,
             status = %response.status(),
             duration = ?duration,
+            client_ip = %client_ip,
             "Request failed"
         );
     }
 
     Ok(response)
-} 
\ No newline at end of file
+} 
+
+/// Tracks a request for the lifetime of its processing future.
+/// 
+/// The guard counts the request in the in-flight gauge used for shutdown
+/// draining, and logs requests whose future was dropped before producing a
+/// response. Handlers await all upstream calls inline, so dropping the request
+/// future (which happens when the client disconnects) also cancels any
+/// in-flight OpenAI or Qdrant requests. Aborted requests are counted per route.
+struct RequestGuard {
+    state: Arc<AppState>,
+    method: Method,
+    route: String,
+    start: Instant,
+    completed: bool,
+}
+
+impl RequestGuard {
+    fn new(state: Arc<AppState>, method: &Method, route: &str, start: Instant) -> Self {
+        state.in_flight_requests.fetch_add(1, Ordering::Relaxed);
+        Self {
+            state,
+            method: method.clone(),
+            route: route.to_owned(),
+            start,
+            completed: false,
+        }
+    }
+}
+
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        self.state.in_flight_requests.fetch_sub(1, Ordering::Relaxed);
+        if !self.completed {
+            self.state.metrics.record_request_cancelled(&self.route);
+            warn!(
+                method = %self.method,
+                route = %self.route,
+                duration = ?self.start.elapsed(),
+                "Request aborted before completion; upstream calls cancelled"
+            );
+        }
+    }
+}
+
+/// Buffers the request body and returns an equivalent request, so downstream
+/// handlers can still consume the body, along with the buffered bytes.
+/// 
+/// Bodies without a content length or larger than `MAX_SAMPLED_BODY_BYTES`
+/// are passed through untouched and no bytes are returned.
+async fn buffer_request_body(
+    request: Request<Body>,
+) -> Result<(Request<Body>, Option<Bytes>), ApiError> {
+    // Only buffer bodies with a known, reasonable size
+    let content_length = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok());
+    if !matches!(content_length, Some(len) if len > 0 && len <= MAX_SAMPLED_BODY_BYTES) {
+        return Ok((request, None));
+    }
+
+    let (parts, body) = request.into_parts();
+    let bytes = axum::body::to_bytes(body, MAX_SAMPLED_BODY_BYTES)
+        .await
+        .map_err(|e| {
+            warn!("Failed to buffer request body for logging: {}", e);
+            ApiError::Validation("request body could not be read".into())
+        })?;
+
+    Ok((Request::from_parts(parts, Body::from(bytes.clone())), Some(bytes)))
+}
+
+/// Produces a log-safe sample of a request body.
+///
+/// JSON bodies have the values of sensitive-looking keys replaced with
+/// `[REDACTED]`; the result is truncated to at most `max_bytes` bytes.
+fn redacted_body_sample(bytes: &[u8], max_bytes: usize) -> String {
+    let mut sample = match serde_json::from_slice::<Value>(bytes) {
+        Ok(mut value) => {
+            redact_value(&mut value);
+            value.to_string()
+        }
+        Err(_) => String::from_utf8_lossy(bytes).into_owned(),
+    };
+
+    if sample.len() > max_bytes {
+        let mut end = max_bytes;
+        while !sample.is_char_boundary(end) {
+            end -= 1;
+        }
+        sample.truncate(end);
+        sample.push_str("...(truncated)");
+    }
+    sample
+}
+
+/// Recursively redacts values stored under sensitive keys.
+fn redact_value(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, v) in map.iter_mut() {
+                let key = key.to_ascii_lowercase();
+                if REDACTED_KEYS.iter().any(|k| key.contains(k)) {
+                    *v = Value::String("[REDACTED]".into());
+                } else {
+                    redact_value(v);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact_value),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::{Body, Bytes},
+        http::{header, Request, StatusCode},
+        middleware::from_fn_with_state,
+        response::Response,
+        routing::{get, post},
+        Router,
+    };
+    use futures::{channel::mpsc::UnboundedSender, StreamExt};
+    use std::io;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use tower::ServiceExt;
+
+    use super::{logging_middleware, shed_middleware};
+    use crate::paths;
+    use crate::state::AppState;
+    use crate::test_support;
+
+    /// Routes answering after `delay`, behind the shedding and logging
+    /// middleware in the order `create_router` applies them.
+    fn slow_router(state: Arc<AppState>, delay: Duration) -> Router {
+        let slow = move || async move {
+            tokio::time::sleep(delay).await;
+            "done"
+        };
+        Router::new()
+            .route("/slow", get(slow))
+            .route(paths::HEALTH, get(slow))
+            .route_layer(from_fn_with_state(state.clone(), shed_middleware))
+            .route_layer(from_fn_with_state(state, logging_middleware))
+    }
+
+    async fn call(router: &Router, uri: &str) -> (Response, Duration) {
+        let start = Instant::now();
+        let request = Request::get(uri).body(Body::empty()).unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        (response, start.elapsed())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn requests_above_the_high_water_mark_are_shed_quickly() {
+        let app = test_support::app(&[("SHED_HIGH_WATER_MARK", "4")]).await;
+        let router = slow_router(app.state.clone(), Duration::from_secs(2));
+
+        let flood = futures::future::join_all((0..20).map(|_| call(&router, "/slow"))).await;
+        let (served, shed): (Vec<_>, Vec<_>) = flood.iter().partition(|(response, _)| response.status().is_success());
+        assert_eq!(served.len(), 4);
+        for (response, elapsed) in shed {
+            assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+            assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+            assert!(*elapsed < Duration::from_millis(500), "shed after {:?}", elapsed);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn probes_are_never_shed() {
+        let app = test_support::app(&[("SHED_HIGH_WATER_MARK", "2")]).await;
+        let router = slow_router(app.state.clone(), Duration::from_millis(300));
+
+        let busy: Vec<_> = (0..2)
+            .map(|_| {
+                let router = router.clone();
+                tokio::spawn(async move { call(&router, "/slow").await.0.status() })
+            })
+            .collect();
+        // Every further request is above the mark
+        while app.state.in_flight() < 2 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        let (shed, _) = call(&router, "/slow").await;
+        assert_eq!(shed.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let (health, _) = call(&router, paths::HEALTH).await;
+        assert_eq!(health.status(), StatusCode::OK);
+        for status in futures::future::join_all(busy).await {
+            status.unwrap();
+        }
+    }
+
+    /// A route answering with the first chunk of the POSTed body, behind the
+    /// logging middleware, and a body whose second chunk is only sent once
+    /// the returned sender is used.
+    fn first_chunk_router(state: Arc<AppState>) -> Router {
+        let first_chunk = |body: Body| async move {
+            let chunk = body.into_data_stream().next().await;
+            chunk.map(|chunk| chunk.unwrap()).unwrap_or_default()
+        };
+        Router::new()
+            .route("/upload", post(first_chunk))
+            .route_layer(from_fn_with_state(state, logging_middleware))
+    }
+
+    fn upload(first: &'static str, rest: &'static str) -> (Request<Body>, UnboundedSender<io::Result<Bytes>>) {
+        let (sender, chunks) = futures::channel::mpsc::unbounded();
+        sender.unbounded_send(Ok(Bytes::from(first))).unwrap();
+        let request = Request::post("/upload")
+            .header(header::CONTENT_LENGTH, first.len() + rest.len())
+            .body(Body::from_stream(chunks))
+            .unwrap();
+        (request, sender)
+    }
+
+    #[test]
+    fn slow_request_logging_is_off_by_default() {
+        assert_eq!(test_support::config(&[]).slow_request_ms, 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn bodies_stream_to_the_handler_unless_they_may_be_logged() {
+        let app = test_support::app(&[]).await;
+        let router = first_chunk_router(app.state.clone());
+        let (request, _sender) = upload("first", "-rest");
+        let response = tokio::time::timeout(Duration::from_secs(5), router.oneshot(request))
+            .await
+            .expect("handler runs before the body is complete")
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body, "first");
+
+        let app = test_support::app(&[("SLOW_REQUEST_MS", "1")]).await;
+        let router = first_chunk_router(app.state.clone());
+        let (request, sender) = upload("first", "-rest");
+        let response = tokio::spawn(router.oneshot(request));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!response.is_finished(), "the body is buffered before the handler runs");
+        sender.unbounded_send(Ok(Bytes::from("-rest"))).unwrap();
+        drop(sender);
+        let response = response.await.unwrap().unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body, "first-rest");
+    }
+}
@@ -1,8 +1,180 @@
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Document {
     pub id: u64,
     pub text: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub embedding: Vec<f32>,
-} 
\ No newline at end of file
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub metadata: Value,
+    /// Time of the last write in milliseconds since the Unix epoch, set on upsert
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<u64>,
+    /// When the document was moved to the trash, in milliseconds since the
+    /// Unix epoch; unset for live documents
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<u64>,
+    /// Version number, counting every change since versioning was turned on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u64>,
+    /// Waiting to be re-embedded by the background worker
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub stale: bool,
+}
+
+/// An earlier version of a document, kept while `DOCUMENT_VERSIONS` is set.
+#[derive(Debug, Clone, Serialize)]
+pub struct DocumentVersion {
+    /// Version number; the document's first version is 1
+    pub version: u64,
+    pub text: String,
+    #[serde(skip_serializing_if = "Value::is_null")]
+    pub metadata: Value,
+    /// When this version was written, in milliseconds since the Unix epoch
+    pub updated_at: Option<u64>,
+    /// When a newer version replaced it, in milliseconds since the Unix epoch
+    pub archived_at: Option<u64>,
+}
+
+impl Document {
+    /// Derives a document id from its text, so re-ingesting the same
+    /// text updates the existing point instead of creating a duplicate.
+    ///
+    /// The id is the first 8 bytes of the text's SHA-256, so it stays the
+    /// same across builds and Rust releases.
+    pub fn id_for_text(text: &str) -> u64 {
+        digest_u64(&[text.as_bytes()])
+    }
+
+    /// Returns a strong ETag for the stored document, derived from its
+    /// last write time and a SHA-256 of its text and metadata.
+    pub fn etag(&self) -> String {
+        let metadata = self.metadata.to_string();
+        let hash = digest_u64(&[self.text.as_bytes(), metadata.as_bytes()]);
+        format!("\"{}-{:016x}\"", self.updated_at.unwrap_or_default(), hash)
+    }
+
+    /// Turns a document whose text was cut into `chunks` into one document
+    /// per chunk, for texts longer than the embedding model accepts.
+    ///
+    /// The first chunk keeps the document's id, so `If-Match` and reads by
+    /// id still find it; the others get ids derived from it and their
+    /// position. Each chunk's metadata gains `parent_id`, `chunk` (its
+    /// position from 0) and `chunks` (how many there are), so searches can
+    /// `group_by` the parent and deletes can filter on it. A single chunk
+    /// leaves the document as it is.
+    ///
+    /// # Returns
+    /// * `Ok(Vec<Document>)` - One document per chunk, in order
+    /// * `Err(String)` - If the metadata is neither an object nor null
+    pub fn into_chunks(mut self, chunks: Vec<String>) -> Result<Vec<Document>, String> {
+        if chunks.len() <= 1 {
+            return Ok(vec![self]);
+        }
+        let metadata = match std::mem::take(&mut self.metadata) {
+            Value::Object(metadata) => metadata,
+            Value::Null => Map::new(),
+            _ => return Err("metadata must be an object for texts stored as chunks".to_string()),
+        };
+        let count = chunks.len();
+        Ok(chunks
+            .into_iter()
+            .enumerate()
+            .map(|(chunk, text)| {
+                let mut metadata = metadata.clone();
+                metadata.insert("parent_id".into(), self.id.into());
+                metadata.insert("chunk".into(), chunk.into());
+                metadata.insert("chunks".into(), count.into());
+                Document {
+                    id: Self::chunk_id(self.id, chunk),
+                    text,
+                    metadata: Value::Object(metadata),
+                    ..self.clone()
+                }
+            })
+            .collect())
+    }
+
+    /// Returns the id of chunk `chunk` of the document with id `parent`.
+    pub fn chunk_id(parent: u64, chunk: usize) -> u64 {
+        match chunk {
+            0 => parent,
+            _ => digest_u64(&[&parent.to_be_bytes(), &(chunk as u64).to_be_bytes()]),
+        }
+    }
+}
+
+/// First 8 bytes of the SHA-256 of `parts`, each prefixed with its length
+/// so that moving bytes from one part to the next changes the digest.
+fn digest_u64(parts: &[&[u8]]) -> u64 {
+    let mut context = aws_lc_rs::digest::Context::new(&aws_lc_rs::digest::SHA256);
+    for part in parts {
+        context.update(&(part.len() as u64).to_be_bytes());
+        context.update(part);
+    }
+    let digest = context.finish();
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&digest.as_ref()[..8]);
+    u64::from_be_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn text_ids_are_stable() {
+        // Pinned so a change of hash function, which would duplicate every
+        // stored document on re-ingest, fails here first.
+        assert_eq!(Document::id_for_text("Rust is fast"), 3_059_692_258_720_350_622);
+        assert_ne!(Document::id_for_text("Rust is fast"), Document::id_for_text("Rust is fast "));
+    }
+
+    #[test]
+    fn etags_follow_text_metadata_and_write_time() {
+        let document = Document {
+            text: "Rust is fast".into(),
+            metadata: json!({ "source": "wiki", "lang": "en" }),
+            updated_at: Some(1_700_000_000_000),
+            ..Default::default()
+        };
+        assert_eq!(document.etag(), "\"1700000000000-ceffaf07bb1c2547\"");
+
+        let reordered = Document { metadata: json!({ "lang": "en", "source": "wiki" }), ..document.clone() };
+        assert_eq!(reordered.etag(), document.etag());
+        let retagged = Document { metadata: json!({ "source": "blog", "lang": "en" }), ..document.clone() };
+        assert_ne!(retagged.etag(), document.etag());
+        let rewritten = Document { updated_at: Some(1_700_000_000_001), ..document.clone() };
+        assert_ne!(rewritten.etag(), document.etag());
+        let moved = Document {
+            text: "Rust is fast{\"lang\":\"en\",".into(),
+            metadata: json!({ "source": "wiki" }),
+            ..document.clone()
+        };
+        assert_ne!(moved.etag(), document.etag());
+    }
+
+    #[test]
+    fn long_documents_become_chunks_of_one_parent() {
+        let document = Document {
+            id: 7,
+            text: "one two three".into(),
+            metadata: json!({ "source": "wiki" }),
+            ..Default::default()
+        };
+        let chunks = document.clone().into_chunks(vec!["one ".into(), "two ".into(), "three".into()]).unwrap();
+        assert_eq!(chunks.iter().map(|chunk| chunk.text.as_str()).collect::<String>(), document.text);
+        assert_eq!(chunks[0].id, 7);
+        assert_eq!(chunks[2].id, Document::chunk_id(7, 2));
+        assert_ne!(chunks[1].id, chunks[2].id);
+        assert_eq!(chunks[1].metadata, json!({ "source": "wiki", "parent_id": 7, "chunk": 1, "chunks": 3 }));
+
+        let whole = document.clone().into_chunks(vec![document.text.clone()]).unwrap();
+        assert_eq!(whole[0].metadata, document.metadata);
+        let tagged = Document { metadata: json!("wiki"), ..document };
+        assert!(tagged.into_chunks(vec!["a".into(), "b".into()]).is_err());
+    }
+}
@@ -0,0 +1,153 @@
+use serde::Serialize;
+use unicode_normalization::UnicodeNormalization;
+
+/// Invisible characters removed by `strip_zero_width`.
+///
+/// The zero-width joiner and non-joiner (U+200D, U+200C) are kept, since
+/// they change how emoji sequences and several scripts are rendered.
+const ZERO_WIDTH: &[char] = &['\u{200B}', '\u{2060}', '\u{FEFF}'];
+
+/// Line separators other than `\n` rewritten by `newlines`.
+const LINE_BREAKS: &[char] = &['\r', '\u{0085}', '\u{2028}', '\u{2029}'];
+
+/// Steps applied to text before it is embedded or hashed into a document id.
+///
+/// Texts that only differ in how they are encoded, e.g. NFC vs NFD accents,
+/// non-breaking spaces or Windows line endings, come out identical.
+/// Applying the same steps twice changes nothing further. Every step is off
+/// by default, since turning one on changes the ids of texts it rewrites.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct TextNormalization {
+    /// Compose characters to Unicode normalization form C
+    pub nfc: bool,
+    /// Turn `\r\n`, lone `\r` and Unicode line separators into `\n`
+    pub newlines: bool,
+    /// Collapse runs of spaces within a line to one space, drop trailing
+    /// spaces and leading or trailing blank lines, and keep at most one
+    /// blank line between paragraphs. Indentation at the start of a line
+    /// is kept as sent, so code keeps its structure.
+    pub whitespace: bool,
+    /// Remove zero-width spaces, word joiners and byte order marks
+    pub strip_zero_width: bool,
+}
+
+impl TextNormalization {
+    /// Every step turned off; texts are used as sent.
+    pub const NONE: Self = Self {
+        nfc: false,
+        newlines: false,
+        whitespace: false,
+        strip_zero_width: false,
+    };
+
+    /// Returns the normalized text.
+    pub fn apply(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        if self.newlines && text.contains(LINE_BREAKS) {
+            text = text.replace("\r\n", "\n").replace(LINE_BREAKS, "\n");
+        }
+        if self.strip_zero_width && text.contains(ZERO_WIDTH) {
+            text = text.replace(ZERO_WIDTH, "");
+        }
+        if self.nfc {
+            text = text.nfc().collect();
+        }
+        if self.whitespace {
+            text = collapse_whitespace(&text);
+        }
+        text
+    }
+}
+
+/// Collapses whitespace line by line, keeping paragraph breaks and each
+/// line's indentation.
+///
+/// Only `\n` separates lines, so run `newlines` first to treat other line
+/// endings the same way.
+fn collapse_whitespace(text: &str) -> String {
+    let mut collapsed = String::with_capacity(text.len());
+    let mut blank_lines = 0;
+    for line in text.split('\n') {
+        let content = line.trim_start();
+        let mut words = content.split_whitespace().peekable();
+        if words.peek().is_none() {
+            blank_lines += 1;
+            continue;
+        }
+        if !collapsed.is_empty() {
+            collapsed.push_str(if blank_lines > 0 { "\n\n" } else { "\n" });
+        }
+        blank_lines = 0;
+        collapsed.push_str(&line[..line.len() - content.len()]);
+        for (i, word) in words.enumerate() {
+            if i > 0 {
+                collapsed.push(' ');
+            }
+            collapsed.push_str(word);
+        }
+    }
+    collapsed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    use crate::models::Document;
+
+    const ALL: TextNormalization = TextNormalization {
+        nfc: true,
+        newlines: true,
+        whitespace: true,
+        strip_zero_width: true,
+    };
+
+    /// Texts mixing letters, accents in both forms, code indentation and
+    /// every kind of whitespace and invisible character the steps handle.
+    fn text() -> impl Strategy<Value = String> {
+        let pieces = prop::sample::select(vec![
+            "a", "e", "é", "e\u{0301}", "\u{0301}", "Å", "A\u{030A}", "\u{212B}", "ñ", "한", "\u{1100}\u{1161}",
+            " ", "  ", "\t", "\u{00A0}", "\n", "\r\n", "\r", "\u{2028}", "\u{0085}", "\u{200B}", "\u{FEFF}",
+            "\u{200D}", "fn main() {", "    x", "}", "😀",
+        ]);
+        prop::collection::vec(prop_oneof![pieces.prop_map(str::to_string), any::<char>().prop_map(String::from)], 0..40)
+            .prop_map(|pieces| pieces.concat())
+    }
+
+    fn steps() -> impl Strategy<Value = TextNormalization> {
+        any::<[bool; 4]>().prop_map(|[nfc, newlines, whitespace, strip_zero_width]| TextNormalization {
+            nfc,
+            newlines,
+            whitespace,
+            strip_zero_width,
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn normalizing_twice_changes_nothing(text in text(), steps in steps()) {
+            let once = steps.apply(&text);
+            prop_assert_eq!(steps.apply(&once), once);
+        }
+
+        #[test]
+        fn composed_and_decomposed_texts_get_one_id(text in text()) {
+            let composed = ALL.apply(&text.nfc().collect::<String>());
+            let decomposed = ALL.apply(&text.nfd().collect::<String>());
+            prop_assert_eq!(Document::id_for_text(&composed), Document::id_for_text(&decomposed));
+        }
+    }
+
+    #[test]
+    fn everything_is_off_by_default() {
+        let text = "  e\u{0301}  x\r\n\u{200B}";
+        assert_eq!(TextNormalization::default().apply(text), text);
+    }
+
+    #[test]
+    fn indentation_is_kept() {
+        let code = "\n\nfn main() {\n    let x  =  1;   \n\n\n\tif x {}\n}\n";
+        assert_eq!(ALL.apply(code), "fn main() {\n    let x = 1;\n\n\tif x {}\n}");
+    }
+}
@@ -0,0 +1,208 @@
+use anyhow::anyhow;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+pub const EMBED: &str = "/api/embed";
+pub const COMPARE_MODELS: &str = "/api/embed/compare-models";
+pub const CHAT: &str = "/api/chat";
+pub const RESET: &str = "/api/reset";
+pub const SEARCH: &str = "/api/search";
+pub const SCORE_DISTRIBUTION: &str = "/api/search/distribution";
+pub const VALIDATE_FILTER: &str = "/api/search/validate";
+pub const ASK: &str = "/api/ask";
+pub const SIMILARITY: &str = "/api/similarity";
+pub const TOKENIZE: &str = "/api/tokenize";
+pub const DOCUMENTS: &str = "/api/documents";
+pub const DOCUMENT: &str = "/api/documents/:id";
+pub const RESTORE_DOCUMENT: &str = "/api/documents/:id/restore";
+pub const DOCUMENT_VERSIONS: &str = "/api/documents/:id/versions";
+pub const RESTORE_VERSION: &str = "/api/documents/:id/versions/:version/restore";
+pub const GET_DOCUMENTS: &str = "/api/documents/get";
+pub const DELETE_DOCUMENTS: &str = "/api/documents/delete";
+pub const DELETE_DOCUMENTS_BY_IDS: &str = "/api/documents/delete-by-ids";
+pub const EXPORT: &str = "/api/documents/export";
+pub const IMPORT: &str = "/api/documents/import";
+pub const RAW_DOCUMENTS: &str = "/api/documents/raw";
+pub const JOBS: &str = "/api/jobs";
+pub const JOB: &str = "/api/jobs/:id";
+pub const STATS: &str = "/api/stats";
+pub const METRICS: &str = "/metrics";
+pub const VERSION: &str = "/api/version";
+pub const HEALTH: &str = "/health";
+pub const READY: &str = "/ready";
+pub const ADMIN_COLLECTIONS: &str = "/api/admin/collections";
+pub const ADMIN_COLLECTION: &str = "/api/admin/collections/:name";
+pub const ADMIN_ALIAS: &str = "/api/admin/alias";
+pub const ADMIN_KEYS: &str = "/api/admin/keys";
+pub const ADMIN_QUOTAS: &str = "/api/admin/quotas";
+pub const ADMIN_TOKEN_BUDGET: &str = "/api/admin/token-budget";
+pub const ADMIN_TOKEN_BUDGET_RESET: &str = "/api/admin/token-budget/reset";
+pub const ADMIN_PURGE_TRASH: &str = "/api/admin/trash/purge";
+pub const ADMIN_MARK_STALE: &str = "/api/admin/documents/mark-stale";
+pub const ADMIN_CONFIG: &str = "/api/admin/config";
+pub const ADMIN_REINDEX: &str = "/api/admin/reindex";
+
+/// Every route path, for settings keyed by route.
+pub const ALL: &[&str] = &[
+    EMBED,
+    COMPARE_MODELS,
+    CHAT,
+    RESET,
+    SEARCH,
+    SCORE_DISTRIBUTION,
+    VALIDATE_FILTER,
+    ASK,
+    SIMILARITY,
+    TOKENIZE,
+    DOCUMENTS,
+    DOCUMENT,
+    RESTORE_DOCUMENT,
+    DOCUMENT_VERSIONS,
+    RESTORE_VERSION,
+    GET_DOCUMENTS,
+    DELETE_DOCUMENTS,
+    DELETE_DOCUMENTS_BY_IDS,
+    EXPORT,
+    IMPORT,
+    RAW_DOCUMENTS,
+    JOBS,
+    JOB,
+    STATS,
+    METRICS,
+    VERSION,
+    HEALTH,
+    READY,
+    ADMIN_COLLECTIONS,
+    ADMIN_COLLECTION,
+    ADMIN_ALIAS,
+    ADMIN_KEYS,
+    ADMIN_QUOTAS,
+    ADMIN_TOKEN_BUDGET,
+    ADMIN_TOKEN_BUDGET_RESET,
+    ADMIN_PURGE_TRASH,
+    ADMIN_MARK_STALE,
+    ADMIN_CONFIG,
+    ADMIN_REINDEX,
+];
+
+/// Short name of a route in settings: its path without the leading
+/// `/api/` (or `/`), e.g. `chat` or `documents/export`.
+pub fn key(path: &str) -> &str {
+    path.strip_prefix("/api/").unwrap_or_else(|| path.trim_start_matches('/'))
+}
+
+/// Paths that operators may serve without authentication via `PUBLIC_PATHS`.
+/// `VERSION` is always served without one; every other route always
+/// requires an API key.
+pub const PUBLIC_ELIGIBLE: &[&str] = &[METRICS, HEALTH, READY];
+
+/// Routes served even above `SHED_HIGH_WATER_MARK`: probes, scrapes and
+/// stats must keep answering under load, and an export is cheap to admit
+/// and costly to restart.
+pub const NEVER_SHED: &[&str] = &[HEALTH, READY, METRICS, STATS, EXPORT];
+
+/// Routes that call Qdrant; they fail fast with a 503 while it is unreachable.
+pub const QDRANT_BACKED: &[&str] = &[
+    SEARCH,
+    SCORE_DISTRIBUTION,
+    ASK,
+    DOCUMENTS,
+    DOCUMENT,
+    RESTORE_DOCUMENT,
+    DOCUMENT_VERSIONS,
+    RESTORE_VERSION,
+    GET_DOCUMENTS,
+    DELETE_DOCUMENTS,
+    DELETE_DOCUMENTS_BY_IDS,
+    EXPORT,
+    IMPORT,
+    RAW_DOCUMENTS,
+    RESET,
+    ADMIN_COLLECTIONS,
+    ADMIN_COLLECTION,
+    ADMIN_ALIAS,
+    ADMIN_PURGE_TRASH,
+    ADMIN_MARK_STALE,
+    ADMIN_REINDEX,
+];
+
+/// Routes that call Qdrant only with `SESSION_STORE=qdrant`, where chat
+/// histories are kept in a collection; they fail fast like `QDRANT_BACKED`.
+pub const QDRANT_SESSIONS: &[&str] = &[CHAT];
+
+/// Per-route request timeouts in seconds, keyed by route path.
+/// 
+/// Parsed from a comma-separated list of `route=seconds` entries, where the
+/// route is its `key` (e.g. `chat=60,embed=10,documents/import=0`).
+/// A route set to 0 has no timeout.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RouteTimeouts(HashMap<&'static str, u64>);
+
+impl RouteTimeouts {
+    /// Returns the timeout of a route, falling back to `default_secs`.
+    /// 
+    /// # Returns
+    /// * `Some(Duration)` - The time the route may take
+    /// * `None` - If the route has no timeout
+    pub fn get(&self, path: &str, default_secs: u64) -> Option<Duration> {
+        let secs = self.0.get(path).copied().unwrap_or(default_secs);
+        (secs > 0).then(|| Duration::from_secs(secs))
+    }
+}
+
+impl FromStr for RouteTimeouts {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut timeouts = HashMap::new();
+        for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (route, secs) = entry
+                .split_once('=')
+                .ok_or_else(|| anyhow!("expected 'route=seconds', got '{}'", entry))?;
+            let route = route.trim();
+            let path = ALL
+                .iter()
+                .find(|path| key(path) == route)
+                .ok_or_else(|| {
+                    let keys: Vec<&str> = ALL.iter().map(|path| key(path)).collect();
+                    anyhow!("unknown route '{}' (valid routes: {})", route, keys.join(", "))
+                })?;
+            let secs = secs
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| anyhow!("invalid timeout '{}' for route '{}'", secs.trim(), route))?;
+            timeouts.insert(*path, secs);
+        }
+        Ok(Self(timeouts))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn route_lists_only_name_routes() {
+        for list in [PUBLIC_ELIGIBLE, NEVER_SHED, QDRANT_BACKED, QDRANT_SESSIONS] {
+            for path in list {
+                assert!(ALL.contains(path), "{} is not a route", path);
+            }
+        }
+        assert!(!PUBLIC_ELIGIBLE.contains(&VERSION));
+    }
+
+    #[test]
+    fn route_timeouts_are_keyed_by_short_route_names() {
+        let timeouts: RouteTimeouts = "chat=60, documents/import=0".parse().unwrap();
+        assert_eq!(timeouts.get(CHAT, 30), Some(Duration::from_secs(60)));
+        assert_eq!(timeouts.get(IMPORT, 30), None);
+        assert_eq!(timeouts.get(SEARCH, 30), Some(Duration::from_secs(30)));
+
+        let unknown = "api/chat=60".parse::<RouteTimeouts>().unwrap_err().to_string();
+        assert!(unknown.contains("unknown route 'api/chat'"), "{}", unknown);
+        assert!("chat".parse::<RouteTimeouts>().is_err());
+        assert!("chat=soon".parse::<RouteTimeouts>().is_err());
+    }
+}
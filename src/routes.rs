@@ -1,42 +1,574 @@
 use axum::{
+    extract::DefaultBodyLimit,
+    error_handling::HandleErrorLayer,
+    http::StatusCode,
     middleware,
-    routing::{post, Router},
+    routing::{delete, get, post, Router},
+    BoxError, Json,
 };
+use serde_json::Value;
 use std::sync::Arc;
-use tower_http::trace::TraceLayer;
+use tower::{limit::GlobalConcurrencyLimitLayer, ServiceBuilder};
+use tower_http::{
+    compression::{
+        predicate::{NotForContentType, Predicate, SizeAbove},
+        CompressionLayer,
+    },
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    trace::TraceLayer,
+};
 
 use crate::{
-    handlers::{handle_embed, handle_message, handle_reset},
-    middleware::{auth_middleware, logging_middleware},
+    handlers::{
+        admin::{handle_create_collection, handle_delete_collection, handle_get_alias, handle_list_collections,
+            handle_list_keys, handle_list_quotas, handle_mark_stale, handle_purge_trash, handle_reset_token_budget,
+            handle_switch_alias, handle_token_budget, handle_config,
+        },
+        handle_ask, handle_compare_models, handle_delete_by_filter, handle_delete_by_ids, handle_delete_document, handle_embed, handle_export, handle_get_document, handle_get_documents,
+        handle_get_job, handle_health, handle_method_not_allowed, handle_not_found, handle_import, handle_list_documents, handle_list_jobs, handle_list_versions, handle_message,
+        handle_metrics, handle_ready, handle_reindex, handle_reset, handle_restore_document, handle_restore_version, handle_score_distribution, handle_search, handle_similarity, handle_stats, handle_store_document,
+        handle_store_raw_document, handle_tokenize, handle_update_document, handle_validate_filter, handle_version,
+    },
+    config::Config,
+    keys::KeyRole,
+    paths,
+    middleware::{
+        admin_auth_middleware, api_version_middleware, auth_middleware, client_ip_middleware, demo_label_middleware,
+        environment_middleware, logging_middleware, operator_auth_middleware, public_rate_limit_middleware,
+        qdrant_availability_middleware, shed_middleware, timeout_middleware,
+    },
     state::AppState,
+    types::{ApiResponse, ErrorCode},
 };
 
-/// API route paths
-pub mod paths {
-    pub const EMBED: &str = "/api/embed";
-    pub const CHAT: &str = "/api/chat";
-    pub const RESET: &str = "/api/reset";
+/// Returns whether `path` is served without authentication.
+/// 
+/// Only paths in `paths::PUBLIC_ELIGIBLE` can be public, and only when
+/// listed in `PUBLIC_PATHS`; config loading rejects any other entry.
+pub fn is_public(public_paths: &[String], path: &str) -> bool {
+    paths::PUBLIC_ELIGIBLE.contains(&path) && public_paths.iter().any(|p| p == path)
 }
 
-/// Creates the application router with all routes and middleware
+/// Body limit of every route but the chat route, axum's default.
+const DEFAULT_BODY_LIMIT: usize = 2 * 1024 * 1024;
+
+/// Returns the body limit of the chat route.
+/// 
+/// Room for `MAX_CHAT_IMAGES` inline images of `MAX_IMAGE_BYTES` each, base64
+/// encoded, on top of the default limit for the message itself. Larger
+/// bodies are refused with 413 before they are read in full.
+pub fn chat_body_limit(config: &Config) -> usize {
+    let encoded_image = config.max_image_bytes.div_ceil(3).saturating_mul(4);
+    config.max_chat_images.saturating_mul(encoded_image).saturating_add(DEFAULT_BODY_LIMIT)
+}
+
+/// Creates the application router with all routes and middleware.
+/// 
+/// Routes are split into a `public` router without authentication and a
+/// `protected` router behind `auth_middleware`, merged at the end. Which
+/// of the eligible operational routes are public is decided by `is_public`.
 pub fn create_router(state: Arc<AppState>) -> Router {
     // Create base router with routes
-    let router = Router::new()
+    let protected = Router::new()
         .route(paths::EMBED, post(handle_embed))
-        .route(paths::CHAT, post(handle_message))
-        .route(paths::RESET, post(handle_reset));
+        .route(paths::COMPARE_MODELS, post(handle_compare_models))
+        .route(
+            paths::CHAT,
+            post(handle_message).layer(DefaultBodyLimit::max(chat_body_limit(&state.config))),
+        )
+        .route(paths::SEARCH, post(handle_search))
+        .route(paths::SCORE_DISTRIBUTION, post(handle_score_distribution))
+        .route(paths::VALIDATE_FILTER, post(handle_validate_filter))
+        .route(paths::ASK, post(handle_ask))
+        .route(paths::SIMILARITY, post(handle_similarity))
+        .route(paths::TOKENIZE, post(handle_tokenize))
+        .route(paths::DOCUMENTS, post(handle_store_document).get(handle_list_documents))
+        .route(
+            paths::DOCUMENT,
+            get(handle_get_document).patch(handle_update_document).delete(handle_delete_document),
+        )
+        .route(paths::RESTORE_DOCUMENT, post(handle_restore_document))
+        .route(paths::DOCUMENT_VERSIONS, get(handle_list_versions))
+        .route(paths::RESTORE_VERSION, post(handle_restore_version))
+        .route(paths::GET_DOCUMENTS, post(handle_get_documents))
+        .route(paths::DELETE_DOCUMENTS, post(handle_delete_by_filter))
+        .route(paths::DELETE_DOCUMENTS_BY_IDS, post(handle_delete_by_ids))
+        .route(paths::EXPORT, get(handle_export))
+        .route(paths::IMPORT, post(handle_import))
+        .route(paths::RAW_DOCUMENTS, post(handle_store_raw_document))
+        .route(paths::JOBS, get(handle_list_jobs))
+        .route(paths::JOB, get(handle_get_job));
 
-    // Add middleware layers
-    router
-        // Global middleware
-        .layer(TraceLayer::new_for_http())
+    // Operational routes are protected unless listed in PUBLIC_PATHS. The
+    // version is always public, so clients can check features before they
+    // have a key.
+    let mut public = Router::new().route(paths::VERSION, get(handle_version));
+    let mut protected = protected;
+    for (path, route) in [
+        (paths::METRICS, get(handle_metrics)),
+        (paths::HEALTH, get(handle_health)),
+        (paths::READY, get(handle_ready)),
+    ] {
+        if is_public(&state.config.public_paths, path) {
+            public = public.route(path, route);
+        } else {
+            protected = protected.route(path, route);
+        }
+    }
+
+    let router = with_tracing(protected)
+        // Bound the handler's time by the route's timeout
+        .route_layer(middleware::from_fn_with_state(state.clone(), timeout_middleware))
+        // Fail fast while Qdrant is unreachable, once the key has been checked
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            qdrant_availability_middleware,
+        ))
         // Authentication middleware
         .route_layer(middleware::from_fn_with_state(
             state.clone(),
             auth_middleware,
         ))
+        .merge(
+            with_tracing(public)
+                .layer(middleware::from_fn_with_state(state.clone(), timeout_middleware))
+                // Without a key there is no quota, so limit by client address
+                .layer(middleware::from_fn_with_state(state.clone(), public_rate_limit_middleware)),
+        );
+
+    // Reset and stats take the admin key, and regular keys only while no
+    // admin key is configured. The destructive reset endpoint can be left
+    // out entirely (requests then 404).
+    let operator = Router::new().route(paths::STATS, get(handle_stats));
+    let operator = if state.config.enable_reset {
+        operator.route(paths::RESET, post(handle_reset))
+    } else {
+        operator
+    };
+    let router = router.merge(
+        with_tracing(operator)
+            .route_layer(middleware::from_fn_with_state(state.clone(), timeout_middleware))
+            .route_layer(middleware::from_fn_with_state(
+                state.clone(),
+                qdrant_availability_middleware,
+            ))
+            .route_layer(middleware::from_fn_with_state(state.clone(), operator_auth_middleware)),
+    );
+
+    // Admin routes check the admin key instead of the regular one, and are
+    // only served when an admin key is configured
+    let router = if state.config.api_keys.has_role(KeyRole::Admin) {
+        let admin = Router::new()
+            .route(
+                paths::ADMIN_COLLECTIONS,
+                post(handle_create_collection).get(handle_list_collections),
+            )
+            .route(paths::ADMIN_COLLECTION, delete(handle_delete_collection))
+            .route(paths::ADMIN_ALIAS, get(handle_get_alias).put(handle_switch_alias))
+            .route(paths::ADMIN_KEYS, get(handle_list_keys))
+            .route(paths::ADMIN_QUOTAS, get(handle_list_quotas))
+            .route(paths::ADMIN_TOKEN_BUDGET, get(handle_token_budget))
+            .route(paths::ADMIN_TOKEN_BUDGET_RESET, post(handle_reset_token_budget))
+            .route(paths::ADMIN_PURGE_TRASH, post(handle_purge_trash))
+            .route(paths::ADMIN_MARK_STALE, post(handle_mark_stale))
+            .route(paths::ADMIN_CONFIG, get(handle_config))
+            .route(paths::ADMIN_REINDEX, post(handle_reindex));
+        router.merge(
+            with_tracing(admin)
+                .route_layer(middleware::from_fn_with_state(state.clone(), timeout_middleware))
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    qdrant_availability_middleware,
+                ))
+                .route_layer(middleware::from_fn_with_state(state.clone(), admin_auth_middleware)),
+        )
+    } else {
+        router
+    };
+
+    // Shed load instead of queueing once MAX_CONCURRENT_REQUESTS are being
+    // processed. The limit is shared by all routes.
+    let router = match state.config.max_concurrent_requests {
+        0 => router,
+        max => router.layer(
+            ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(|_: BoxError| async {
+                    (
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        Json(ApiResponse::<Value>::error(
+                            ErrorCode::Unavailable,
+                            "Server is at capacity, retry later".into(),
+                        )),
+                    )
+                }))
+                .load_shed()
+                .layer(GlobalConcurrencyLimitLayer::new(max)),
+        ),
+    };
+
+    let router = router
+        // Answer a known path with an unsupported method in the error envelope
+        .method_not_allowed_fallback(handle_method_not_allowed)
+        // Shed requests above SHED_HIGH_WATER_MARK, counted by the logging
+        // middleware, before they take a concurrency slot
+        .route_layer(middleware::from_fn_with_state(state.clone(), shed_middleware))
         // Logging middleware
-        .route_layer(middleware::from_fn(logging_middleware))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            logging_middleware,
+        ))
+        // Resolve the client address and apply the IP filter and rate limit
+        // before anything else, authentication included
+        .route_layer(middleware::from_fn_with_state(state.clone(), client_ip_middleware))
+        // Answer unknown paths in the error envelope
+        .fallback(handle_not_found)
+        // Render the envelope in the requested version, refusals by the layers
+        // above and unknown paths included
+        .layer(middleware::from_fn_with_state(state.clone(), api_version_middleware));
+
+    // Label the responses of a demo instance, refusals by the layers above included
+    let router = if state.config.demo_mode {
+        router.layer(middleware::from_fn(demo_label_middleware))
+    } else {
+        router
+    };
+
+    // Name the deployment on every response, unmatched routes included
+    let router = if state.config.environment.is_some() {
+        router.layer(middleware::from_fn_with_state(state.clone(), environment_middleware))
+    } else {
+        router
+    };
+
+    // Compress responses for clients that accept gzip or br, once every layer
+    // above has rewritten them. Event streams are exempt; streamed exports are
+    // compressed chunk by chunk as they are produced.
+    let router = if state.config.compression_enabled {
+        let predicate = SizeAbove::new(state.config.compression_min_bytes)
+            .and(NotForContentType::GRPC)
+            .and(NotForContentType::IMAGES)
+            .and(NotForContentType::SSE);
+        router.layer(
+            CompressionLayer::new()
+                .gzip(true)
+                .br(true)
+                .compress_when(predicate),
+        )
+    } else {
+        router
+    };
+
+    router
+        // Tag every request with an x-request-id (kept if the client sent one)
+        // and echo it in the response
+        .layer(
+            ServiceBuilder::new()
+                .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+                .layer(PropagateRequestIdLayer::x_request_id()),
+        )
         // Application state
         .with_state(state)
-} 
\ No newline at end of file
+}
+
+/// Adds request tracing to the given routes.
+fn with_tracing(router: Router<Arc<AppState>>) -> Router<Arc<AppState>> {
+    router
+        // Global middleware
+        .layer(TraceLayer::new_for_http())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header, Method, StatusCode},
+    };
+    use serde_json::{json, Value};
+    use std::io::Read;
+
+    use super::paths;
+    use crate::middleware::API_VERSION_HEADER;
+    use crate::test_support::{self, TestApp, USER_KEY};
+
+    /// Stores a document and searches for it, sending `x-api-version: version` when given.
+    async fn search(app: &TestApp, version: Option<&str>) -> test_support::TestResponse {
+        let stored = app.post(paths::DOCUMENTS, &json!({ "text": "Rust is fast", "metadata": { "lang": "en" } })).await;
+        assert_eq!(stored.status, StatusCode::OK, "{}", stored.text);
+        let mut request = test_support::request(Method::POST, paths::SEARCH, Some(USER_KEY))
+            .header(header::CONTENT_TYPE, "application/json");
+        if let Some(version) = version {
+            request = request.header(API_VERSION_HEADER, version);
+        }
+        app.send(request.body(Body::from(json!({ "query": "Rust" }).to_string())).unwrap())
+            .await
+    }
+
+    /// The fields of an object, without the label every demo response gets.
+    fn keys(value: &Value) -> Vec<&str> {
+        let mut keys: Vec<&str> = value
+            .as_object()
+            .expect("object")
+            .keys()
+            .map(String::as_str)
+            .filter(|key| *key != "demo")
+            .collect();
+        keys.sort_unstable();
+        keys
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn version_2_is_the_default() {
+        let app = test_support::app(&[]).await;
+        let found = search(&app, None).await;
+        assert_eq!(found.status, StatusCode::OK, "{}", found.text);
+        assert_eq!(found.headers[API_VERSION_HEADER], "2");
+        let hit = &found.body["data"]["hits"][0];
+        assert_eq!(hit["text"], "Rust is fast");
+        assert_eq!(hit["metadata"]["lang"], "en");
+        assert!(found.body["data"]["query_time_ms"].is_u64());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn version_1_gets_the_original_search_shape() {
+        let app = test_support::app(&[]).await;
+        let found = search(&app, Some("1")).await;
+        assert_eq!(found.status, StatusCode::OK, "{}", found.text);
+        assert_eq!(found.headers[API_VERSION_HEADER], "1");
+        assert_eq!(keys(&found.body), ["data", "status"]);
+        assert_eq!(keys(&found.body["data"]), ["low_confidence", "results", "score_threshold"]);
+        let hit = &found.body["data"]["results"][0];
+        assert_eq!(hit["payload"]["text"], "Rust is fast");
+        assert_eq!(hit["payload"]["metadata"]["lang"], "en");
+        assert!(hit.get("text").is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn api_version_sets_the_default() {
+        let app = test_support::app(&[("API_VERSION", "1")]).await;
+        let found = search(&app, None).await;
+        assert_eq!(found.headers[API_VERSION_HEADER], "1");
+        assert!(found.body["data"]["results"].is_array());
+
+        let found = search(&app, Some("2")).await;
+        assert!(found.body["data"]["hits"].is_array());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn version_1_errors_drop_the_newer_fields() {
+        let app = test_support::app(&[]).await;
+        let refused = app
+            .send(
+                test_support::request(Method::GET, paths::JOBS, None)
+                    .header(API_VERSION_HEADER, "1")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await;
+        assert_eq!(refused.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(keys(&refused.body), ["data", "error", "status"]);
+
+        let refused = app.call(Method::GET, paths::JOBS, None).await;
+        assert!(refused.body["code"].is_string());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn version_1_responses_are_compressed_once_cut_down() {
+        let app = test_support::app(&[("COMPRESSION_MIN_BYTES", "0")]).await;
+        let request = test_support::request(Method::GET, paths::JOBS, Some(USER_KEY)).header(API_VERSION_HEADER, "1");
+        let found = accepting(&app, "gzip", request).await;
+        assert_eq!(found.status, StatusCode::OK);
+        assert_eq!(found.headers[header::CONTENT_ENCODING], "gzip");
+        let body: Value = serde_json::from_str(&gunzip(&found.bytes)).unwrap();
+        assert_eq!(keys(&body), ["data", "status"]);
+        assert_eq!(body["status"], "success");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn unsupported_versions_are_refused() {
+        let app = test_support::app(&[]).await;
+        let refused = search(&app, Some("3")).await;
+        assert_eq!(refused.status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn chat_bodies_have_room_for_the_allowed_images() {
+        let app = test_support::app(&[("MAX_CHAT_IMAGES", "1"), ("MAX_IMAGE_BYTES", "3000000")]).await;
+        let image = |bytes: usize, mime_type: &str| {
+            json!({ "message": "What is this?", "images": [{ "data": "A".repeat(bytes / 3 * 4), "mime_type": mime_type }] })
+        };
+
+        // Larger than axum's default limit once encoded, but within MAX_IMAGE_BYTES
+        let allowed = app.post(paths::CHAT, &image(2_700_000, "image/png")).await;
+        assert_eq!(allowed.status, StatusCode::OK, "{}", allowed.text);
+
+        let too_large = app.post(paths::CHAT, &image(3_000_003, "image/png")).await;
+        assert_eq!(too_large.status, StatusCode::PAYLOAD_TOO_LARGE, "{}", too_large.text);
+        assert!(too_large.text.contains("image data must not exceed 3000000 bytes"), "{}", too_large.text);
+
+        let over_the_body_limit = app.post(paths::CHAT, &image(6_000_000, "image/png")).await;
+        assert_eq!(over_the_body_limit.status, StatusCode::PAYLOAD_TOO_LARGE);
+
+        let wrong_type = app.post(paths::CHAT, &image(30, "image/bmp")).await;
+        assert_eq!(wrong_type.status, StatusCode::BAD_REQUEST);
+        assert_eq!(wrong_type.body["code"], "validation_failed");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn routing_failures_use_the_error_envelope() {
+        let app = test_support::app(&[]).await;
+
+        let missing = app.get("/api/nothing-here").await;
+        assert_eq!(missing.status, StatusCode::NOT_FOUND);
+        assert_eq!(missing.body["code"], "not_found");
+        assert_eq!(missing.body["demo"], true);
+
+        let wrong_method = app.call(Method::DELETE, paths::SEARCH, Some(USER_KEY)).await;
+        assert_eq!(wrong_method.status, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(wrong_method.body["code"], "validation_failed");
+        assert_eq!(wrong_method.headers[header::ALLOW], "POST");
+
+        let bad_query = app.get(&format!("{}?limit=many", paths::DOCUMENTS)).await;
+        assert_eq!(bad_query.status, StatusCode::BAD_REQUEST);
+        assert_eq!(bad_query.body["code"], "validation_failed");
+
+        let bad_path = app.get("/api/documents/not-a-number").await;
+        assert_eq!(bad_path.status, StatusCode::BAD_REQUEST);
+        assert_eq!(bad_path.body["code"], "validation_failed");
+
+        let text = "x".repeat(3 * 1024 * 1024);
+        let too_large = app.post(paths::SEARCH, &json!({ "query": text })).await;
+        assert_eq!(too_large.status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(too_large.body["code"], "validation_failed");
+
+        let empty = app.post(paths::CHAT, &json!({ "message": " " })).await;
+        assert_eq!(empty.status, StatusCode::BAD_REQUEST);
+        assert_eq!(empty.body["status"], "error");
+
+        let v1 = app
+            .send(
+                test_support::request(Method::GET, "/api/nothing-here", Some(USER_KEY))
+                    .header(API_VERSION_HEADER, "1")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await;
+        assert_eq!(v1.status, StatusCode::NOT_FOUND);
+        assert_eq!(keys(&v1.body), ["data", "error", "status"]);
+    }
+
+    /// Sends `request` asking for a `encoding` response.
+    async fn accepting(app: &TestApp, encoding: &str, request: axum::http::request::Builder) -> test_support::TestResponse {
+        app.send(request.header(header::ACCEPT_ENCODING, encoding).body(Body::empty()).unwrap())
+            .await
+    }
+
+    fn gunzip(bytes: &[u8]) -> String {
+        let mut text = String::new();
+        flate2::read::GzDecoder::new(bytes).read_to_string(&mut text).expect("valid gzip");
+        text
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn large_responses_are_compressed_for_clients_that_accept_it() {
+        let app = test_support::app(&[]).await;
+        for i in 0..20 {
+            let text = format!("Rust document {} {}", i, "with a long and repetitive body ".repeat(20));
+            app.post(paths::DOCUMENTS, &json!({ "text": text })).await;
+        }
+        let search = || {
+            test_support::request(Method::POST, paths::SEARCH, Some(USER_KEY))
+                .header(header::CONTENT_TYPE, "application/json")
+        };
+        let query = json!({ "query": "Rust document", "limit": 20 }).to_string();
+
+        let plain = app.send(search().body(Body::from(query.clone())).unwrap()).await;
+        assert!(plain.headers.get(header::CONTENT_ENCODING).is_none());
+        let gzipped = app
+            .send(search().header(header::ACCEPT_ENCODING, "gzip").body(Body::from(query.clone())).unwrap())
+            .await;
+        assert_eq!(gzipped.status, StatusCode::OK);
+        assert_eq!(gzipped.headers[header::CONTENT_ENCODING], "gzip");
+        assert!(gzipped.bytes.len() < plain.bytes.len() / 2);
+        let body: Value = serde_json::from_str(&gunzip(&gzipped.bytes)).unwrap();
+        assert_eq!(body["data"]["hits"], plain.body["data"]["hits"]);
+        let brotli = app
+            .send(search().header(header::ACCEPT_ENCODING, "br").body(Body::from(query)).unwrap())
+            .await;
+        assert_eq!(brotli.headers[header::CONTENT_ENCODING], "br");
+
+        // Responses under COMPRESSION_MIN_BYTES are sent as they are
+        let small = accepting(&app, "gzip", test_support::request(Method::GET, paths::JOBS, Some(USER_KEY))).await;
+        assert_eq!(small.status, StatusCode::OK);
+        assert!(small.headers.get(header::CONTENT_ENCODING).is_none());
+
+        // The streamed export decompresses to every line
+        let export = test_support::request(Method::GET, paths::EXPORT, Some(USER_KEY));
+        let export = accepting(&app, "gzip", export).await;
+        assert_eq!(export.headers[header::CONTENT_ENCODING], "gzip");
+        assert_eq!(gunzip(&export.bytes).lines().count(), 20);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn compression_can_be_switched_off() {
+        let app = test_support::app(&[("COMPRESSION_ENABLED", "false"), ("COMPRESSION_MIN_BYTES", "0")]).await;
+        app.post(paths::DOCUMENTS, &json!({ "text": "Rust is fast" })).await;
+        let export = accepting(&app, "gzip", test_support::request(Method::GET, paths::EXPORT, Some(USER_KEY))).await;
+        assert!(export.headers.get(header::CONTENT_ENCODING).is_none());
+        assert_eq!(export.text.lines().count(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn chat_fails_fast_only_when_sessions_need_qdrant() {
+        let chat = json!({ "message": "hello" });
+
+        let memory = test_support::app(&[]).await;
+        memory.state.qdrant_service.mark_connecting();
+        assert_eq!(memory.post(paths::SEARCH, &json!({ "query": "rust" })).await.status, StatusCode::SERVICE_UNAVAILABLE);
+        let answered = memory.post(paths::CHAT, &chat).await;
+        assert_eq!(answered.status, StatusCode::OK, "{}", answered.text);
+
+        let stored = test_support::app(&[("SESSION_STORE", "qdrant")]).await;
+        stored.state.qdrant_service.mark_connecting();
+        let refused = stored.post(paths::CHAT, &chat).await;
+        assert_eq!(refused.status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(refused.text.contains("Qdrant is unreachable"), "{}", refused.text);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn only_listed_operational_routes_are_public() {
+        let app = test_support::app(&[("PUBLIC_PATHS", "/metrics")]).await;
+        let anonymous = |path: &'static str| app.call(Method::GET, path, None);
+
+        assert_eq!(anonymous(paths::METRICS).await.status, StatusCode::OK);
+        assert_eq!(anonymous(paths::VERSION).await.status, StatusCode::OK);
+        for path in [paths::HEALTH, paths::READY, paths::JOBS, paths::ADMIN_KEYS] {
+            assert_eq!(anonymous(path).await.status, StatusCode::UNAUTHORIZED, "{}", path);
+        }
+        let search = app.call_json(Method::POST, paths::SEARCH, None, &json!({ "query": "rust" })).await;
+        assert_eq!(search.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(app.get(paths::HEALTH).await.status, StatusCode::OK);
+
+        // Without PUBLIC_PATHS only the version is served without a key
+        let closed = test_support::app(&[]).await;
+        assert_eq!(closed.call(Method::GET, paths::METRICS, None).await.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(closed.call(Method::GET, paths::VERSION, None).await.status, StatusCode::OK);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn enable_reset_alone_decides_whether_reset_is_served() {
+        // The default config, without ADMIN_API_KEY
+        let app = test_support::app(&[("ADMIN_API_KEY", "")]).await;
+        assert_eq!(app.get(paths::ADMIN_KEYS).await.status, StatusCode::NOT_FOUND);
+        let reset = app.call(Method::POST, paths::RESET, Some(USER_KEY)).await;
+        assert_eq!(reset.status, StatusCode::OK, "{}", reset.text);
+        let version = app.call(Method::GET, paths::VERSION, None).await;
+        assert_eq!(version.body["data"]["features"]["reset"], true);
+
+        let off = test_support::app(&[("ADMIN_API_KEY", ""), ("ENABLE_RESET", "false")]).await;
+        let reset = off.call(Method::POST, paths::RESET, Some(USER_KEY)).await;
+        assert_eq!(reset.status, StatusCode::NOT_FOUND);
+        assert_eq!(off.get(paths::STATS).await.status, StatusCode::OK);
+        let version = off.call(Method::GET, paths::VERSION, None).await;
+        assert_eq!(version.body["data"]["features"]["reset"], false);
+    }
+}
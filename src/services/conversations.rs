@@ -0,0 +1,374 @@
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::OnceCell;
+use tracing::warn;
+
+use crate::keys::unix_now;
+use crate::services::{
+    openai::{models, ChatTurn},
+    qdrant, tokenizer, QdrantService,
+};
+
+/// Maximum number of turns kept per conversation; older turns are dropped first.
+pub const MAX_HISTORY_TURNS: usize = 50;
+
+/// Start of the system turn that holds a summary of earlier turns.
+pub const SUMMARY_PREFIX: &str = "Summary of the earlier conversation: ";
+
+/// Tokens a chat message takes on top of its text, for its role and formatting.
+const TURN_OVERHEAD_TOKENS: usize = 4;
+
+/// Where chat histories are kept, selected with `SESSION_STORE`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SessionBackend {
+    /// Process memory; histories are lost on restart
+    #[default]
+    Memory,
+    /// A dedicated Qdrant collection; histories survive restarts and deploys
+    Qdrant,
+}
+
+impl FromStr for SessionBackend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "memory" => Ok(Self::Memory),
+            "qdrant" => Ok(Self::Qdrant),
+            _ => Err(anyhow!("expected 'memory' or 'qdrant', got '{}'", s)),
+        }
+    }
+}
+
+/// Storage for chat histories keyed by conversation id.
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    /// Returns the stored turns of a conversation, oldest first, or an
+    /// empty history for unknown or expired conversations.
+    async fn history(&self, id: &str) -> Result<Vec<ChatTurn>>;
+
+    /// Appends the turns of one exchange to a conversation, creating it if
+    /// needed. Histories are capped at `MAX_HISTORY_TURNS`.
+    async fn append(&self, id: &str, turns: Vec<ChatTurn>) -> Result<()>;
+
+    /// Replaces the history of a conversation, e.g. after summarizing it.
+    ///
+    /// Turns appended by another request since the history was loaded
+    /// are overwritten.
+    async fn replace(&self, id: &str, turns: Vec<ChatTurn>) -> Result<()>;
+
+    /// Removes expired conversations and returns how many were removed.
+    async fn sweep(&self) -> Result<u64>;
+}
+
+/// Returns how many leading turns to fold into a summary, or `None` while
+/// the history is within both limits.
+///
+/// A history is summarized once it has more than `max_turns` turns or takes
+/// more than `max_tokens` tokens of the chat model; a limit of 0 is off. Up
+/// to `keep_recent` of the latest turns are kept verbatim, as long as they
+/// take at most half of `max_tokens`, so a few long turns can't keep the
+/// history over the limit.
+pub fn summary_split(turns: &[ChatTurn], max_turns: usize, max_tokens: usize, keep_recent: usize) -> Option<usize> {
+    let tokens: Vec<usize> = match max_tokens {
+        0 => vec![0; turns.len()],
+        _ => turns.iter().map(turn_tokens).collect(),
+    };
+    let too_long = max_turns > 0 && turns.len() > max_turns;
+    let too_large = max_tokens > 0 && tokens.iter().sum::<usize>() > max_tokens;
+    if !too_long && !too_large {
+        return None;
+    }
+
+    let mut budget = if max_tokens > 0 { max_tokens / 2 } else { usize::MAX };
+    let kept = tokens
+        .iter()
+        .rev()
+        .take(keep_recent)
+        .take_while(|&&n| match budget.checked_sub(n) {
+            Some(left) => {
+                budget = left;
+                true
+            }
+            None => false,
+        })
+        .count();
+    let split = turns.len() - kept;
+    (split > 0).then_some(split)
+}
+
+/// Tokens a turn adds to a chat request, counted with the chat model's encoding.
+pub fn turn_tokens(turn: &ChatTurn) -> usize {
+    let text = tokenizer::count_tokens(models::CHAT_MODEL, &turn.content).unwrap_or(turn.content.len() / 4);
+    text + TURN_OVERHEAD_TOKENS
+}
+
+/// Drops the oldest turns beyond `MAX_HISTORY_TURNS`.
+fn truncate_history(turns: &mut Vec<ChatTurn>) {
+    let excess = turns.len().saturating_sub(MAX_HISTORY_TURNS);
+    turns.drain(..excess);
+}
+
+/// A stored conversation and the time it was last used.
+struct Conversation {
+    turns: Vec<ChatTurn>,
+    last_used: Instant,
+}
+
+/// Bounded in-memory store of chat histories keyed by conversation id.
+///
+/// Conversations expire after `ttl` without use. When the store is full,
+/// the least recently used conversation is evicted to make room.
+pub struct ConversationStore {
+    /// Conversations keyed by id
+    conversations: Mutex<HashMap<String, Conversation>>,
+    /// Maximum number of conversations kept at once
+    max_conversations: usize,
+    /// Idle time after which a conversation is discarded
+    ttl: Duration,
+}
+
+impl ConversationStore {
+    /// Creates an empty conversation store.
+    ///
+    /// # Arguments
+    /// * `max_conversations` - Maximum number of conversations kept at once
+    /// * `ttl` - Idle time after which a conversation is discarded
+    pub fn new(max_conversations: usize, ttl: Duration) -> Self {
+        Self {
+            conversations: Mutex::new(HashMap::new()),
+            max_conversations,
+            ttl,
+        }
+    }
+
+    /// Returns the stored turns of a conversation.
+    ///
+    /// # Returns
+    /// The prior turns, or an empty history for unknown or expired conversations
+    pub fn history(&self, id: &str) -> Vec<ChatTurn> {
+        let mut conversations = self.lock();
+        match conversations.get(id) {
+            Some(conversation) if conversation.last_used.elapsed() < self.ttl => {
+                conversation.turns.clone()
+            }
+            Some(_) => {
+                conversations.remove(id);
+                Vec::new()
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// Appends turns to a conversation, creating it if needed.
+    ///
+    /// Appending (rather than replacing the history) keeps turns from
+    /// concurrent requests on the same conversation.
+    pub fn append(&self, id: &str, turns: impl IntoIterator<Item = ChatTurn>) {
+        let mut conversations = self.lock();
+        let now = Instant::now();
+
+        if !conversations.contains_key(id) {
+            conversations.retain(|_, c| now.duration_since(c.last_used) < self.ttl);
+            if conversations.len() >= self.max_conversations {
+                let oldest = conversations
+                    .iter()
+                    .min_by_key(|(_, c)| c.last_used)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    conversations.remove(&oldest);
+                }
+            }
+        }
+
+        let conversation = conversations.entry(id.to_string()).or_insert_with(|| Conversation {
+            turns: Vec::new(),
+            last_used: now,
+        });
+        conversation.turns.extend(turns);
+        truncate_history(&mut conversation.turns);
+        conversation.last_used = now;
+    }
+
+    /// Replaces the turns of a conversation, creating it if needed.
+    pub fn replace(&self, id: &str, turns: Vec<ChatTurn>) {
+        let mut conversations = self.lock();
+        if let Some(conversation) = conversations.get_mut(id) {
+            conversation.turns = turns;
+            truncate_history(&mut conversation.turns);
+            conversation.last_used = Instant::now();
+        } else {
+            drop(conversations);
+            self.append(id, turns);
+        }
+    }
+
+    /// Removes conversations idle for longer than the TTL.
+    ///
+    /// # Returns
+    /// The number of conversations removed
+    pub fn remove_expired(&self) -> usize {
+        let mut conversations = self.lock();
+        let before = conversations.len();
+        conversations.retain(|_, c| c.last_used.elapsed() < self.ttl);
+        before - conversations.len()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Conversation>> {
+        // A panic while holding the lock cannot leave the map inconsistent
+        self.conversations.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[async_trait]
+impl SessionStore for ConversationStore {
+    async fn history(&self, id: &str) -> Result<Vec<ChatTurn>> {
+        Ok(ConversationStore::history(self, id))
+    }
+
+    async fn append(&self, id: &str, turns: Vec<ChatTurn>) -> Result<()> {
+        ConversationStore::append(self, id, turns);
+        Ok(())
+    }
+
+    async fn replace(&self, id: &str, turns: Vec<ChatTurn>) -> Result<()> {
+        ConversationStore::replace(self, id, turns);
+        Ok(())
+    }
+
+    async fn sweep(&self) -> Result<u64> {
+        Ok(self.remove_expired() as u64)
+    }
+}
+
+/// Chat histories persisted in a dedicated Qdrant collection.
+///
+/// Each conversation is one point whose id is a UUID derived from the
+/// conversation id, carrying the history as a JSON `turns` payload field.
+/// An append reads the stored history and writes it back with the new
+/// exchange, so each turn costs one write. Two requests appending to the
+/// same conversation at the same moment can overwrite each other's turn.
+pub struct QdrantSessionStore {
+    /// Service bound to the session collection
+    qdrant: QdrantService,
+    /// Idle time after which a conversation is discarded
+    ttl: Duration,
+    /// Set once the session collection is known to exist
+    ready: OnceCell<()>,
+}
+
+impl QdrantSessionStore {
+    /// Creates a store on the collection of `qdrant`, creating the
+    /// collection if needed.
+    ///
+    /// # Arguments
+    /// * `qdrant` - Service whose collection holds the sessions
+    /// * `ttl` - Idle time after which a conversation is discarded
+    /// * `optional` - If Qdrant can't be reached, set the collection up on
+    ///   first use instead of failing
+    pub async fn open(qdrant: QdrantService, ttl: Duration, optional: bool) -> Result<Self> {
+        let store = Self { qdrant, ttl, ready: OnceCell::new() };
+        match store.ready().await {
+            Err(e) if optional && qdrant::is_unreachable(&e) => {
+                warn!("Qdrant not yet reachable, will create the session collection on first use: {:#}", e);
+            }
+            result => result?,
+        }
+        Ok(store)
+    }
+
+    /// Makes sure the session collection exists, failing fast while Qdrant is unreachable.
+    async fn ready(&self) -> Result<()> {
+        if !self.qdrant.admit() {
+            return Err(anyhow!("Qdrant is unreachable"));
+        }
+        self.ready
+            .get_or_try_init(|| self.qdrant.ensure_session_collection())
+            .await?;
+        Ok(())
+    }
+
+    /// Loads the stored turns, or an empty history if none are stored or they expired.
+    async fn load(&self, id: &str) -> Result<Vec<ChatTurn>> {
+        self.ready().await?;
+        let Some((turns, updated_at)) = self.qdrant.get_session(&point_id(id)).await? else {
+            return Ok(Vec::new());
+        };
+        if unix_now().saturating_sub(updated_at) >= self.ttl.as_secs() {
+            return Ok(Vec::new());
+        }
+        serde_json::from_value(turns)
+            .with_context(|| format!("stored history of conversation '{}' is malformed", id))
+    }
+}
+
+#[async_trait]
+impl SessionStore for QdrantSessionStore {
+    async fn history(&self, id: &str) -> Result<Vec<ChatTurn>> {
+        self.load(id).await
+    }
+
+    async fn append(&self, id: &str, turns: Vec<ChatTurn>) -> Result<()> {
+        let mut history = self.load(id).await?;
+        history.extend(turns);
+        self.replace(id, history).await
+    }
+
+    async fn replace(&self, id: &str, mut turns: Vec<ChatTurn>) -> Result<()> {
+        truncate_history(&mut turns);
+        self.ready().await?;
+        self.qdrant
+            .put_session(&point_id(id), id, &serde_json::to_value(&turns)?, unix_now())
+            .await
+    }
+
+    async fn sweep(&self) -> Result<u64> {
+        self.ready().await?;
+        let cutoff = unix_now().saturating_sub(self.ttl.as_secs());
+        self.qdrant.delete_sessions_before(cutoff).await
+    }
+}
+
+/// Derives a stable point UUID from a conversation id.
+fn point_id(id: &str) -> String {
+    let digest = aws_lc_rs::digest::digest(&aws_lc_rs::digest::SHA256, id.as_bytes());
+    let hex: String = digest.as_ref()[..16].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Alternating user and assistant turns of about `words` tokens each.
+    fn history(turns: usize, words: usize) -> Vec<ChatTurn> {
+        let text = "word ".repeat(words);
+        (0..turns)
+            .map(|i| if i % 2 == 0 { ChatTurn::user(text.as_str()) } else { ChatTurn::assistant(text.as_str()) })
+            .collect()
+    }
+
+    #[test]
+    fn many_short_turns_are_summarized_by_count() {
+        assert_eq!(summary_split(&history(30, 5), 30, 4096, 10), None);
+        assert_eq!(summary_split(&history(31, 5), 30, 4096, 10), Some(21));
+        assert_eq!(summary_split(&history(31, 5), 0, 4096, 10), None);
+    }
+
+    #[test]
+    fn a_few_long_turns_are_summarized_by_tokens() {
+        let long = history(4, 1500);
+        assert!(long.iter().map(turn_tokens).sum::<usize>() > 4096);
+        assert_eq!(summary_split(&long, 30, 0, 10), None);
+        // Only the turns fitting half the limit stay verbatim: one of 1500 tokens
+        assert_eq!(summary_split(&long, 30, 4096, 10), Some(3));
+        assert_eq!(summary_split(&long, 30, 3100, 10), Some(3));
+        // A single turn over half the limit is summarized as well
+        assert_eq!(summary_split(&long, 30, 2500, 10), Some(4));
+        assert_eq!(summary_split(&history(4, 100), 30, 4096, 10), None);
+    }
+}
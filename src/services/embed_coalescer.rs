@@ -0,0 +1,137 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::sync::{oneshot, Notify};
+
+/// A coalesced text's vector and the prompt tokens charged for it, or the
+/// error of the batch it was sent in.
+pub type Outcome = Result<(Vec<f32>, u32), String>;
+
+/// Texts collected for one batched embedding call.
+#[derive(Default)]
+struct Batch {
+    /// Texts in the order they joined; the leader's comes first
+    texts: Vec<String>,
+    /// Senders of the followers' outcomes, one per text after the first
+    waiters: Vec<oneshot::Sender<Outcome>>,
+}
+
+/// A batch and the signal that it is full.
+struct Shared {
+    batch: Mutex<Batch>,
+    full: Notify,
+}
+
+/// Collects single-text embedding requests arriving within a short window
+/// so they can be sent to OpenAI as one batch.
+///
+/// The first request of a window becomes the batch's leader: it waits for
+/// the window to pass or the batch to fill, makes the call, and hands each
+/// follower its vector. If the leader goes away first, its followers get
+/// no outcome and are expected to embed their text on their own.
+pub struct EmbedCoalescer {
+    /// How long a leader waits for followers
+    window: Duration,
+    /// Most texts sent in one call
+    max_batch: usize,
+    /// Batch that new requests join, if one is collecting
+    open: Mutex<Option<Arc<Shared>>>,
+}
+
+/// How a request takes part in a batch.
+pub enum Joined<'a> {
+    /// The request started the batch and makes the call
+    Leader(Leader<'a>),
+    /// The request waits for the leader's outcome
+    Follower(oneshot::Receiver<Outcome>),
+}
+
+/// The request that started a batch.
+///
+/// Dropping it closes the batch, so later requests start a new one.
+pub struct Leader<'a> {
+    coalescer: &'a EmbedCoalescer,
+    shared: Arc<Shared>,
+}
+
+impl EmbedCoalescer {
+    /// Creates a coalescer with no batch collecting.
+    ///
+    /// # Arguments
+    /// * `window` - How long a leader waits for followers
+    /// * `max_batch` - Most texts sent in one call
+    pub fn new(window: Duration, max_batch: usize) -> Self {
+        Self {
+            window,
+            max_batch,
+            open: Mutex::new(None),
+        }
+    }
+
+    /// Adds a text to the batch that is collecting, or starts one.
+    pub fn join(&self, text: &str) -> Joined<'_> {
+        let mut open = lock(&self.open);
+        if let Some(shared) = open.as_ref() {
+            let (sender, receiver) = oneshot::channel();
+            let mut batch = lock(&shared.batch);
+            batch.texts.push(text.to_string());
+            batch.waiters.push(sender);
+            if batch.texts.len() >= self.max_batch {
+                drop(batch);
+                // The permit is kept if the leader isn't waiting yet
+                shared.full.notify_one();
+                *open = None;
+            }
+            return Joined::Follower(receiver);
+        }
+
+        let shared = Arc::new(Shared {
+            batch: Mutex::new(Batch {
+                texts: vec![text.to_string()],
+                waiters: Vec::new(),
+            }),
+            full: Notify::new(),
+        });
+        if self.max_batch > 1 {
+            *open = Some(shared.clone());
+        }
+        Joined::Leader(Leader { coalescer: self, shared })
+    }
+}
+
+impl Leader<'_> {
+    /// Waits for the window to pass or the batch to fill, then closes it.
+    ///
+    /// # Returns
+    /// The batch's texts, the leader's first, and the senders of the
+    /// followers' outcomes in the order of `texts[1..]`
+    pub async fn collect(self) -> (Vec<String>, Vec<oneshot::Sender<Outcome>>) {
+        if self.coalescer.max_batch > 1 {
+            tokio::select! {
+                _ = tokio::time::sleep(self.coalescer.window) => {}
+                _ = self.shared.full.notified() => {}
+            }
+        }
+        self.close();
+        let batch = std::mem::take(&mut *lock(&self.shared.batch));
+        (batch.texts, batch.waiters)
+    }
+
+    /// Stops new requests from joining this batch.
+    fn close(&self) {
+        let mut open = lock(&self.coalescer.open);
+        if open.as_ref().is_some_and(|shared| Arc::ptr_eq(shared, &self.shared)) {
+            *open = None;
+        }
+    }
+}
+
+impl Drop for Leader<'_> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // Every update leaves the state consistent, so a poisoned lock is still usable
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
@@ -1,5 +1,13 @@
+pub mod conversations;
+pub mod embed_coalescer;
 pub mod openai;
+pub mod openai_queue;
 pub mod qdrant;
+pub mod response_cache;
+pub mod tokenizer;
 
-pub use openai::OpenAIService;
-pub use qdrant::QdrantService; 
\ No newline at end of file
+pub use conversations::{ConversationStore, QdrantSessionStore, SessionBackend, SessionStore};
+pub use openai::{HttpPoolSettings, OpenAIService};
+pub use openai_queue::{OpenAIQueue, QueueError};
+pub use qdrant::QdrantService;
+pub use response_cache::ResponseCache; 
\ No newline at end of file
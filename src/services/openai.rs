@@ -1,14 +1,35 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
 use async_openai::{
     config::OpenAIConfig,
     types::{
-        ChatCompletionRequestMessage, CreateChatCompletionRequest,
-        CreateEmbeddingRequest, EmbeddingInput,
-        ChatCompletionRequestUserMessageContent,
+        ChatCompletionRequestAssistantMessage, ChatCompletionRequestMessage, ChatCompletionRequestSystemMessage,
+        CreateChatCompletionRequest,
+        CreateEmbeddingRequest, EmbeddingInput, EncodingFormat,
+        ChatCompletionRequestUserMessageContent, ChatCompletionRequestUserMessageContentPart,
+        ChatCompletionRequestMessageContentPartImage, ChatCompletionRequestMessageContentPartText,
+        ChatCompletionTokenLogprob, FinishReason, ImageUrl, Stop,
     },
     Client,
 };
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{
+    metrics::{Metrics, OpenAIOperation},
+    normalize::TextNormalization,
+    services::{
+        embed_coalescer::{EmbedCoalescer, Joined},
+        response_cache::CacheKey,
+        tokenizer, ResponseCache,
+    },
+    types::EmbeddingRequest,
+    vector_math,
+};
 
 /// Model configuration for OpenAI API calls.
 /// These constants define the specific models and parameters used.
@@ -16,28 +37,464 @@ pub mod models {
     /// GPT-4 Turbo model for chat completions (latest version)
     pub const CHAT_MODEL: &str = "gpt-4";
     /// Text embedding model (latest version)
-    pub const EMBEDDING_MODEL: &str = "text-embedding-3-large";         
+    pub const EMBEDDING_MODEL: &str = "text-embedding-3-large";
     /// Temperature for response generation (0.0 = deterministic, 1.0 = creative)
     pub const TEMPERATURE: f32 = 0.7;
+    /// Highest temperature OpenAI accepts
+    pub const MAX_TEMPERATURE: f32 = 2.0;
+    /// Default chat models a request may pick instead of `CHAT_MODEL`
+    pub const CHAT_MODELS: &[&str] = &["gpt-4", "gpt-4-turbo", "gpt-4o", "gpt-4o-mini"];
+    /// Instructions for condensing the early part of a long conversation
+    pub const SUMMARY_PROMPT: &str = "Summarize the following conversation in a few sentences. \
+        Keep every fact, preference and decision the user stated, and any open questions. \
+        Write it as notes for the assistant continuing the conversation.";
+    /// Default model for rewriting follow-up questions into search queries
+    pub const REWRITE_MODEL: &str = "gpt-4o-mini";
+    /// Instructions for turning a follow-up question into a standalone search query
+    pub const REWRITE_PROMPT: &str = "Rewrite the follow-up question at the end of this \
+        conversation as a standalone search query. Resolve pronouns and references such as \
+        \"the second one\" using the conversation, keep the user's key terms, and reply with \
+        the query only.";
+
+    /// Native output dimensions of the known embedding models.
+    pub const EMBEDDING_DIMENSIONS: &[(&str, u64)] = &[
+        ("text-embedding-ada-002", 1536),
+        ("text-embedding-3-small", 1536),
+        ("text-embedding-3-large", 3072),
+    ];
+
+    /// Returns the native vector dimension of a known embedding model.
+    pub fn embedding_dimension(model: &str) -> Option<u64> {
+        EMBEDDING_DIMENSIONS
+            .iter()
+            .find(|(name, _)| *name == model)
+            .map(|(_, dimension)| *dimension)
+    }
+
+    /// Returns the known embedding models whose native dimension equals `dimension`.
+    pub fn models_with_dimension(dimension: u64) -> Vec<&'static str> {
+        EMBEDDING_DIMENSIONS
+            .iter()
+            .filter(|(_, d)| *d == dimension)
+            .map(|(name, _)| *name)
+            .collect()
+    }
+
+    /// Returns whether the model accepts a `dimensions` override (text-embedding-3 family).
+    pub fn supports_dimensions_override(model: &str) -> bool {
+        model.starts_with("text-embedding-3-")
+    }
+
+    /// Default model for chat messages with images
+    pub const VISION_MODEL: &str = "gpt-4o";
+
+    /// Chat models known to accept image inputs.
+    pub const VISION_MODELS: &[&str] = &["gpt-4o", "gpt-4o-mini", "gpt-4-turbo"];
+
+    /// Returns whether the chat model accepts image inputs.
+    pub fn supports_vision(model: &str) -> bool {
+        VISION_MODELS.contains(&model)
+    }
+}
+
+/// Image types OpenAI accepts as base64 image inputs.
+pub const IMAGE_MIME_TYPES: &[&str] = &["image/png", "image/jpeg", "image/gif", "image/webp"];
+
+/// An image attached to a chat message.
+/// 
+/// # Example
+/// ```json
+/// { "url": "https://example.com/screenshot.png" }
+/// ```
+/// ```json
+/// { "data": "iVBORw0KGgo...", "mime_type": "image/png" }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ImageInput {
+    /// An image OpenAI downloads itself
+    Url {
+        /// `http` or `https` URL of the image
+        url: String,
+    },
+    /// An image sent inline
+    Base64 {
+        /// Base64-encoded image bytes
+        data: String,
+        /// One of `IMAGE_MIME_TYPES`
+        mime_type: String,
+    },
+}
+
+/// Why an attached image was refused.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ImageError {
+    /// The URL, type or encoding is not accepted
+    #[error("{0}")]
+    Invalid(String),
+    /// The decoded data is larger than `MAX_IMAGE_BYTES`
+    #[error("image data must not exceed {0} bytes")]
+    TooLarge(usize),
+}
+
+impl ImageInput {
+    /// Checks the URL scheme, or the type, encoding and decoded size of inline data.
+    pub fn validate(&self, max_bytes: usize) -> Result<(), ImageError> {
+        match self {
+            Self::Url { url } => {
+                if url.starts_with("https://") || url.starts_with("http://") {
+                    Ok(())
+                } else {
+                    Err(ImageError::Invalid("image url must start with http:// or https://".to_string()))
+                }
+            }
+            Self::Base64 { data, mime_type } => {
+                if !IMAGE_MIME_TYPES.contains(&mime_type.as_str()) {
+                    return Err(ImageError::Invalid(format!(
+                        "image mime_type must be one of {}",
+                        IMAGE_MIME_TYPES.join(", ")
+                    )));
+                }
+                // Reject oversized data before spending time decoding it
+                if data.len() / 4 * 3 > max_bytes {
+                    return Err(ImageError::TooLarge(max_bytes));
+                }
+                let decoded = STANDARD
+                    .decode(data)
+                    .map_err(|e| ImageError::Invalid(format!("image data is not valid base64: {}", e)))?;
+                if decoded.len() > max_bytes {
+                    return Err(ImageError::TooLarge(max_bytes));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// The URL sent to OpenAI; inline images become a `data:` URL.
+    fn to_url(&self) -> String {
+        match self {
+            Self::Url { url } => url.clone(),
+            Self::Base64 { data, mime_type } => format!("data:{};base64,{}", mime_type, data),
+        }
+    }
+}
+
+/// Prompt sent to the chat model by `/api/ask`, with `{context}` and
+/// `{question}` placeholders filled in per request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PromptTemplate(String);
+
+impl PromptTemplate {
+    const CONTEXT: &'static str = "{context}";
+    const QUESTION: &'static str = "{question}";
+
+    /// Fills in the placeholders in a single pass, so placeholder-like text
+    /// inside the context or question is left alone.
+    pub fn render(&self, context: &str, question: &str) -> String {
+        let mut rendered = String::with_capacity(self.0.len() + context.len() + question.len());
+        let mut rest = self.0.as_str();
+        while let Some(start) = rest.find('{') {
+            rendered.push_str(&rest[..start]);
+            let tail = &rest[start..];
+            if let Some(after) = tail.strip_prefix(Self::CONTEXT) {
+                rendered.push_str(context);
+                rest = after;
+            } else if let Some(after) = tail.strip_prefix(Self::QUESTION) {
+                rendered.push_str(question);
+                rest = after;
+            } else {
+                rendered.push('{');
+                rest = &tail[1..];
+            }
+        }
+        rendered.push_str(rest);
+        rendered
+    }
+}
+
+impl Default for PromptTemplate {
+    fn default() -> Self {
+        Self(
+            "Answer the question using only the context below. If the context does not \
+             contain the answer, say that you don't know.\n\nContext:\n{context}\n\nQuestion: {question}"
+                .to_string(),
+        )
+    }
+}
+
+impl FromStr for PromptTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let missing: Vec<&str> = [Self::CONTEXT, Self::QUESTION]
+            .into_iter()
+            .filter(|placeholder| !s.contains(placeholder))
+            .collect();
+        if missing.is_empty() {
+            Ok(Self(s.to_string()))
+        } else {
+            Err(format!("missing placeholder {}", missing.join(" and ")))
+        }
+    }
+}
+
+/// OpenAI accepts at most this many stop sequences per request.
+pub const MAX_STOP_SEQUENCES: usize = 4;
+
+/// OpenAI returns at most this many alternatives per token.
+pub const MAX_TOP_LOGPROBS: u8 = 20;
+
+/// Limits and sampling settings for the answer generated by a chat completion.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CompletionOptions {
+    /// Chat model replacing `CHAT_MODEL` (or the vision model for messages with images)
+    pub model: Option<String>,
+    /// Sampling temperature; `TEMPERATURE` when `None`
+    pub temperature: Option<f32>,
+    /// Maximum number of completion tokens; the model's own limit when `None`
+    pub max_tokens: Option<u32>,
+    /// Sequences at which the model stops generating (at most `MAX_STOP_SEQUENCES`)
+    pub stop: Vec<String>,
+    /// Seed for best-effort deterministic sampling
+    pub seed: Option<i64>,
+    /// Return the log probability of every answer token
+    pub logprobs: bool,
+    /// Most likely alternatives returned per answer token (requires `logprobs`)
+    pub top_logprobs: Option<u8>,
+}
+
+impl CompletionOptions {
+    /// Applies a request's overrides to these configured options.
+    /// 
+    /// `max_tokens` may lower the configured maximum but not raise it, and
+    /// `stop` replaces the configured sequences (an empty list clears them).
+    /// 
+    /// # Returns
+    /// * `Ok(CompletionOptions)` - The options to use for the request
+    /// * `Err(String)` - If an override is out of bounds
+    pub fn with_overrides(&self, max_tokens: Option<u32>, stop: Option<Vec<String>>) -> Result<Self, String> {
+        let max_tokens = match (max_tokens, self.max_tokens) {
+            (Some(0), _) => return Err("max_tokens must be at least 1".to_string()),
+            (Some(requested), Some(max)) if requested > max => {
+                return Err(format!("max_tokens must not exceed {}", max))
+            }
+            (Some(requested), _) => Some(requested),
+            (None, configured) => configured,
+        };
+        let stop = match stop {
+            Some(stop) if stop.len() > MAX_STOP_SEQUENCES => {
+                return Err(format!("stop accepts at most {} sequences", MAX_STOP_SEQUENCES))
+            }
+            Some(stop) if stop.iter().any(String::is_empty) => {
+                return Err("stop sequences cannot be empty".to_string())
+            }
+            Some(stop) => stop,
+            None => self.stop.clone(),
+        };
+        Ok(Self { max_tokens, stop, ..self.clone() })
+    }
+
+    /// Sets a request's seed and log probability settings.
+    /// 
+    /// `top_logprobs` turns on `logprobs` unless it is explicitly disabled.
+    /// 
+    /// # Returns
+    /// * `Ok(CompletionOptions)` - The options to use for the request
+    /// * `Err(String)` - If `top_logprobs` is out of bounds or set without `logprobs`
+    pub fn with_sampling(
+        self,
+        seed: Option<i64>,
+        logprobs: Option<bool>,
+        top_logprobs: Option<u8>,
+    ) -> Result<Self, String> {
+        if top_logprobs.is_some_and(|top| top > MAX_TOP_LOGPROBS) {
+            return Err(format!("top_logprobs must not exceed {}", MAX_TOP_LOGPROBS));
+        }
+        let logprobs = logprobs.unwrap_or(top_logprobs.is_some());
+        if top_logprobs.is_some() && !logprobs {
+            return Err("top_logprobs requires logprobs".to_string());
+        }
+        Ok(Self { seed, logprobs, top_logprobs, ..self })
+    }
+
+    /// Sets a request's model and temperature.
+    /// 
+    /// # Arguments
+    /// * `model` - Chat model to use; must be one of `allowed_models`
+    /// * `temperature` - Sampling temperature from 0 to `MAX_TEMPERATURE`
+    /// * `allowed_models` - Models requests may pick (`CHAT_MODELS`)
+    /// 
+    /// # Returns
+    /// * `Ok(CompletionOptions)` - The options to use for the request
+    /// * `Err(String)` - If the model is not allowed or the temperature is out of range
+    pub fn with_model(
+        self,
+        model: Option<String>,
+        temperature: Option<f32>,
+        allowed_models: &[String],
+    ) -> Result<Self, String> {
+        if let Some(model) = model.as_deref().filter(|model| !allowed_models.iter().any(|m| m == model)) {
+            return Err(format!(
+                "model '{}' is not one of CHAT_MODELS ({})",
+                model,
+                allowed_models.join(", ")
+            ));
+        }
+        if temperature.is_some_and(|t| !(0.0..=models::MAX_TEMPERATURE).contains(&t)) {
+            return Err(format!("temperature must be between 0 and {}", models::MAX_TEMPERATURE));
+        }
+        Ok(Self { model, temperature, ..self })
+    }
+}
+
+/// Builds a chat completion request for the conversation with the given limits.
+/// 
+/// Images are attached to the last turn, which is the user's new message.
+fn chat_request(
+    model: &str,
+    turns: &[ChatTurn],
+    images: &[ImageInput],
+    options: &CompletionOptions,
+) -> CreateChatCompletionRequest {
+    let mut messages: Vec<ChatCompletionRequestMessage> = turns.iter().map(Into::into).collect();
+    if let (Some(turn), Some(message)) = (turns.last(), messages.last_mut()) {
+        if !images.is_empty() {
+            *message = message_with_images(&turn.content, images);
+        }
+    }
+    CreateChatCompletionRequest {
+        model: model.into(),
+        messages,
+        temperature: Some(options.temperature.unwrap_or(models::TEMPERATURE)),
+        max_completion_tokens: options.max_tokens,
+        stop: (!options.stop.is_empty()).then(|| Stop::StringArray(options.stop.clone())),
+        seed: options.seed,
+        logprobs: options.logprobs.then_some(true),
+        top_logprobs: options.top_logprobs,
+        ..Default::default()
+    }
+}
+
+/// Builds a user message whose content is the text followed by the images.
+fn message_with_images(text: &str, images: &[ImageInput]) -> ChatCompletionRequestMessage {
+    let mut parts = vec![ChatCompletionRequestUserMessageContentPart::Text(
+        ChatCompletionRequestMessageContentPartText { text: text.to_string() },
+    )];
+    parts.extend(images.iter().map(|image| {
+        ChatCompletionRequestUserMessageContentPart::ImageUrl(ChatCompletionRequestMessageContentPartImage {
+            image_url: ImageUrl { url: image.to_url(), detail: None },
+        })
+    }));
+    ChatCompletionRequestMessage::User(async_openai::types::ChatCompletionRequestUserMessage {
+        content: ChatCompletionRequestUserMessageContent::Array(parts),
+        name: None,
+    })
+}
+
+/// Formats turns as a plain transcript, one `role: content` paragraph per turn.
+fn transcript(turns: &[ChatTurn]) -> String {
+    turns
+        .iter()
+        .map(|turn| {
+            let role = match turn.role {
+                ChatRole::User => "user",
+                ChatRole::Assistant => "assistant",
+                ChatRole::System => "context",
+            };
+            format!("{}: {}", role, turn.content)
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
 }
 
 /// Response structure for chat completion requests.
 /// 
 /// Contains both the generated response text and usage statistics
 /// for token consumption tracking.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CompletionResponse {
     /// The generated response text from the model
     pub response: String,
     /// Token usage statistics for the request
     pub usage: Usage,
+    /// Why the model stopped: `stop`, `length` (cut off at `max_tokens`) or `content_filter`
+    pub finish_reason: Option<FinishReason>,
+    /// Backend configuration the answer was generated with. Models that
+    /// don't report one leave it out, and then a seed is not honored either.
+    pub system_fingerprint: Option<String>,
+    /// Log probabilities of the answer's tokens, when requested
+    pub logprobs: Option<Vec<ChatCompletionTokenLogprob>>,
+    /// Whether the model produced no text; `finish_reason` and `refusal` say why
+    pub empty: bool,
+    /// The model's explanation when it declined to answer
+    pub refusal: Option<String>,
+    /// Whether the answer was served from the response cache
+    #[serde(default)]
+    pub cached: bool,
+}
+
+/// Author of a chat turn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatRole {
+    /// Message sent by the user
+    User,
+    /// Reply generated by the model
+    Assistant,
+    /// Instructions or context for the model, such as a summary of earlier turns
+    System,
+}
+
+/// A single message of a conversation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatTurn {
+    /// Author of the message
+    pub role: ChatRole,
+    /// Message text
+    pub content: String,
+}
+
+impl ChatTurn {
+    /// Creates a user turn.
+    pub fn user(content: impl Into<String>) -> Self {
+        Self { role: ChatRole::User, content: content.into() }
+    }
+
+    /// Creates an assistant turn.
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self { role: ChatRole::Assistant, content: content.into() }
+    }
+
+    /// Creates a system turn.
+    pub fn system(content: impl Into<String>) -> Self {
+        Self { role: ChatRole::System, content: content.into() }
+    }
+}
+
+impl From<&ChatTurn> for ChatCompletionRequestMessage {
+    fn from(turn: &ChatTurn) -> Self {
+        match turn.role {
+            ChatRole::User => ChatCompletionRequestMessage::User(
+                async_openai::types::ChatCompletionRequestUserMessage {
+                    content: ChatCompletionRequestUserMessageContent::Text(turn.content.clone()),
+                    name: None,
+                }
+            ),
+            ChatRole::Assistant => ChatCompletionRequestMessage::Assistant(
+                ChatCompletionRequestAssistantMessage::from(turn.content.as_str())
+            ),
+            ChatRole::System => ChatCompletionRequestMessage::System(
+                ChatCompletionRequestSystemMessage::from(turn.content.as_str())
+            ),
+        }
+    }
 }
 
 /// Token usage statistics for API requests.
 /// 
 /// Tracks the number of tokens used in both the prompt and response,
 /// useful for monitoring API usage and costs.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
 pub struct Usage {
     /// Number of tokens in the input prompt
     pub prompt_tokens: u32,
@@ -47,6 +504,158 @@ pub struct Usage {
     pub total_tokens: u32,
 }
 
+impl std::ops::AddAssign for Usage {
+    fn add_assign(&mut self, other: Self) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+    }
+}
+
+/// Most tokens OpenAI accepts across all the inputs of one embedding request.
+pub const MAX_REQUEST_TOKENS: usize = 300_000;
+
+/// Groups texts, with their token counts, into consecutive requests of at
+/// most `max_tokens` tokens and `EmbeddingRequest::MAX_BATCH_SIZE` inputs.
+/// 
+/// A text over `max_tokens` on its own still gets a request of its own.
+fn request_batches(inputs: Vec<(String, usize)>, max_tokens: usize) -> Vec<Vec<String>> {
+    let mut batches: Vec<Vec<String>> = Vec::new();
+    let mut tokens = 0;
+    for (text, count) in inputs {
+        match batches.last_mut() {
+            Some(batch) if tokens + count <= max_tokens && batch.len() < EmbeddingRequest::MAX_BATCH_SIZE => {
+                tokens += count;
+                batch.push(text);
+            }
+            _ => {
+                tokens = count;
+                batches.push(vec![text]);
+            }
+        }
+    }
+    batches
+}
+
+/// Embedding vectors for a batch of inputs and the tokens they consumed.
+#[derive(Debug)]
+pub struct Embeddings {
+    /// One vector per input, in input order
+    pub vectors: Vec<Vec<f32>>,
+    /// Tokens billed for the request; embeddings have no completion tokens
+    pub usage: Usage,
+}
+
+impl Embeddings {
+    fn new(vectors: Vec<Vec<f32>>, prompt_tokens: u32, total_tokens: u32) -> Self {
+        Self {
+            vectors,
+            usage: Usage {
+                prompt_tokens,
+                completion_tokens: 0,
+                total_tokens,
+            },
+        }
+    }
+}
+
+/// Default upper bound on a single OpenAI request.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Connection pool settings of the HTTP client used for OpenAI calls.
+#[derive(Debug, Clone, Copy)]
+pub struct HttpPoolSettings {
+    /// Idle connections kept open to the API host
+    pub max_idle_per_host: usize,
+    /// Maximum time to establish a connection, TLS handshake included
+    pub connect_timeout: Duration,
+    /// How long an idle connection is kept before being closed
+    pub idle_timeout: Duration,
+    /// Interval of TCP keepalive probes on open connections
+    pub tcp_keepalive: Duration,
+}
+
+impl HttpPoolSettings {
+    /// Pooled connections idle for longer than this are closed
+    pub const IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+    /// Keepalive probes stop NATs and load balancers from dropping quiet connections
+    pub const TCP_KEEPALIVE: Duration = Duration::from_secs(60);
+
+    /// Builds settings with the default idle timeout and keepalive interval.
+    pub fn new(max_idle_per_host: usize, connect_timeout: Duration) -> Self {
+        Self {
+            max_idle_per_host,
+            connect_timeout,
+            idle_timeout: Self::IDLE_TIMEOUT,
+            tcp_keepalive: Self::TCP_KEEPALIVE,
+        }
+    }
+}
+
+/// Headers the client sets itself, which would silently override an extra header.
+const RESERVED_HEADERS: &[&str] = &["authorization", "openai-organization", "openai-project", "content-type"];
+
+/// Parts of a header name marking its value as secret.
+const SENSITIVE_HEADER_WORDS: &[&str] = &["key", "token", "secret", "password", "auth", "cookie", "signature"];
+
+/// Extra headers sent with every OpenAI request, e.g. for an API gateway.
+/// 
+/// Parsed from a comma-separated list of `Name=value` entries; only the
+/// first `=` separates name and value. Values of headers whose names look
+/// secret are marked sensitive and shown as `[REDACTED]` when displayed.
+#[derive(Debug, Clone, Default)]
+pub struct ExtraHeaders(HeaderMap);
+
+impl ExtraHeaders {
+    /// Returns whether no extra headers are configured.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl FromStr for ExtraHeaders {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut headers = HeaderMap::new();
+        for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            // The entry isn't echoed, since it may hold a secret
+            let (name, value) = entry
+                .split_once('=')
+                .ok_or_else(|| anyhow!("expected 'Name=value' entries"))?;
+            let name = HeaderName::from_bytes(name.trim().as_bytes())
+                .map_err(|_| anyhow!("invalid header name '{}'", name.trim()))?;
+            if RESERVED_HEADERS.contains(&name.as_str()) {
+                return Err(anyhow!("header '{}' is set by the service itself", name));
+            }
+            let mut value = HeaderValue::from_str(value.trim())
+                .map_err(|_| anyhow!("invalid value for header '{}'", name))?;
+            if SENSITIVE_HEADER_WORDS.iter().any(|word| name.as_str().contains(word)) {
+                value.set_sensitive(true);
+            }
+            headers.append(name, value);
+        }
+        Ok(Self(headers))
+    }
+}
+
+impl std::fmt::Display for ExtraHeaders {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return write!(f, "none");
+        }
+        let entries: Vec<String> = self
+            .0
+            .iter()
+            .map(|(name, value)| match value.to_str() {
+                Ok(shown) if !value.is_sensitive() => format!("{}={}", name, shown),
+                _ => format!("{}=[REDACTED]", name),
+            })
+            .collect();
+        write!(f, "{}", entries.join(","))
+    }
+}
+
 /// Service for interacting with OpenAI's API.
 /// 
 /// This service provides methods for:
@@ -57,6 +666,31 @@ pub struct Usage {
 pub struct OpenAIService {
     /// OpenAI API client instance
     client: Client<OpenAIConfig>,
+    /// Model used for embedding requests
+    embedding_model: String,
+    /// Optional reduced output dimension for the embedding model
+    embedding_dimensions: Option<u32>,
+    /// Request base64-encoded embeddings from OpenAI to reduce response size
+    base64_embeddings: bool,
+    /// Latency, token and cost metrics
+    metrics: Arc<Metrics>,
+    /// Maximum time to wait for a single OpenAI request
+    timeout: Duration,
+    /// Chat model for messages with images; images are refused when `None`
+    vision_model: Option<String>,
+    /// Chat model rewriting follow-up questions into search queries
+    rewrite_model: String,
+    /// Completions served again for repeated prompts; caching is off when `None`
+    response_cache: Option<ResponseCache>,
+    /// Normalization applied to every text before it is embedded
+    normalization: TextNormalization,
+    /// Batches single-text embeddings arriving close together; each text
+    /// is sent on its own when `None`
+    coalescer: Option<EmbedCoalescer>,
+    /// Headers added to every request by the HTTP client
+    extra_headers: ExtraHeaders,
+    /// Connection pool of the HTTP client; reqwest's defaults when `None`
+    http_pool: Option<HttpPoolSettings>,
 }
 
 impl OpenAIService {
@@ -64,23 +698,233 @@ impl OpenAIService {
     /// 
     /// # Arguments
     /// * `api_key` - OpenAI API key for authentication
+    /// * `org_id` - Optional organization sent as the `OpenAI-Organization` header
+    /// * `project_id` - Optional project sent as the `OpenAI-Project` header
     /// 
     /// # Returns
     /// A new OpenAIService instance configured with the provided API key
-    pub fn new(api_key: &str) -> Self {
-        let config = OpenAIConfig::new().with_api_key(api_key);
+    pub fn new(api_key: &str, org_id: Option<&str>, project_id: Option<&str>) -> Self {
+        let mut config = OpenAIConfig::new().with_api_key(api_key);
+        // Keys scoped to an organization or project are rejected without these headers
+        if let Some(org_id) = org_id {
+            config = config.with_org_id(org_id);
+        }
+        if let Some(project_id) = project_id {
+            config = config.with_project_id(project_id);
+        }
         Self {
             client: Client::with_config(config),
+            embedding_model: models::EMBEDDING_MODEL.to_string(),
+            embedding_dimensions: None,
+            base64_embeddings: false,
+            metrics: Arc::default(),
+            timeout: DEFAULT_TIMEOUT,
+            vision_model: Some(models::VISION_MODEL.to_string()),
+            rewrite_model: models::REWRITE_MODEL.to_string(),
+            response_cache: None,
+            normalization: TextNormalization::NONE,
+            coalescer: None,
+            extra_headers: ExtraHeaders::default(),
+            http_pool: None,
+        }
+    }
+
+    /// Sends requests to another OpenAI-compatible API (`None` keeps api.openai.com).
+    pub fn with_api_base(mut self, api_base: Option<&str>) -> Self {
+        if let Some(api_base) = api_base {
+            let config = self.client.config().clone().with_api_base(api_base);
+            self.client = Client::with_config(config);
+            self.rebuild_http_client();
         }
+        self
+    }
+
+    /// Normalizes every text before embedding it, so texts that only
+    /// differ in encoding or spacing get the same vector.
+    pub fn with_text_normalization(mut self, normalization: TextNormalization) -> Self {
+        self.normalization = normalization;
+        self
+    }
+
+    /// Caches chat completions that are meant to be repeatable.
+    /// 
+    /// Only requests at temperature 0 or with a seed are cached, since other
+    /// answers legitimately vary; messages with images are never cached.
+    /// Prompts are compared with their whitespace collapsed.
+    /// 
+    /// # Arguments
+    /// * `max_entries` - Maximum number of completions kept at once
+    /// * `ttl` - Time after which a completion is generated again
+    pub fn with_response_cache(mut self, max_entries: usize, ttl: Duration) -> Self {
+        self.response_cache = Some(ResponseCache::new(max_entries, ttl));
+        self
+    }
+
+    /// Sends single-text embeddings of the configured model that arrive
+    /// within `window` of each other as one batched request.
+    /// 
+    /// Each caller still gets its own vector and is charged the tokens of
+    /// its own text. A window of zero sends every text on its own.
+    /// 
+    /// # Arguments
+    /// * `window` - How long the first text of a batch waits for others
+    /// * `max_batch` - Most texts sent in one request
+    pub fn with_embedding_coalescing(mut self, window: Duration, max_batch: usize) -> Self {
+        self.coalescer = (!window.is_zero()).then(|| EmbedCoalescer::new(window, max_batch));
+        self
+    }
+
+    /// Sets the chat model that rewrites follow-up questions into search queries.
+    pub fn with_rewrite_model(mut self, model: &str) -> Self {
+        self.rewrite_model = model.to_string();
+        self
+    }
+
+    /// Sets the chat model used for messages with images (`None` refuses them).
+    pub fn with_vision_model(mut self, model: Option<&str>) -> Self {
+        self.vision_model = model.map(str::to_string);
+        self
+    }
+
+    /// Sets the maximum time to wait for a single OpenAI request.
+    /// 
+    /// Requests still running after this are dropped, which aborts them.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sends the given headers with every request, e.g. a token for a
+    /// gateway in front of OpenAI.
+    pub fn with_extra_headers(mut self, headers: ExtraHeaders) -> Self {
+        self.extra_headers = headers;
+        self.rebuild_http_client();
+        self
+    }
+
+    /// Tunes the HTTP client for connection reuse.
+    /// 
+    /// The client is shared by every call, so connections (and their TLS
+    /// sessions) are kept in a pool and reused; HTTP/2 is used when the API
+    /// offers it. Opened connections are counted in
+    /// `openai_connections_opened_total` of the metrics set by `with_metrics`.
+    /// 
+    /// # Arguments
+    /// * `settings` - Pool size, connect timeout, idle timeout and keepalive interval
+    pub fn with_http_pool(mut self, settings: HttpPoolSettings) -> Self {
+        self.http_pool = Some(settings);
+        self.rebuild_http_client();
+        self
+    }
+
+    /// Records latency, token usage and estimated cost into shared metrics.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        if self.http_pool.is_some() {
+            // The connection counter of the pool reports to the new metrics
+            self.rebuild_http_client();
+        }
+        self
+    }
+
+    /// Rebuilds the HTTP client from the extra headers, pool settings and
+    /// metrics, so the builder methods setting them work in any order.
+    /// 
+    /// Like `reqwest::Client::new`, panics if the TLS backend cannot be
+    /// initialized.
+    fn rebuild_http_client(&mut self) {
+        let Some(settings) = self.http_pool else {
+            if !self.extra_headers.is_empty() {
+                let http_client = reqwest::Client::builder()
+                    .default_headers(self.extra_headers.0.clone())
+                    .build()
+                    .expect("the OpenAI HTTP client can be built");
+                self.client = self.client.clone().with_http_client(http_client);
+            }
+            return;
+        };
+        let metrics = self.metrics.clone();
+        let http_client = reqwest::Client::builder()
+            .pool_max_idle_per_host(settings.max_idle_per_host)
+            .pool_idle_timeout(settings.idle_timeout)
+            .connect_timeout(settings.connect_timeout)
+            .tcp_keepalive(settings.tcp_keepalive)
+            .default_headers(self.extra_headers.0.clone())
+            // The connector only runs when no pooled connection is free
+            .connector_layer(tower::util::MapRequestLayer::new(move |request| {
+                metrics.record_openai_connection();
+                request
+            }))
+            .build()
+            .expect("the OpenAI HTTP client can be built");
+        self.client = self.client.clone().with_http_client(http_client);
+    }
+
+    /// Requests embeddings from OpenAI as base64 instead of JSON float arrays.
+    /// 
+    /// Base64 responses are roughly half the size; they are decoded into
+    /// `Vec<f32>` internally, so callers are unaffected.
+    pub fn with_base64_embeddings(mut self, enabled: bool) -> Self {
+        self.base64_embeddings = enabled;
+        self
+    }
+
+    /// Configures the embedding model and an optional dimension override.
+    /// 
+    /// # Arguments
+    /// * `model` - Name of the OpenAI embedding model
+    /// * `dimensions` - Reduced output dimension (text-embedding-3 models only)
+    /// 
+    /// # Returns
+    /// * `Ok(Self)` - The reconfigured service
+    /// * `Err(anyhow::Error)` - If the override is unsupported or exceeds the native size
+    pub fn with_embedding_model(mut self, model: &str, dimensions: Option<u32>) -> Result<Self> {
+        if let Some(requested) = dimensions {
+            if !models::supports_dimensions_override(model) {
+                return Err(anyhow!(
+                    "embedding model '{}' does not support a dimensions override",
+                    model
+                ));
+            }
+            if let Some(native) = models::embedding_dimension(model) {
+                if requested == 0 || u64::from(requested) > native {
+                    return Err(anyhow!(
+                        "embedding dimensions must be between 1 and {} for '{}', got {}",
+                        native,
+                        model,
+                        requested
+                    ));
+                }
+            }
+        }
+
+        self.embedding_model = model.to_string();
+        self.embedding_dimensions = dimensions;
+        Ok(self)
+    }
+
+    /// Returns the dimension of the vectors produced by the configured embedding model.
+    /// 
+    /// The `dimensions` override takes precedence over the model's native size.
+    /// 
+    /// # Returns
+    /// * `Some(u64)` - The vector dimension
+    /// * `None` - If the model is unknown and no override is configured
+    pub fn embedding_dimension(&self) -> Option<u64> {
+        self.embedding_dimensions
+            .map(u64::from)
+            .or_else(|| models::embedding_dimension(&self.embedding_model))
     }
 
     /// Generates an embedding vector for the given text.
     /// 
-    /// Uses OpenAI's text-embedding-3-large model to create
-    /// a high-quality vector representation of the input text.
+    /// Uses the configured embedding model (text-embedding-3-large by default)
+    /// to create a high-quality vector representation of the input text.
     /// 
     /// # Arguments
     /// * `text` - The text to convert into an embedding
+    /// * `model` - Embed with this model instead of the configured one; its
+    ///   native dimension is used, not `EMBEDDING_DIMENSIONS`
     /// 
     /// # Returns
     /// * `Ok(Vec<f32>)` - The embedding vector on success
@@ -88,23 +932,283 @@ impl OpenAIService {
     /// 
     /// # Example
     /// ```no_run
-    /// let embedding = service.get_embedding("Hello, world!").await?;
+    /// let embedding = service.get_embedding("Hello, world!", None).await?;
+    /// let small = service.get_embedding("Hello, world!", Some("text-embedding-3-small")).await?;
+    /// ```
+    pub async fn get_embedding(&self, text: &str, model: Option<&str>) -> Result<Vec<f32>> {
+        self.get_embedding_with_usage(text, model)
+            .await
+            .map(|(embedding, _)| embedding)
+    }
+
+    /// Generates an embedding vector for the given text along with the
+    /// tokens the request consumed.
+    /// 
+    /// # Arguments
+    /// * `text` - The text to convert into an embedding
+    /// * `model` - Embed with this model instead of the configured one
+    /// 
+    /// # Returns
+    /// * `Ok((Vec<f32>, Usage))` - The embedding vector and its token usage
+    /// * `Err(anyhow::Error)` - If the API request fails
+    pub async fn get_embedding_with_usage(&self, text: &str, model: Option<&str>) -> Result<(Vec<f32>, Usage)> {
+        let text = self.normalization.apply(text);
+        if let Some(coalescer) = &self.coalescer {
+            // Batched vectors must all come from the same model
+            if model.is_none_or(|model| model == self.embedding_model) {
+                return self.get_coalesced_embedding(coalescer, text).await;
+            }
+        }
+
+        let embeddings = self.create_embeddings(EmbeddingInput::String(text), model).await?;
+
+        // Return the first (and only) embedding
+        let embedding = embeddings
+            .vectors
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("OpenAI returned no embedding"))?;
+        Ok((embedding, embeddings.usage))
+    }
+
+    /// Embeds a text of the configured model as part of a coalesced batch.
+    /// 
+    /// The request that starts a batch sends it and hands out the vectors;
+    /// the others wait for theirs. Usage is recorded by every request for
+    /// its own share of the batch, so it is attributed to the right key.
+    async fn get_coalesced_embedding(&self, coalescer: &EmbedCoalescer, text: String) -> Result<(Vec<f32>, Usage)> {
+        let leader = match coalescer.join(&text) {
+            Joined::Leader(leader) => leader,
+            Joined::Follower(outcome) => {
+                return match outcome.await {
+                    Ok(Ok((embedding, tokens))) => {
+                        self.metrics.record_openai_usage(OpenAIOperation::Embed, &self.embedding_model, tokens, 0);
+                        Ok((embedding, Usage { prompt_tokens: tokens, completion_tokens: 0, total_tokens: tokens }))
+                    }
+                    Ok(Err(message)) => Err(anyhow!(message)),
+                    // The leader was cancelled before sending the batch
+                    Err(_) => {
+                        let embeddings = self.create_embeddings(EmbeddingInput::String(text), None).await?;
+                        let embedding = embeddings
+                            .vectors
+                            .into_iter()
+                            .next()
+                            .ok_or_else(|| anyhow!("OpenAI returned no embedding"))?;
+                        Ok((embedding, embeddings.usage))
+                    }
+                };
+            }
+        };
+
+        let (texts, waiters) = leader.collect().await;
+        if texts.len() > 1 {
+            tracing::debug!(texts = texts.len(), "Sending coalesced embedding batch");
+        }
+        let shares = self.apportion_tokens(&texts);
+        let embeddings = match self.request_embeddings(EmbeddingInput::StringArray(texts), None).await {
+            Ok(embeddings) if embeddings.vectors.len() == shares.len() => embeddings,
+            Ok(embeddings) => {
+                let message = format!(
+                    "OpenAI returned {} embeddings for {} texts",
+                    embeddings.vectors.len(),
+                    shares.len()
+                );
+                for waiter in waiters {
+                    let _ = waiter.send(Err(message.clone()));
+                }
+                return Err(anyhow!(message));
+            }
+            Err(e) => {
+                for waiter in waiters {
+                    let _ = waiter.send(Err(e.to_string()));
+                }
+                return Err(e);
+            }
+        };
+
+        let total = embeddings.usage.prompt_tokens;
+        let shares = scale_shares(&shares, total);
+        let mut vectors = embeddings.vectors.into_iter().zip(shares);
+        let (embedding, tokens) = vectors.next().ok_or_else(|| anyhow!("OpenAI returned no embedding"))?;
+        // Followers that gave up waiting just drop their vector
+        for (waiter, outcome) in waiters.into_iter().zip(vectors) {
+            let _ = waiter.send(Ok(outcome));
+        }
+        self.metrics.record_openai_usage(OpenAIOperation::Embed, &self.embedding_model, tokens, 0);
+        Ok((embedding, Usage { prompt_tokens: tokens, completion_tokens: 0, total_tokens: tokens }))
+    }
+
+    /// Counts the tokens of each text of a coalesced batch locally.
+    /// 
+    /// Texts the tokenizer can't count weigh one token each.
+    fn apportion_tokens(&self, texts: &[String]) -> Vec<u32> {
+        texts
+            .iter()
+            .map(|text| tokenizer::count_tokens(&self.embedding_model, text).map_or(1, |n| n.max(1) as u32))
+            .collect()
+    }
+
+    /// Generates embedding vectors for a batch of texts in a single request.
+    /// 
+    /// # Arguments
+    /// * `texts` - The texts to convert into embeddings
+    /// 
+    /// # Returns
+    /// * `Ok(Embeddings)` - One embedding per input, in input order, and the token usage
+    /// * `Err(anyhow::Error)` - If the API request fails
+    /// 
+    /// # Example
+    /// ```no_run
+    /// let embeddings = service.get_embeddings(&["Hello".into(), "World".into()]).await?;
+    /// println!("{} vectors, {} tokens", embeddings.vectors.len(), embeddings.usage.total_tokens);
     /// ```
-    pub async fn get_embedding(&self, text: &str) -> Result<Vec<f32>> {
+    pub async fn get_embeddings(&self, texts: &[String]) -> Result<Embeddings> {
+        let texts = texts.iter().map(|text| self.normalization.apply(text)).collect();
+        self.create_embeddings(EmbeddingInput::StringArray(texts), None)
+            .await
+    }
+
+    /// Like `get_embeddings`, but embeds with `model` instead of the
+    /// configured one, at the model's native dimension.
+    pub async fn get_model_embeddings(&self, texts: &[String], model: &str) -> Result<Embeddings> {
+        let texts = texts.iter().map(|text| self.normalization.apply(text)).collect();
+        self.create_embeddings(EmbeddingInput::StringArray(texts), Some(model))
+            .await
+    }
+
+    /// Generates embedding vectors for the texts of stored documents.
+    /// 
+    /// Texts that fit together are sent in one request, and more requests
+    /// are made where their tokens would go over `MAX_REQUEST_TOKENS`.
+    /// Ingestion stores texts longer than the embedding model accepts as
+    /// several chunks, so each fits; a longer text reaching this point (a
+    /// point stored with its own embedding, re-embedded later) can't be
+    /// split without changing ids, and its leading part that fits is
+    /// embedded instead.
+    /// 
+    /// # Arguments
+    /// * `texts` - The document texts to convert into embeddings
+    /// 
+    /// # Returns
+    /// * `Ok(Embeddings)` - One embedding per text, in input order, and the token usage
+    /// * `Err(anyhow::Error)` - If an API request fails
+    pub async fn get_document_embeddings(&self, texts: &[String]) -> Result<Embeddings> {
+        let (model, normalization) = (self.embedding_model.clone(), self.normalization);
+        let owned = texts.to_vec();
+        // Encoding long texts takes a while; keep it off the async workers
+        let inputs: Vec<(String, usize)> = tokio::task::spawn_blocking(move || {
+            let max_tokens = tokenizer::context_window(&model);
+            owned
+                .into_iter()
+                .map(|text| {
+                    let mut text = normalization.apply(&text);
+                    let (length, tokens) = match tokenizer::split(&model, &text, max_tokens).as_deref() {
+                        Some([(piece, tokens), rest @ ..]) => {
+                            if !rest.is_empty() {
+                                tracing::warn!(
+                                    chars = text.chars().count(),
+                                    max_tokens,
+                                    "Embedding only the leading part of a document over the token limit"
+                                );
+                            }
+                            (piece.len(), *tokens)
+                        }
+                        _ => (text.len(), 0),
+                    };
+                    text.truncate(length);
+                    (text, tokens)
+                })
+                .collect()
+        })
+        .await?;
+
+        let mut embeddings = Embeddings { vectors: Vec::with_capacity(inputs.len()), usage: Usage::default() };
+        for request in request_batches(inputs, MAX_REQUEST_TOKENS) {
+            let count = request.len();
+            let batch = self.create_embeddings(EmbeddingInput::StringArray(request), None).await?;
+            if batch.vectors.len() != count {
+                return Err(anyhow!("OpenAI returned {} embeddings for {} inputs", batch.vectors.len(), count));
+            }
+            embeddings.vectors.extend(batch.vectors);
+            embeddings.usage += batch.usage;
+        }
+        Ok(embeddings)
+    }
+
+    /// Generates the embedding vector of one document's text.
+    /// 
+    /// Cuts texts over the token limit like `get_document_embeddings`.
+    pub async fn get_document_embedding(&self, text: &str) -> Result<Vec<f32>> {
+        self.get_document_embeddings(&[text.to_string()])
+            .await?
+            .vectors
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("OpenAI returned no embedding"))
+    }
+
+    /// Sends an embedding request and returns the vectors in input order.
+    /// 
+    /// When base64 encoding is enabled, each vector is decoded and checked
+    /// against the expected embedding dimension.
+    async fn create_embeddings(&self, input: EmbeddingInput, model: Option<&str>) -> Result<Embeddings> {
+        let embeddings = self.request_embeddings(input, model).await?;
+        self.metrics.record_openai_usage(
+            OpenAIOperation::Embed,
+            model.unwrap_or(&self.embedding_model),
+            embeddings.usage.prompt_tokens,
+            0,
+        );
+        Ok(embeddings)
+    }
+
+    /// Like `create_embeddings`, but leaves recording the token usage to
+    /// the caller.
+    async fn request_embeddings(&self, input: EmbeddingInput, model: Option<&str>) -> Result<Embeddings> {
+        // The dimensions override only applies to the configured model
+        let (model, dimensions, expected) = match model {
+            Some(model) if model != self.embedding_model => {
+                (model, None, models::embedding_dimension(model))
+            }
+            _ => (
+                self.embedding_model.as_str(),
+                self.embedding_dimensions,
+                self.embedding_dimension(),
+            ),
+        };
+
         // Create the embedding request with model configuration
         let request = CreateEmbeddingRequest {
-            model: models::EMBEDDING_MODEL.into(),
-            input: EmbeddingInput::String(text.to_string()),
-            encoding_format: None,
-            dimensions: None,
+            model: model.to_string(),
+            input,
+            encoding_format: self.base64_embeddings.then_some(EncodingFormat::Base64),
+            dimensions,
             user: None,
         };
 
-        // Send request to OpenAI API
-        let response = self.client.embeddings().create(request).await?;
-        
-        // Return the first (and only) embedding
-        Ok(response.data[0].embedding.clone())
+        // Send request to OpenAI API; results carry their input index,
+        // so input order is restored explicitly
+        if self.base64_embeddings {
+            let mut response = self
+                .call(OpenAIOperation::Embed, model, self.client.embeddings().create_base64(request))
+                .await?;
+            response.data.sort_by_key(|e| e.index);
+
+            let expected = expected.map(|d| d as usize);
+            let vectors = response
+                .data
+                .into_iter()
+                .map(|e| vector_math::decode_base64(&e.embedding.0, expected))
+                .collect::<Result<_, _>>()?;
+            Ok(Embeddings::new(vectors, response.usage.prompt_tokens, response.usage.total_tokens))
+        } else {
+            let mut response = self
+                .call(OpenAIOperation::Embed, model, self.client.embeddings().create(request))
+                .await?;
+            response.data.sort_by_key(|e| e.index);
+            let vectors = response.data.into_iter().map(|e| e.embedding).collect();
+            Ok(Embeddings::new(vectors, response.usage.prompt_tokens, response.usage.total_tokens))
+        }
     }
 
     /// Generates a chat completion response for the given message.
@@ -114,42 +1218,336 @@ impl OpenAIService {
     /// 
     /// # Arguments
     /// * `message` - The user's input message
+    /// * `images` - Images sent along with the message (may be empty)
+    /// * `options` - Maximum tokens and stop sequences for the answer
     /// 
     /// # Returns
-    /// * `Ok(CompletionResponse)` - The generated response and usage stats
+    /// * `Ok(CompletionResponse)` - The generated response, usage stats and finish reason
     /// * `Err(anyhow::Error)` - If the API request fails
     /// 
     /// # Example
     /// ```no_run
-    /// let response = service.generate_completion("What is Rust?").await?;
+    /// let response = service.generate_completion("What is Rust?", &[], &CompletionOptions::default()).await?;
     /// println!("Response: {}", response.response);
     /// println!("Total tokens: {}", response.usage.total_tokens);
     /// ```
-    pub async fn generate_completion(&self, message: &str) -> Result<CompletionResponse> {
+    pub async fn generate_completion(
+        &self,
+        message: &str,
+        images: &[ImageInput],
+        options: &CompletionOptions,
+    ) -> Result<CompletionResponse> {
+        self.generate_chat_completion(&[ChatTurn::user(message)], images, options).await
+    }
+
+    /// Generates the next assistant reply for a conversation.
+    /// 
+    /// # Arguments
+    /// * `turns` - The conversation so far, oldest first, ending with the user's message
+    /// * `images` - Images attached to the user's message; switches to the vision model
+    /// * `options` - Maximum tokens, stop sequences and the model and temperature to use
+    /// 
+    /// # Returns
+    /// * `Ok(CompletionResponse)` - The generated response, usage stats and finish reason;
+    ///   `empty` is set when the model returned no text, `cached` when the
+    ///   response cache answered instead of the model
+    /// * `Err(anyhow::Error)` - If the API request fails, or images are sent without a vision model
+    pub async fn generate_chat_completion(
+        &self,
+        turns: &[ChatTurn],
+        images: &[ImageInput],
+        options: &CompletionOptions,
+    ) -> Result<CompletionResponse> {
+        let model = match (images, options.model.as_deref()) {
+            (_, Some(model)) => model,
+            ([], None) => models::CHAT_MODEL,
+            (_, None) => self
+                .vision_model
+                .as_deref()
+                .ok_or_else(|| anyhow!("images were sent but no vision model is configured"))?,
+        };
+        let cache = self
+            .response_cache
+            .as_ref()
+            .filter(|_| images.is_empty())
+            .and_then(|cache| Some((cache, CacheKey::new(model, turns, options)?)));
+        if let Some((cache, key)) = &cache {
+            let hit = cache.get(key);
+            self.metrics.record_chat_cache(hit.is_some());
+            if let Some(response) = hit {
+                return Ok(response);
+            }
+        }
+
         // Create the chat completion request with model and parameters
+        let request = chat_request(model, turns, images, options);
+
+        // Send request to OpenAI API; image tokens are included in the prompt tokens
+        let response = self
+            .call(OpenAIOperation::Chat, model, self.client.chat().create(request))
+            .await?;
+        if let Some(usage) = &response.usage {
+            self.metrics.record_openai_usage(
+                OpenAIOperation::Chat,
+                model,
+                usage.prompt_tokens,
+                usage.completion_tokens,
+            );
+        }
+        
+        // Format and return the response; a missing choice or blank content
+        // is passed on as an empty completion rather than an error
+        let usage = Usage {
+            prompt_tokens: response.usage.as_ref().map_or(0, |u| u.prompt_tokens),
+            completion_tokens: response.usage.as_ref().map_or(0, |u| u.completion_tokens),
+            total_tokens: response.usage.as_ref().map_or(0, |u| u.total_tokens),
+        };
+        let system_fingerprint = response.system_fingerprint;
+        let Some(choice) = response.choices.into_iter().next() else {
+            return Ok(CompletionResponse {
+                response: String::new(),
+                usage,
+                finish_reason: None,
+                system_fingerprint,
+                logprobs: None,
+                empty: true,
+                refusal: None,
+                cached: false,
+            });
+        };
+        let text = choice.message.content.unwrap_or_default();
+        let completion = CompletionResponse {
+            empty: text.trim().is_empty(),
+            response: text,
+            usage,
+            finish_reason: choice.finish_reason,
+            system_fingerprint,
+            logprobs: choice.logprobs.and_then(|logprobs| logprobs.content),
+            refusal: choice.message.refusal,
+            cached: false,
+        };
+        // Empty answers are not kept, so asking again gets a fresh try
+        if let Some((cache, key)) = cache.filter(|_| !completion.empty) {
+            cache.insert(key, completion.clone());
+        }
+        Ok(completion)
+    }
+
+    /// Condenses conversation turns into a short summary.
+    /// 
+    /// The turns are sent as one transcript, so an earlier summary at their
+    /// head is folded into the new one.
+    /// 
+    /// # Arguments
+    /// * `turns` - The turns to summarize, oldest first
+    /// 
+    /// # Returns
+    /// * `Ok(CompletionResponse)` - The summary and the tokens it cost
+    /// * `Err(anyhow::Error)` - If the API request fails
+    pub async fn summarize_turns(&self, turns: &[ChatTurn]) -> Result<CompletionResponse> {
+        self.instruct(OpenAIOperation::Summarize, models::CHAT_MODEL, models::SUMMARY_PROMPT, transcript(turns))
+            .await
+    }
+
+    /// Rewrites a follow-up question into a standalone search query.
+    /// 
+    /// Questions like "what about the second one?" only make sense together
+    /// with the conversation, so the rewrite model resolves such references
+    /// using the turns before the question.
+    /// 
+    /// # Arguments
+    /// * `turns` - The conversation before the question, oldest first
+    /// * `question` - The user's latest question
+    /// 
+    /// # Returns
+    /// * `Ok(CompletionResponse)` - The search query and the tokens it cost
+    /// * `Err(anyhow::Error)` - If the API request fails
+    pub async fn rewrite_query(&self, turns: &[ChatTurn], question: &str) -> Result<CompletionResponse> {
+        let input = format!("{}\n\nFollow-up question: {}", transcript(turns), question);
+        self.instruct(OpenAIOperation::Rewrite, &self.rewrite_model, models::REWRITE_PROMPT, input)
+            .await
+    }
+
+    /// Runs a single instruction over some input at temperature 0 and
+    /// returns the non-empty reply.
+    async fn instruct(
+        &self,
+        operation: OpenAIOperation,
+        model: &str,
+        instruction: &str,
+        input: String,
+    ) -> Result<CompletionResponse> {
         let request = CreateChatCompletionRequest {
-            model: models::CHAT_MODEL.into(),
-            messages: vec![ChatCompletionRequestMessage::User(
-                async_openai::types::ChatCompletionRequestUserMessage {
-                    content: ChatCompletionRequestUserMessageContent::Text(message.to_string()),
-                    name: None,
-                }
-            )],
-            temperature: Some(models::TEMPERATURE),
+            model: model.into(),
+            messages: vec![
+                (&ChatTurn::system(instruction)).into(),
+                (&ChatTurn::user(input)).into(),
+            ],
+            temperature: Some(0.0),
             ..Default::default()
         };
 
-        // Send request to OpenAI API
-        let response = self.client.chat().create(request).await?;
-        
-        // Format and return the response
+        let response = self
+            .call(operation, model, self.client.chat().create(request))
+            .await?;
+        let usage = response.usage.as_ref().map_or_else(Usage::default, |u| Usage {
+            prompt_tokens: u.prompt_tokens,
+            completion_tokens: u.completion_tokens,
+            total_tokens: u.total_tokens,
+        });
+        self.metrics.record_openai_usage(
+            operation,
+            model,
+            usage.prompt_tokens,
+            usage.completion_tokens,
+        );
+
+        let system_fingerprint = response.system_fingerprint;
+        let choice = response.choices.into_iter().next();
+        let finish_reason = choice.as_ref().and_then(|choice| choice.finish_reason);
+        let reply = choice
+            .and_then(|choice| choice.message.content)
+            .filter(|reply| !reply.trim().is_empty())
+            .ok_or_else(|| anyhow!("OpenAI returned an empty {} reply", operation.as_str()))?;
         Ok(CompletionResponse {
-            response: response.choices[0].message.content.clone().unwrap_or_default(),
-            usage: Usage {
-                prompt_tokens: response.usage.as_ref().map_or(0, |u| u.prompt_tokens),
-                completion_tokens: response.usage.as_ref().map_or(0, |u| u.completion_tokens),
-                total_tokens: response.usage.as_ref().map_or(0, |u| u.total_tokens),
-            },
+            response: reply,
+            usage,
+            finish_reason,
+            system_fingerprint,
+            logprobs: None,
+            empty: false,
+            refusal: None,
+            cached: false,
         })
     }
-} 
\ No newline at end of file
+
+    /// Awaits an OpenAI call, recording its latency and bounding it by the timeout.
+    /// 
+    /// If this future is dropped (e.g. because the client disconnected and axum
+    /// dropped the handler), the in-flight HTTP request is aborted and the
+    /// cancellation is counted in the metrics.
+    async fn call<T, E>(
+        &self,
+        operation: OpenAIOperation,
+        model: &str,
+        call: impl Future<Output = std::result::Result<T, E>>,
+    ) -> Result<T>
+    where
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        let timer = self.metrics.openai_timer(operation, model);
+        let result = tokio::time::timeout(self.timeout, call).await;
+        timer.finish();
+
+        match result {
+            Ok(response) => Ok(response?),
+            Err(_) => Err(anyhow!(
+                "OpenAI {} request timed out after {:?}",
+                operation.as_str(),
+                self.timeout
+            )),
+        }
+    }
+}
+
+/// Splits the tokens billed for a batch in proportion to each text's
+/// locally counted tokens.
+///
+/// The last text takes the rounding remainder, so the shares add up to
+/// `total` exactly.
+fn scale_shares(counts: &[u32], total: u32) -> Vec<u32> {
+    let sum: u64 = counts.iter().map(|&n| u64::from(n)).sum();
+    let mut shares: Vec<u32> = counts
+        .iter()
+        .map(|&n| (u64::from(total) * u64::from(n) / sum.max(1)) as u32)
+        .collect();
+    let assigned: u32 = shares.iter().sum();
+    if let Some(last) = shares.last_mut() {
+        *last += total.saturating_sub(assigned);
+    }
+    shares
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inputs(tokens: &[usize]) -> Vec<(String, usize)> {
+        tokens.iter().enumerate().map(|(i, tokens)| (i.to_string(), *tokens)).collect()
+    }
+
+    #[test]
+    fn requests_stay_under_the_token_cap() {
+        let batches = request_batches(inputs(&[100, 200, 50, 300, 10]), 350);
+        assert_eq!(batches, [vec!["0", "1", "2"], vec!["3", "4"]]);
+        let oversized = request_batches(inputs(&[500, 10]), 350);
+        assert_eq!(oversized, [vec!["0"], vec!["1"]]);
+        assert!(request_batches(Vec::new(), 350).is_empty());
+    }
+
+    #[test]
+    fn requests_stay_under_the_input_cap() {
+        let batches = request_batches(inputs(&vec![1; EmbeddingRequest::MAX_BATCH_SIZE + 1]), MAX_REQUEST_TOKENS);
+        assert_eq!(batches.iter().map(Vec::len).collect::<Vec<_>>(), [EmbeddingRequest::MAX_BATCH_SIZE, 1]);
+    }
+
+    /// Connections the service's HTTP client has opened so far.
+    fn connections_opened(metrics: &Metrics) -> u64 {
+        let rendered = metrics.render().expect("metrics render");
+        rendered
+            .lines()
+            .find_map(|line| line.strip_prefix("openai_connections_opened_total "))
+            .and_then(|count| count.parse().ok())
+            .expect("connection counter is exported")
+    }
+
+    /// A pooled service talking to a fresh demo OpenAI, reporting into `metrics`.
+    async fn pooled_service(pool_size: usize, metrics: Arc<Metrics>) -> OpenAIService {
+        let upstreams = crate::demo::start().await.expect("demo upstreams start");
+        OpenAIService::new("demo-key", None, None)
+            .with_http_pool(HttpPoolSettings::new(pool_size, Duration::from_secs(5)))
+            .with_api_base(Some(&upstreams.openai_base))
+            .with_metrics(metrics)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn pooled_connections_are_reused_whatever_the_builder_order() {
+        let metrics = Arc::new(Metrics::default());
+        let service = pooled_service(4, metrics.clone()).await;
+        for _ in 0..10 {
+            service.get_embedding("Rust is fast", None).await.expect("embedding");
+        }
+        // Counted although the metrics were set after the pool; a call can
+        // start before the previous connection is back in the pool
+        let opened = connections_opened(&metrics);
+        assert!((1..10).contains(&opened), "{} connections for 10 calls", opened);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "load test, run with `cargo test -- --ignored`"]
+    async fn pool_keeps_a_connection_per_worker_under_load() {
+        const WORKERS: usize = 16;
+        let metrics = Arc::new(Metrics::default());
+        let service = Arc::new(pooled_service(WORKERS, metrics.clone()).await);
+        let burst = |calls: usize| {
+            let service = service.clone();
+            async move {
+                let calls = (0..calls).map(|i| {
+                    let service = service.clone();
+                    async move { service.get_embedding(&format!("text {}", i), None).await }
+                });
+                for result in futures::future::join_all(calls).await {
+                    result.expect("embedding");
+                }
+            }
+        };
+
+        burst(4 * WORKERS).await;
+        let warmed_up = connections_opened(&metrics);
+        assert!(warmed_up >= WORKERS as u64);
+        for _ in 0..20 {
+            burst(WORKERS).await;
+        }
+        assert_eq!(connections_opened(&metrics), warmed_up);
+    }
+}
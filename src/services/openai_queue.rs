@@ -0,0 +1,343 @@
+use std::future::Future;
+use std::num::NonZeroUsize;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tracing::warn;
+
+use crate::metrics::{Metrics, RequestContext};
+use crate::services::OpenAIService;
+
+/// A queued OpenAI call, type-erased so one channel carries every kind of job.
+type Job = Box<dyn FnOnce(Arc<OpenAIService>) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send>;
+
+/// Why a job could not be run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum QueueError {
+    /// Every worker is busy and the queue is at `OPENAI_QUEUE_DEPTH`
+    #[error("OpenAI request queue is full")]
+    Full,
+    /// The workers have shut down
+    #[error("OpenAI workers are not running")]
+    Closed,
+    /// OpenAI is turned off with `DISABLE_OPENAI`
+    #[error("OpenAI is disabled on this server (DISABLE_OPENAI); this endpoint needs it")]
+    Disabled,
+}
+
+/// Kind of traffic a job belongs to, deciding which workers may run it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// A client is waiting on the call, e.g. chat, search or embed
+    Interactive,
+    /// Bulk work such as imports, reindexing and stale re-embedding
+    Background,
+}
+
+impl Priority {
+    fn label(self) -> &'static str {
+        match self {
+            Self::Interactive => "interactive",
+            Self::Background => "background",
+        }
+    }
+}
+
+/// A job tagged with its priority, as carried by the channels.
+type Queued = (Priority, Job);
+
+/// Receiving end of a channel, shared by the workers pulling from it.
+type SharedReceiver = Arc<Mutex<mpsc::Receiver<Queued>>>;
+
+/// Runs OpenAI calls on fixed pools of worker tasks.
+///
+/// Handlers submit jobs to a bounded channel and await their result, so at
+/// most `OPENAI_WORKERS` plus `OPENAI_BACKGROUND_WORKERS` calls are in
+/// flight however many HTTP requests are being served. When the channel is full, jobs are rejected right away
+/// instead of piling up behind a slow or rate-limited API.
+///
+/// With `OPENAI_BACKGROUND_WORKERS` set, background jobs get a channel and
+/// worker pool of their own, so bulk work can't take the interactive
+/// workers. Background workers pick up waiting interactive jobs first:
+/// interactive traffic borrows idle background capacity, never the other
+/// way round. An idle background worker may take an interactive job even
+/// while an interactive worker is free too; either way it runs at once.
+pub struct OpenAIQueue {
+    interactive: mpsc::Sender<Queued>,
+    /// Channel of background jobs; they share the interactive one when `None`
+    background: Option<mpsc::Sender<Queued>>,
+    metrics: Arc<Metrics>,
+    /// Whether every job is rejected because OpenAI is turned off
+    disabled: bool,
+}
+
+impl OpenAIQueue {
+    /// Starts the worker tasks; they stop once the queue is dropped.
+    ///
+    /// # Arguments
+    /// * `service` - The OpenAI service jobs are run against
+    /// * `workers` - Number of interactive calls run concurrently
+    /// * `background_workers` - Additional workers reserved for background
+    ///   jobs; with 0 all jobs share `workers`
+    /// * `depth` - Jobs of each priority that may wait for a free worker
+    ///   before new ones are rejected
+    /// * `metrics` - Metrics registry rejected and running jobs are counted in
+    pub fn start(
+        service: OpenAIService,
+        workers: NonZeroUsize,
+        background_workers: usize,
+        depth: NonZeroUsize,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        let service = Arc::new(service);
+        let (interactive, receiver) = mpsc::channel::<Queued>(depth.get());
+        let interactive_jobs: SharedReceiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..workers.get() {
+            let service = service.clone();
+            let jobs = interactive_jobs.clone();
+            let metrics = metrics.clone();
+            tokio::spawn(async move {
+                loop {
+                    // Only hold the lock while waiting, not while the job runs
+                    let job = jobs.lock().await.recv().await;
+                    match job {
+                        Some(job) => run(job, "interactive", &service, &metrics).await,
+                        None => break,
+                    }
+                }
+            });
+        }
+
+        let background = (background_workers > 0).then(|| {
+            let (background, receiver) = mpsc::channel::<Queued>(depth.get());
+            let background_jobs: SharedReceiver = Arc::new(Mutex::new(receiver));
+            for _ in 0..background_workers {
+                let service = service.clone();
+                let interactive_jobs = interactive_jobs.clone();
+                let background_jobs = background_jobs.clone();
+                let metrics = metrics.clone();
+                tokio::spawn(async move {
+                    loop {
+                        // Waiting interactive jobs go first
+                        let job = tokio::select! {
+                            biased;
+                            Some(job) = async { interactive_jobs.lock().await.recv().await } => Some(job),
+                            job = async { background_jobs.lock().await.recv().await } => job,
+                        };
+                        match job {
+                            Some(job) => run(job, "background", &service, &metrics).await,
+                            None => break,
+                        }
+                    }
+                });
+            }
+            background
+        });
+
+        Self {
+            interactive,
+            background,
+            metrics,
+            disabled: false,
+        }
+    }
+
+    /// Creates a queue without workers that rejects every job with
+    /// `QueueError::Disabled`, for running with `DISABLE_OPENAI`.
+    pub fn disabled(metrics: Arc<Metrics>) -> Self {
+        let (interactive, _) = mpsc::channel::<Queued>(1);
+        Self {
+            interactive,
+            background: None,
+            metrics,
+            disabled: true,
+        }
+    }
+
+    /// Returns whether the queue was created with `disabled`.
+    pub fn is_disabled(&self) -> bool {
+        self.disabled
+    }
+
+    /// Number of calls waiting for a free worker.
+    pub fn queued(&self) -> usize {
+        [Some(&self.interactive), self.background.as_ref()]
+            .into_iter()
+            .flatten()
+            .map(|sender| sender.max_capacity() - sender.capacity())
+            .sum()
+    }
+
+    /// Queues an interactive call and waits for its result.
+    ///
+    /// See `submit_with` for how the call is run.
+    pub async fn submit<T, F, Fut>(&self, job: F) -> Result<T, QueueError>
+    where
+        T: Send + 'static,
+        F: FnOnce(Arc<OpenAIService>) -> Fut + Send + 'static,
+        Fut: Future<Output = T> + Send + 'static,
+    {
+        self.submit_with(Priority::Interactive, job).await
+    }
+
+    /// Queues a background call and waits for its result.
+    ///
+    /// See `submit_with` for how the call is run.
+    pub async fn submit_background<T, F, Fut>(&self, job: F) -> Result<T, QueueError>
+    where
+        T: Send + 'static,
+        F: FnOnce(Arc<OpenAIService>) -> Fut + Send + 'static,
+        Fut: Future<Output = T> + Send + 'static,
+    {
+        self.submit_with(Priority::Background, job).await
+    }
+
+    /// Queues a call with the given priority and waits for its result.
+    ///
+    /// The call runs with the submitting request's metrics context, so its
+    /// latency and tokens are attributed as if it ran in the handler. If the
+    /// caller stops waiting (e.g. the client disconnected), a queued job is
+    /// skipped and a running one is aborted.
+    ///
+    /// # Arguments
+    /// * `priority` - Which workers may run the call
+    /// * `job` - Builds the call from the shared service
+    ///
+    /// # Returns
+    /// * `Ok(T)` - The call's output
+    /// * `Err(QueueError)` - If the queue is full, the workers have stopped or
+    ///   OpenAI is disabled
+    ///
+    /// # Example
+    /// ```no_run
+    /// let embedding = queue
+    ///     .submit(move |openai| async move { openai.get_embedding(&text, None).await })
+    ///     .await??;
+    /// ```
+    async fn submit_with<T, F, Fut>(&self, priority: Priority, job: F) -> Result<T, QueueError>
+    where
+        T: Send + 'static,
+        F: FnOnce(Arc<OpenAIService>) -> Fut + Send + 'static,
+        Fut: Future<Output = T> + Send + 'static,
+    {
+        if self.disabled {
+            return Err(QueueError::Disabled);
+        }
+        let (result_sender, result) = oneshot::channel();
+        let context = RequestContext::capture();
+        let job: Job = Box::new(move |service| {
+            Box::pin(async move {
+                let mut result_sender = result_sender;
+                let output = tokio::select! {
+                    output = context.scope(job(service)) => output,
+                    _ = result_sender.closed() => return,
+                };
+                let _ = result_sender.send(output);
+            })
+        });
+
+        let sender = match priority {
+            Priority::Background => self.background.as_ref().unwrap_or(&self.interactive),
+            Priority::Interactive => &self.interactive,
+        };
+        match sender.try_send((priority, job)) {
+            Ok(()) => {}
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                self.metrics.record_openai_queue_rejected();
+                warn!(priority = priority.label(), "OpenAI request queue is full, rejecting call");
+                return Err(QueueError::Full);
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => return Err(QueueError::Closed),
+        }
+        result.await.map_err(|_| QueueError::Closed)
+    }
+}
+
+/// Runs a job on a worker of `pool`, counting it as in flight meanwhile.
+async fn run((priority, job): Queued, pool: &str, service: &Arc<OpenAIService>, metrics: &Metrics) {
+    let in_flight = metrics.openai_calls_in_flight(pool, priority.label());
+    in_flight.inc();
+    job(service.clone()).await;
+    in_flight.dec();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{Duration, Instant};
+
+    /// Most jobs of each priority seen running at once.
+    #[derive(Default)]
+    struct Concurrency {
+        running: [AtomicUsize; 2],
+        peak: [AtomicUsize; 2],
+    }
+
+    impl Concurrency {
+        fn peak(&self, priority: Priority) -> usize {
+            self.peak[priority as usize].load(Ordering::SeqCst)
+        }
+    }
+
+    fn queue(workers: usize, background_workers: usize) -> OpenAIQueue {
+        OpenAIQueue::start(
+            OpenAIService::new("test-key", None, None),
+            NonZeroUsize::new(workers).unwrap(),
+            background_workers,
+            NonZeroUsize::new(8).unwrap(),
+            Arc::default(),
+        )
+    }
+
+    /// Submits a job that sleeps for `duration` instead of calling OpenAI.
+    async fn sleep(queue: &OpenAIQueue, priority: Priority, duration: Duration, seen: Arc<Concurrency>) {
+        queue
+            .submit_with(priority, move |_| async move {
+                let slot = priority as usize;
+                let running = seen.running[slot].fetch_add(1, Ordering::SeqCst) + 1;
+                seen.peak[slot].fetch_max(running, Ordering::SeqCst);
+                tokio::time::sleep(duration).await;
+                seen.running[slot].fetch_sub(1, Ordering::SeqCst);
+            })
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn interactive_calls_borrow_idle_background_workers() {
+        let queue = queue(1, 1);
+        let seen = Arc::new(Concurrency::default());
+        let start = Instant::now();
+        let calls = (0..2).map(|_| sleep(&queue, Priority::Interactive, Duration::from_millis(200), seen.clone()));
+        futures::future::join_all(calls).await;
+        assert_eq!(seen.peak(Priority::Interactive), 2);
+        assert!(start.elapsed() < Duration::from_millis(350), "{:?}", start.elapsed());
+    }
+
+    #[tokio::test]
+    async fn background_calls_never_take_interactive_workers() {
+        let queue = queue(2, 1);
+        let seen = Arc::new(Concurrency::default());
+        let background = (0..3).map(|_| sleep(&queue, Priority::Background, Duration::from_millis(100), seen.clone()));
+        let interactive = async {
+            // Submitted while background work is queued, and still runs at once
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            let start = Instant::now();
+            sleep(&queue, Priority::Interactive, Duration::from_millis(10), seen.clone()).await;
+            start.elapsed()
+        };
+        let (_, waited) = tokio::join!(futures::future::join_all(background), interactive);
+        assert_eq!(seen.peak(Priority::Background), 1);
+        assert!(waited < Duration::from_millis(80), "{:?}", waited);
+    }
+
+    #[tokio::test]
+    async fn one_pool_is_shared_without_background_workers() {
+        let queue = queue(2, 0);
+        let seen = Arc::new(Concurrency::default());
+        let calls = (0..4).map(|_| sleep(&queue, Priority::Background, Duration::from_millis(50), seen.clone()));
+        futures::future::join_all(calls).await;
+        assert_eq!(seen.peak(Priority::Background), 2);
+    }
+}
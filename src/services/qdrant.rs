@@ -1,15 +1,838 @@
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use qdrant_client::{
-    Qdrant,
+    Qdrant, QdrantError,
     config::QdrantConfig,
-    qdrant::{PointStruct, Vectors, Value as QdrantValue, WriteOrdering, DeletePoints, Filter, PointId, SearchPoints, SearchResponse, PointsSelector, points_selector::PointsSelectorOneOf},
+    qdrant::{
+        PointStruct, Vectors, Value as QdrantValue, WriteOrdering, WriteOrderingType, DeletePoints,
+        Filter, PointsSelector, points_selector::PointsSelectorOneOf, ReadConsistency,
+        ReadConsistencyType, read_consistency, SearchPoints, ScoredPoint, WithPayloadSelector,
+        point_id::PointIdOptions, PointId, ScrollPoints, RetrievedPoint, WithVectorsSelector,
+        VectorsOutput, vectors_output, vector_output, ShardKeySelector, ShardingMethod, CreateCollection, VectorsConfig,
+        VectorParams, Distance, vectors_config, GetPoints, CountPoints, Condition, CollectionStatus,
+        CreateAliasBuilder, PointsIdsList, SearchBatchPoints, CreateFieldIndexCollection, FieldType,
+        PayloadIndexParams, payload_index_params::IndexParams, TextIndexParams, TokenizerType,
+        r#match::MatchValue, SetPayloadPoints, DeletePayloadPoints, Range, SearchPointGroups,
+        PointGroup, group_id, UpdateMode, UpsertPoints,
+    },
 };
+use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
 use std::collections::HashMap;
-use std::error::Error;
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tonic::Code;
+use tracing::{info, warn};
 
-use crate::models::Document;
-use crate::config::Config;
+use crate::metrics::{self, Metrics};
+use crate::models::{Document, DocumentVersion};
+use crate::types::{SearchGroup, SearchHit};
+use crate::vector_math;
+
+/// Consecutive unreachable calls after which Qdrant is considered down.
+const UNREACHABLE_AFTER: u32 = 3;
+
+/// First wait before probing an unreachable Qdrant again; doubles per failed probe.
+pub const MIN_RECONNECT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Longest wait between probes of an unreachable Qdrant.
+pub const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Whether Qdrant can be reached, as last observed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Connectivity {
+    /// Startup checks have not succeeded yet (`QDRANT_OPTIONAL_AT_BOOT`)
+    Connecting,
+    /// Calls are reaching Qdrant
+    Connected,
+    /// Recent calls could not reach Qdrant; it is probed again with backoff
+    Unavailable,
+}
+
+/// Connectivity state shared by the calls of a `QdrantService`.
+#[derive(Debug)]
+struct Link {
+    connectivity: Connectivity,
+    /// Consecutive calls that could not reach Qdrant
+    failures: u32,
+    /// Wait before the next probe while unavailable
+    backoff: Duration,
+    /// When the next call may go through as a probe while unavailable
+    retry_at: Instant,
+}
+
+impl Default for Link {
+    fn default() -> Self {
+        Self {
+            connectivity: Connectivity::Connected,
+            failures: 0,
+            backoff: MIN_RECONNECT_BACKOFF,
+            retry_at: Instant::now(),
+        }
+    }
+}
+
+/// Returns whether a Qdrant call failed because the server could not be
+/// reached or did not answer in time, rather than rejecting the request.
+fn is_unreachable_error(error: &QdrantError) -> bool {
+    match error {
+        QdrantError::ResponseError { status } => match status.code() {
+            Code::Unavailable | Code::DeadlineExceeded => true,
+            // The client reports failed connection attempts as internal errors
+            Code::Internal => status.message().starts_with("Failed to connect"),
+            _ => false,
+        },
+        QdrantError::Io(_) => true,
+        _ => false,
+    }
+}
+
+/// Returns whether a Qdrant call failed because its collection does not
+/// exist, e.g. after it was deleted outside the service.
+fn is_collection_missing(error: &QdrantError) -> bool {
+    matches!(error, QdrantError::ResponseError { status } if status.code() == Code::NotFound)
+}
+
+/// Returns the gRPC status code of the Qdrant call that failed with `error`,
+/// if any of its causes is one.
+pub fn status_code(error: &anyhow::Error) -> Option<Code> {
+    error.chain().find_map(|cause| match cause.downcast_ref::<QdrantError>()? {
+        QdrantError::ResponseError { status } => Some(status.code()),
+        _ => None,
+    })
+}
+
+/// Reads the name out of Qdrant's "Collection `name` doesn't exist!" message.
+fn missing_collection_name(message: &str) -> Option<&str> {
+    let (_, rest) = message.split_once('`')?;
+    rest.split_once('`').map(|(name, _)| name)
+}
+
+/// Reads the sizes out of Qdrant's "Vector dimension error: expected dim:
+/// 1536, got 3" message.
+fn vector_dimension_error(message: &str) -> Option<(u64, u64)> {
+    let (_, rest) = message.split_once("expected dim: ")?;
+    let (expected, rest) = rest.split_once(',')?;
+    let actual = rest.trim().strip_prefix("got ")?;
+    let actual: String = actual.chars().take_while(char::is_ascii_digit).collect();
+    Some((expected.trim().parse().ok()?, actual.parse().ok()?))
+}
+
+/// Returns whether any cause of `error` is a Qdrant call that could not
+/// reach the server.
+pub fn is_unreachable(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .any(|cause| cause.downcast_ref::<QdrantError>().is_some_and(is_unreachable_error))
+}
+
+/// Reciprocal rank fusion constant; keeps the top few ranks from dominating
+/// the fused score.
+const RRF_K: f32 = 60.0;
+
+/// Least a score threshold moves when relaxed, so a threshold of zero loosens too.
+const MIN_RELAX_MARGIN: f32 = 0.1;
+
+/// Payload flag set on documents in the trash.
+const DELETED_FIELD: &str = "deleted";
+
+/// Payload field holding when a document was moved to the trash, in
+/// milliseconds since the Unix epoch.
+const DELETED_AT_FIELD: &str = "deleted_at";
+
+/// Payload field holding a document's version number when versioning is on.
+const VERSION_FIELD: &str = "version";
+
+/// Payload flag set on points that hold an earlier version of a document.
+const IS_VERSION_FIELD: &str = "is_version";
+
+/// Payload field linking an earlier version to its document's id.
+const VERSION_OF_FIELD: &str = "version_of";
+
+/// Payload field holding when a version was replaced, in milliseconds since
+/// the Unix epoch.
+const ARCHIVED_AT_FIELD: &str = "archived_at";
+
+/// Most versions of one document read in a single listing.
+const MAX_VERSIONS_LISTED: u32 = 1000;
+
+/// Payload flag set on documents waiting to be re-embedded.
+const STALE_FIELD: &str = "stale";
+
+/// Payload field holding when a document was last written, in milliseconds
+/// since the Unix epoch.
+const UPDATED_AT_FIELD: &str = "updated_at";
+
+/// Payload field listing, as JSON pointers into `metadata`, the integers
+/// stored as strings under `LARGE_INTEGERS=string`, so reads can turn them
+/// back into numbers.
+pub(crate) const LARGE_INTEGERS_FIELD: &str = "large_integers";
+
+/// Attempts at a versioned write before giving up on documents that other
+/// writers keep changing.
+const VERSIONED_WRITE_ATTEMPTS: usize = 5;
+
+/// Read consistency level for search operations against a Qdrant cluster.
+///
+/// Accepted textual forms are `all`, `majority`, `quorum`, or a positive
+/// integer factor (the number of replicas that must be queried).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "JsonValue")]
+pub enum ReadConsistencyLevel {
+    /// Query the given number of replicas
+    Factor(u64),
+    /// Query all replicas and return points present on all of them
+    All,
+    /// Query all replicas and return points present on a majority of them
+    Majority,
+    /// Query half + 1 replicas and return points present on all of them
+    Quorum,
+}
+
+impl FromStr for ReadConsistencyLevel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "all" => Ok(Self::All),
+            "majority" => Ok(Self::Majority),
+            "quorum" => Ok(Self::Quorum),
+            other => match other.parse::<u64>() {
+                Ok(factor) if factor > 0 => Ok(Self::Factor(factor)),
+                _ => Err(anyhow!(
+                    "expected 'all', 'majority', 'quorum' or a positive factor, got '{}'",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+impl TryFrom<JsonValue> for ReadConsistencyLevel {
+    type Error = anyhow::Error;
+
+    fn try_from(value: JsonValue) -> Result<Self> {
+        match value {
+            JsonValue::String(s) => s.parse(),
+            JsonValue::Number(n) => n.to_string().parse(),
+            other => Err(anyhow!("invalid read consistency: {}", other)),
+        }
+    }
+}
+
+impl From<ReadConsistencyLevel> for ReadConsistency {
+    fn from(level: ReadConsistencyLevel) -> Self {
+        let value = match level {
+            ReadConsistencyLevel::Factor(factor) => read_consistency::Value::Factor(factor),
+            ReadConsistencyLevel::All => read_consistency::Value::Type(ReadConsistencyType::All as i32),
+            ReadConsistencyLevel::Majority => read_consistency::Value::Type(ReadConsistencyType::Majority as i32),
+            ReadConsistencyLevel::Quorum => read_consistency::Value::Type(ReadConsistencyType::Quorum as i32),
+        };
+        ReadConsistency { value: Some(value) }
+    }
+}
+
+/// Write ordering guarantee for upsert and delete operations.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WriteOrderingLevel {
+    /// Writes may be reordered (Qdrant's default, fastest)
+    #[default]
+    Weak,
+    /// Writes go through a dynamically elected leader
+    Medium,
+    /// Writes go through the permanent leader
+    Strong,
+}
+
+impl FromStr for WriteOrderingLevel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "weak" => Ok(Self::Weak),
+            "medium" => Ok(Self::Medium),
+            "strong" => Ok(Self::Strong),
+            _ => Err(anyhow!("expected 'weak', 'medium' or 'strong', got '{}'", s)),
+        }
+    }
+}
+
+impl From<WriteOrderingLevel> for WriteOrdering {
+    fn from(level: WriteOrderingLevel) -> Self {
+        let ordering = match level {
+            WriteOrderingLevel::Weak => WriteOrderingType::Weak,
+            WriteOrderingLevel::Medium => WriteOrderingType::Medium,
+            WriteOrderingLevel::Strong => WriteOrderingType::Strong,
+        };
+        WriteOrdering { r#type: ordering as i32 }
+    }
+}
+
+/// How payload integers beyond the signed 64-bit range are stored.
+///
+/// Qdrant payload integers are `i64`, so values from 2^63 to `u64::MAX`
+/// (e.g. large external ids) don't fit one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LargeIntegers {
+    /// Store the exact digits as a string; filter on them with a keyword match
+    #[default]
+    String,
+    /// Store the nearest double, which keeps only about 16 significant digits
+    Double,
+}
+
+impl FromStr for LargeIntegers {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "string" => Ok(Self::String),
+            "double" => Ok(Self::Double),
+            _ => Err(anyhow!("expected 'string' or 'double', got '{}'", s)),
+        }
+    }
+}
+
+/// Value a payload field must match in a `DocumentFilter` condition.
+///
+/// Arrays match when the field equals any of their elements.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum MatchSpec {
+    /// Exact boolean value
+    Bool(bool),
+    /// Exact integer value
+    Integer(i64),
+    /// Exact string value
+    Keyword(String),
+    /// Any of the given integers
+    Integers(Vec<i64>),
+    /// Any of the given strings
+    Keywords(Vec<String>),
+}
+
+impl From<MatchSpec> for MatchValue {
+    fn from(spec: MatchSpec) -> Self {
+        match spec {
+            MatchSpec::Bool(value) => value.into(),
+            MatchSpec::Integer(value) => value.into(),
+            MatchSpec::Keyword(value) => value.into(),
+            MatchSpec::Integers(values) => values.into(),
+            MatchSpec::Keywords(values) => values.into(),
+        }
+    }
+}
+
+/// Condition requiring the payload field `key` to match a value.
+///
+/// Metadata fields are addressed as `metadata.<field>`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct FieldMatch {
+    /// Payload field path
+    pub key: String,
+    /// Value the field must match
+    #[serde(rename = "match")]
+    pub value: MatchSpec,
+}
+
+/// JSON representation of a Qdrant payload filter.
+///
+/// All `must` conditions, at least one `should` condition (if any are
+/// given) and none of the `must_not` conditions have to hold.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct DocumentFilter {
+    /// Conditions that must all hold
+    #[serde(default)]
+    pub must: Vec<FieldMatch>,
+    /// Conditions of which at least one must hold
+    #[serde(default)]
+    pub should: Vec<FieldMatch>,
+    /// Conditions that must not hold
+    #[serde(default)]
+    pub must_not: Vec<FieldMatch>,
+}
+
+/// Clauses a `DocumentFilter` may have.
+const FILTER_CLAUSES: &[&str] = &["must", "should", "must_not"];
+
+/// Fields of a `FieldMatch` condition.
+const CONDITION_FIELDS: &[&str] = &["key", "match"];
+
+/// A problem found in a filter, at the path of the offending part.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct FilterProblem {
+    /// Location within the filter, such as `must[2].match`; empty for the filter itself
+    pub path: String,
+    pub message: String,
+}
+
+impl FilterProblem {
+    fn new(path: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            message: message.into(),
+        }
+    }
+
+    /// Lists problems on one line, for error messages and logs.
+    pub fn join(problems: &[FilterProblem]) -> String {
+        problems
+            .iter()
+            .map(|problem| match problem.path.as_str() {
+                "" => problem.message.clone(),
+                path => format!("{} {}", path, problem.message),
+            })
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+}
+
+impl DocumentFilter {
+    /// Returns whether the filter has no conditions, i.e. matches every point.
+    pub fn is_empty(&self) -> bool {
+        self.must.is_empty() && self.should.is_empty() && self.must_not.is_empty()
+    }
+
+    /// Parses a filter sent by a client, checking every clause first.
+    ///
+    /// Unlike deserializing it directly, which stops at the first mistake
+    /// and names it in serde's terms, every problem is reported with the
+    /// path of the clause it is in, e.g. `must[2].mach`.
+    ///
+    /// # Returns
+    /// * `Ok(DocumentFilter)` - The filter, if it is valid
+    /// * `Err(Vec<FilterProblem>)` - Each problem found, clause by clause
+    pub fn parse(value: &JsonValue) -> std::result::Result<Self, Vec<FilterProblem>> {
+        let problems = Self::check(value);
+        if !problems.is_empty() {
+            return Err(problems);
+        }
+        serde_json::from_value(value.clone()).map_err(|e| vec![FilterProblem::new("", e.to_string())])
+    }
+
+    /// Returns the problems of a filter without parsing it.
+    pub fn check(value: &JsonValue) -> Vec<FilterProblem> {
+        let mut problems = Vec::new();
+        let Some(clauses) = value.as_object() else {
+            problems.push(FilterProblem::new(
+                "",
+                format!("filter must be an object, got {}", json_kind(value)),
+            ));
+            return problems;
+        };
+
+        for (clause, conditions) in clauses {
+            if !FILTER_CLAUSES.contains(&clause.as_str()) {
+                problems.push(FilterProblem::new(clause.as_str(), unknown(clause, FILTER_CLAUSES)));
+                continue;
+            }
+            let Some(conditions) = conditions.as_array() else {
+                problems.push(FilterProblem::new(
+                    clause.as_str(),
+                    format!("must be an array of conditions, got {}", json_kind(conditions)),
+                ));
+                continue;
+            };
+            for (i, condition) in conditions.iter().enumerate() {
+                check_condition(&format!("{}[{}]", clause, i), condition, &mut problems);
+            }
+        }
+        problems
+    }
+}
+
+/// Checks one `{"key": ..., "match": ...}` condition at `path`.
+fn check_condition(path: &str, condition: &JsonValue, problems: &mut Vec<FilterProblem>) {
+    let Some(fields) = condition.as_object() else {
+        problems.push(FilterProblem::new(
+            path,
+            format!("must be a condition object with \"key\" and \"match\", got {}", json_kind(condition)),
+        ));
+        return;
+    };
+    for field in fields.keys().filter(|field| !CONDITION_FIELDS.contains(&field.as_str())) {
+        problems.push(FilterProblem::new(format!("{}.{}", path, field), unknown(field, CONDITION_FIELDS)));
+    }
+
+    let key_path = format!("{}.key", path);
+    match fields.get("key") {
+        None => problems.push(FilterProblem::new(key_path, "is missing; name the payload field to match")),
+        Some(JsonValue::String(key)) if key.trim().is_empty() => {
+            problems.push(FilterProblem::new(key_path, "must not be empty"))
+        }
+        Some(JsonValue::String(_)) => {}
+        Some(other) => problems.push(FilterProblem::new(
+            key_path,
+            format!("must be a string, got {}", json_kind(other)),
+        )),
+    }
+
+    let match_path = format!("{}.match", path);
+    match fields.get("match") {
+        None => problems.push(FilterProblem::new(match_path, "is missing; give the value the field must match")),
+        Some(JsonValue::Array(values)) => {
+            // Every element must have the type of the first
+            let first = values.first().and_then(|value| match_element(value).ok());
+            for (i, value) in values.iter().enumerate() {
+                let path = format!("{}[{}]", match_path, i);
+                match (match_element(value), first) {
+                    (Err(message), _) => problems.push(FilterProblem::new(path, message)),
+                    (Ok(kind), Some(expected)) if kind != expected => problems.push(FilterProblem::new(
+                        path,
+                        format!("must be {} like the first element, got {}", expected, json_kind(value)),
+                    )),
+                    _ => {}
+                }
+            }
+        }
+        Some(JsonValue::Bool(_) | JsonValue::String(_)) => {}
+        Some(value @ JsonValue::Number(_)) => {
+            if let Err(message) = match_element(value) {
+                problems.push(FilterProblem::new(match_path, message));
+            }
+        }
+        Some(other) => problems.push(FilterProblem::new(
+            match_path,
+            format!(
+                "must be a boolean, integer, string, or array of integers or strings, got {}",
+                json_kind(other)
+            ),
+        )),
+    }
+}
+
+/// Returns the kind of an element of a `match` array, or why it can't be one.
+fn match_element(value: &JsonValue) -> std::result::Result<&'static str, String> {
+    match value {
+        JsonValue::String(_) => Ok("a string"),
+        JsonValue::Number(number) if number.is_i64() => Ok("an integer"),
+        JsonValue::Number(number) if number.is_u64() => {
+            Err("must be an integer within the signed 64-bit range".to_string())
+        }
+        JsonValue::Number(_) => Err("must be an integer, got a decimal number".to_string()),
+        other => Err(format!("must be an integer or a string, got {}", json_kind(other))),
+    }
+}
+
+/// Describes an unknown field, suggesting the expected name closest to it.
+fn unknown(name: &str, expected: &[&str]) -> String {
+    let closest = expected
+        .iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .min()
+        .filter(|(distance, _)| *distance <= 2);
+    match closest {
+        Some((_, candidate)) => format!("is not a known field; did you mean \"{}\"?", candidate),
+        None => format!(
+            "is not a known field; expected {}",
+            expected.iter().map(|name| format!("\"{}\"", name)).collect::<Vec<_>>().join(", ")
+        ),
+    }
+}
+
+/// Number of single-character edits turning `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Names the JSON type of a value, for error messages.
+fn json_kind(value: &JsonValue) -> &'static str {
+    match value {
+        JsonValue::Null => "null",
+        JsonValue::Bool(_) => "a boolean",
+        JsonValue::Number(_) => "a number",
+        JsonValue::String(_) => "a string",
+        JsonValue::Array(_) => "an array",
+        JsonValue::Object(_) => "an object",
+    }
+}
+
+impl From<DocumentFilter> for Filter {
+    fn from(filter: DocumentFilter) -> Self {
+        let conditions = |matches: Vec<FieldMatch>| -> Vec<Condition> {
+            matches
+                .into_iter()
+                .map(|m| Condition::matches(m.key, MatchValue::from(m.value)))
+                .collect()
+        };
+        Filter {
+            must: conditions(filter.must),
+            should: conditions(filter.should),
+            must_not: conditions(filter.must_not),
+            ..Default::default()
+        }
+    }
+}
+
+/// Which documents a search looks at and how it reads them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SearchScope<'a> {
+    /// Payload filter the documents must match, besides being current
+    pub filter: Option<&'a DocumentFilter>,
+    /// Read consistency override for this search
+    pub read_consistency: Option<ReadConsistencyLevel>,
+    /// Shard key to search in (custom sharding only)
+    pub shard_key: Option<&'a str>,
+}
+
+/// Error returned when a vector's length does not match the collection's vector size.
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("vector has {actual} dimensions but collection '{collection}' expects {expected}")]
+pub struct DimensionMismatch {
+    /// Name of the target collection
+    pub collection: String,
+    /// Vector size configured for the collection
+    pub expected: u64,
+    /// Length of the supplied vector
+    pub actual: u64,
+}
+
+/// Error returned when documents kept changing under a versioned write.
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("documents {ids:?} were changed by other writers on each of {attempts} attempts")]
+pub struct WriteConflict {
+    /// Ids of the documents that could not be written
+    pub ids: Vec<u64>,
+    pub attempts: usize,
+}
+
+/// A failed Qdrant call the client can do something about, recognised by
+/// `QdrantService::translate_error`.
+#[derive(Debug, thiserror::Error)]
+pub enum QdrantRejection {
+    /// The collection does not exist
+    #[error("collection '{collection}' does not exist; {hint}")]
+    CollectionMissing {
+        collection: String,
+        /// What would fix it
+        hint: &'static str,
+    },
+    /// A vector's length differs from the collection's vector size
+    #[error(transparent)]
+    DimensionMismatch(DimensionMismatch),
+    /// A point id is neither an unsigned integer nor a UUID
+    #[error("invalid point id: {0}; ids must be unsigned integers or UUIDs")]
+    InvalidPointId(String),
+    /// The request exceeded the gRPC message size limit
+    #[error("request to Qdrant too large ({0}); send fewer or smaller documents at a time")]
+    PayloadTooLarge(String),
+    /// Something else the request names does not exist, e.g. a shard key
+    #[error("not found in Qdrant: {0}")]
+    NotFound(String),
+    /// Qdrant refused the request's arguments for another reason
+    #[error("rejected by Qdrant: {0}")]
+    InvalidArgument(String),
+    /// What the request creates exists already
+    #[error("already exists in Qdrant: {0}")]
+    AlreadyExists(String),
+}
+
+/// Name, size and health of a collection, as reported by Qdrant.
+#[derive(Debug, Serialize)]
+pub struct CollectionSummary {
+    /// Collection name
+    pub name: String,
+    /// Approximate number of points in the collection
+    pub points_count: Option<u64>,
+    /// Optimizer status (`green`, `yellow`, `grey` or `red`)
+    pub status: String,
+}
+
+/// Distance metric used to compare vectors in the collection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DistanceMetric {
+    /// Cosine similarity (Qdrant normalizes vectors internally)
+    #[default]
+    Cosine,
+    /// Dot product; only meaningful for normalized vectors
+    Dot,
+    /// Euclidean distance
+    #[serde(alias = "euclidean")]
+    Euclid,
+    /// Manhattan distance
+    Manhattan,
+}
+
+impl FromStr for DistanceMetric {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "cosine" => Ok(Self::Cosine),
+            "dot" => Ok(Self::Dot),
+            "euclid" | "euclidean" => Ok(Self::Euclid),
+            "manhattan" => Ok(Self::Manhattan),
+            _ => Err(anyhow!("expected 'cosine', 'dot', 'euclid' or 'manhattan', got '{}'", s)),
+        }
+    }
+}
+
+impl DistanceMetric {
+    /// Whether a higher score means a closer match (similarities) rather
+    /// than a more distant one (distances).
+    pub fn higher_is_closer(self) -> bool {
+        matches!(self, Self::Cosine | Self::Dot)
+    }
+
+    /// Returns whether a search score is at least as close as `threshold`.
+    pub fn clears(self, score: f32, threshold: f32) -> bool {
+        if self.higher_is_closer() {
+            score >= threshold
+        } else {
+            score <= threshold
+        }
+    }
+
+    /// Loosens a score threshold by subtracting a margin from a similarity
+    /// threshold, half its magnitude so negative dot products loosen too, and
+    /// by doubling a distance threshold. A threshold of zero moves by
+    /// `MIN_RELAX_MARGIN`.
+    pub fn relax(self, threshold: f32) -> f32 {
+        if self.higher_is_closer() {
+            threshold - (threshold.abs() / 2.0).max(MIN_RELAX_MARGIN)
+        } else {
+            threshold + threshold.abs().max(MIN_RELAX_MARGIN)
+        }
+    }
+
+    /// Scores two vectors the way Qdrant scores a search hit under this metric.
+    ///
+    /// # Returns
+    /// * `Ok(f32)` - A similarity for cosine and dot, a distance otherwise
+    /// * `Err(ZeroVector)` - For cosine, if either vector has no magnitude
+    pub fn score(self, a: &[f32], b: &[f32]) -> Result<f32, vector_math::ZeroVector> {
+        match self {
+            Self::Cosine => vector_math::cosine_similarity(a, b),
+            Self::Dot => Ok(vector_math::dot(a, b)),
+            Self::Euclid => Ok(vector_math::euclidean_distance(a, b)),
+            Self::Manhattan => Ok(vector_math::manhattan_distance(a, b)),
+        }
+    }
+}
+
+impl From<DistanceMetric> for Distance {
+    fn from(metric: DistanceMetric) -> Self {
+        match metric {
+            DistanceMetric::Cosine => Distance::Cosine,
+            DistanceMetric::Dot => Distance::Dot,
+            DistanceMetric::Euclid => Distance::Euclid,
+            DistanceMetric::Manhattan => Distance::Manhattan,
+        }
+    }
+}
+
+/// Sharding method used by the collection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ShardingMode {
+    /// Qdrant distributes points across shards by id (default)
+    #[default]
+    Auto,
+    /// Points are routed to user-defined shard keys
+    Custom,
+}
+
+impl FromStr for ShardingMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(Self::Auto),
+            "custom" => Ok(Self::Custom),
+            _ => Err(anyhow!("expected 'auto' or 'custom', got '{}'", s)),
+        }
+    }
+}
+
+/// Type of a payload index, which decides the filters it speeds up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayloadSchema {
+    /// Exact string matches
+    Keyword,
+    /// Integer matches and ranges
+    Integer,
+    /// Float ranges
+    Float,
+    /// Boolean matches
+    Bool,
+    /// RFC 3339 datetime ranges
+    Datetime,
+    /// UUID matches
+    Uuid,
+}
+
+impl FromStr for PayloadSchema {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "keyword" => Ok(Self::Keyword),
+            "integer" => Ok(Self::Integer),
+            "float" => Ok(Self::Float),
+            "bool" => Ok(Self::Bool),
+            "datetime" => Ok(Self::Datetime),
+            "uuid" => Ok(Self::Uuid),
+            _ => Err(anyhow!(
+                "expected 'keyword', 'integer', 'float', 'bool', 'datetime' or 'uuid', got '{}'",
+                s
+            )),
+        }
+    }
+}
+
+impl From<PayloadSchema> for FieldType {
+    fn from(schema: PayloadSchema) -> Self {
+        match schema {
+            PayloadSchema::Keyword => FieldType::Keyword,
+            PayloadSchema::Integer => FieldType::Integer,
+            PayloadSchema::Float => FieldType::Float,
+            PayloadSchema::Bool => FieldType::Bool,
+            PayloadSchema::Datetime => FieldType::Datetime,
+            PayloadSchema::Uuid => FieldType::Uuid,
+        }
+    }
+}
+
+/// A payload field indexed whenever a collection is created, written as
+/// `field:type`, e.g. `metadata.source:keyword`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PayloadIndex {
+    /// Payload path of the field; metadata fields are `metadata.<name>`
+    pub field: String,
+    pub schema: PayloadSchema,
+}
+
+impl FromStr for PayloadIndex {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (field, schema) = s
+            .rsplit_once(':')
+            .ok_or_else(|| anyhow!("expected 'field:type', got '{}'", s))?;
+        let field = field.trim();
+        if field.is_empty() {
+            return Err(anyhow!("missing field name in '{}'", s));
+        }
+        Ok(Self {
+            field: field.to_string(),
+            schema: schema.trim().parse()?,
+        })
+    }
+}
 
 /// Service for interacting with the Qdrant vector database.
 /// 
@@ -21,6 +844,52 @@ pub struct QdrantService {
     client: Qdrant,
     /// Name of the collection where documents are stored
     collection_name: String,
+    /// Alias addressed instead of `collection_name`, so the collection behind it can be swapped
+    alias: Option<String>,
+    /// Read consistency applied to searches unless overridden per request
+    read_consistency: Option<ReadConsistencyLevel>,
+    /// Write ordering applied to writes unless overridden per request
+    write_ordering: WriteOrderingLevel,
+    /// Sharding method of the collection; custom sharding requires a shard key per operation
+    sharding: ShardingMode,
+    /// Shard keys of the tenants API keys belong to, by key fingerprint
+    tenants: HashMap<String, String>,
+    /// Expected vector size, used to validate vectors before upserting
+    vector_size: Option<u64>,
+    /// Distance metric used when creating the collection
+    distance: DistanceMetric,
+    /// Whether vectors are L2-normalized before upserts and searches
+    normalize: bool,
+    /// Whether a newly created collection keeps its vectors on disk
+    on_disk: bool,
+    /// Shards of a newly created collection (per shard key with custom sharding)
+    shard_number: u32,
+    /// Copies of each shard kept across the cluster's nodes
+    replication_factor: u32,
+    /// Payload fields indexed right after a collection is created
+    payload_indexes: Vec<PayloadIndex>,
+    /// Earlier versions kept per document; 0 turns versioning off
+    version_retention: usize,
+    /// Storage of payload integers above `i64::MAX`
+    large_integers: LargeIntegers,
+    /// Recreate the collection when a call finds it missing, then retry the call
+    auto_create: bool,
+    /// Whether the collection gets a full-text index on `text` when recreated
+    text_index: bool,
+    /// Fail the collection checks when an existing collection's vector size
+    /// differs from the embedding dimension, rather than only warning
+    strict_vector_size: bool,
+    /// Held while recreating the collection, so concurrent calls do it once
+    recreating: tokio::sync::Mutex<()>,
+    /// Latency metrics for Qdrant calls
+    metrics: Arc<Metrics>,
+    /// Incremented on every successful write made through this service
+    version: AtomicU64,
+    /// Last `updated_at` stamped on a document, so no two writes through
+    /// this service share one
+    last_write: AtomicU64,
+    /// Whether Qdrant is reachable, updated from the outcome of every call
+    link: Mutex<Link>,
 }
 
 impl QdrantService {
@@ -59,72 +928,761 @@ impl QdrantService {
         Ok(Self {
             client,
             collection_name: collection_name.to_string(),
+            alias: None,
+            read_consistency: None,
+            write_ordering: WriteOrderingLevel::default(),
+            sharding: ShardingMode::default(),
+            tenants: HashMap::new(),
+            vector_size: None,
+            distance: DistanceMetric::default(),
+            normalize: false,
+            on_disk: false,
+            shard_number: 1,
+            replication_factor: 1,
+            payload_indexes: Vec::new(),
+            version_retention: 0,
+            large_integers: LargeIntegers::default(),
+            auto_create: false,
+            text_index: false,
+            strict_vector_size: true,
+            recreating: tokio::sync::Mutex::new(()),
+            metrics: Arc::default(),
+            version: AtomicU64::new(0),
+            last_write: AtomicU64::new(0),
+            link: Mutex::new(Link::default()),
         })
     }
 
-    /// Converts a JSON value to a Qdrant value.
-    fn json_to_qdrant_value(value: &JsonValue) -> QdrantValue {
-        match value {
-            JsonValue::Null => QdrantValue {
-                kind: Some(qdrant_client::qdrant::value::Kind::NullValue(0)),
-            },
-            JsonValue::Bool(b) => QdrantValue {
-                kind: Some(qdrant_client::qdrant::value::Kind::BoolValue(*b)),
-            },
-            JsonValue::Number(n) => {
-                if let Some(i) = n.as_i64() {
-                    QdrantValue {
-                        kind: Some(qdrant_client::qdrant::value::Kind::IntegerValue(i)),
-                    }
-                } else if let Some(f) = n.as_f64() {
-                    QdrantValue {
-                        kind: Some(qdrant_client::qdrant::value::Kind::DoubleValue(f)),
-                    }
-                } else {
-                    QdrantValue {
-                        kind: Some(qdrant_client::qdrant::value::Kind::NullValue(0)),
-                    }
-                }
-            },
-            JsonValue::String(s) => QdrantValue {
-                kind: Some(qdrant_client::qdrant::value::Kind::StringValue(s.clone())),
-            },
-            JsonValue::Array(arr) => QdrantValue {
-                kind: Some(qdrant_client::qdrant::value::Kind::ListValue(
-                    qdrant_client::qdrant::ListValue {
-                        values: arr.iter().map(|v| Self::json_to_qdrant_value(v)).collect(),
-                    },
-                )),
-            },
-            JsonValue::Object(obj) => {
-                let mut struct_value = HashMap::new();
-                for (k, v) in obj {
-                    struct_value.insert(k.clone(), Self::json_to_qdrant_value(v));
-                }
-                QdrantValue {
-                    kind: Some(qdrant_client::qdrant::value::Kind::StructValue(
-                        qdrant_client::qdrant::Struct { fields: struct_value },
-                    )),
+    /// Addresses the collection through an alias instead of its name.
+    /// 
+    /// `ensure_collection` points the alias at the configured collection if
+    /// it does not exist yet; after that, `switch_alias` moves it.
+    pub fn with_alias(mut self, alias: Option<&str>) -> Self {
+        self.alias = alias.map(str::to_string);
+        self
+    }
+
+    /// Name that document operations address: the alias when one is set,
+    /// otherwise the configured collection.
+    pub fn collection(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.collection_name)
+    }
+
+    /// Returns the configured alias, if document operations go through one.
+    pub fn alias(&self) -> Option<&str> {
+        self.alias.as_deref()
+    }
+
+    /// Returns the vector size new vectors are checked against, if configured.
+    pub fn vector_size(&self) -> Option<u64> {
+        self.vector_size
+    }
+
+    /// Records Qdrant call latencies into shared metrics.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Makes a Qdrant call with `request` against the collection, recording
+    /// its latency under `operation`.
+    /// 
+    /// With auto-create on, a call that finds the collection missing
+    /// recreates it and is made once more. Only then is the request kept
+    /// for the retry; otherwise it is handed over without a copy.
+    async fn timed<T, R, F, Fut>(&self, operation: &str, request: R, call: F) -> Result<T, QdrantError>
+    where
+        R: Clone,
+        F: Fn(R) -> Fut,
+        Fut: Future<Output = Result<T, QdrantError>>,
+    {
+        if !self.auto_create {
+            return self.timed_in(operation, self.collection(), call(request)).await;
+        }
+        let output = self.timed_in(operation, self.collection(), call(request.clone())).await;
+        match output {
+            Err(e) if is_collection_missing(&e) => {
+                warn!("Collection '{}' not found, recreating it", self.collection());
+                if let Err(create_error) = self.recreate_collection().await {
+                    warn!("Failed to recreate collection '{}': {:#}", self.collection(), create_error);
+                    return Err(e);
                 }
-            },
+                self.timed_in(operation, self.collection(), call(request)).await
+            }
+            output => output,
         }
     }
 
-    /// Stores or updates a document in the Qdrant collection.
+    /// Sets the collection up again after it went missing.
     /// 
-    /// This method performs an upsert operation, which means:
-    /// - If a document with the same ID exists, it will be updated
+    /// Calls that find it missing at the same time wait for the first to
+    /// finish, then find it in place.
+    async fn recreate_collection(&self) -> Result<()> {
+        let _recreating = self.recreating.lock().await;
+        let vector_size = self
+            .vector_size
+            .ok_or_else(|| anyhow!("the vector size is unknown"))?;
+        self.ensure_collection(vector_size).await?;
+        if self.text_index {
+            self.ensure_text_index().await?;
+        }
+        Ok(())
+    }
+
+    /// Like `timed`, for calls against a collection other than the configured one.
+    async fn timed_in<T, F>(&self, operation: &str, collection: &str, call: F) -> Result<T, QdrantError>
+    where
+        F: Future<Output = Result<T, QdrantError>>,
+    {
+        let timer = self.metrics.qdrant_timer(operation, collection);
+        let output = call.await;
+        timer.finish();
+        self.observe(output.as_ref().err());
+        output
+    }
+
+    /// Returns whether Qdrant is reachable, as last observed.
+    pub fn connectivity(&self) -> Connectivity {
+        self.link().connectivity
+    }
+
+    /// Returns whether a request that needs Qdrant should go ahead.
+    /// 
+    /// While Qdrant is unavailable, requests are turned away so they fail
+    /// fast instead of waiting for connection timeouts. Once the backoff has
+    /// passed, one request is let through as a probe; if it reaches Qdrant,
+    /// everything is let through again.
+    pub fn admit(&self) -> bool {
+        let mut link = self.link();
+        match link.connectivity {
+            Connectivity::Connected => true,
+            Connectivity::Connecting => false,
+            Connectivity::Unavailable => {
+                let now = Instant::now();
+                if now < link.retry_at {
+                    return false;
+                }
+                // Hold off other requests until the probe has had its chance
+                link.retry_at = now + link.backoff;
+                true
+            }
+        }
+    }
+
+    /// Marks the service as waiting for its startup checks to succeed.
+    /// 
+    /// Calls keep their connectivity at `Connecting` until `mark_connected`.
+    pub fn mark_connecting(&self) {
+        self.link().connectivity = Connectivity::Connecting;
+    }
+
+    /// Marks the service as connected once its startup checks have succeeded.
+    pub fn mark_connected(&self) {
+        *self.link() = Link::default();
+    }
+
+    /// Updates the connectivity from the outcome of a call.
+    fn observe(&self, error: Option<&QdrantError>) {
+        let mut link = self.link();
+        if link.connectivity == Connectivity::Connecting {
+            return;
+        }
+        if !error.is_some_and(is_unreachable_error) {
+            // Any answer, even an error, means the server is reachable
+            if link.connectivity == Connectivity::Unavailable {
+                info!("Qdrant is reachable again");
+            }
+            *link = Link::default();
+            return;
+        }
+
+        link.failures += 1;
+        match link.connectivity {
+            Connectivity::Unavailable => {
+                link.backoff = (link.backoff * 2).min(MAX_RECONNECT_BACKOFF);
+                link.retry_at = Instant::now() + link.backoff;
+            }
+            _ if link.failures >= UNREACHABLE_AFTER => {
+                warn!(
+                    "Qdrant unreachable after {} calls, failing requests until it recovers",
+                    link.failures
+                );
+                link.connectivity = Connectivity::Unavailable;
+                link.retry_at = Instant::now() + link.backoff;
+            }
+            _ => {}
+        }
+    }
+
+    fn link(&self) -> MutexGuard<'_, Link> {
+        // The state is a few plain fields, consistent even after a panic
+        self.link.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Sets the default read consistency used for searches.
+    pub fn with_read_consistency(mut self, level: Option<ReadConsistencyLevel>) -> Self {
+        self.read_consistency = level;
+        self
+    }
+
+    /// Sets the default write ordering used for upserts and deletes.
+    pub fn with_write_ordering(mut self, level: WriteOrderingLevel) -> Self {
+        self.write_ordering = level;
+        self
+    }
+
+    /// Sets the sharding method used for collection creation and request routing.
+    pub fn with_sharding(mut self, sharding: ShardingMode) -> Self {
+        self.sharding = sharding;
+        self
+    }
+
+    /// Sets the shard keys of the tenants API keys belong to, by key fingerprint.
+    pub fn with_tenants(mut self, tenants: HashMap<String, String>) -> Self {
+        self.tenants = tenants;
+        self
+    }
+
+    /// Builds the shard key selector for an operation.
+    /// 
+    /// With custom sharding every operation must target a shard key: the one
+    /// supplied, or else the tenant of the API key making the request. With
+    /// automatic sharding a shard key is rejected. Both cases fail with a
+    /// descriptive error instead of Qdrant's generic one.
+    /// 
+    /// # Arguments
+    /// * `shard_key` - Shard key supplied for the operation, if any
+    /// 
+    /// # Returns
+    /// * `Ok(Option<ShardKeySelector>)` - Selector to attach to the request
+    /// * `Err(anyhow::Error)` - If the shard key does not fit the sharding method
+    pub fn shard_key_selector(&self, shard_key: Option<&str>) -> Result<Option<ShardKeySelector>> {
+        let tenant = match (self.sharding, shard_key) {
+            (ShardingMode::Custom, None) => metrics::request_key().and_then(|key| self.tenants.get(&key)),
+            _ => None,
+        };
+        match (self.sharding, shard_key.or(tenant.map(String::as_str))) {
+            (ShardingMode::Custom, Some(key)) if !key.trim().is_empty() => {
+                Ok(Some(ShardKeySelector::from(key.to_string())))
+            }
+            (ShardingMode::Custom, _) => Err(anyhow!(
+                "a shard_key is required because collection '{}' uses custom sharding and the API key has no tenant",
+                self.collection()
+            )),
+            (ShardingMode::Auto, Some(_)) => Err(anyhow!(
+                "shard_key is only supported when SHARDING=custom"
+            )),
+            (ShardingMode::Auto, None) => Ok(None),
+        }
+    }
+
+    /// Sets the expected vector size used to validate vectors before upserting.
+    pub fn with_vector_size(mut self, vector_size: u64) -> Self {
+        self.vector_size = Some(vector_size);
+        self
+    }
+
+    /// Sets the distance metric and whether vectors are normalized.
+    /// 
+    /// # Arguments
+    /// * `distance` - Distance metric used when creating the collection
+    /// * `normalize` - L2-normalize vectors before upserts and searches
+    pub fn with_distance(mut self, distance: DistanceMetric, normalize: bool) -> Self {
+        self.distance = distance;
+        self.normalize = normalize;
+        self
+    }
+
+    /// Sets whether a newly created collection stores its vectors on disk.
+    /// 
+    /// On-disk vectors are memory-mapped, trading search latency for a
+    /// much smaller RAM footprint. Existing collections are not changed.
+    pub fn with_on_disk(mut self, on_disk: bool) -> Self {
+        self.on_disk = on_disk;
+        self
+    }
+
+    /// Sets how many shards a newly created collection is split into and
+    /// how many copies of each are kept.
+    /// 
+    /// Both are 1 on a single node. On a cluster, shards spread a collection
+    /// over the nodes and replicas keep it available when one goes down.
+    /// Existing collections are not changed.
+    pub fn with_replication(mut self, shard_number: u32, replication_factor: u32) -> Self {
+        self.shard_number = shard_number;
+        self.replication_factor = replication_factor;
+        self
+    }
+
+    /// Sets the payload fields indexed whenever a collection is created.
+    pub fn with_payload_indexes(mut self, indexes: Vec<PayloadIndex>) -> Self {
+        self.payload_indexes = indexes;
+        self
+    }
+
+    /// Sets how payload integers above `i64::MAX` are stored.
+    pub fn with_large_integers(mut self, large_integers: LargeIntegers) -> Self {
+        self.large_integers = large_integers;
+        self
+    }
+
+    /// Recreates the collection when a document operation finds it missing.
+    /// 
+    /// The collection is set up as by `ensure_collection`, plus the text
+    /// index when `text_index` is set, and the operation is retried once.
+    /// Requires the vector size to be configured.
+    pub fn with_auto_create(mut self, enabled: bool, text_index: bool) -> Self {
+        self.auto_create = enabled;
+        self.text_index = text_index;
+        self
+    }
+
+    /// Sets whether `ensure_collection` fails on an existing collection
+    /// whose vector size differs from the embedding dimension, or only
+    /// logs a warning. Writes of mismatched vectors are refused either way.
+    pub fn with_strict_vector_size(mut self, strict: bool) -> Self {
+        self.strict_vector_size = strict;
+        self
+    }
+
+    /// Keeps up to `retention` earlier versions of each document written
+    /// with `upsert_document`; 0 turns versioning off.
+    pub fn with_version_retention(mut self, retention: usize) -> Self {
+        self.version_retention = retention;
+        self
+    }
+
+    /// Applies the configured normalization to a vector.
+    /// 
+    /// # Returns
+    /// * `Ok(Vec<f32>)` - The vector, normalized if normalization is enabled
+    /// * `Err(ZeroVector)` - If normalization is enabled and the vector has no magnitude
+    fn prepare_vector(&self, mut vector: Vec<f32>) -> Result<Vec<f32>, vector_math::ZeroVector> {
+        if self.normalize {
+            vector_math::normalize_in_place(&mut vector)?;
+        }
+        Ok(vector)
+    }
+
+    /// Returns the vector size configured for an existing collection.
+    /// 
+    /// # Arguments
+    /// * `name` - Name or alias of the collection
+    /// 
+    /// # Returns
+    /// * `Ok(Some(u64))` - The size of the collection's (unnamed) vector
+    /// * `Ok(None)` - If the collection uses named vectors or reports no config
+    /// * `Err(anyhow::Error)` - If the collection info request fails
+    pub async fn collection_vector_size(&self, name: &str) -> Result<Option<u64>> {
+        let info = self
+            .timed_in("collection_info", name, self.client.collection_info(name))
+            .await
+            .with_context(|| format!("fetching info for collection '{}' failed", name))?;
+        let size = info
+            .result
+            .and_then(|info| info.config)
+            .and_then(|config| config.params)
+            .and_then(|params| params.vectors_config)
+            .and_then(|vectors| vectors.config)
+            .and_then(|config| match config {
+                vectors_config::Config::Params(params) => Some(params.size),
+                vectors_config::Config::ParamsMap(_) => None,
+            });
+        Ok(size)
+    }
+
+    /// Checks that a vector matches the expected vector size.
+    /// 
+    /// # Returns
+    /// * `Ok(())` - If the length matches or no size is configured
+    /// * `Err(DimensionMismatch)` - If the length differs
+    pub fn check_vector(&self, vector: &[f32]) -> Result<(), DimensionMismatch> {
+        match self.vector_size {
+            Some(expected) if expected != vector.len() as u64 => Err(DimensionMismatch {
+                collection: self.collection().to_string(),
+                expected,
+                actual: vector.len() as u64,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Recognises the failures of this service's calls that the client can
+    /// act on, from the gRPC status Qdrant answered with or the checks made
+    /// before calling it.
+    /// 
+    /// The status code decides the kind of failure; the message only refines
+    /// it (which collection is missing, which sizes differ), since its wording
+    /// changes between Qdrant releases.
+    /// 
+    /// # Returns
+    /// * `Some(QdrantRejection)` - For something missing, invalid arguments such
+    ///   as a wrong vector size or point id, an oversized request or a name taken
+    /// * `None` - For any other failure, which is the server's problem
+    pub fn translate_error(&self, error: &anyhow::Error) -> Option<QdrantRejection> {
+        if let Some(mismatch) = error.chain().find_map(|cause| cause.downcast_ref::<DimensionMismatch>()) {
+            return Some(QdrantRejection::DimensionMismatch(mismatch.clone()));
+        }
+        let status = error.chain().find_map(|cause| match cause.downcast_ref::<QdrantError>()? {
+            QdrantError::ResponseError { status } => Some(status),
+            _ => None,
+        })?;
+        let message = status.message();
+        match status.code() {
+            Code::NotFound if message.contains("Collection") => Some(QdrantRejection::CollectionMissing {
+                collection: missing_collection_name(message).unwrap_or(self.collection()).to_string(),
+                hint: if self.auto_create {
+                    "recreating it failed, see the server logs"
+                } else {
+                    "create it with POST /api/admin/collections or set AUTO_CREATE_COLLECTION=true"
+                },
+            }),
+            Code::NotFound => Some(QdrantRejection::NotFound(message.to_string())),
+            Code::InvalidArgument => Some(match vector_dimension_error(message) {
+                Some((expected, actual)) => QdrantRejection::DimensionMismatch(DimensionMismatch {
+                    collection: self.collection().to_string(),
+                    expected,
+                    actual,
+                }),
+                None if message.contains("UUID") || message.to_lowercase().contains("point id") => {
+                    QdrantRejection::InvalidPointId(message.to_string())
+                }
+                None => QdrantRejection::InvalidArgument(message.to_string()),
+            }),
+            // Both are what tonic and Qdrant answer for a message over the size limit
+            Code::OutOfRange | Code::ResourceExhausted => Some(QdrantRejection::PayloadTooLarge(message.to_string())),
+            Code::AlreadyExists => Some(QdrantRejection::AlreadyExists(message.to_string())),
+            _ => None,
+        }
+    }
+
+    /// Creates the collection if it does not exist yet, or verifies that an
+    /// existing collection's vector size matches the embedding dimension.
+    /// 
+    /// New collections use the configured distance metric, sharding method
+    /// and on-disk vector storage setting.
+    /// With custom sharding, shard keys must be created separately before use.
+    /// 
+    /// With an alias, an existing alias is checked instead; a missing one is
+    /// created pointing at the configured collection, which is set up as above.
+    /// 
+    /// # Arguments
+    /// * `vector_size` - Dimension of the stored embedding vectors
+    /// 
+    /// # Returns
+    /// * `Ok(())` - If the collection exists with a matching size or was created
+    /// * `Err(anyhow::Error)` - If the sizes differ, or the check or creation fails
+    pub async fn ensure_collection(&self, vector_size: u64) -> Result<()> {
+        let Some(alias) = &self.alias else {
+            return self.ensure_named_collection(&self.collection_name, vector_size).await;
+        };
+        if let Some(target) = self.alias_target(alias).await? {
+            return self.check_vector_size(&target, vector_size).await;
+        }
+        self.ensure_named_collection(&self.collection_name, vector_size).await?;
+        self.switch_alias(alias, &self.collection_name).await
+    }
+
+    async fn ensure_named_collection(&self, name: &str, vector_size: u64) -> Result<()> {
+        if self.collection_exists(name).await? {
+            return self.check_vector_size(name, vector_size).await;
+        }
+        self.create_collection(name, vector_size, self.distance, self.on_disk)
+            .await
+    }
+
+    /// Fails if an existing collection's vectors don't match the embedding
+    /// dimension, or only warns without `strict_vector_size`.
+    async fn check_vector_size(&self, name: &str, vector_size: u64) -> Result<()> {
+        match self.collection_vector_size(name).await? {
+            Some(existing) if existing != vector_size => {
+                let message = format!(
+                    "collection '{}' stores {}-dimensional vectors but the embedding model produces {}; \
+                     recreate the collection, use a different COLLECTION_NAME, or configure \
+                     EMBEDDING_MODEL/EMBEDDING_DIMENSIONS to produce {}-dimensional vectors",
+                    name, existing, vector_size, existing
+                );
+                if self.strict_vector_size {
+                    return Err(anyhow!(message));
+                }
+                warn!(
+                    "{}; continuing because STRICT_VECTOR_SIZE is off, but storing and searching \
+                     documents will fail until the sizes match",
+                    message
+                );
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the collection an alias points at, if the alias exists.
+    pub async fn alias_target(&self, alias: &str) -> Result<Option<String>> {
+        let response = self
+            .timed_in("list_aliases", "", self.client.list_aliases())
+            .await
+            .context("listing aliases failed")?;
+        Ok(response
+            .aliases
+            .into_iter()
+            .find(|description| description.alias_name == alias)
+            .map(|description| description.collection_name))
+    }
+
+    /// Points an alias at a collection, creating the alias if needed.
+    /// 
+    /// Qdrant replaces an existing alias in a single operation, so requests
+    /// addressing the alias move from the old collection to the new one
+    /// without a gap. The old collection is left untouched.
+    /// 
+    /// # Arguments
+    /// * `alias` - The alias to point
+    /// * `new_collection` - Collection the alias should resolve to
+    pub async fn switch_alias(&self, alias: &str, new_collection: &str) -> Result<()> {
+        self.timed_in(
+            "switch_alias",
+            new_collection,
+            self.client.create_alias(CreateAliasBuilder::new(new_collection, alias)),
+        )
+        .await
+        .with_context(|| format!("pointing alias '{}' at '{}' failed", alias, new_collection))?;
+        if self.alias.as_deref() == Some(alias) {
+            self.version.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    /// Returns the collection that currently serves document operations:
+    /// the alias target when an alias is set, otherwise the configured collection.
+    pub async fn serving_collection(&self) -> Result<String> {
+        match &self.alias {
+            Some(alias) => Ok(self
+                .alias_target(alias)
+                .await?
+                .unwrap_or_else(|| self.collection_name.clone())),
+            None => Ok(self.collection_name.clone()),
+        }
+    }
+
+    /// Creates a collection with the configured sharding method, shard and
+    /// replica counts and payload indexes.
+    /// 
+    /// # Arguments
+    /// * `name` - Name of the new collection
+    /// * `vector_size` - Dimension of the stored vectors
+    /// * `distance` - Distance metric for the vectors
+    /// * `on_disk` - Keep vectors on disk instead of in RAM
+    /// 
+    /// # Returns
+    /// * `Ok(())` - If the collection was created
+    /// * `Err(anyhow::Error)` - If creation fails, e.g. because it already exists
+    pub async fn create_collection(
+        &self,
+        name: &str,
+        vector_size: u64,
+        distance: DistanceMetric,
+        on_disk: bool,
+    ) -> Result<()> {
+        let sharding_method = match self.sharding {
+            ShardingMode::Auto => ShardingMethod::Auto,
+            ShardingMode::Custom => ShardingMethod::Custom,
+        };
+        // `on_disk` is understood by every server version, unlike its `memory` replacement
+        #[allow(deprecated)]
+        let create_collection = CreateCollection {
+            collection_name: name.to_string(),
+            vectors_config: Some(VectorsConfig {
+                config: Some(vectors_config::Config::Params(VectorParams {
+                    size: vector_size,
+                    distance: Distance::from(distance) as i32,
+                    on_disk: Some(on_disk),
+                    ..Default::default()
+                })),
+            }),
+            sharding_method: Some(sharding_method as i32),
+            shard_number: Some(self.shard_number),
+            replication_factor: Some(self.replication_factor),
+            ..Default::default()
+        };
+
+        self.timed_in("create_collection", name, self.client.create_collection(create_collection))
+            .await
+            .with_context(|| format!("creating collection '{}' failed", name))?;
+
+        // Index before any points arrive, so filtered searches are fast from the start
+        for index in &self.payload_indexes {
+            self.create_field_index(name, &index.field, index.schema.into(), None)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Checks whether a collection exists.
+    pub async fn collection_exists(&self, name: &str) -> Result<bool> {
+        self.timed_in("collection_exists", name, self.client.collection_exists(name))
+            .await
+            .with_context(|| format!("checking whether collection '{}' exists failed", name))
+    }
+
+    /// Lists all collections with their point counts and status.
+    /// 
+    /// Collection info is fetched one collection at a time, so the counts
+    /// are not a consistent snapshot across collections.
+    pub async fn list_collections(&self) -> Result<Vec<CollectionSummary>> {
+        let response = self
+            .timed_in("list_collections", "", self.client.list_collections())
+            .await
+            .context("listing collections failed")?;
+
+        let mut summaries = Vec::with_capacity(response.collections.len());
+        for collection in response.collections {
+            let info = self
+                .timed_in("collection_info", &collection.name, self.client.collection_info(&collection.name))
+                .await
+                .with_context(|| format!("fetching info for collection '{}' failed", collection.name))?
+                .result;
+            let status = info
+                .as_ref()
+                .and_then(|info| CollectionStatus::try_from(info.status).ok())
+                .map_or("unknown", |status| match status {
+                    CollectionStatus::Green => "green",
+                    CollectionStatus::Yellow => "yellow",
+                    CollectionStatus::Grey => "grey",
+                    CollectionStatus::Red => "red",
+                    CollectionStatus::UnknownCollectionStatus => "unknown",
+                });
+            summaries.push(CollectionSummary {
+                points_count: info.and_then(|info| info.points_count),
+                status: status.to_string(),
+                name: collection.name,
+            });
+        }
+        summaries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(summaries)
+    }
+
+    /// Deletes a collection and all of its points.
+    /// 
+    /// # Returns
+    /// * `Ok(())` - If the collection was deleted
+    /// * `Err(anyhow::Error)` - If the collection serves document operations, or deletion fails
+    pub async fn delete_collection(&self, name: &str) -> Result<()> {
+        if name == self.serving_collection().await? {
+            return Err(anyhow!("refusing to delete the serving collection '{}'", name));
+        }
+        self.timed_in("delete_collection", name, self.client.delete_collection(name))
+            .await
+            .with_context(|| format!("deleting collection '{}' failed", name))?;
+        Ok(())
+    }
+
+    /// Resolves the write ordering for an operation, preferring the per-request override.
+    pub fn effective_write_ordering(&self, requested: Option<WriteOrderingLevel>) -> WriteOrderingLevel {
+        requested.unwrap_or(self.write_ordering)
+    }
+
+    /// Resolves the read consistency for a search, preferring the per-request override.
+    fn effective_read_consistency(
+        &self,
+        requested: Option<ReadConsistencyLevel>,
+    ) -> Option<ReadConsistency> {
+        requested.or(self.read_consistency).map(ReadConsistency::from)
+    }
+
+    /// Converts a JSON value to a Qdrant value.
+    /// 
+    /// Integers that fit an `i64` stay integers. Larger ones, up to
+    /// `u64::MAX`, are stored as `large_integers` says instead of silently
+    /// becoming doubles. Numbers beyond `u64::MAX` and fractions are doubles.
+    /// Metadata written as a payload also records where the integers stored
+    /// as strings are; see `metadata_payload`.
+    fn json_to_qdrant_value(&self, value: &JsonValue) -> QdrantValue {
+        match value {
+            JsonValue::Null => QdrantValue {
+                kind: Some(qdrant_client::qdrant::value::Kind::NullValue(0)),
+            },
+            JsonValue::Bool(b) => QdrantValue {
+                kind: Some(qdrant_client::qdrant::value::Kind::BoolValue(*b)),
+            },
+            JsonValue::Number(n) => {
+                if let Some(i) = n.as_i64() {
+                    QdrantValue {
+                        kind: Some(qdrant_client::qdrant::value::Kind::IntegerValue(i)),
+                    }
+                } else if let (Some(u), LargeIntegers::String) = (n.as_u64(), self.large_integers) {
+                    QdrantValue {
+                        kind: Some(qdrant_client::qdrant::value::Kind::StringValue(u.to_string())),
+                    }
+                } else if let Some(f) = n.as_f64() {
+                    QdrantValue {
+                        kind: Some(qdrant_client::qdrant::value::Kind::DoubleValue(f)),
+                    }
+                } else {
+                    QdrantValue {
+                        kind: Some(qdrant_client::qdrant::value::Kind::NullValue(0)),
+                    }
+                }
+            },
+            JsonValue::String(s) => QdrantValue {
+                kind: Some(qdrant_client::qdrant::value::Kind::StringValue(s.clone())),
+            },
+            JsonValue::Array(arr) => QdrantValue {
+                kind: Some(qdrant_client::qdrant::value::Kind::ListValue(
+                    qdrant_client::qdrant::ListValue {
+                        values: arr.iter().map(|v| self.json_to_qdrant_value(v)).collect(),
+                    },
+                )),
+            },
+            JsonValue::Object(obj) => {
+                let mut struct_value = HashMap::new();
+                for (k, v) in obj {
+                    struct_value.insert(k.clone(), self.json_to_qdrant_value(v));
+                }
+                QdrantValue {
+                    kind: Some(qdrant_client::qdrant::value::Kind::StructValue(
+                        qdrant_client::qdrant::Struct { fields: struct_value },
+                    )),
+                }
+            },
+        }
+    }
+
+    /// Converts document metadata to its payload fields: `metadata` itself
+    /// and, when integers had to be stored as strings, `LARGE_INTEGERS_FIELD`
+    /// listing where they are.
+    fn metadata_payload(&self, metadata: &JsonValue) -> Vec<(String, QdrantValue)> {
+        let mut fields = vec![("metadata".to_string(), self.json_to_qdrant_value(metadata))];
+        if self.large_integers == LargeIntegers::String {
+            let mut pointers = Vec::new();
+            large_integer_pointers(metadata, &mut String::new(), &mut pointers);
+            if !pointers.is_empty() {
+                fields.push((LARGE_INTEGERS_FIELD.to_string(), QdrantValue::from(pointers)));
+            }
+        }
+        fields
+    }
+
+    /// Stores or updates a document in the Qdrant collection.
+    /// 
+    /// This method performs an upsert operation, which means:
+    /// - If a document with the same ID exists, it will be updated
     /// - If no document with the ID exists, a new one will be created
     /// 
     /// The document's embedding vector and metadata are stored together,
     /// allowing for vector similarity search with metadata filtering.
     /// 
+    /// With versioning on, the write goes through `upsert_documents`: the
+    /// stored document is numbered and, when the new text or metadata differ
+    /// from it, copied to a version point first.
+    /// 
     /// # Arguments
     /// * `doc` - Document containing the ID, embedding vector, and metadata
+    /// * `ordering` - Optional write ordering override for this operation
+    /// * `shard_key` - Shard key to route the write to (custom sharding only)
     /// 
     /// # Returns
-    /// * `Ok(())` - Document was successfully stored
-    /// * `Err(anyhow::Error)` - If the storage operation fails
+    /// * `Ok(Option<u64>)` - The stored document's version number, if versioning is on
+    /// * `Err(anyhow::Error)` - If the storage operation fails, or a `WriteConflict`
     /// 
     /// # Example
     /// ```no_run
@@ -133,67 +1691,1899 @@ impl QdrantService {
     ///     embedding: vec![0.1, 0.2, 0.3],
     ///     // ... other fields
     /// };
-    /// service.upsert_document(&doc).await?;
+    /// service.upsert_document(&doc, None, None).await?;
     /// ```
-    pub async fn upsert_document(&self, doc: &Document) -> Result<()> {
-        use qdrant_client::qdrant::UpsertPoints;
+    pub async fn upsert_document(
+        &self,
+        doc: &Document,
+        ordering: Option<WriteOrderingLevel>,
+        shard_key: Option<&str>,
+    ) -> Result<Option<u64>> {
+        let versions = self
+            .upsert_documents(std::slice::from_ref(doc), ordering, shard_key)
+            .await?;
+        Ok(versions.into_iter().next().flatten())
+    }
 
-        // Convert document to JSON value
-        let json_value = serde_json::to_value(doc)?;
-        
-        // Convert JSON object to Qdrant payload
-        let payload = match json_value {
-            JsonValue::Object(obj) => obj.into_iter()
-                .filter(|(k, _)| k != "embedding") // Skip embedding field
-                .map(|(k, v)| (k, Self::json_to_qdrant_value(&v)))
-                .collect(),
-            _ => return Err(anyhow::anyhow!("Document serialization failed")),
+    /// Numbers a versioned write and archives the document it replaces.
+    /// 
+    /// A new document starts at version 1, and one stored before versioning
+    /// was turned on counts as version 1. Rewriting the same text and
+    /// metadata keeps the version number and archives nothing.
+    /// 
+    /// # Arguments
+    /// * `doc` - The document about to be written
+    /// * `current` - The stored document it replaces, read with its vector
+    /// 
+    /// # Returns
+    /// * `Ok((u64, Option<PointStruct>))` - The new version number and the point
+    ///   holding the replaced version, if any
+    fn next_version(&self, doc: &Document, current: Option<Document>) -> Result<(u64, Option<PointStruct>)> {
+        let Some(current) = current else {
+            return Ok((1, None));
         };
+        let version = current.version.unwrap_or(1);
+        // Compare the metadata as it is stored, so that numbers only stored
+        // approximately (LARGE_INTEGERS=double) don't count as a change
+        let same_metadata =
+            self.json_to_qdrant_value(&current.metadata) == self.json_to_qdrant_value(&doc.metadata);
+        if current.text == doc.text && same_metadata {
+            return Ok((version, None));
+        }
+        Ok((version + 1, Some(self.version_point(current, version)?)))
+    }
 
-        // Construct the point structure for Qdrant
-        let point = PointStruct {
-            id: Some(doc.id.clone().into()),
-            vectors: Some(Vectors::from(doc.embedding.clone())),
-            payload,
-        };
+    /// Returns the `updated_at` of a write: the current time in milliseconds
+    /// since the Unix epoch, moved past the previous write's if needed.
+    /// 
+    /// Conditional writes tell documents apart by it, so two writes made in
+    /// the same millisecond must not get the same stamp.
+    fn write_stamp(&self) -> Result<u64> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
+        let previous = self
+            .last_write
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |last| Some(now.max(last + 1)))
+            .unwrap_or_default();
+        Ok(now.max(previous + 1))
+    }
 
-        // Create the upsert points operation
-        let upsert_operation = UpsertPoints {
-            collection_name: self.collection_name.clone(),
-            points: vec![point],
-            ordering: Some(WriteOrdering::default().into()),
-            ..Default::default()
+    /// Condition holding while the point `id` is still as `read`: written at
+    /// the same time, with the same version and trash state, or still absent.
+    /// 
+    /// Every write of a document stamps `updated_at`, so a match means no
+    /// other write got to it in between.
+    fn unchanged_since(id: u64, read: Option<&Document>) -> Condition {
+        let equals = |key: &str, value: Option<u64>| match value {
+            Some(value) => Condition::matches(key, value as i64),
+            None => Condition::is_empty(key),
         };
+        let mut conditions = vec![Condition::has_id([id])];
+        match read {
+            Some(read) => conditions.extend([
+                equals(UPDATED_AT_FIELD, read.updated_at),
+                equals(VERSION_FIELD, read.version),
+                equals(DELETED_AT_FIELD, read.deleted_at),
+            ]),
+            None => conditions.push(Condition::is_empty(UPDATED_AT_FIELD)),
+        }
+        Filter::must(conditions).into()
+    }
 
-        // Perform the upsert operation
-        self.client
-            .upsert_points(upsert_operation)
-            .await?;
+    /// Converts a stored document into the point holding it as an earlier version.
+    fn version_point(&self, document: Document, version: u64) -> Result<PointStruct> {
+        let archived_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64;
+        let mut payload = HashMap::from([
+            ("text".to_string(), QdrantValue::from(document.text)),
+            (VERSION_FIELD.to_string(), QdrantValue::from(version as i64)),
+            (IS_VERSION_FIELD.to_string(), QdrantValue::from(true)),
+            (VERSION_OF_FIELD.to_string(), Self::version_of(document.id)),
+            (ARCHIVED_AT_FIELD.to_string(), QdrantValue::from(archived_at)),
+        ]);
+        payload.extend(self.metadata_payload(&document.metadata));
+        if let Some(updated_at) = document.updated_at {
+            payload.insert("updated_at".to_string(), QdrantValue::from(updated_at as i64));
+        }
+        Ok(PointStruct {
+            id: Some(Self::version_point_id(document.id, version)),
+            vectors: Some(Vectors::from(document.embedding)),
+            payload,
+        })
+    }
+
+    /// Returns the id of the point holding a version of a document.
+    /// 
+    /// Version points use UUIDs, so they never collide with the numeric ids
+    /// of documents and are skipped wherever documents are read.
+    fn version_point_id(id: u64, version: u64) -> PointId {
+        uuid::Uuid::from_u64_pair(id, version).to_string().into()
+    }
+
+    /// Returns the `version_of` payload value for a document id.
+    /// 
+    /// Qdrant integers are signed, so the id is stored as its bit pattern.
+    fn version_of(id: u64) -> QdrantValue {
+        QdrantValue::from(id as i64)
+    }
 
+    /// Deletes the versions of a document that fall outside the retention.
+    async fn prune_versions(
+        &self,
+        id: u64,
+        version: u64,
+        ordering: Option<WriteOrderingLevel>,
+        shard_key: Option<&str>,
+    ) -> Result<()> {
+        // Versions 1 through `version - 1` are archived; keep the newest ones
+        let oldest_kept = version.saturating_sub(self.version_retention as u64);
+        if oldest_kept <= 1 {
+            return Ok(());
+        }
+        let filter = Filter::must([
+            Condition::matches(IS_VERSION_FIELD, true),
+            Condition::matches(VERSION_OF_FIELD, id as i64),
+            Condition::range(
+                VERSION_FIELD,
+                Range {
+                    lt: Some(oldest_kept as f64),
+                    ..Default::default()
+                },
+            ),
+        ]);
+        self.delete_by_filter(filter, ordering, shard_key).await?;
         Ok(())
     }
 
-    /// Deletes all points from the collection.
+    /// Lists the earlier versions of a document, newest first.
     /// 
-    /// This method effectively resets the collection by removing all stored vectors.
+    /// # Arguments
+    /// * `id` - Numeric point id of the document
+    /// * `shard_key` - Shard key of the document (custom sharding only)
     /// 
     /// # Returns
-    /// * `Ok(())` - If all points were deleted successfully
-    /// * `Err(Box<dyn Error>)` - If the deletion fails
-    pub async fn delete_all_points(&self) -> Result<(), Box<dyn Error>> {
-        let points_selector = PointsSelector {
-            points_selector_one_of: Some(PointsSelectorOneOf::Filter(Filter::default())),
+    /// * `Ok(Vec<DocumentVersion>)` - The archived versions; empty if there are none
+    /// * `Err(anyhow::Error)` - If the shard key is invalid or the scroll fails
+    pub async fn list_versions(&self, id: u64, shard_key: Option<&str>) -> Result<Vec<DocumentVersion>> {
+        let request = ScrollPoints {
+            collection_name: self.collection().to_string(),
+            filter: Some(Filter::must([
+                Condition::matches(IS_VERSION_FIELD, true),
+                Condition::matches(VERSION_OF_FIELD, id as i64),
+            ])),
+            limit: Some(MAX_VERSIONS_LISTED),
+            with_payload: Some(WithPayloadSelector::from(true)),
+            with_vectors: Some(WithVectorsSelector::from(false)),
+            read_consistency: self.effective_read_consistency(None),
+            shard_key_selector: self.shard_key_selector(shard_key)?,
+            ..Default::default()
+        };
+        let response = self
+            .timed("scroll", request, |request| self.client.scroll(request))
+            .await
+            .with_context(|| format!("listing versions of point {} in '{}' failed", id, self.collection()))?;
+
+        let mut versions: Vec<DocumentVersion> = response
+            .result
+            .into_iter()
+            .filter_map(Self::retrieved_point_to_version)
+            .collect();
+        versions.sort_by_key(|version| std::cmp::Reverse(version.version));
+        Ok(versions)
+    }
+
+    /// Fetches one earlier version of a document.
+    /// 
+    /// # Arguments
+    /// * `id` - Numeric point id of the document
+    /// * `version` - Version number to fetch
+    /// * `shard_key` - Shard key of the document (custom sharding only)
+    /// 
+    /// # Returns
+    /// * `Ok(Some(DocumentVersion))` - The archived version
+    /// * `Ok(None)` - If that version was never archived or has been pruned
+    /// * `Err(anyhow::Error)` - If the request fails
+    pub async fn get_version(
+        &self,
+        id: u64,
+        version: u64,
+        shard_key: Option<&str>,
+    ) -> Result<Option<DocumentVersion>> {
+        let request = GetPoints {
+            collection_name: self.collection().to_string(),
+            ids: vec![Self::version_point_id(id, version)],
+            with_payload: Some(WithPayloadSelector::from(true)),
+            with_vectors: Some(WithVectorsSelector::from(false)),
+            read_consistency: self.effective_read_consistency(None),
+            shard_key_selector: self.shard_key_selector(shard_key)?,
+            ..Default::default()
+        };
+        let response = self
+            .timed("get", request, |request| self.client.get_points(request))
+            .await
+            .with_context(|| {
+                format!("fetching version {} of point {} from '{}' failed", version, id, self.collection())
+            })?;
+
+        Ok(response
+            .result
+            .into_iter()
+            .find_map(Self::retrieved_point_to_version))
+    }
+
+    /// Stores several documents with a single upsert request.
+    /// 
+    /// With versioning on, the stored documents are read first to number
+    /// each write and archive the versions it replaces, and the upsert only
+    /// replaces documents still as they were read. Documents another writer
+    /// got to first are read and written again, up to
+    /// `VERSIONED_WRITE_ATTEMPTS` times. Versions beyond the retention are
+    /// then deleted, oldest first.
+    /// 
+    /// # Arguments
+    /// * `docs` - Documents containing their IDs, embedding vectors and metadata
+    /// * `ordering` - Optional write ordering override for this operation
+    /// * `shard_key` - Shard key to route the writes to (custom sharding only)
+    /// 
+    /// # Returns
+    /// * `Ok(Vec<Option<u64>>)` - The version number of each document, if versioning is on
+    /// * `Err(anyhow::Error)` - If any vector is invalid or the upsert fails, or a
+    ///   `WriteConflict` if documents kept changing
+    pub async fn upsert_documents(
+        &self,
+        docs: &[Document],
+        ordering: Option<WriteOrderingLevel>,
+        shard_key: Option<&str>,
+    ) -> Result<Vec<Option<u64>>> {
+        if docs.is_empty() {
+            return Ok(Vec::new());
+        }
+        if self.version_retention == 0 {
+            let updated_at = self.write_stamp()?;
+            let points = docs
+                .iter()
+                .map(|doc| self.document_point(doc, updated_at))
+                .collect::<Result<Vec<_>>>()?;
+            self.upsert_points(points, None, ordering, shard_key).await?;
+            return Ok(vec![None; docs.len()]);
+        }
+
+        let mut versions = vec![None; docs.len()];
+        let mut pending: Vec<usize> = (0..docs.len()).collect();
+        for attempt in 1..=VERSIONED_WRITE_ATTEMPTS {
+            let ids: Vec<u64> = pending.iter().map(|&i| docs[i].id).collect();
+            let current = self.get_documents(&ids, true, shard_key).await?;
+            let updated_at = self.write_stamp()?;
+            let mut points = Vec::with_capacity(pending.len());
+            let mut unchanged = Vec::with_capacity(pending.len());
+            for (&i, current) in pending.iter().zip(current) {
+                let doc = &docs[i];
+                unchanged.push(Self::unchanged_since(doc.id, current.as_ref()));
+                let (version, archived) = self.next_version(doc, current)?;
+                let mut point = self.document_point(doc, updated_at)?;
+                point
+                    .payload
+                    .insert(VERSION_FIELD.to_string(), QdrantValue::from(version as i64));
+                points.push(point);
+                points.extend(archived);
+                versions[i] = Some(version);
+            }
+            let condition = Some((Filter::should(unchanged), UpdateMode::Upsert));
+            self.upsert_points(points, condition, ordering, shard_key).await?;
+
+            // Documents that don't carry this write's stamp were changed in between
+            let stored = self.get_documents(&ids, false, shard_key).await?;
+            pending = pending
+                .into_iter()
+                .zip(stored)
+                .filter(|(_, stored)| stored.as_ref().is_some_and(|stored| stored.updated_at != Some(updated_at)))
+                .map(|(i, _)| i)
+                .collect();
+            if pending.is_empty() {
+                break;
+            }
+            if attempt == VERSIONED_WRITE_ATTEMPTS {
+                return Err(WriteConflict {
+                    ids: pending.iter().map(|&i| docs[i].id).collect(),
+                    attempts: attempt,
+                }
+                .into());
+            }
+            info!("Writing {} documents again after concurrent changes", pending.len());
+        }
+
+        // The documents are stored either way; leftovers go with the next write
+        for (doc, version) in docs.iter().zip(&versions) {
+            if let Some(version) = *version {
+                if let Err(e) = self.prune_versions(doc.id, version, ordering, shard_key).await {
+                    warn!("Failed to prune old versions of document {}: {:#}", doc.id, e);
+                }
+            }
+        }
+        Ok(versions)
+    }
+
+    /// Stores new embeddings for documents, unless they changed since they were read.
+    /// 
+    /// For reindexing and re-embedding: each document is written back as
+    /// read, with its new embedding and without the stale flag. Documents
+    /// that another writer changed, trashed, restored or deleted since they
+    /// were read are left alone, since that writer's version is the one to
+    /// keep. The text and metadata are unchanged, so a versioned document
+    /// keeps its version number (1 if it was stored before versioning).
+    /// 
+    /// # Arguments
+    /// * `docs` - Documents as read, with `updated_at`, `version` and `deleted_at`, and
+    ///   their new embeddings
+    /// * `ordering` - Optional write ordering override for this operation
+    /// * `shard_key` - Shard key to route the writes to (custom sharding only)
+    /// 
+    /// # Returns
+    /// * `Ok(u64)` - The number of documents written; the rest had changed
+    /// * `Err(anyhow::Error)` - If any vector is invalid or the upsert fails
+    pub async fn replace_embeddings(
+        &self,
+        docs: &[Document],
+        ordering: Option<WriteOrderingLevel>,
+        shard_key: Option<&str>,
+    ) -> Result<u64> {
+        if docs.is_empty() {
+            return Ok(0);
+        }
+        let updated_at = self.write_stamp()?;
+        let mut points = Vec::with_capacity(docs.len());
+        for doc in docs {
+            let mut point = self.document_point(&Document { stale: false, ..doc.clone() }, updated_at)?;
+            if self.version_retention > 0 {
+                let version = doc.version.unwrap_or(1);
+                point
+                    .payload
+                    .insert(VERSION_FIELD.to_string(), QdrantValue::from(version as i64));
+            }
+            points.push(point);
+        }
+        let unchanged = docs.iter().map(|doc| Self::unchanged_since(doc.id, Some(doc)));
+        let condition = Some((Filter::should(unchanged), UpdateMode::UpdateOnly));
+        self.upsert_points(points, condition, ordering, shard_key).await?;
+
+        let ids: Vec<u64> = docs.iter().map(|doc| doc.id).collect();
+        let written = self
+            .get_documents(&ids, false, shard_key)
+            .await?
+            .into_iter()
+            .filter(|stored| stored.as_ref().is_some_and(|stored| stored.updated_at == Some(updated_at)))
+            .count() as u64;
+        if written < docs.len() as u64 {
+            info!(
+                "Left {} of {} documents alone, as they changed after they were read",
+                docs.len() as u64 - written,
+                docs.len()
+            );
+        }
+        Ok(written)
+    }
+
+    /// Sends an upsert, optionally only replacing the points matching a filter.
+    /// 
+    /// # Arguments
+    /// * `points` - Points to store
+    /// * `condition` - Filter existing points must match to be replaced, and whether
+    ///   points that don't exist yet are inserted
+    /// * `ordering` - Optional write ordering override for this operation
+    /// * `shard_key` - Shard key to route the writes to (custom sharding only)
+    async fn upsert_points(
+        &self,
+        points: Vec<PointStruct>,
+        condition: Option<(Filter, UpdateMode)>,
+        ordering: Option<WriteOrderingLevel>,
+        shard_key: Option<&str>,
+    ) -> Result<()> {
+        let count = points.len();
+        let upsert_operation = self.upsert_request(points, condition, ordering, shard_key)?;
+        self.timed("upsert", upsert_operation, |request| self.client.upsert_points(request))
+            .await
+            .with_context(|| format!("upserting {} points into '{}' failed", count, self.collection()))?;
+        self.version.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Builds the upsert request sent by `upsert_points`.
+    fn upsert_request(
+        &self,
+        points: Vec<PointStruct>,
+        condition: Option<(Filter, UpdateMode)>,
+        ordering: Option<WriteOrderingLevel>,
+        shard_key: Option<&str>,
+    ) -> Result<UpsertPoints> {
+        let (update_filter, update_mode) = match condition {
+            Some((filter, mode)) => (Some(filter), Some(mode as i32)),
+            None => (None, None),
+        };
+        Ok(UpsertPoints {
+            collection_name: self.collection().to_string(),
+            points,
+            ordering: Some(self.effective_write_ordering(ordering).into()),
+            shard_key_selector: self.shard_key_selector(shard_key)?,
+            update_filter,
+            update_mode,
+            ..Default::default()
+        })
+    }
+
+    /// Converts a document into the point stored in Qdrant, stamped with the write time.
+    fn document_point(&self, doc: &Document, updated_at: u64) -> Result<PointStruct> {
+        // Reject vectors that would fail inside Qdrant with an opaque error
+        self.check_vector(&doc.embedding)?;
+
+        // Convert document to JSON value
+        let json_value = serde_json::to_value(doc)?;
+        
+        // Convert JSON object to Qdrant payload, stamping the write time
+        let mut payload: HashMap<String, QdrantValue> = match json_value {
+            JsonValue::Object(obj) => obj.into_iter()
+                // The embedding is the vector; metadata is converted below
+                .filter(|(k, _)| k != "embedding" && k != "metadata")
+                .map(|(k, v)| (k, self.json_to_qdrant_value(&v)))
+                .collect(),
+            _ => return Err(anyhow::anyhow!("Document serialization failed")),
+        };
+        if !doc.metadata.is_null() {
+            payload.extend(self.metadata_payload(&doc.metadata));
+        }
+        payload.insert(UPDATED_AT_FIELD.to_string(), QdrantValue::from(updated_at as i64));
+        if doc.deleted_at.is_some() {
+            payload.insert(DELETED_FIELD.to_string(), QdrantValue::from(true));
+        }
+
+        // Construct the point structure for Qdrant
+        Ok(PointStruct {
+            id: Some(doc.id.into()),
+            vectors: Some(Vectors::from(self.prepare_vector(doc.embedding.clone())?)),
+            payload,
+        })
+    }
+
+    /// Deletes all points from the collection.
+    /// 
+    /// This method effectively resets the collection by removing all stored vectors.
+    /// Points are counted before the delete, so the returned count can be off if
+    /// other writers change the collection in between.
+    /// 
+    /// # Arguments
+    /// * `collection` - Collection to clear; defaults to the configured collection
+    /// * `ordering` - Optional write ordering override for this operation
+    /// * `shard_key` - Shard key to delete from (custom sharding only)
+    /// 
+    /// # Returns
+    /// * `Ok(u64)` - The number of deleted points
+    /// * `Err(anyhow::Error)` - If the deletion fails
+    pub async fn delete_all_points(
+        &self,
+        collection: Option<&str>,
+        ordering: Option<WriteOrderingLevel>,
+        shard_key: Option<&str>,
+    ) -> Result<u64> {
+        let collection = collection.unwrap_or(self.collection());
+        let shard_key_selector = self.shard_key_selector(shard_key)?;
+
+        let count_points = CountPoints {
+            collection_name: collection.to_string(),
+            exact: Some(true),
+            shard_key_selector: shard_key_selector.clone(),
+            ..Default::default()
+        };
+        let count = self
+            .timed_in("count", collection, self.client.count(count_points))
+            .await
+            .with_context(|| format!("counting points to delete in '{}' failed", collection))?
+            .result
+            .map_or(0, |result| result.count);
+
+        let points_selector = PointsSelector {
+            points_selector_one_of: Some(PointsSelectorOneOf::Filter(Filter::default())),
         };
         let delete_points = DeletePoints {
-            collection_name: self.collection_name.clone(),
+            collection_name: collection.to_string(),
             points: Some(points_selector),
-            ordering: Some(WriteOrdering::default().into()),
+            ordering: Some(self.effective_write_ordering(ordering).into()),
+            shard_key_selector,
+            ..Default::default()
+        };
+        self.timed_in("delete", collection, self.client.delete_points(delete_points))
+            .await
+            .with_context(|| format!("deleting all points from '{}' failed", collection))?;
+        if collection == self.collection() {
+            self.version.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(count)
+    }
+
+    /// Deletes all points matching a payload filter.
+    /// 
+    /// Matching points are counted before the delete, so the returned count
+    /// can be off if other writers change the collection in between.
+    /// 
+    /// # Arguments
+    /// * `filter` - Payload filter selecting the points to delete
+    /// * `ordering` - Optional write ordering override for this operation
+    /// * `shard_key` - Shard key to delete from (custom sharding only)
+    /// 
+    /// # Returns
+    /// * `Ok(u64)` - The number of points deleted
+    /// * `Err(anyhow::Error)` - If counting or deleting fails
+    pub async fn delete_by_filter(
+        &self,
+        filter: Filter,
+        ordering: Option<WriteOrderingLevel>,
+        shard_key: Option<&str>,
+    ) -> Result<u64> {
+        let shard_key_selector = self.shard_key_selector(shard_key)?;
+
+        let count_points = CountPoints {
+            collection_name: self.collection().to_string(),
+            filter: Some(filter.clone()),
+            exact: Some(true),
+            shard_key_selector: shard_key_selector.clone(),
+            ..Default::default()
+        };
+        let matched = self
+            .timed("count", count_points, |request| self.client.count(request))
+            .await
+            .with_context(|| format!("counting points to delete in '{}' failed", self.collection()))?
+            .result
+            .map_or(0, |result| result.count);
+        if matched == 0 {
+            return Ok(0);
+        }
+
+        let delete_points = DeletePoints {
+            collection_name: self.collection().to_string(),
+            points: Some(PointsSelector {
+                points_selector_one_of: Some(PointsSelectorOneOf::Filter(filter)),
+            }),
+            ordering: Some(self.effective_write_ordering(ordering).into()),
+            shard_key_selector,
+            ..Default::default()
+        };
+        self.timed("delete", delete_points, |request| self.client.delete_points(request))
+            .await
+            .with_context(|| format!("deleting points by filter from '{}' failed", self.collection()))?;
+        self.version.fetch_add(1, Ordering::Relaxed);
+
+        Ok(matched)
+    }
+
+    /// Deletes the points with the given ids in a single request.
+    /// 
+    /// Ids that don't exist are ignored. With versioning on, the earlier
+    /// versions of the deleted documents are deleted as well.
+    /// 
+    /// # Arguments
+    /// * `ids` - Ids of the points to delete
+    /// * `ordering` - Optional write ordering override for this operation
+    /// * `shard_key` - Shard key to delete from (custom sharding only)
+    /// 
+    /// # Returns
+    /// * `Ok(())` - If the delete was applied
+    /// * `Err(anyhow::Error)` - If the shard key is invalid or the delete fails
+    pub async fn delete_points(
+        &self,
+        ids: Vec<u64>,
+        ordering: Option<WriteOrderingLevel>,
+        shard_key: Option<&str>,
+    ) -> Result<()> {
+        let delete_points = self.delete_request(&ids, ordering, shard_key)?;
+        self.timed("delete", delete_points, |request| self.client.delete_points(request))
+            .await
+            .with_context(|| format!("deleting points by id from '{}' failed", self.collection()))?;
+        self.version.fetch_add(1, Ordering::Relaxed);
+
+        if self.version_retention > 0 {
+            let versions = Filter::must([
+                Condition::matches(IS_VERSION_FIELD, true),
+                Condition::matches(VERSION_OF_FIELD, ids.iter().map(|&id| id as i64).collect::<Vec<_>>()),
+            ]);
+            self.delete_by_filter(versions, ordering, shard_key)
+                .await
+                .context("deleting versions of the deleted points failed")?;
+        }
+        Ok(())
+    }
+
+    /// Builds the request `delete_points` sends for the points themselves.
+    fn delete_request(
+        &self,
+        ids: &[u64],
+        ordering: Option<WriteOrderingLevel>,
+        shard_key: Option<&str>,
+    ) -> Result<DeletePoints> {
+        Ok(DeletePoints {
+            collection_name: self.collection().to_string(),
+            points: Some(PointsSelector {
+                points_selector_one_of: Some(PointsSelectorOneOf::Points(PointsIdsList {
+                    ids: ids.iter().copied().map(PointId::from).collect(),
+                })),
+            }),
+            ordering: Some(self.effective_write_ordering(ordering).into()),
+            shard_key_selector: self.shard_key_selector(shard_key)?,
+            ..Default::default()
+        })
+    }
+
+    /// Searches the collection for the points nearest to the given vector.
+    /// 
+    /// # Arguments
+    /// * `vector` - Query embedding vector
+    /// * `limit` - Maximum number of results to return
+    /// * `scope` - Payload filter, read consistency override and shard key of the search
+    /// 
+    /// # Returns
+    /// * `Ok(Vec<SearchHit>)` - Matching documents, nearest first
+    /// * `Err(anyhow::Error)` - If the search fails
+    pub async fn search(&self, vector: Vec<f32>, limit: u64, scope: SearchScope<'_>) -> Result<Vec<SearchHit>> {
+        let request = self.search_request(vector, limit, scope)?;
+        let response = self
+            .timed("search", request, |request| self.client.search_points(request))
+            .await
+            .with_context(|| format!("search in '{}' failed", self.collection()))?;
+
+        Ok(Self::search_hits(response.result))
+    }
+
+    /// Runs the same search as `search` but only returns the scores.
+    /// 
+    /// Payloads are not fetched, so large limits stay cheap.
+    /// 
+    /// # Returns
+    /// * `Ok(Vec<f32>)` - Scores of the matching points, nearest first
+    /// * `Err(anyhow::Error)` - If the search fails
+    pub async fn search_scores(&self, vector: Vec<f32>, limit: u64, scope: SearchScope<'_>) -> Result<Vec<f32>> {
+        let request = SearchPoints {
+            with_payload: Some(WithPayloadSelector::from(false)),
+            ..self.search_request(vector, limit, scope)?
+        };
+        let response = self
+            .timed("search", request, |request| self.client.search_points(request))
+            .await
+            .with_context(|| format!("search in '{}' failed", self.collection()))?;
+
+        Ok(response.result.into_iter().map(|point| point.score).collect())
+    }
+
+    /// Searches for the nearest points, grouped by the value of a payload field.
+    /// 
+    /// Groups are ordered by their best hit, and each holds up to
+    /// `group_size` hits, so a document split into many points contributes
+    /// one group instead of crowding out the others. Points without the
+    /// field are left out.
+    /// 
+    /// # Arguments
+    /// * `vector` - Query embedding vector
+    /// * `group_by` - Payload field to group by, e.g. `metadata.parent_id`; its
+    ///   values must be keywords or integers
+    /// * `group_size` - Maximum number of hits per group
+    /// * `limit` - Maximum number of groups to return
+    /// * `scope` - Payload filter, read consistency override and shard key of the search
+    /// 
+    /// # Returns
+    /// * `Ok(Vec<SearchGroup>)` - Groups with their `id` (the field value) and `hits`
+    /// * `Err(anyhow::Error)` - If the search fails
+    pub async fn search_groups(
+        &self,
+        vector: Vec<f32>,
+        group_by: &str,
+        group_size: u32,
+        limit: u64,
+        scope: SearchScope<'_>,
+    ) -> Result<Vec<SearchGroup>> {
+        let request = SearchPointGroups {
+            collection_name: self.collection().to_string(),
+            vector: self.prepare_vector(vector)?,
+            filter: Some(Self::search_filter(scope.filter)),
+            limit: u32::try_from(limit).unwrap_or(u32::MAX),
+            with_payload: Some(WithPayloadSelector::from(true)),
+            group_by: group_by.to_string(),
+            group_size,
+            read_consistency: self.effective_read_consistency(scope.read_consistency),
+            shard_key_selector: self.shard_key_selector(scope.shard_key)?,
             ..Default::default()
         };
-        self.client
-            .delete_points(delete_points)
+        let response = self
+            .timed("search_groups", request, |request| self.client.search_groups(request))
             .await
-            .map_err(|e| Box::new(e) as Box<dyn Error>)?;
+            .with_context(|| format!("grouped search by '{}' in '{}' failed", group_by, self.collection()))?;
+
+        Ok(response
+            .result
+            .map(|result| result.groups.into_iter().map(Self::point_group_to_search_group).collect())
+            .unwrap_or_default())
+    }
+
+    /// Converts a group of scored points into a `SearchGroup`.
+    fn point_group_to_search_group(group: PointGroup) -> SearchGroup {
+        let id = match group.id.and_then(|id| id.kind) {
+            Some(group_id::Kind::UnsignedValue(value)) => JsonValue::from(value),
+            Some(group_id::Kind::IntegerValue(value)) => JsonValue::from(value),
+            Some(group_id::Kind::StringValue(value)) => JsonValue::from(value),
+            None => JsonValue::Null,
+        };
+        SearchGroup {
+            id,
+            hits: Self::search_hits(group.hits),
+        }
+    }
+
+    /// Builds the search request sent to Qdrant.
+    fn search_request(&self, vector: Vec<f32>, limit: u64, scope: SearchScope<'_>) -> Result<SearchPoints> {
+        Ok(SearchPoints {
+            collection_name: self.collection().to_string(),
+            vector: self.prepare_vector(vector)?,
+            limit,
+            filter: Some(Self::search_filter(scope.filter)),
+            with_payload: Some(WithPayloadSelector::from(true)),
+            read_consistency: self.effective_read_consistency(scope.read_consistency),
+            shard_key_selector: self.shard_key_selector(scope.shard_key)?,
+            ..Default::default()
+        })
+    }
+
+    /// Filter of a search: current documents matching the client's filter, if any.
+    fn search_filter(filter: Option<&DocumentFilter>) -> Filter {
+        let Some(filter) = filter.filter(|filter| !filter.is_empty()) else {
+            return Self::document_filter(false);
+        };
+        Filter {
+            must: vec![Condition::from(Filter::from(filter.clone()))],
+            ..Self::document_filter(false)
+        }
+    }
+
+    /// Filter matching current documents, leaving out earlier versions and,
+    /// unless `include_deleted`, documents in the trash.
+    fn document_filter(include_deleted: bool) -> Filter {
+        let mut hidden = vec![Condition::matches(IS_VERSION_FIELD, true)];
+        if !include_deleted {
+            hidden.push(Condition::matches(DELETED_FIELD, true));
+        }
+        Filter::must_not(hidden)
+    }
+
+    /// Searches by vector and by keyword, merging both rankings.
+    /// 
+    /// The keyword ranking holds the documents whose `text` contains every
+    /// word of `keywords`, ordered by vector similarity. Words are only
+    /// matched with the full-text index of `ensure_text_index`; without it,
+    /// Qdrant matches `keywords` as a case-sensitive substring instead. Both rankings are
+    /// fused with weighted reciprocal rank fusion, so a document scores
+    /// `weight / (60 + rank)` for each ranking it appears in. Each result's
+    /// `score` is the fused score; `vector_score` and `keyword_match` show
+    /// where it came from.
+    /// 
+    /// # Arguments
+    /// * `vector` - Query embedding vector
+    /// * `keywords` - Text that must appear in the document text
+    /// * `limit` - Maximum number of results to return
+    /// * `keyword_weight` - Weight of the keyword ranking (0 to 1); the vector ranking gets the rest
+    /// * `scope` - Payload filter, read consistency override and shard key of the search
+    /// 
+    /// # Returns
+    /// * `Ok(Vec<SearchHit>)` - Matching documents by fused score
+    /// * `Err(anyhow::Error)` - If the search fails
+    pub async fn hybrid_search(
+        &self,
+        vector: Vec<f32>,
+        keywords: &str,
+        limit: u64,
+        keyword_weight: f32,
+        scope: SearchScope<'_>,
+    ) -> Result<Vec<SearchHit>> {
+        // Deeper rankings let documents that rank moderately in both rise to the top
+        let candidates = limit.saturating_mul(2);
+        let dense = self.search_request(vector, candidates, scope)?;
+        let mut keyword_filter = Self::search_filter(scope.filter);
+        keyword_filter.must.push(Condition::matches_text("text", keywords));
+        let keyword = SearchPoints {
+            filter: Some(keyword_filter),
+            ..dense.clone()
+        };
+        let request = SearchBatchPoints {
+            collection_name: self.collection().to_string(),
+            search_points: vec![dense, keyword],
+            read_consistency: self.effective_read_consistency(scope.read_consistency),
+            ..Default::default()
+        };
+        let response = self
+            .timed("search", request, |request| self.client.search_batch_points(request))
+            .await
+            .with_context(|| format!("hybrid search in '{}' failed", self.collection()))?;
+
+        let mut rankings = response.result.into_iter().map(|batch| batch.result);
+        let dense = rankings.next().unwrap_or_default();
+        let keyword = rankings.next().unwrap_or_default();
+        Ok(Self::fuse_rankings(dense, keyword, 1.0 - keyword_weight, keyword_weight, limit))
+    }
+
+    /// Merges a vector and a keyword ranking with weighted reciprocal rank fusion.
+    fn fuse_rankings(
+        dense: Vec<ScoredPoint>,
+        keyword: Vec<ScoredPoint>,
+        dense_weight: f32,
+        keyword_weight: f32,
+        limit: u64,
+    ) -> Vec<SearchHit> {
+        // Ranks count before unconvertible points are dropped, as in Qdrant's ordering
+        let mut fused: Vec<(f32, SearchHit)> = Vec::new();
+        for (weight, is_keyword, ranking) in [(dense_weight, false, dense), (keyword_weight, true, keyword)] {
+            for (rank, point) in ranking.into_iter().enumerate() {
+                let contribution = weight / (RRF_K + rank as f32 + 1.0);
+                let Some(hit) = Self::search_hit(point) else {
+                    continue;
+                };
+                let index = match fused.iter().position(|(_, fused_hit)| fused_hit.id == hit.id) {
+                    Some(index) => index,
+                    None => {
+                        let hit = SearchHit {
+                            vector_score: Some(hit.score),
+                            keyword_match: Some(false),
+                            ..hit
+                        };
+                        fused.push((0.0, hit));
+                        fused.len() - 1
+                    }
+                };
+                let (score, hit) = &mut fused[index];
+                *score += contribution;
+                if is_keyword {
+                    hit.keyword_match = Some(true);
+                }
+            }
+        }
+
+        fused.sort_by(|a, b| b.0.total_cmp(&a.0));
+        fused
+            .into_iter()
+            .take(limit as usize)
+            .map(|(score, hit)| SearchHit { score, ..hit })
+            .collect()
+    }
+
+    /// Creates a full-text index on the document `text` payload.
+    /// 
+    /// Hybrid search needs it to match keywords word by word and regardless
+    /// of case; without it every candidate's text is scanned for `keywords`
+    /// as one substring. Creating an index that already exists is a no-op.
+    pub async fn ensure_text_index(&self) -> Result<()> {
+        let params = PayloadIndexParams {
+            index_params: Some(IndexParams::TextIndexParams(TextIndexParams {
+                tokenizer: TokenizerType::Word as i32,
+                lowercase: Some(true),
+                ..Default::default()
+            })),
+        };
+        self.create_field_index(self.collection(), "text", FieldType::Text, Some(params))
+            .await
+    }
+
+    /// Creates the configured payload indexes on the serving collection.
+    /// 
+    /// New collections are indexed when created; this also indexes an
+    /// existing collection, e.g. one created by an earlier start whose
+    /// indexing failed. Creating an index that already exists is a no-op.
+    pub async fn ensure_payload_indexes(&self) -> Result<()> {
+        for index in &self.payload_indexes {
+            self.create_field_index(self.collection(), &index.field, index.schema.into(), None)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Creates a payload index on a field, waiting until it is built.
+    async fn create_field_index(
+        &self,
+        collection: &str,
+        field: &str,
+        field_type: FieldType,
+        params: Option<PayloadIndexParams>,
+    ) -> Result<()> {
+        let request = CreateFieldIndexCollection {
+            collection_name: collection.to_string(),
+            wait: Some(true),
+            field_name: field.to_string(),
+            field_type: Some(field_type as i32),
+            field_index_params: params,
+            ..Default::default()
+        };
+        self.timed_in("create_index", collection, self.client.create_field_index(request))
+            .await
+            .with_context(|| format!("creating index on '{}' in '{}' failed", field, collection))?;
         Ok(())
     }
-} 
\ No newline at end of file
+
+    /// Converts scored points into search hits, dropping those that don't convert.
+    fn search_hits(points: Vec<ScoredPoint>) -> Vec<SearchHit> {
+        points.into_iter().filter_map(Self::search_hit).collect()
+    }
+
+    /// Converts a scored point into a search hit.
+    /// 
+    /// A point that doesn't convert is logged and skipped rather than
+    /// failing the whole search.
+    fn search_hit(point: ScoredPoint) -> Option<SearchHit> {
+        SearchHit::try_from(point)
+            .map_err(|e| warn!("Dropping search hit: {:#}", e))
+            .ok()
+    }
+
+    /// Reads one page of documents from the collection using the scroll API.
+    /// 
+    /// Points that don't have a numeric id or a `text` payload are skipped.
+    /// 
+    /// # Arguments
+    /// * `offset` - Point id to start from (`None` for the first page)
+    /// * `limit` - Maximum number of points in the page
+    /// * `with_vectors` - Whether to include embedding vectors
+    /// * `include_deleted` - Whether to include documents in the trash
+    /// * `shard_key` - Shard key to read from (custom sharding only)
+    /// 
+    /// # Returns
+    /// * `Ok((Vec<Document>, Option<PointId>))` - The page and the offset of the next page, if any
+    /// * `Err(anyhow::Error)` - If the scroll request fails
+    pub async fn scroll_documents(
+        &self,
+        offset: Option<PointId>,
+        limit: u32,
+        with_vectors: bool,
+        include_deleted: bool,
+        shard_key: Option<&str>,
+    ) -> Result<(Vec<Document>, Option<PointId>)> {
+        self.scroll(Self::document_filter(include_deleted), offset, limit, with_vectors, shard_key)
+            .await
+    }
+
+    /// Reads one page of the documents matching `filter`.
+    async fn scroll(
+        &self,
+        filter: Filter,
+        offset: Option<PointId>,
+        limit: u32,
+        with_vectors: bool,
+        shard_key: Option<&str>,
+    ) -> Result<(Vec<Document>, Option<PointId>)> {
+        let request = ScrollPoints {
+            collection_name: self.collection().to_string(),
+            filter: Some(filter),
+            offset,
+            limit: Some(limit),
+            with_payload: Some(WithPayloadSelector::from(true)),
+            with_vectors: Some(WithVectorsSelector::from(with_vectors)),
+            read_consistency: self.effective_read_consistency(None),
+            shard_key_selector: self.shard_key_selector(shard_key)?,
+            ..Default::default()
+        };
+        let response = self
+            .timed("scroll", request, |request| self.client.scroll(request))
+            .await
+            .with_context(|| format!("scroll through '{}' failed", self.collection()))?;
+
+        let documents = response
+            .result
+            .into_iter()
+            .filter_map(Self::retrieved_point_to_document)
+            .collect();
+        Ok((documents, response.next_page_offset))
+    }
+
+    /// Fetches a single document by id.
+    /// 
+    /// # Arguments
+    /// * `id` - Numeric point id of the document
+    /// * `with_vectors` - Whether to include the embedding vector
+    /// * `shard_key` - Shard key to read from (custom sharding only)
+    /// 
+    /// # Returns
+    /// * `Ok(Some(Document))` - The stored document
+    /// * `Ok(None)` - If no document with that id exists
+    /// * `Err(anyhow::Error)` - If the request fails
+    pub async fn get_document(
+        &self,
+        id: u64,
+        with_vectors: bool,
+        shard_key: Option<&str>,
+    ) -> Result<Option<Document>> {
+        let request = GetPoints {
+            collection_name: self.collection().to_string(),
+            ids: vec![id.into()],
+            with_payload: Some(WithPayloadSelector::from(true)),
+            with_vectors: Some(WithVectorsSelector::from(with_vectors)),
+            read_consistency: self.effective_read_consistency(None),
+            shard_key_selector: self.shard_key_selector(shard_key)?,
+            ..Default::default()
+        };
+        let response = self
+            .timed("get", request, |request| self.client.get_points(request))
+            .await
+            .with_context(|| format!("fetching point {} from '{}' failed", id, self.collection()))?;
+
+        Ok(response
+            .result
+            .into_iter()
+            .find_map(Self::retrieved_point_to_document))
+    }
+
+    /// Fetches several documents by id with a single request.
+    /// 
+    /// # Arguments
+    /// * `ids` - Numeric point ids of the documents; duplicates are fetched once
+    /// * `with_vectors` - Whether to include the embedding vectors
+    /// * `shard_key` - Shard key to read from (custom sharding only)
+    /// 
+    /// # Returns
+    /// * `Ok(Vec<Option<Document>>)` - One entry per requested id, in request order;
+    ///   `None` where no document with that id exists
+    /// * `Err(anyhow::Error)` - If the request fails
+    pub async fn get_documents(
+        &self,
+        ids: &[u64],
+        with_vectors: bool,
+        shard_key: Option<&str>,
+    ) -> Result<Vec<Option<Document>>> {
+        let mut distinct = ids.to_vec();
+        distinct.sort_unstable();
+        distinct.dedup();
+        let request = GetPoints {
+            collection_name: self.collection().to_string(),
+            ids: distinct.into_iter().map(PointId::from).collect(),
+            with_payload: Some(WithPayloadSelector::from(true)),
+            with_vectors: Some(WithVectorsSelector::from(with_vectors)),
+            read_consistency: self.effective_read_consistency(None),
+            shard_key_selector: self.shard_key_selector(shard_key)?,
+            ..Default::default()
+        };
+        let response = self
+            .timed("get", request, |request| self.client.get_points(request))
+            .await
+            .with_context(|| format!("fetching {} points from '{}' failed", ids.len(), self.collection()))?;
+
+        // Qdrant returns found points in no particular order
+        let found: HashMap<u64, Document> = response
+            .result
+            .into_iter()
+            .filter_map(Self::retrieved_point_to_document)
+            .map(|document| (document.id, document))
+            .collect();
+        Ok(ids.iter().map(|id| found.get(id).cloned()).collect())
+    }
+
+    /// Counts the documents in the collection exactly.
+    /// 
+    /// # Arguments
+    /// * `include_deleted` - Whether to count documents in the trash
+    /// * `shard_key` - Shard key to count in (custom sharding only)
+    pub async fn count_documents(&self, include_deleted: bool, shard_key: Option<&str>) -> Result<u64> {
+        self.count(Self::document_filter(include_deleted), shard_key).await
+    }
+
+    /// Counts the points matching `filter` exactly.
+    async fn count(&self, filter: Filter, shard_key: Option<&str>) -> Result<u64> {
+        let request = CountPoints {
+            collection_name: self.collection().to_string(),
+            filter: Some(filter),
+            exact: Some(true),
+            read_consistency: self.effective_read_consistency(None),
+            shard_key_selector: self.shard_key_selector(shard_key)?,
+            ..Default::default()
+        };
+        let response = self
+            .timed("count", request, |request| self.client.count(request))
+            .await
+            .with_context(|| format!("counting points in '{}' failed", self.collection()))?;
+
+        Ok(response.result.map_or(0, |result| result.count))
+    }
+
+    /// Moves a document to the trash, stamping the deletion time.
+    /// 
+    /// The point stays in the collection with `deleted` and `deleted_at` set
+    /// in its payload, which hides it from searches, listings and counts
+    /// until it is restored or purged.
+    /// 
+    /// # Arguments
+    /// * `id` - Numeric point id of the document
+    /// * `ordering` - Optional write ordering override for this operation
+    /// * `shard_key` - Shard key of the document (custom sharding only)
+    /// 
+    /// # Returns
+    /// * `Ok(u64)` - The deletion time in milliseconds since the Unix epoch
+    /// * `Err(anyhow::Error)` - If the shard key is invalid or the update fails
+    pub async fn soft_delete_document(
+        &self,
+        id: u64,
+        ordering: Option<WriteOrderingLevel>,
+        shard_key: Option<&str>,
+    ) -> Result<u64> {
+        let deleted_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
+        let request = SetPayloadPoints {
+            collection_name: self.collection().to_string(),
+            payload: HashMap::from([
+                (DELETED_FIELD.to_string(), QdrantValue::from(true)),
+                (DELETED_AT_FIELD.to_string(), QdrantValue::from(deleted_at as i64)),
+            ]),
+            points_selector: Some(Self::point_selector(id)),
+            ordering: Some(self.effective_write_ordering(ordering).into()),
+            shard_key_selector: self.shard_key_selector(shard_key)?,
+            ..Default::default()
+        };
+        self.timed("set_payload", request, |request| self.client.set_payload(request))
+            .await
+            .with_context(|| format!("moving point {} in '{}' to the trash failed", id, self.collection()))?;
+        self.version.fetch_add(1, Ordering::Relaxed);
+        Ok(deleted_at)
+    }
+
+    /// Takes a document out of the trash by clearing its deletion flag.
+    /// 
+    /// # Arguments
+    /// * `id` - Numeric point id of the document
+    /// * `ordering` - Optional write ordering override for this operation
+    /// * `shard_key` - Shard key of the document (custom sharding only)
+    pub async fn restore_document(
+        &self,
+        id: u64,
+        ordering: Option<WriteOrderingLevel>,
+        shard_key: Option<&str>,
+    ) -> Result<()> {
+        let request = DeletePayloadPoints {
+            collection_name: self.collection().to_string(),
+            keys: vec![DELETED_FIELD.to_string(), DELETED_AT_FIELD.to_string()],
+            points_selector: Some(Self::point_selector(id)),
+            ordering: Some(self.effective_write_ordering(ordering).into()),
+            shard_key_selector: self.shard_key_selector(shard_key)?,
+            ..Default::default()
+        };
+        self.timed("delete_payload", request, |request| self.client.delete_payload(request))
+            .await
+            .with_context(|| format!("restoring point {} in '{}' failed", id, self.collection()))?;
+        self.version.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Permanently deletes the documents that have been in the trash longer
+    /// than `retention`.
+    /// 
+    /// # Arguments
+    /// * `retention` - How long trashed documents are kept; zero empties the trash
+    /// * `shard_key` - Shard key to purge (custom sharding only)
+    /// 
+    /// # Returns
+    /// * `Ok(u64)` - The number of documents removed
+    /// * `Err(anyhow::Error)` - If the shard key is invalid or the delete fails
+    pub async fn purge_trash(&self, retention: Duration, shard_key: Option<&str>) -> Result<u64> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let cutoff = now.saturating_sub(retention).as_millis() as u64;
+        let filter = Filter::must([
+            Condition::matches(DELETED_FIELD, true),
+            Condition::range(
+                DELETED_AT_FIELD,
+                Range {
+                    lte: Some(cutoff as f64),
+                    ..Default::default()
+                },
+            ),
+        ]);
+        self.delete_by_filter(filter, None, shard_key).await
+    }
+
+    /// Flags a document for re-embedding, or clears the flag.
+    /// 
+    /// # Arguments
+    /// * `id` - Numeric point id of the document
+    /// * `stale` - Whether the document should be re-embedded
+    /// * `ordering` - Optional write ordering override for this operation
+    /// * `shard_key` - Shard key of the document (custom sharding only)
+    pub async fn mark_stale(
+        &self,
+        id: u64,
+        stale: bool,
+        ordering: Option<WriteOrderingLevel>,
+        shard_key: Option<&str>,
+    ) -> Result<()> {
+        let selector = Self::point_selector(id);
+        if stale {
+            self.flag_stale(selector, ordering, shard_key).await
+        } else {
+            let request = DeletePayloadPoints {
+                collection_name: self.collection().to_string(),
+                keys: vec![STALE_FIELD.to_string()],
+                points_selector: Some(selector),
+                ordering: Some(self.effective_write_ordering(ordering).into()),
+                shard_key_selector: self.shard_key_selector(shard_key)?,
+                ..Default::default()
+            };
+            self.timed("delete_payload", request, |request| self.client.delete_payload(request))
+                .await
+                .with_context(|| format!("clearing the stale flag of point {} in '{}' failed", id, self.collection()))?;
+            self.version.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    /// Flags every document matching `filter` for re-embedding.
+    /// 
+    /// Documents in the trash are flagged as well; earlier versions are not.
+    /// Points are counted before the update, so the returned count can be
+    /// off if other writers change the collection in between.
+    /// 
+    /// # Arguments
+    /// * `filter` - Payload filter selecting the documents; an empty one selects all
+    /// * `ordering` - Optional write ordering override for this operation
+    /// * `shard_key` - Shard key to update (custom sharding only)
+    /// 
+    /// # Returns
+    /// * `Ok(u64)` - The number of documents flagged
+    /// * `Err(anyhow::Error)` - If the shard key is invalid or the update fails
+    pub async fn mark_stale_by_filter(
+        &self,
+        mut filter: Filter,
+        ordering: Option<WriteOrderingLevel>,
+        shard_key: Option<&str>,
+    ) -> Result<u64> {
+        filter.must_not.push(Condition::matches(IS_VERSION_FIELD, true));
+        let matched = self.count(filter.clone(), shard_key).await?;
+        if matched == 0 {
+            return Ok(0);
+        }
+        let selector = PointsSelector {
+            points_selector_one_of: Some(PointsSelectorOneOf::Filter(filter)),
+        };
+        self.flag_stale(selector, ordering, shard_key).await?;
+        Ok(matched)
+    }
+
+    /// Sets the stale flag on the selected points.
+    async fn flag_stale(
+        &self,
+        selector: PointsSelector,
+        ordering: Option<WriteOrderingLevel>,
+        shard_key: Option<&str>,
+    ) -> Result<()> {
+        let request = SetPayloadPoints {
+            collection_name: self.collection().to_string(),
+            payload: HashMap::from([(STALE_FIELD.to_string(), QdrantValue::from(true))]),
+            points_selector: Some(selector),
+            ordering: Some(self.effective_write_ordering(ordering).into()),
+            shard_key_selector: self.shard_key_selector(shard_key)?,
+            ..Default::default()
+        };
+        self.timed("set_payload", request, |request| self.client.set_payload(request))
+            .await
+            .with_context(|| format!("flagging points in '{}' as stale failed", self.collection()))?;
+        self.version.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Reads up to `limit` documents flagged as stale, trashed ones included.
+    /// 
+    /// Re-embedding a document clears its flag, so each call returns the
+    /// next documents still waiting.
+    pub async fn stale_documents(&self, limit: u32, shard_key: Option<&str>) -> Result<Vec<Document>> {
+        let (documents, _) = self.scroll(Self::stale_filter(), None, limit, false, shard_key).await?;
+        Ok(documents)
+    }
+
+    /// Counts the documents flagged as stale, trashed ones included.
+    pub async fn count_stale(&self, shard_key: Option<&str>) -> Result<u64> {
+        self.count(Self::stale_filter(), shard_key).await
+    }
+
+    /// Filter matching the documents flagged as stale.
+    fn stale_filter() -> Filter {
+        Filter {
+            must: vec![Condition::matches(STALE_FIELD, true)],
+            must_not: vec![Condition::matches(IS_VERSION_FIELD, true)],
+            ..Default::default()
+        }
+    }
+
+    /// Selects a single point by id.
+    fn point_selector(id: u64) -> PointsSelector {
+        PointsSelector {
+            points_selector_one_of: Some(PointsSelectorOneOf::Points(PointsIdsList {
+                ids: vec![id.into()],
+            })),
+        }
+    }
+
+    /// Creates the collection as a chat session store unless it already exists.
+    /// 
+    /// Sessions carry no embedding, so each point gets a 1-dimensional
+    /// placeholder vector. The collection always uses automatic sharding,
+    /// with the configured shard and replica counts.
+    pub async fn ensure_session_collection(&self) -> Result<()> {
+        if self.collection_exists(self.collection()).await? {
+            return Ok(());
+        }
+
+        let create_collection = CreateCollection {
+            collection_name: self.collection().to_string(),
+            vectors_config: Some(VectorsConfig {
+                config: Some(vectors_config::Config::Params(VectorParams {
+                    size: 1,
+                    distance: Distance::Dot as i32,
+                    ..Default::default()
+                })),
+            }),
+            shard_number: Some(self.shard_number),
+            replication_factor: Some(self.replication_factor),
+            ..Default::default()
+        };
+        self.timed("create_collection", create_collection, |request| self.client.create_collection(request))
+            .await
+            .with_context(|| format!("creating session collection '{}' failed", self.collection()))?;
+        Ok(())
+    }
+
+    /// Loads a stored chat session.
+    /// 
+    /// # Arguments
+    /// * `point_id` - UUID of the session's point
+    /// 
+    /// # Returns
+    /// * `Ok(Some((turns, updated_at)))` - The stored turns and the last write in unix seconds
+    /// * `Ok(None)` - If no session is stored under the id
+    pub async fn get_session(&self, point_id: &str) -> Result<Option<(JsonValue, u64)>> {
+        let request = GetPoints {
+            collection_name: self.collection().to_string(),
+            ids: vec![point_id.to_string().into()],
+            with_payload: Some(WithPayloadSelector::from(true)),
+            with_vectors: Some(WithVectorsSelector::from(false)),
+            ..Default::default()
+        };
+        let response = self
+            .timed("get", request, |request| self.client.get_points(request))
+            .await
+            .with_context(|| format!("loading session {} from '{}' failed", point_id, self.collection()))?;
+
+        Ok(response.result.into_iter().next().map(|point| {
+            let mut payload = point.payload;
+            let turns = payload.remove("turns").map_or(JsonValue::Null, JsonValue::from);
+            let updated_at = payload
+                .remove("updated_at")
+                .and_then(|v| JsonValue::from(v).as_u64())
+                .unwrap_or(0);
+            (turns, updated_at)
+        }))
+    }
+
+    /// Stores a chat session, replacing any previous version.
+    /// 
+    /// # Arguments
+    /// * `point_id` - UUID of the session's point
+    /// * `session_id` - Session id as sent by clients, kept for inspection
+    /// * `turns` - The full history to store
+    /// * `updated_at` - Write time in unix seconds, used for expiry
+    pub async fn put_session(
+        &self,
+        point_id: &str,
+        session_id: &str,
+        turns: &JsonValue,
+        updated_at: u64,
+    ) -> Result<()> {
+        use qdrant_client::qdrant::UpsertPoints;
+
+        let payload = HashMap::from([
+            ("session_id".to_string(), QdrantValue::from(session_id.to_string())),
+            ("turns".to_string(), self.json_to_qdrant_value(turns)),
+            ("updated_at".to_string(), QdrantValue::from(updated_at as i64)),
+        ]);
+        let upsert_operation = UpsertPoints {
+            collection_name: self.collection().to_string(),
+            points: vec![PointStruct {
+                id: Some(point_id.to_string().into()),
+                vectors: Some(Vectors::from(vec![1.0])),
+                payload,
+            }],
+            ordering: Some(self.write_ordering.into()),
+            ..Default::default()
+        };
+        self.timed("upsert", upsert_operation, |request| self.client.upsert_points(request))
+            .await
+            .with_context(|| format!("saving session {} to '{}' failed", point_id, self.collection()))?;
+        Ok(())
+    }
+
+    /// Deletes sessions last written before `cutoff` (unix seconds).
+    /// 
+    /// # Returns
+    /// * `Ok(u64)` - The number of sessions deleted
+    pub async fn delete_sessions_before(&self, cutoff: u64) -> Result<u64> {
+        let filter = Filter::must([Condition::range(
+            "updated_at",
+            Range {
+                lt: Some(cutoff as f64),
+                ..Default::default()
+            },
+        )]);
+        self.delete_by_filter(filter, None, None).await
+    }
+
+    /// Returns a counter that changes whenever this service writes to the collection.
+    /// 
+    /// The counter is per process and starts at zero, so it only identifies
+    /// changes made through this instance.
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::Relaxed)
+    }
+
+    /// Converts a retrieved point back into a `Document`.
+    fn retrieved_point_to_document(point: RetrievedPoint) -> Option<Document> {
+        let id = match point.id.and_then(|id| id.point_id_options) {
+            Some(PointIdOptions::Num(num)) => num,
+            _ => return None,
+        };
+        let mut payload: serde_json::Map<String, JsonValue> = point
+            .payload
+            .into_iter()
+            .map(|(k, v)| (k, JsonValue::from(v)))
+            .collect();
+        let text = match payload.remove("text") {
+            Some(JsonValue::String(text)) => text,
+            _ => return None,
+        };
+
+        Some(Document {
+            id,
+            text,
+            embedding: point.vectors.map(Self::dense_vector).unwrap_or_default(),
+            metadata: take_metadata(&mut payload),
+            updated_at: payload.remove("updated_at").and_then(|v| v.as_u64()),
+            deleted_at: payload.remove(DELETED_AT_FIELD).and_then(|v| v.as_u64()),
+            version: payload.remove(VERSION_FIELD).and_then(|v| v.as_u64()),
+            stale: payload.remove(STALE_FIELD).and_then(|v| v.as_bool()).unwrap_or(false),
+        })
+    }
+
+    /// Converts a retrieved version point into a `DocumentVersion`.
+    fn retrieved_point_to_version(point: RetrievedPoint) -> Option<DocumentVersion> {
+        let mut payload: serde_json::Map<String, JsonValue> = point
+            .payload
+            .into_iter()
+            .map(|(k, v)| (k, JsonValue::from(v)))
+            .collect();
+        Some(DocumentVersion {
+            version: payload.remove(VERSION_FIELD)?.as_u64()?,
+            text: match payload.remove("text") {
+                Some(JsonValue::String(text)) => text,
+                _ => return None,
+            },
+            metadata: take_metadata(&mut payload),
+            updated_at: payload.remove("updated_at").and_then(|v| v.as_u64()),
+            archived_at: payload.remove(ARCHIVED_AT_FIELD).and_then(|v| v.as_u64()),
+        })
+    }
+
+    /// Extracts the dense (unnamed) vector from a vectors output.
+    pub(crate) fn dense_vector(vectors: VectorsOutput) -> Vec<f32> {
+        match vectors.vectors_options {
+            Some(vectors_output::VectorsOptions::Vector(output)) => match output.vector {
+                Some(vector_output::Vector::Dense(dense)) => dense.data,
+                #[allow(deprecated)]
+                _ => output.data,
+            },
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Collects the JSON pointers of the integers in `value` that don't fit an
+/// `i64` but fit a `u64`, the ones `LARGE_INTEGERS=string` stores as strings.
+fn large_integer_pointers(value: &JsonValue, pointer: &mut String, pointers: &mut Vec<String>) {
+    let len = pointer.len();
+    match value {
+        JsonValue::Number(n) if n.as_i64().is_none() && n.as_u64().is_some() => pointers.push(pointer.clone()),
+        JsonValue::Array(values) => {
+            for (i, value) in values.iter().enumerate() {
+                pointer.push_str(&format!("/{}", i));
+                large_integer_pointers(value, pointer, pointers);
+                pointer.truncate(len);
+            }
+        }
+        JsonValue::Object(fields) => {
+            for (key, value) in fields {
+                pointer.push('/');
+                pointer.push_str(&key.replace('~', "~0").replace('/', "~1"));
+                large_integer_pointers(value, pointer, pointers);
+                pointer.truncate(len);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Removes `metadata` from a read payload, turning the integers listed in
+/// `LARGE_INTEGERS_FIELD` back from strings into numbers.
+pub(crate) fn take_metadata(payload: &mut serde_json::Map<String, JsonValue>) -> JsonValue {
+    let mut metadata = payload.remove("metadata").unwrap_or(JsonValue::Null);
+    if let Some(JsonValue::Array(pointers)) = payload.remove(LARGE_INTEGERS_FIELD) {
+        for pointer in pointers.iter().filter_map(JsonValue::as_str) {
+            if let Some(value) = metadata.pointer_mut(pointer) {
+                if let Some(n) = value.as_str().and_then(|digits| digits.parse::<u64>().ok()) {
+                    *value = JsonValue::from(n);
+                }
+            }
+        }
+    }
+    metadata
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_support::{self, TestApp};
+
+    /// Stores a document through the API and reads it back with its vector.
+    async fn stored(app: &TestApp, text: &str) -> Document {
+        let response = app.post("/api/documents", &json!({ "text": text })).await;
+        let id = response.body["data"]["id"].as_u64().expect("stored document id");
+        app.state
+            .qdrant_service
+            .get_document(id, true, None)
+            .await
+            .unwrap()
+            .expect("stored document")
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn bulk_upserts_number_and_archive_versions() {
+        let app = test_support::app(&[("DOCUMENT_VERSIONS", "3")]).await;
+        let qdrant = &app.state.qdrant_service;
+        let first = stored(&app, "Rust is fast").await;
+        assert_eq!(first.version, Some(1));
+
+        let edited = Document { text: "Rust is very fast".into(), ..first.clone() };
+        let unchanged = stored(&app, "Go is simple").await;
+        let versions = qdrant.upsert_documents(&[edited, unchanged.clone()], None, None).await.unwrap();
+        assert_eq!(versions, [Some(2), Some(1)]);
+
+        let archived = qdrant.list_versions(first.id, None).await.unwrap();
+        assert_eq!(archived.len(), 1);
+        assert_eq!(archived[0].text, "Rust is fast");
+        assert!(qdrant.list_versions(unchanged.id, None).await.unwrap().is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn conditional_upserts_keep_newer_writes() {
+        let app = test_support::app(&[]).await;
+        let qdrant = &app.state.qdrant_service;
+        let read = stored(&app, "Rust is fast").await;
+        let newer = Document { text: "Rust is safe".into(), ..read.clone() };
+        qdrant.upsert_document(&newer, None, None).await.unwrap();
+
+        // A write conditioned on the first read must not replace the newer one
+        let point = qdrant.document_point(&read, qdrant.write_stamp().unwrap()).unwrap();
+        let condition = (Filter::should([QdrantService::unchanged_since(read.id, Some(&read))]), UpdateMode::Upsert);
+        qdrant.upsert_points(vec![point], Some(condition), None, None).await.unwrap();
+        let current = qdrant.get_document(read.id, false, None).await.unwrap().unwrap();
+        assert_eq!(current.text, "Rust is safe");
+
+        // Nor may a write expecting no document replace one inserted meanwhile
+        let point = qdrant.document_point(&read, qdrant.write_stamp().unwrap()).unwrap();
+        let condition = (Filter::should([QdrantService::unchanged_since(read.id, None)]), UpdateMode::Upsert);
+        qdrant.upsert_points(vec![point], Some(condition), None, None).await.unwrap();
+        let current = qdrant.get_document(read.id, false, None).await.unwrap().unwrap();
+        assert_eq!(current.text, "Rust is safe");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn replaced_embeddings_skip_documents_changed_since_the_read() {
+        let app = test_support::app(&[("DOCUMENT_VERSIONS", "3")]).await;
+        let qdrant = &app.state.qdrant_service;
+        let kept = stored(&app, "Rust is fast").await;
+        let rewritten = stored(&app, "Go is simple").await;
+        let trashed = stored(&app, "Zig is small").await;
+        let deleted = stored(&app, "C is old").await;
+
+        qdrant
+            .upsert_document(&Document { text: "Go is simple and fast".into(), ..rewritten.clone() }, None, None)
+            .await
+            .unwrap();
+        qdrant.soft_delete_document(trashed.id, None, None).await.unwrap();
+        qdrant.delete_points(vec![deleted.id], None, None).await.unwrap();
+
+        let reembedded: Vec<Document> = [&kept, &rewritten, &trashed, &deleted]
+            .into_iter()
+            .map(|doc| Document { stale: true, ..doc.clone() })
+            .collect();
+        let written = qdrant.replace_embeddings(&reembedded, None, None).await.unwrap();
+        assert_eq!(written, 1);
+
+        let kept = qdrant.get_document(kept.id, false, None).await.unwrap().unwrap();
+        assert_eq!(kept.version, Some(1));
+        assert!(!kept.stale);
+        let rewritten = qdrant.get_document(rewritten.id, false, None).await.unwrap().unwrap();
+        assert_eq!(rewritten.text, "Go is simple and fast");
+        assert_eq!(rewritten.version, Some(2));
+        let trashed = qdrant.get_document(trashed.id, false, None).await.unwrap().unwrap();
+        assert!(trashed.deleted_at.is_some());
+        assert!(qdrant.get_document(deleted.id, false, None).await.unwrap().is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn consistency_settings_reach_the_requests() {
+        let app = test_support::app(&[]).await;
+        let qdrant = &app.state.qdrant_service;
+        let search = qdrant.search_request(vec![1.0; 4], 3, SearchScope::default()).unwrap();
+        assert_eq!(search.read_consistency, None);
+        let upsert = qdrant.upsert_request(Vec::new(), None, None, None).unwrap();
+        assert_eq!(upsert.ordering, Some(WriteOrdering { r#type: WriteOrderingType::Weak as i32 }));
+
+        let app = test_support::app(&[("QDRANT_READ_CONSISTENCY", "majority"), ("QDRANT_WRITE_ORDERING", "medium")]).await;
+        let qdrant = &app.state.qdrant_service;
+        let majority = Some(ReadConsistency {
+            value: Some(read_consistency::Value::Type(ReadConsistencyType::Majority as i32)),
+        });
+        let search = qdrant.search_request(vec![1.0; 4], 3, SearchScope::default()).unwrap();
+        assert_eq!(search.read_consistency, majority);
+        let scope = SearchScope { read_consistency: Some(ReadConsistencyLevel::Factor(2)), ..Default::default() };
+        let search = qdrant.search_request(vec![1.0; 4], 3, scope).unwrap();
+        assert_eq!(search.read_consistency, Some(ReadConsistency { value: Some(read_consistency::Value::Factor(2)) }));
+
+        let medium = Some(WriteOrdering { r#type: WriteOrderingType::Medium as i32 });
+        let strong = Some(WriteOrdering { r#type: WriteOrderingType::Strong as i32 });
+        assert_eq!(qdrant.upsert_request(Vec::new(), None, None, None).unwrap().ordering, medium);
+        let upsert = qdrant.upsert_request(Vec::new(), None, Some(WriteOrderingLevel::Strong), None).unwrap();
+        assert_eq!(upsert.ordering, strong);
+        assert_eq!(qdrant.delete_request(&[1], None, None).unwrap().ordering, medium);
+        let delete = qdrant.delete_request(&[1], Some(WriteOrderingLevel::Strong), None).unwrap();
+        assert_eq!(delete.ordering, strong);
+
+        let reset = app.admin_post("/api/reset", &json!({ "write_ordering": "strong" })).await;
+        assert_eq!(reset.body["data"]["strong_ordering"], true, "{}", reset.text);
+        let reset = app.admin_post("/api/reset", &json!({})).await;
+        assert_eq!(reset.body["data"]["strong_ordering"], false, "{}", reset.text);
+    }
+
+    /// Metadata with integers at and beyond the `i64` range.
+    fn large_metadata() -> JsonValue {
+        json!({
+            "user_id": u64::MAX,
+            "ids": [1, 9_223_372_036_854_775_808_u64, "9223372036854775808"],
+            "a/b": { "~c": i64::MAX as u64 + 1 },
+            "small": -5,
+            "ratio": 0.5
+        })
+    }
+
+    #[test]
+    fn large_integers_are_located_by_json_pointer() {
+        let mut pointers = Vec::new();
+        large_integer_pointers(&large_metadata(), &mut String::new(), &mut pointers);
+        pointers.sort();
+        assert_eq!(pointers, ["/a~1b/~0c", "/ids/1", "/user_id"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn large_integers_round_trip_as_numbers() {
+        let app = test_support::app(&[]).await;
+        let response = app
+            .post("/api/documents", &json!({ "text": "Rust is fast", "metadata": large_metadata() }))
+            .await;
+        let id = response.body["data"]["id"].as_u64().expect("stored document id");
+        let qdrant = &app.state.qdrant_service;
+        let document = qdrant.get_document(id, false, None).await.unwrap().unwrap();
+        assert_eq!(document.metadata, large_metadata());
+
+        // Stored as exact strings, so a string match finds them
+        let filter = json!({ "must": [{ "key": "metadata.user_id", "match": u64::MAX.to_string() }] });
+        let hits = app.post("/api/search", &json!({ "query": "Rust", "filter": filter })).await;
+        let hits = &hits.body["data"]["hits"];
+        assert_eq!(hits.as_array().map(Vec::len), Some(1), "{}", hits);
+        assert_eq!(hits[0]["metadata"], large_metadata());
+        assert!(!hits[0].to_string().contains(LARGE_INTEGERS_FIELD));
+
+        // An export imports back with the same types
+        let export = app.get("/api/documents/export").await;
+        let copy = test_support::app(&[]).await;
+        let imported = copy
+            .send(
+                test_support::request(axum::http::Method::POST, "/api/documents/import", Some(test_support::USER_KEY))
+                    .body(axum::body::Body::from(export.text))
+                    .unwrap(),
+            )
+            .await;
+        assert_eq!(imported.body["data"]["imported"], 1, "{}", imported.text);
+        let document = copy.state.qdrant_service.get_document(id, false, None).await.unwrap().unwrap();
+        assert_eq!(document.metadata, large_metadata());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn rewriting_large_integers_keeps_the_version() {
+        for mode in ["string", "double"] {
+            let app = test_support::app(&[("DOCUMENT_VERSIONS", "3"), ("LARGE_INTEGERS", mode)]).await;
+            let document = json!({ "text": "Rust is fast", "metadata": large_metadata() });
+            app.post("/api/documents", &document).await;
+            let rewritten = app.post("/api/documents", &document).await;
+            assert_eq!(rewritten.body["data"]["version"], 1, "{}: {}", mode, rewritten.text);
+        }
+    }
+
+    /// A failed call answered by Qdrant with `code` and `message`.
+    fn rejected(code: Code, message: &str) -> anyhow::Error {
+        anyhow::Error::from(QdrantError::ResponseError { status: tonic::Status::new(code, message) })
+            .context("Qdrant call failed")
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn qdrant_statuses_are_translated_by_code() {
+        let app = test_support::app(&[]).await;
+        let qdrant = &app.state.qdrant_service;
+        let translate = |code, message| qdrant.translate_error(&rejected(code, message));
+
+        let missing = translate(Code::NotFound, "Not found: Collection `tenant-b` doesn't exist!");
+        assert!(matches!(missing, Some(QdrantRejection::CollectionMissing { ref collection, .. }) if collection == "tenant-b"));
+        let reworded = translate(Code::NotFound, "Collection tenant-b is missing");
+        assert!(matches!(reworded, Some(QdrantRejection::CollectionMissing { ref collection, .. }) if collection == "documents"));
+        assert!(matches!(translate(Code::NotFound, "Shard key 7 not found"), Some(QdrantRejection::NotFound(_))));
+
+        let mismatch = translate(Code::InvalidArgument, "Wrong input: Vector dimension error: expected dim: 1536, got 3");
+        assert!(matches!(mismatch, Some(QdrantRejection::DimensionMismatch(DimensionMismatch { expected: 1536, actual: 3, .. }))));
+        assert!(matches!(translate(Code::InvalidArgument, "Unable to parse UUID: x"), Some(QdrantRejection::InvalidPointId(_))));
+        assert!(matches!(translate(Code::InvalidArgument, "Bad request: anything else"), Some(QdrantRejection::InvalidArgument(_))));
+
+        for code in [Code::OutOfRange, Code::ResourceExhausted] {
+            assert!(matches!(translate(code, "message length 9000000 exceeds 4194304"), Some(QdrantRejection::PayloadTooLarge(_))));
+        }
+        assert!(matches!(translate(Code::AlreadyExists, "Collection `x` already exists!"), Some(QdrantRejection::AlreadyExists(_))));
+
+        for code in [Code::Internal, Code::Unavailable, Code::PermissionDenied, Code::Unknown] {
+            assert!(translate(code, "Collection `x` doesn't exist! too large").is_none(), "{:?}", code);
+        }
+        assert!(qdrant.translate_error(&anyhow!("not a Qdrant error")).is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn failures_name_the_operation_and_collection() {
+        let app = test_support::app(&[]).await;
+        let qdrant = &app.state.qdrant_service;
+        let document = stored(&app, "Rust is fast").await;
+        qdrant.client.delete_collection(qdrant.collection()).await.unwrap();
+
+        let upsert = qdrant.upsert_documents(std::slice::from_ref(&document), None, None).await;
+        let search = qdrant.search(document.embedding.clone(), 3, SearchScope::default()).await;
+        let errors = [
+            (upsert.map(drop), "upserting 1 points into 'documents' failed"),
+            (search.map(drop), "search in 'documents' failed"),
+            (qdrant.get_document(document.id, false, None).await.map(drop), "fetching point"),
+            (qdrant.count_documents(false, None).await.map(drop), "counting points in 'documents' failed"),
+            (qdrant.delete_points(vec![document.id], None, None).await, "deleting points by id from 'documents' failed"),
+        ];
+        for (result, context) in errors {
+            let error = result.expect_err(context);
+            let chain: Vec<String> = error.chain().map(ToString::to_string).collect();
+            assert!(chain.iter().any(|cause| cause.contains(context)), "{:?}", chain);
+            // The Qdrant status stays in the chain below the context
+            assert_eq!(status_code(&error), Some(Code::NotFound), "{:?}", chain);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn auto_create_recreates_a_deleted_collection() {
+        let app = test_support::app(&[("AUTO_CREATE_COLLECTION", "true")]).await;
+        let qdrant = &app.state.qdrant_service;
+        qdrant.client.delete_collection(qdrant.collection()).await.unwrap();
+
+        let response = app.post("/api/documents", &json!({ "text": "Rust is fast" })).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.text);
+        assert!(qdrant.client.collection_exists(qdrant.collection()).await.unwrap());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn a_deleted_collection_stays_missing_without_auto_create() {
+        let app = test_support::app(&[]).await;
+        let qdrant = &app.state.qdrant_service;
+        qdrant.client.delete_collection(qdrant.collection()).await.unwrap();
+
+        let response = app.post("/api/documents", &json!({ "text": "Rust is fast" })).await;
+        assert_eq!(response.status, StatusCode::NOT_FOUND, "{}", response.text);
+        assert!(!qdrant.client.collection_exists(qdrant.collection()).await.unwrap());
+    }
+
+    /// Ids of the documents a hybrid search for `keywords` matched by keyword.
+    async fn keyword_matches(app: &TestApp, keywords: &str) -> Vec<crate::types::HitId> {
+        let qdrant = &app.state.qdrant_service;
+        let vector = stored(app, "Key rotation: rotate the key").await.embedding;
+        let hits = qdrant.hybrid_search(vector, keywords, 10, 0.5, SearchScope::default()).await.unwrap();
+        hits.into_iter().filter(|hit| hit.keyword_match == Some(true)).map(|hit| hit.id).collect()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn hybrid_keywords_match_words_with_the_text_index() {
+        let app = test_support::app(&[]).await;
+        assert_eq!(keyword_matches(&app, "ROTATE key").await.len(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn hybrid_keywords_match_a_substring_without_the_text_index() {
+        let app = test_support::app(&[("QDRANT_TEXT_INDEX", "false")]).await;
+        assert!(keyword_matches(&app, "rotate key").await.is_empty());
+        assert_eq!(keyword_matches(&app, "rotate the").await.len(), 1);
+    }
+
+    #[test]
+    fn missing_collections_are_detected_by_code() {
+        let status = |code, message| QdrantError::ResponseError { status: tonic::Status::new(code, message) };
+        assert!(is_collection_missing(&status(Code::NotFound, "Not found: Collection `x` doesn't exist!")));
+        assert!(is_collection_missing(&status(Code::NotFound, "collection x is gone")));
+        assert!(!is_collection_missing(&status(Code::Internal, "Collection `x` doesn't exist!")));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn shard_keys_come_from_the_request_or_the_tenant_of_its_key() {
+        let app = test_support::app(&[]).await;
+        let service = |sharding| {
+            QdrantService::new(&app.state.config.qdrant_url, None, "documents")
+                .unwrap()
+                .with_sharding(sharding)
+                .with_tenants(HashMap::from([("sha256:tenant-a".to_string(), "tenant-a".to_string())]))
+        };
+        let selector = |key: &str| Some(ShardKeySelector::from(key.to_string()));
+        let custom = service(ShardingMode::Custom);
+
+        assert_eq!(custom.shard_key_selector(Some("tenant-b")).unwrap(), selector("tenant-b"));
+        assert!(custom.shard_key_selector(None).is_err());
+        assert!(custom.shard_key_selector(Some(" ")).is_err());
+        metrics::charge_to_key("sha256:tenant-a".to_string(), async {
+            assert_eq!(custom.shard_key_selector(None).unwrap(), selector("tenant-a"));
+            assert_eq!(custom.shard_key_selector(Some("tenant-b")).unwrap(), selector("tenant-b"));
+        })
+        .await;
+        metrics::charge_to_key("sha256:other".to_string(), async {
+            let error = custom.shard_key_selector(None).unwrap_err().to_string();
+            assert!(error.contains("shard_key is required"), "{}", error);
+        })
+        .await;
+
+        let auto = service(ShardingMode::Auto);
+        metrics::charge_to_key("sha256:tenant-a".to_string(), async {
+            assert_eq!(auto.shard_key_selector(None).unwrap(), None);
+            assert!(auto.shard_key_selector(Some("tenant-a")).is_err());
+        })
+        .await;
+    }
+
+    #[test]
+    fn relaxed_thresholds_are_always_looser() {
+        assert_eq!(DistanceMetric::Cosine.relax(0.8), 0.4);
+        assert_eq!(DistanceMetric::Dot.relax(-2.0), -3.0);
+        assert_eq!(DistanceMetric::Dot.relax(0.0), -MIN_RELAX_MARGIN);
+        assert_eq!(DistanceMetric::Euclid.relax(1.5), 3.0);
+        assert_eq!(DistanceMetric::Manhattan.relax(0.0), MIN_RELAX_MARGIN);
+        for metric in [DistanceMetric::Cosine, DistanceMetric::Dot, DistanceMetric::Euclid, DistanceMetric::Manhattan] {
+            for threshold in [-4.0, -0.5, 0.0, 0.3, 7.0] {
+                let relaxed = metric.relax(threshold);
+                assert!(relaxed != threshold && metric.clears(threshold, relaxed), "{:?} {} -> {}", metric, threshold, relaxed);
+            }
+        }
+    }
+
+    /// Fields with a payload index in the serving collection.
+    async fn indexed_fields(qdrant: &QdrantService) -> Vec<String> {
+        let info = qdrant.client.collection_info(qdrant.collection()).await.unwrap();
+        info.result.expect("collection info").payload_schema.into_keys().collect()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn missing_payload_indexes_are_created_by_the_startup_checks() {
+        let app = test_support::app(&[("PAYLOAD_INDEXES", "metadata.source:keyword")]).await;
+        let qdrant = &app.state.qdrant_service;
+        assert!(indexed_fields(qdrant).await.contains(&"metadata.source".to_string()));
+
+        // As if creating it had failed once the collection existed
+        let delete = qdrant_client::qdrant::DeleteFieldIndexCollectionBuilder::new(qdrant.collection(), "metadata.source");
+        qdrant.client.delete_field_index(delete).await.unwrap();
+        assert!(!indexed_fields(qdrant).await.contains(&"metadata.source".to_string()));
+
+        crate::prepare_qdrant(qdrant, qdrant.vector_size().unwrap(), false).await.unwrap();
+        assert!(indexed_fields(qdrant).await.contains(&"metadata.source".to_string()));
+    }
+}
@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::services::openai::{models, ChatRole, ChatTurn, CompletionOptions, CompletionResponse, Usage};
+
+/// Everything a chat completion's answer depends on.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    model: String,
+    /// Bit pattern of the temperature, since floats don't implement `Hash`
+    temperature: u32,
+    seed: Option<i64>,
+    max_tokens: Option<u32>,
+    stop: Vec<String>,
+    logprobs: bool,
+    top_logprobs: Option<u8>,
+    /// The conversation with whitespace trimmed and collapsed
+    prompt: Vec<(ChatRole, String)>,
+}
+
+impl CacheKey {
+    /// Builds the key of a completion request.
+    ///
+    /// # Returns
+    /// * `Some(CacheKey)` - If the request samples at temperature 0 or with a seed
+    /// * `None` - If its answer is meant to vary between calls
+    pub fn new(model: &str, turns: &[ChatTurn], options: &CompletionOptions) -> Option<Self> {
+        let temperature = options.temperature.unwrap_or(models::TEMPERATURE);
+        if temperature != 0.0 && options.seed.is_none() {
+            return None;
+        }
+        Some(Self {
+            model: model.to_string(),
+            // -0.0 and 0.0 sample alike but differ in bits
+            temperature: if temperature == 0.0 { 0 } else { temperature.to_bits() },
+            seed: options.seed,
+            max_tokens: options.max_tokens,
+            stop: options.stop.clone(),
+            logprobs: options.logprobs,
+            top_logprobs: options.top_logprobs,
+            prompt: turns
+                .iter()
+                .map(|turn| (turn.role, turn.content.split_whitespace().collect::<Vec<_>>().join(" ")))
+                .collect(),
+        })
+    }
+}
+
+/// A cached completion and when it was stored and last served.
+struct Entry {
+    response: CompletionResponse,
+    stored: Instant,
+    last_used: Instant,
+}
+
+/// Bounded in-memory cache of chat completions.
+///
+/// Entries expire `ttl` after they were stored, however often they are
+/// served. When the cache is full, the least recently used entry is
+/// evicted to make room.
+pub struct ResponseCache {
+    /// Completions keyed by what they were generated from
+    entries: Mutex<HashMap<CacheKey, Entry>>,
+    /// Maximum number of completions kept at once
+    max_entries: usize,
+    /// Time after which a completion is generated again
+    ttl: Duration,
+}
+
+impl ResponseCache {
+    /// Creates an empty cache.
+    ///
+    /// # Arguments
+    /// * `max_entries` - Maximum number of completions kept at once
+    /// * `ttl` - Time after which a completion is generated again
+    pub fn new(max_entries: usize, ttl: Duration) -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            max_entries,
+            ttl,
+        }
+    }
+
+    /// Returns the cached completion for a key.
+    ///
+    /// The copy returned is marked `cached` and reports no token usage,
+    /// since serving it cost nothing.
+    ///
+    /// # Returns
+    /// * `Some(CompletionResponse)` - The completion stored under the key
+    /// * `None` - If nothing is stored under the key or the entry has expired
+    pub fn get(&self, key: &CacheKey) -> Option<CompletionResponse> {
+        let mut entries = self.lock();
+        match entries.get_mut(key) {
+            Some(entry) if entry.stored.elapsed() < self.ttl => {
+                entry.last_used = Instant::now();
+                Some(CompletionResponse {
+                    usage: Usage::default(),
+                    cached: true,
+                    ..entry.response.clone()
+                })
+            }
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Stores a completion, replacing any earlier one under the key.
+    pub fn insert(&self, key: CacheKey, response: CompletionResponse) {
+        let mut entries = self.lock();
+        let now = Instant::now();
+
+        if !entries.contains_key(&key) {
+            entries.retain(|_, entry| now.duration_since(entry.stored) < self.ttl);
+            if entries.len() >= self.max_entries {
+                let oldest = entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.last_used)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    entries.remove(&oldest);
+                }
+            }
+        }
+
+        entries.insert(key, Entry { response, stored: now, last_used: now });
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<CacheKey, Entry>> {
+        // A panic while holding the lock cannot leave the map inconsistent
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
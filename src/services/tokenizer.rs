@@ -0,0 +1,140 @@
+use tiktoken_rs::{
+    model::get_context_size,
+    tokenizer::{get_tokenizer, Tokenizer},
+    CoreBPE,
+};
+
+/// Longest input the OpenAI embedding models accept, in tokens.
+const EMBEDDING_CONTEXT_TOKENS: usize = 8191;
+
+/// Counts the tokens `text` costs under `model`, locally.
+///
+/// The count covers the text alone; chat requests add a few tokens per
+/// message for roles and formatting.
+///
+/// # Arguments
+/// * `model` - OpenAI model name, e.g. `gpt-4` or `text-embedding-3-small`
+/// * `text` - Text to count
+///
+/// # Returns
+/// * `Some(usize)` - Number of tokens
+/// * `None` - If the model's encoding is unknown
+pub fn count_tokens(model: &str, text: &str) -> Option<usize> {
+    Some(encoding(model)?.encode_with_special_tokens(text).len())
+}
+
+/// Returns the size of the model's context window in tokens.
+///
+/// For embedding models this is the longest input they accept; unknown
+/// chat models fall back to 4096.
+pub fn context_window(model: &str) -> usize {
+    if model.starts_with("text-embedding-") {
+        EMBEDDING_CONTEXT_TOKENS
+    } else {
+        get_context_size(model)
+    }
+}
+
+/// Tokens left spare in each piece cut by `split`, since a piece encoded on
+/// its own can take a few more tokens than it did within the whole text.
+const SPLIT_MARGIN_TOKENS: usize = 16;
+
+/// Splits `text` into consecutive pieces that each fit in `max_tokens`
+/// tokens under `model`.
+///
+/// Pieces are cut between tokens. A window of tokens ending inside a
+/// multi-byte character doesn't decode to text, so such a window gives up
+/// tokens until it does (or takes more, for a lone token that isn't a
+/// whole character), and concatenating the pieces gives back `text`.
+/// Text that already fits comes back as a single piece.
+///
+/// # Arguments
+/// * `model` - OpenAI model name whose encoding is used
+/// * `text` - Text to split
+/// * `max_tokens` - Most tokens a piece may take
+///
+/// # Returns
+/// * `Some(Vec<(&str, usize)>)` - Each piece with its approximate token count
+/// * `None` - If the model's encoding is unknown
+pub fn split<'a>(model: &str, text: &'a str, max_tokens: usize) -> Option<Vec<(&'a str, usize)>> {
+    let bpe = encoding(model)?;
+    let tokens = bpe.encode_with_special_tokens(text);
+    if tokens.len() <= max_tokens {
+        return Some(vec![(text, tokens.len())]);
+    }
+
+    let window = max_tokens.saturating_sub(SPLIT_MARGIN_TOKENS).max(1);
+    let mut pieces = Vec::new();
+    let (mut start, mut rest) = (0, tokens.as_slice());
+    while !rest.is_empty() {
+        let longest = window.min(rest.len());
+        let decoded = (1..=longest)
+            .rev()
+            .chain(longest + 1..=rest.len())
+            .find_map(|take| bpe.decode(rest[..take].to_vec()).ok().map(|piece| (take, piece.len())));
+        // Only fails if the tokens don't decode at all, which a lossless
+        // encoding of valid text never does
+        let Some((take, length)) = decoded else {
+            pieces.push((&text[start..], rest.len()));
+            break;
+        };
+        pieces.push((&text[start..start + length], take));
+        start += length;
+        rest = &rest[take..];
+    }
+    Some(pieces)
+}
+
+/// Returns the shared encoder of the model's encoding, built on first use.
+fn encoding(model: &str) -> Option<&'static CoreBPE> {
+    Some(match get_tokenizer(model)? {
+        Tokenizer::O200kBase => tiktoken_rs::o200k_base_singleton(),
+        Tokenizer::Cl100kBase => tiktoken_rs::cl100k_base_singleton(),
+        Tokenizer::P50kBase => tiktoken_rs::p50k_base_singleton(),
+        Tokenizer::P50kEdit => tiktoken_rs::p50k_edit_singleton(),
+        Tokenizer::R50kBase | Tokenizer::Gpt2 => tiktoken_rs::r50k_base_singleton(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODEL: &str = "text-embedding-3-small";
+
+    /// Checks that the pieces put back together give `text` and that each fits.
+    fn check_pieces(text: &str, max_tokens: usize) -> usize {
+        let pieces = split(MODEL, text, max_tokens).unwrap();
+        assert_eq!(pieces.iter().map(|(piece, _)| *piece).collect::<String>(), text);
+        for (piece, _) in &pieces {
+            assert!(count_tokens(MODEL, piece).unwrap() <= max_tokens, "{:?} is too long", piece);
+        }
+        pieces.len()
+    }
+
+    #[test]
+    fn text_that_fits_stays_whole() {
+        assert_eq!(split(MODEL, "Rust is fast", 10).unwrap(), [("Rust is fast", 4)]);
+    }
+
+    #[test]
+    fn long_text_is_cut_between_tokens() {
+        let text = "Rust is fast. ".repeat(500);
+        assert!(check_pieces(&text, 100) > 1);
+    }
+
+    #[test]
+    fn cuts_fall_on_character_boundaries() {
+        // Emoji and CJK characters take several tokens each in cl100k
+        for text in ["🦀🎉🚀".repeat(300), "日本語のテキスト".repeat(300), "añadió ü ß €".repeat(300)] {
+            for max_tokens in [17, 18, 19, 64] {
+                assert!(check_pieces(&text, max_tokens) > 1);
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_models_are_not_split() {
+        assert!(split("no-such-model", "text", 1).is_none());
+    }
+}
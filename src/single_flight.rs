@@ -0,0 +1,103 @@
+use futures::future::{BoxFuture, FutureExt, WeakShared};
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// A running call's id and a handle that doesn't keep it alive.
+type Running<V> = (u64, WeakShared<BoxFuture<'static, V>>);
+
+/// Calls running by key.
+struct Calls<K, V> {
+    /// Id of the last call started
+    last_id: u64,
+    running: HashMap<K, Running<V>>,
+}
+
+/// Runs concurrent calls with the same key once, handing every caller the
+/// output.
+///
+/// The first caller of a key starts the call; callers arriving while it
+/// runs wait on the same future instead of starting their own. The call is
+/// forgotten as soon as it completes, so outputs are shared between
+/// concurrent callers but never cached. It runs in the tasks of the callers
+/// waiting on it: it carries on when the first caller goes away, and is
+/// dropped once none is left.
+pub struct SingleFlight<K, V> {
+    calls: Arc<Mutex<Calls<K, V>>>,
+}
+
+/// Removes a call from the running ones when it completes or is dropped.
+struct Forget<K: Eq + Hash, V> {
+    calls: Arc<Mutex<Calls<K, V>>>,
+    key: K,
+    id: u64,
+}
+
+impl<K, V> Default for SingleFlight<K, V> {
+    fn default() -> Self {
+        Self {
+            calls: Arc::new(Mutex::new(Calls {
+                last_id: 0,
+                running: HashMap::new(),
+            })),
+        }
+    }
+}
+
+impl<K, V> SingleFlight<K, V>
+where
+    K: Eq + Hash + Clone + Send + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    /// Runs `call`, or waits for the call already running for `key`.
+    ///
+    /// # Returns
+    /// The call's output, and whether it came from a call started by an
+    /// earlier caller (in which case `call` was dropped unused)
+    pub async fn run<F>(&self, key: K, call: F) -> (V, bool)
+    where
+        F: Future<Output = V> + Send + 'static,
+    {
+        let (flight, joined) = {
+            let mut calls = lock(&self.calls);
+            match calls.running.get(&key).and_then(|(_, call)| call.upgrade()) {
+                Some(flight) => (flight, true),
+                None => {
+                    calls.last_id += 1;
+                    let id = calls.last_id;
+                    let forget = Forget {
+                        calls: self.calls.clone(),
+                        key: key.clone(),
+                        id,
+                    };
+                    let flight = async move {
+                        let _forget = forget;
+                        call.await
+                    }
+                    .boxed()
+                    .shared();
+                    if let Some(weak) = flight.downgrade() {
+                        calls.running.insert(key, (id, weak));
+                    }
+                    (flight, false)
+                }
+            }
+        };
+        (flight.await, joined)
+    }
+}
+
+impl<K: Eq + Hash, V> Drop for Forget<K, V> {
+    fn drop(&mut self) {
+        let mut calls = lock(&self.calls);
+        // A call started after this one was dropped may hold the key by now
+        if calls.running.get(&self.key).is_some_and(|(id, _)| *id == self.id) {
+            calls.running.remove(&self.key);
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
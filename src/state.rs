@@ -1,4 +1,19 @@
-use crate::{config::Config, services::{OpenAIService, QdrantService}};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{
+    audit::AuditLog,
+    client_ip::IpRateLimiter,
+    config::Config,
+    handlers::{RankedDocuments, SearchKey},
+    jobs::JobRegistry,
+    metrics::Metrics,
+    services::{OpenAIQueue, QdrantService, SessionStore},
+    single_flight::SingleFlight,
+    stats::RuntimeStats,
+    warmup::Readiness,
+};
 
 /// Application state shared across all requests.
 /// 
@@ -8,10 +23,33 @@ use crate::{config::Config, services::{OpenAIService, QdrantService}};
 pub struct AppState {
     /// Application configuration
     pub config: Config,
-    /// OpenAI service for embeddings and chat
-    pub openai_service: OpenAIService,
+    /// Worker pool running the OpenAI calls for embeddings and chat
+    pub openai: OpenAIQueue,
     /// Qdrant service for vector storage
     pub qdrant_service: QdrantService,
+    /// Number of requests currently being processed
+    pub in_flight_requests: AtomicUsize,
+    /// Moving average of how long requests take to be answered
+    pub service_time: ServiceTime,
+    /// Prometheus metrics shared with the services
+    pub metrics: Arc<Metrics>,
+    /// Counters served by `/api/stats`, shared with `metrics`
+    pub stats: Arc<RuntimeStats>,
+    /// Server-side chat histories keyed by conversation id
+    pub conversations: Box<dyn SessionStore>,
+    /// Record of destructive operations
+    pub audit: AuditLog,
+    /// Background jobs started by requests, such as reindexing
+    pub jobs: Arc<JobRegistry>,
+    /// Per-address request limit; `None` when `IP_RATE_LIMIT_PER_MINUTE` is 0
+    pub ip_limiter: Option<IpRateLimiter>,
+    /// Per-address limit of the routes served without a key; `None` when
+    /// `PUBLIC_RATE_LIMIT_PER_MINUTE` is 0
+    pub public_ip_limiter: Option<IpRateLimiter>,
+    /// Whether `/ready` reports ready, and what the warm-up found
+    pub readiness: Readiness,
+    /// Searches in flight, joined by identical ones with `SEARCH_COALESCING`
+    pub search_flights: SingleFlight<SearchKey, RankedDocuments>,
 }
 
 impl AppState {
@@ -22,20 +60,78 @@ impl AppState {
     /// 
     /// # Arguments
     /// * `config` - Application configuration
-    /// * `openai_service` - Initialized OpenAI service
+    /// * `openai` - Started OpenAI worker pool
     /// * `qdrant_service` - Initialized Qdrant service
+    /// * `metrics` - Metrics registry the services report into
+    /// * `conversations` - Store for chat histories
+    /// * `audit` - Opened audit log
     /// 
     /// # Returns
     /// A new AppState instance
     pub fn new(
         config: Config,
-        openai_service: OpenAIService,
+        openai: OpenAIQueue,
         qdrant_service: QdrantService,
+        metrics: Arc<Metrics>,
+        conversations: Box<dyn SessionStore>,
+        audit: AuditLog,
     ) -> Self {
+        let ip_limiter = (config.ip_rate_limit_per_minute > 0)
+            .then(|| IpRateLimiter::new(config.ip_rate_limit_per_minute, config.ip_rate_limit_burst));
+        let public_ip_limiter = (config.public_rate_limit_per_minute > 0).then(|| {
+            IpRateLimiter::new(config.public_rate_limit_per_minute, config.public_rate_limit_burst)
+        });
         Self {
             config,
-            openai_service,
+            openai,
             qdrant_service,
+            in_flight_requests: AtomicUsize::new(0),
+            service_time: ServiceTime::default(),
+            stats: metrics.runtime_stats(),
+            metrics,
+            conversations,
+            audit,
+            jobs: Arc::new(JobRegistry::default()),
+            ip_limiter,
+            public_ip_limiter,
+            readiness: Readiness::default(),
+            search_flights: SingleFlight::default(),
         }
     }
-} 
\ No newline at end of file
+
+    /// Returns the number of requests currently being processed.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight_requests.load(Ordering::Relaxed)
+    }
+}
+
+/// Exponentially weighted moving average of request service times.
+/// 
+/// Each completed request moves the average an eighth of the way towards
+/// its own duration, so it follows load changes within a few dozen
+/// requests without jumping on a single slow one.
+#[derive(Default)]
+pub struct ServiceTime {
+    /// Average in microseconds; 0 until the first request completes
+    average_micros: AtomicU64,
+}
+
+impl ServiceTime {
+    /// Adds a completed request's duration to the average.
+    pub fn record(&self, duration: Duration) {
+        let sample = duration.as_micros().min(u128::from(u64::MAX)) as u64;
+        let _ = self
+            .average_micros
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |average| {
+                Some(match average {
+                    0 => sample.max(1),
+                    _ => (average - average / 8 + sample / 8).max(1),
+                })
+            });
+    }
+
+    /// Returns the current average, zero before any request completed.
+    pub fn average(&self) -> Duration {
+        Duration::from_micros(self.average_micros.load(Ordering::Relaxed))
+    }
+}
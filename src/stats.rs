@@ -0,0 +1,198 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::keys::{format_rfc3339, unix_now};
+
+/// Runtime counters served by `/api/stats`.
+///
+/// A lighter alternative to `/metrics` for quick checks: plain totals since
+/// startup, or since the last read that reset them, rather than Prometheus
+/// series. Requests are counted by the logging middleware, tokens and chat
+/// cache lookups by the metrics the services report into.
+pub struct RuntimeStats {
+    /// When counting started, in seconds since the epoch
+    since: AtomicU64,
+    requests: AtomicU64,
+    errors: AtomicU64,
+    latency_micros: AtomicU64,
+    prompt_tokens: AtomicU64,
+    completion_tokens: AtomicU64,
+    chat_cache_hits: AtomicU64,
+    chat_cache_misses: AtomicU64,
+    routes: Mutex<HashMap<String, RouteCounts>>,
+}
+
+/// Counts of one route.
+#[derive(Debug, Default, Clone, Copy)]
+struct RouteCounts {
+    requests: u64,
+    errors: u64,
+    latency_micros: u64,
+}
+
+/// Totals of one route in a snapshot.
+#[derive(Debug, Serialize)]
+pub struct RouteStats {
+    pub requests: u64,
+    /// Responses with a 4xx or 5xx status
+    pub errors: u64,
+    pub avg_latency_ms: f64,
+}
+
+/// Token totals in a snapshot.
+#[derive(Debug, Serialize)]
+pub struct TokenStats {
+    pub prompt: u64,
+    pub completion: u64,
+    pub total: u64,
+}
+
+/// Chat response cache lookups in a snapshot.
+#[derive(Debug, Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Share of lookups that were hits; `None` before the first lookup
+    pub hit_rate: Option<f64>,
+}
+
+/// The counters at one point in time, as returned by `/api/stats`.
+#[derive(Debug, Serialize)]
+pub struct StatsSnapshot {
+    /// When counting started: at startup or at the last reset
+    pub since: String,
+    /// Seconds counted over
+    pub elapsed_secs: u64,
+    pub requests: u64,
+    pub errors: u64,
+    pub avg_latency_ms: f64,
+    /// Counts by route, as written in the router
+    pub routes: BTreeMap<String, RouteStats>,
+    pub tokens: TokenStats,
+    pub chat_cache: CacheStats,
+    /// Whether the counters were reset by this read
+    pub reset: bool,
+}
+
+impl Default for RuntimeStats {
+    fn default() -> Self {
+        Self {
+            since: AtomicU64::new(unix_now()),
+            requests: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            latency_micros: AtomicU64::new(0),
+            prompt_tokens: AtomicU64::new(0),
+            completion_tokens: AtomicU64::new(0),
+            chat_cache_hits: AtomicU64::new(0),
+            chat_cache_misses: AtomicU64::new(0),
+            routes: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl RuntimeStats {
+    /// Counts a completed request; 4xx and 5xx statuses count as errors.
+    pub fn record_request(&self, route: &str, status: u16, duration: Duration) {
+        let micros = duration.as_micros().min(u128::from(u64::MAX)) as u64;
+        let error = status >= 400;
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.errors.fetch_add(u64::from(error), Ordering::Relaxed);
+        self.latency_micros.fetch_add(micros, Ordering::Relaxed);
+
+        let mut routes = self.routes.lock().unwrap_or_else(|e| e.into_inner());
+        let counts = routes.entry(route.to_owned()).or_default();
+        counts.requests += 1;
+        counts.errors += u64::from(error);
+        counts.latency_micros += micros;
+    }
+
+    /// Counts the tokens of an OpenAI call.
+    pub fn record_tokens(&self, prompt_tokens: u32, completion_tokens: u32) {
+        self.prompt_tokens.fetch_add(u64::from(prompt_tokens), Ordering::Relaxed);
+        self.completion_tokens.fetch_add(u64::from(completion_tokens), Ordering::Relaxed);
+    }
+
+    /// Counts a chat response cache lookup.
+    pub fn record_chat_cache(&self, hit: bool) {
+        let counter = if hit { &self.chat_cache_hits } else { &self.chat_cache_misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the current totals, starting the counts over if `reset` is set.
+    ///
+    /// A reset takes each counter and zeroes it in one step, so nothing
+    /// counted concurrently is lost; it shows up in the next snapshot. The
+    /// totals of a snapshot may be off from the sum of its routes by the
+    /// requests completing while it is taken.
+    pub fn snapshot(&self, reset: bool) -> StatsSnapshot {
+        let now = unix_now();
+        let take = |counter: &AtomicU64| {
+            if reset {
+                counter.swap(0, Ordering::Relaxed)
+            } else {
+                counter.load(Ordering::Relaxed)
+            }
+        };
+        let since = if reset {
+            self.since.swap(now, Ordering::Relaxed)
+        } else {
+            self.since.load(Ordering::Relaxed)
+        };
+
+        let routes = {
+            let mut routes = self.routes.lock().unwrap_or_else(|e| e.into_inner());
+            if reset {
+                std::mem::take(&mut *routes)
+            } else {
+                routes.clone()
+            }
+        };
+        let requests = take(&self.requests);
+        let latency_micros = take(&self.latency_micros);
+        let prompt = take(&self.prompt_tokens);
+        let completion = take(&self.completion_tokens);
+        let hits = take(&self.chat_cache_hits);
+        let misses = take(&self.chat_cache_misses);
+
+        StatsSnapshot {
+            since: format_rfc3339(since),
+            elapsed_secs: now.saturating_sub(since),
+            requests,
+            errors: take(&self.errors),
+            avg_latency_ms: average_ms(latency_micros, requests),
+            routes: routes
+                .into_iter()
+                .map(|(route, counts)| {
+                    let stats = RouteStats {
+                        requests: counts.requests,
+                        errors: counts.errors,
+                        avg_latency_ms: average_ms(counts.latency_micros, counts.requests),
+                    };
+                    (route, stats)
+                })
+                .collect(),
+            tokens: TokenStats {
+                prompt,
+                completion,
+                total: prompt + completion,
+            },
+            chat_cache: CacheStats {
+                hits,
+                misses,
+                hit_rate: (hits + misses > 0).then(|| hits as f64 / (hits + misses) as f64),
+            },
+            reset,
+        }
+    }
+}
+
+/// Average of a microsecond total in milliseconds, 0 without samples.
+fn average_ms(total_micros: u64, count: u64) -> f64 {
+    if count == 0 {
+        return 0.0;
+    }
+    (total_micros as f64 / count as f64 / 1000.0 * 100.0).round() / 100.0
+}
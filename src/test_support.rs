@@ -0,0 +1,187 @@
+//! Helpers for tests that drive the router against the demo upstreams.
+//!
+//! Every `TestApp` starts its own stub OpenAI and in-memory Qdrant from
+//! `demo`, so tests run without credentials or a Qdrant container and
+//! don't share any stored documents. Tests using it must run on the
+//! multi-threaded runtime (`#[tokio::test(flavor = "multi_thread")]`): the
+//! Qdrant client checks the server version with a blocking call at startup.
+
+use axum::{
+    body::{Body, Bytes},
+    http::{header, HeaderMap, Method, Request, StatusCode},
+    Router,
+};
+use serde_json::Value;
+use std::net::Ipv4Addr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tower::ServiceExt;
+
+use crate::{
+    config::Config,
+    demo,
+    jobs::{JobState, JobStatus},
+    routes,
+    state::AppState,
+};
+
+/// Key accepted by the user routes of a `TestApp`.
+pub const USER_KEY: &str = "test-user-key";
+/// Key accepted by the admin routes of a `TestApp`.
+pub const ADMIN_KEY: &str = "test-admin-key";
+
+/// Settings every test starts from; the ones a test passes take precedence.
+const DEFAULT_VARS: &[(&str, &str)] = &[
+    ("API_KEY", USER_KEY),
+    ("ADMIN_API_KEY", ADMIN_KEY),
+    ("DEMO_MODE", "true"),
+    ("TOKEN_USAGE_FILE", ""),
+];
+
+/// Serializes the tests that load a `Config`, which is read from the environment.
+static ENV: Mutex<()> = Mutex::new(());
+
+/// Loads and validates a `Config` from `DEFAULT_VARS` and `vars`.
+///
+/// The variables are only set while the config is read.
+pub fn config(vars: &[(&str, &str)]) -> Config {
+    try_config(vars).expect("test config is valid")
+}
+
+/// Like `config`, but returns the error of an invalid config.
+pub fn try_config(vars: &[(&str, &str)]) -> anyhow::Result<Config> {
+    let _env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+    let names: Vec<&str> = DEFAULT_VARS.iter().chain(vars).map(|(name, _)| *name).collect();
+    for (name, value) in DEFAULT_VARS.iter().chain(vars) {
+        std::env::set_var(name, value);
+    }
+    let config = Config::from_env().and_then(|config| config.validate().map(|()| config));
+    for name in names {
+        std::env::remove_var(name);
+    }
+    config
+}
+
+/// The router and state of a service running against fresh demo upstreams.
+pub struct TestApp {
+    pub state: Arc<AppState>,
+    router: Router,
+}
+
+/// A response with its body read, parsed as JSON when it is JSON.
+#[derive(Debug)]
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Value,
+    pub text: String,
+    /// The body as sent, e.g. still compressed
+    pub bytes: Bytes,
+}
+
+/// Starts a service configured with `DEFAULT_VARS` and `vars`.
+pub async fn app(vars: &[(&str, &str)]) -> TestApp {
+    TestApp::new(config(vars)).await
+}
+
+impl TestApp {
+    /// Starts demo upstreams and a service using them with `config`.
+    pub async fn new(config: Config) -> Self {
+        let upstreams = demo::start().await.expect("demo upstreams start");
+        Self::start(config, upstreams.qdrant_url, &upstreams.openai_base).await
+    }
+
+    /// Like `new`, but with `openai` answering the OpenAI calls.
+    ///
+    /// For tests that need to slow down or watch the upstream, usually by
+    /// layering `demo::openai::router()`.
+    pub async fn with_openai(config: Config, openai: Router) -> Self {
+        let upstreams = demo::start().await.expect("demo upstreams start");
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.expect("loopback port");
+        let openai_base = format!("http://{}/v1", listener.local_addr().expect("bound address"));
+        tokio::spawn(async move { axum::serve(listener, openai).await });
+        Self::start(config, upstreams.qdrant_url, &openai_base).await
+    }
+
+    async fn start(mut config: Config, qdrant_url: String, openai_base: &str) -> Self {
+        config.qdrant_url = qdrant_url;
+        config.qdrant_api_key = None;
+        let (state, _, _) = crate::build_state(config, Some(openai_base))
+            .await
+            .expect("state builds against the demo upstreams");
+        state.readiness.mark_ready(None);
+        Self {
+            router: routes::create_router(state.clone()),
+            state,
+        }
+    }
+
+    /// Sends a request through the whole router.
+    pub async fn send(&self, request: Request<Body>) -> TestResponse {
+        let response = self.router.clone().oneshot(request).await.expect("router is infallible");
+        let (parts, body) = response.into_parts();
+        let bytes = axum::body::to_bytes(body, usize::MAX).await.expect("body is readable");
+        let text = String::from_utf8_lossy(&bytes).into_owned();
+        TestResponse {
+            status: parts.status,
+            headers: parts.headers,
+            body: serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+            text,
+            bytes,
+        }
+    }
+
+    /// Sends a request without a body, authenticated with `key` when given.
+    pub async fn call(&self, method: Method, uri: &str, key: Option<&str>) -> TestResponse {
+        self.send(request(method, uri, key).body(Body::empty()).expect("valid request"))
+            .await
+    }
+
+    /// Sends a JSON body, authenticated with `key` when given.
+    pub async fn call_json(&self, method: Method, uri: &str, key: Option<&str>, body: &Value) -> TestResponse {
+        self.send(
+            request(method, uri, key)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .expect("valid request"),
+        )
+        .await
+    }
+
+    /// `GET` with the user key.
+    pub async fn get(&self, uri: &str) -> TestResponse {
+        self.call(Method::GET, uri, Some(USER_KEY)).await
+    }
+
+    /// `POST` of a JSON body with the user key.
+    pub async fn post(&self, uri: &str, body: &Value) -> TestResponse {
+        self.call_json(Method::POST, uri, Some(USER_KEY), body).await
+    }
+
+    /// `POST` of a JSON body with the admin key.
+    pub async fn admin_post(&self, uri: &str, body: &Value) -> TestResponse {
+        self.call_json(Method::POST, uri, Some(ADMIN_KEY), body).await
+    }
+
+    /// Waits up to ten seconds for a background job to stop.
+    pub async fn finished_job(&self, id: &str) -> JobStatus {
+        for _ in 0..1000 {
+            let job = self.state.jobs.get(id).expect("job is known");
+            if job.state != JobState::Running {
+                return job;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("job {} still running", id);
+    }
+}
+
+/// Starts a request, with the `x-api-key` header when `key` is given.
+pub fn request(method: Method, uri: &str, key: Option<&str>) -> axum::http::request::Builder {
+    let builder = Request::builder().method(method).uri(uri);
+    match key {
+        Some(key) => builder.header("x-api-key", key),
+        None => builder,
+    }
+}
@@ -0,0 +1,111 @@
+use anyhow::{anyhow, Context, Result};
+use axum_server::tls_rustls::RustlsConfig;
+use std::path::{Path, PathBuf};
+
+/// Locations of the PEM-encoded certificate chain and private key.
+#[derive(Debug, Clone)]
+pub struct TlsPaths {
+    /// Certificate chain, leaf certificate first
+    pub cert: PathBuf,
+    /// Private key (PKCS#8, PKCS#1 or SEC1)
+    pub key: PathBuf,
+}
+
+impl TlsPaths {
+    /// Builds the paths from the optional config values.
+    ///
+    /// # Returns
+    /// * `Ok(Some(TlsPaths))` - If both paths are set
+    /// * `Ok(None)` - If neither is set (plain HTTP)
+    /// * `Err(anyhow::Error)` - If only one of them is set
+    pub fn from_config(cert: Option<&str>, key: Option<&str>) -> Result<Option<Self>> {
+        match (cert, key) {
+            (Some(cert), Some(key)) => Ok(Some(Self {
+                cert: cert.into(),
+                key: key.into(),
+            })),
+            (None, None) => Ok(None),
+            _ => Err(anyhow!("TLS_CERT_PATH and TLS_KEY_PATH must be set together")),
+        }
+    }
+
+    /// Loads the certificate and key into a new rustls config.
+    pub async fn load(&self) -> Result<RustlsConfig> {
+        // Several crypto backends are compiled in through other dependencies,
+        // so rustls cannot pick one on its own. Fails harmlessly if already set.
+        let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
+
+        let (certs, key) = self.read().await?;
+        RustlsConfig::from_der(certs, key)
+            .await
+            .with_context(|| self.invalid_pair())
+    }
+
+    /// Replaces the certificate and key of a running config.
+    ///
+    /// On failure the previous certificate stays in use.
+    pub async fn reload(&self, config: &RustlsConfig) -> Result<()> {
+        let (certs, key) = self.read().await?;
+        config
+            .reload_from_der(certs, key)
+            .await
+            .with_context(|| self.invalid_pair())
+    }
+
+    /// Reads and parses both PEM files into DER.
+    async fn read(&self) -> Result<(Vec<Vec<u8>>, Vec<u8>)> {
+        let cert_pem = read_file(&self.cert, "certificate").await?;
+        let certs = rustls_pemfile::certs(&mut cert_pem.as_slice())
+            .map(|cert| cert.map(|cert| cert.to_vec()))
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("invalid TLS certificate {}", self.cert.display()))?;
+        if certs.is_empty() {
+            return Err(anyhow!("no certificates found in {}", self.cert.display()));
+        }
+
+        let key_pem = read_file(&self.key, "private key").await?;
+        let key = rustls_pemfile::private_key(&mut key_pem.as_slice())
+            .with_context(|| format!("invalid TLS private key {}", self.key.display()))?
+            .ok_or_else(|| anyhow!("no private key found in {}", self.key.display()))?;
+
+        Ok((certs, key.secret_der().to_vec()))
+    }
+
+    fn invalid_pair(&self) -> String {
+        format!(
+            "TLS certificate {} and key {} are not a usable pair",
+            self.cert.display(),
+            self.key.display()
+        )
+    }
+}
+
+async fn read_file(path: &Path, what: &str) -> Result<Vec<u8>> {
+    tokio::fs::read(path)
+        .await
+        .with_context(|| format!("failed to read TLS {} {}", what, path.display()))
+}
+
+/// Reloads the certificate whenever the process receives SIGHUP,
+/// so renewed certificates are picked up without a restart.
+#[cfg(unix)]
+pub fn reload_on_sighup(paths: TlsPaths, config: RustlsConfig) -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            match paths.reload(&config).await {
+                Ok(()) => tracing::info!("Reloaded TLS certificate from {}", paths.cert.display()),
+                Err(e) => tracing::error!("Failed to reload TLS certificate, keeping the current one: {:#}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Certificate reloading relies on SIGHUP and is unavailable on this platform.
+#[cfg(not(unix))]
+pub fn reload_on_sighup(_paths: TlsPaths, _config: RustlsConfig) -> Result<()> {
+    Ok(())
+}
@@ -1,5 +1,27 @@
-use serde::{Deserialize, Serialize};
-use validator::Validate;
+use axum::{
+    async_trait,
+    body::Bytes,
+    extract::{rejection::JsonRejection, FromRef, FromRequest, FromRequestParts, Path, Query, Request},
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use qdrant_client::qdrant::{point_id::PointIdOptions, ScoredPoint};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use std::str::FromStr;
+use std::sync::Arc;
+use validator::{Validate, ValidationError};
+
+use crate::budget::BudgetExceeded;
+use crate::entitlements::Violation;
+use crate::services::openai::{ImageError, ImageInput, Usage};
+use crate::services::QueueError;
+use crate::state::AppState;
+use crate::services::qdrant::{
+    take_metadata, DistanceMetric, FilterProblem, QdrantService, ReadConsistencyLevel, WriteOrderingLevel,
+};
+use crate::vector_math;
 
 /// Request payload for chat message endpoints.
 /// 
@@ -8,21 +30,926 @@ use validator::Validate;
 #[derive(Debug, Serialize, Deserialize, Validate)]
 pub struct MessageRequest {
     /// The message text to be processed.
-    /// Must not be empty.
-    #[validate(length(min = 1, message = "Message cannot be empty"))]
+    /// Must not be empty or only whitespace.
+    #[validate(custom(function = "not_blank", message = "Message cannot be empty"))]
     pub message: String,
+    /// Optional id of a server-side conversation to continue.
+    /// Prior turns are loaded and the new exchange is appended to it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[validate(custom(function = "not_blank", message = "Conversation id cannot be empty"))]
+    pub conversation_id: Option<String>,
+    /// Maximum tokens of the answer; may not exceed `MAX_COMPLETION_TOKENS`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    /// Stop sequences replacing `STOP_SEQUENCES` (at most 4).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+    /// Seed for reproducible answers; OpenAI honors it on a best-effort basis.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    /// Return the log probability of every answer token.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<bool>,
+    /// Most likely alternatives per answer token (0-20); implies `logprobs`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_logprobs: Option<u8>,
+    /// Images to ask about, as URLs or base64 data; answered by the vision model.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<ImageInput>,
+    /// Chat model for this message, one of `CHAT_MODELS`; must accept images
+    /// when `images` are sent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// Sampling temperature from 0 to 2; defaults to 0.7.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+}
+
+/// Rejects texts that are empty or only whitespace.
+fn not_blank(text: &str) -> Result<(), ValidationError> {
+    match text.trim().is_empty() {
+        true => Err(ValidationError::new("blank")),
+        false => Ok(()),
+    }
 }
 
 /// Request payload for embedding generation endpoints.
 /// 
-/// This struct represents the JSON payload for generating
-/// text embeddings using OpenAI's API.
-#[derive(Debug, Serialize, Deserialize, Validate)]
-pub struct EmbeddingRequest {
-    /// The text to be converted into an embedding vector.
+/// This enum represents the JSON payload for generating text embeddings
+/// using OpenAI's API. Either a single `text` or a batch of `texts` may be
+/// sent to the same endpoint.
+/// 
+/// # Example Requests
+/// ```json
+/// { "text": "Your text to embed" }
+/// ```
+/// ```json
+/// { "texts": ["First text", "Second text"] }
+/// ```
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum EmbeddingRequest {
+    /// A single text to be converted into an embedding vector.
+    Single {
+        /// The text to embed. Must not be empty.
+        text: String,
+    },
+    /// Multiple texts to be converted into a matrix of embedding vectors.
+    Batch {
+        /// The texts to embed. Must not be empty and contain no empty entries.
+        texts: Vec<String>,
+    },
+}
+
+impl EmbeddingRequest {
+    /// Maximum number of inputs accepted in a single batch request.
+    pub const MAX_BATCH_SIZE: usize = 2048;
+
+    /// Checks that the request contains at least one input and no empty texts.
+    /// 
+    /// # Returns
+    /// * `Ok(())` - If the request is valid
+    /// * `Err(String)` - A message describing the validation failure
+    pub fn validate_inputs(&self) -> Result<(), String> {
+        match self {
+            Self::Single { text } if text.trim().is_empty() => Err("Text cannot be empty".into()),
+            Self::Single { .. } => Ok(()),
+            Self::Batch { texts } if texts.is_empty() => Err("Texts cannot be empty".into()),
+            Self::Batch { texts } if texts.len() > Self::MAX_BATCH_SIZE => Err(format!(
+                "At most {} texts can be embedded per request",
+                Self::MAX_BATCH_SIZE
+            )),
+            Self::Batch { texts } => match texts.iter().position(|t| t.trim().is_empty()) {
+                Some(index) => Err(format!("Text at index {} cannot be empty", index)),
+                None => Ok(()),
+            },
+        }
+    }
+}
+
+/// Wire format for vectors returned by the embedding endpoint.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmbeddingFormat {
+    /// JSON array of floats
+    #[default]
+    Float,
+    /// Base64-encoded little-endian f32 buffer
+    Base64,
+}
+
+/// Query parameters for the embedding endpoint.
+#[derive(Debug, Default, Deserialize)]
+pub struct EmbedQuery {
+    /// Wire format of the returned vectors (`float` or `base64`).
+    #[serde(default)]
+    pub format: EmbeddingFormat,
+}
+
+/// A base64-encoded embedding together with its dimension count.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EncodedEmbedding {
+    /// Base64 of the vector's little-endian f32 bytes
+    pub embedding: String,
+    /// Number of f32 values in the vector
+    pub dimensions: usize,
+}
+
+impl EncodedEmbedding {
+    /// Encodes a vector for transport.
+    pub fn new(vector: &[f32]) -> Self {
+        Self {
+            embedding: vector_math::encode_base64(vector),
+            dimensions: vector.len(),
+        }
+    }
+}
+
+/// Response payload for embedding generation endpoints.
+/// 
+/// Mirrors the request shape: a single vector for `text`,
+/// or a list of vectors (in input order) for `texts`. With
+/// `format=base64` each vector is returned as an `EncodedEmbedding`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum EmbeddingResponse {
+    /// Embedding of a single input text
+    Single(Vec<f32>),
+    /// Embeddings of a batch of input texts
+    Batch(Vec<Vec<f32>>),
+    /// Base64-encoded embedding of a single input text
+    EncodedSingle(EncodedEmbedding),
+    /// Base64-encoded embeddings of a batch of input texts
+    EncodedBatch(Vec<EncodedEmbedding>),
+}
+
+impl Default for EmbeddingResponse {
+    fn default() -> Self {
+        Self::Single(Vec::new())
+    }
+}
+
+/// Request payload for the document ingestion endpoint.
+/// 
+/// The document text is embedded with OpenAI unless a pre-computed
+/// `embedding` is supplied, in which case it is stored as-is.
+#[derive(Debug, Deserialize, Validate)]
+pub struct DocumentRequest {
+    /// Optional document id; derived from the text when omitted.
+    #[serde(default)]
+    pub id: Option<u64>,
+    /// The document text.
     /// Must not be empty.
     #[validate(length(min = 1, message = "Text cannot be empty"))]
     pub text: String,
+    /// Arbitrary metadata stored alongside the document.
+    #[serde(default)]
+    pub metadata: Value,
+    /// Optional pre-computed embedding; skips the OpenAI call when present.
+    #[serde(default)]
+    pub embedding: Option<Vec<f32>>,
+    /// Optional write ordering override for the upsert.
+    #[serde(default)]
+    pub write_ordering: Option<WriteOrderingLevel>,
+    /// Shard key to store the document in; required with custom sharding.
+    #[serde(default)]
+    pub shard_key: Option<String>,
+}
+
+/// Request payload for the raw document upsert endpoint.
+/// 
+/// Stores a document with a pre-computed embedding, bypassing OpenAI.
+#[derive(Debug, Deserialize, Validate)]
+pub struct RawDocumentRequest {
+    /// Document id.
+    pub id: u64,
+    /// The document text.
+    /// Must not be empty.
+    #[validate(length(min = 1, message = "Text cannot be empty"))]
+    pub text: String,
+    /// Pre-computed embedding matching the collection's vector size.
+    pub embedding: Vec<f32>,
+    /// Arbitrary metadata stored alongside the document.
+    #[serde(default)]
+    pub metadata: Value,
+    /// Optional write ordering override for the upsert.
+    #[serde(default)]
+    pub write_ordering: Option<WriteOrderingLevel>,
+    /// Shard key to store the document in; required with custom sharding.
+    #[serde(default)]
+    pub shard_key: Option<String>,
+}
+
+/// Request payload for the bulk delete endpoint.
+#[derive(Debug, Deserialize)]
+pub struct DeleteByFilterRequest {
+    /// Payload filter selecting the documents to delete, checked with
+    /// `DocumentFilter::parse`; must not be empty.
+    pub filter: Value,
+    /// Optional write ordering override for the delete.
+    #[serde(default)]
+    pub write_ordering: Option<WriteOrderingLevel>,
+    /// Shard key to delete from; required with custom sharding.
+    #[serde(default)]
+    pub shard_key: Option<String>,
+}
+
+/// Most ids a single delete-by-ids request may list.
+pub const MAX_DELETE_IDS: usize = 1000;
+
+/// Request payload for deleting documents by id.
+#[derive(Debug, Deserialize)]
+pub struct DeleteByIdsRequest {
+    /// Ids of the documents to delete; between 1 and `MAX_DELETE_IDS`.
+    pub ids: Vec<u64>,
+    /// Optional write ordering override for the delete.
+    #[serde(default)]
+    pub write_ordering: Option<WriteOrderingLevel>,
+    /// Shard key to delete from; required with custom sharding.
+    #[serde(default)]
+    pub shard_key: Option<String>,
+}
+
+/// Most ids a single batch get request may list.
+pub const MAX_GET_IDS: usize = 1000;
+
+/// Request payload for fetching documents by id.
+/// 
+/// # Example Request
+/// ```json
+/// { "ids": [1712345678901, 1712345678902], "include_vectors": false }
+/// ```
+#[derive(Debug, Deserialize)]
+pub struct GetDocumentsRequest {
+    /// Ids of the documents to fetch; between 1 and `MAX_GET_IDS`.
+    pub ids: Vec<u64>,
+    /// Whether to include the embedding vectors.
+    #[serde(default)]
+    pub include_vectors: bool,
+    /// Shard key to read from; required with custom sharding.
+    #[serde(default)]
+    pub shard_key: Option<String>,
+}
+
+/// Query parameters for the collection export endpoint.
+#[derive(Debug, Default, Deserialize)]
+pub struct ExportQuery {
+    /// Whether to include embedding vectors in the exported documents.
+    #[serde(default)]
+    pub with_vectors: bool,
+    /// Shard key to export; required when the collection uses custom sharding.
+    #[serde(default)]
+    pub shard_key: Option<String>,
+}
+
+/// Query parameters for the runtime stats endpoint.
+#[derive(Debug, Default, Deserialize)]
+pub struct StatsQuery {
+    /// Whether to start the counts over after reading them.
+    #[serde(default)]
+    pub reset: bool,
+}
+
+/// Query parameters for the document import endpoint.
+#[derive(Debug, Default, Deserialize)]
+pub struct ImportQuery {
+    /// Optional write ordering override for the upserts.
+    #[serde(default)]
+    pub write_ordering: Option<WriteOrderingLevel>,
+    /// Shard key to import into; required when the collection uses custom sharding.
+    #[serde(default)]
+    pub shard_key: Option<String>,
+}
+
+/// One line of an import body, in the format written by the export endpoint.
+/// 
+/// # Example Line
+/// ```json
+/// {"id": 42, "text": "Rust is a systems programming language", "metadata": {"category": "Programming"}}
+/// ```
+#[derive(Debug, Deserialize)]
+pub struct ImportRecord {
+    /// Optional document id; derived from the text when omitted.
+    #[serde(default)]
+    pub id: Option<u64>,
+    /// The document text.
+    pub text: String,
+    /// Arbitrary metadata stored alongside the document.
+    #[serde(default)]
+    pub metadata: Value,
+    /// Optional pre-computed embedding; the text is embedded when absent or empty.
+    #[serde(default)]
+    pub embedding: Vec<f32>,
+    /// When the document was moved to the trash, as written by the export;
+    /// the document is imported into the trash when set.
+    #[serde(default)]
+    pub deleted_at: Option<u64>,
+    /// Whether the document is waiting to be re-embedded, as written by the export.
+    #[serde(default)]
+    pub stale: bool,
+}
+
+/// Query parameters for reading a single document.
+#[derive(Debug, Default, Deserialize)]
+pub struct DocumentQuery {
+    /// Whether to include the embedding vector; also accepted as `with_vector`.
+    #[serde(default, alias = "with_vector")]
+    pub with_vectors: bool,
+    /// Shard key to read from; required when the collection uses custom sharding.
+    #[serde(default)]
+    pub shard_key: Option<String>,
+}
+
+/// Query parameters for deleting a single document.
+#[derive(Debug, Default, Deserialize)]
+pub struct DeleteDocumentQuery {
+    /// Move the document to the trash instead of removing it.
+    #[serde(default)]
+    pub soft: bool,
+    /// Optional write ordering override for the delete.
+    #[serde(default)]
+    pub write_ordering: Option<WriteOrderingLevel>,
+    /// Shard key of the document; required when the collection uses custom sharding.
+    #[serde(default)]
+    pub shard_key: Option<String>,
+}
+
+/// Query parameters for restoring a document from the trash.
+#[derive(Debug, Default, Deserialize)]
+pub struct RestoreDocumentQuery {
+    /// Optional write ordering override for the restore.
+    #[serde(default)]
+    pub write_ordering: Option<WriteOrderingLevel>,
+    /// Shard key of the document; required when the collection uses custom sharding.
+    #[serde(default)]
+    pub shard_key: Option<String>,
+}
+
+/// Request payload for updating a document's flags.
+/// 
+/// # Example Request
+/// ```json
+/// { "stale": true }
+/// ```
+#[derive(Debug, Deserialize)]
+pub struct UpdateDocumentRequest {
+    /// Whether the document should be re-embedded by the background worker.
+    pub stale: bool,
+    /// Optional write ordering override for the update.
+    #[serde(default)]
+    pub write_ordering: Option<WriteOrderingLevel>,
+    /// Shard key of the document; required when the collection uses custom sharding.
+    #[serde(default)]
+    pub shard_key: Option<String>,
+}
+
+/// Request payload for checking a filter without running it.
+#[derive(Debug, Deserialize)]
+pub struct ValidateFilterRequest {
+    /// Payload filter to check
+    pub filter: Value,
+}
+
+/// Request payload for flagging the documents matching a filter as stale.
+#[derive(Debug, Default, Deserialize)]
+pub struct MarkStaleRequest {
+    /// Payload filter selecting the documents, checked with `DocumentFilter::parse`;
+    /// required unless `all` is set.
+    #[serde(default)]
+    pub filter: Value,
+    /// Flags every document; may not be combined with a filter.
+    #[serde(default)]
+    pub all: bool,
+    /// Optional write ordering override for the update.
+    #[serde(default)]
+    pub write_ordering: Option<WriteOrderingLevel>,
+    /// Shard key to update; required when the collection uses custom sharding.
+    #[serde(default)]
+    pub shard_key: Option<String>,
+}
+
+/// Query parameters for reading the versions of a document.
+#[derive(Debug, Default, Deserialize)]
+pub struct VersionQuery {
+    /// Shard key of the document; required when the collection uses custom sharding.
+    #[serde(default)]
+    pub shard_key: Option<String>,
+}
+
+/// Query parameters for purging the trash.
+#[derive(Debug, Default, Deserialize)]
+pub struct PurgeTrashQuery {
+    /// Purge documents trashed more than this many seconds ago instead of
+    /// using `TRASH_RETENTION_SECS`; 0 empties the trash.
+    #[serde(default)]
+    pub older_than_secs: Option<u64>,
+    /// Shard key to purge; required when the collection uses custom sharding.
+    #[serde(default)]
+    pub shard_key: Option<String>,
+}
+
+/// Query parameters for listing documents page by page.
+#[derive(Debug, Default, Deserialize)]
+pub struct ListDocumentsQuery {
+    /// Maximum number of documents in the page.
+    #[serde(default)]
+    pub limit: Option<u32>,
+    /// Id of the first document of the page, as returned in `next_offset`.
+    #[serde(default)]
+    pub offset: Option<u64>,
+    /// Whether to include embedding vectors.
+    #[serde(default)]
+    pub with_vectors: bool,
+    /// Shard key to list; required when the collection uses custom sharding.
+    #[serde(default)]
+    pub shard_key: Option<String>,
+}
+
+/// Request payload for the tokenize endpoint.
+/// 
+/// # Example Request
+/// ```json
+/// { "text": "How many tokens is this?", "model": "text-embedding-3-small" }
+/// ```
+#[derive(Debug, Deserialize, Validate)]
+pub struct TokenizeRequest {
+    /// The text to count.
+    #[validate(custom(function = "not_blank", message = "Text cannot be empty"))]
+    pub text: String,
+    /// Model whose encoding is used; defaults to the chat model.
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+/// Most pairs a single similarity request may compare.
+pub const MAX_SIMILARITY_PAIRS: usize = 100;
+
+/// Request payload for the text similarity endpoint.
+/// 
+/// Either one pair is given as `text_a` and `text_b`, or several as `pairs`.
+/// 
+/// # Example Request
+/// ```json
+/// { "text_a": "How do I reset my password?", "text_b": "Password recovery steps" }
+/// ```
+#[derive(Debug, Deserialize, Validate)]
+pub struct SimilarityRequest {
+    /// First text to compare.
+    #[serde(default)]
+    #[validate(custom(function = "not_blank", message = "Both texts must be non-empty"))]
+    pub text_a: Option<String>,
+    /// Second text to compare.
+    #[serde(default)]
+    #[validate(custom(function = "not_blank", message = "Both texts must be non-empty"))]
+    pub text_b: Option<String>,
+    /// Pairs to compare instead of `text_a` and `text_b`; at most `MAX_SIMILARITY_PAIRS`.
+    #[serde(default)]
+    #[validate]
+    pub pairs: Option<Vec<TextPair>>,
+    /// Metric of the `score`; defaults to the collection's `QDRANT_DISTANCE`.
+    #[serde(default)]
+    pub metric: Option<DistanceMetric>,
+}
+
+/// Two texts compared by the similarity endpoint.
+#[derive(Debug, Deserialize, Validate)]
+pub struct TextPair {
+    #[validate(custom(function = "not_blank", message = "Both texts of a pair must be non-empty"))]
+    pub text_a: String,
+    #[validate(custom(function = "not_blank", message = "Both texts of a pair must be non-empty"))]
+    pub text_b: String,
+}
+
+/// Most candidate texts a single model comparison may rank.
+pub const MAX_COMPARE_CANDIDATES: usize = 100;
+
+/// Request payload for the embedding model comparison endpoint.
+/// 
+/// # Example Request
+/// ```json
+/// {
+///     "text": "How do I reset my password?",
+///     "candidates": ["Password recovery steps", "Shipping rates", "Account settings"],
+///     "models": ["text-embedding-3-small", "text-embedding-3-large"]
+/// }
+/// ```
+#[derive(Debug, Deserialize, Validate)]
+pub struct CompareModelsRequest {
+    /// Text embedded with every model, and the candidates are ranked against.
+    #[validate(length(min = 1, message = "Text cannot be empty"))]
+    pub text: String,
+    /// Texts each model ranks by their similarity to `text`. Without
+    /// them, each model only reports the dimension and norm of its vector.
+    #[serde(default)]
+    #[validate(length(
+        min = 2,
+        max = "MAX_COMPARE_CANDIDATES",
+        message = "candidates must hold between 2 and 100 texts"
+    ))]
+    pub candidates: Option<Vec<String>>,
+    /// Models to compare, a subset of `COMPARE_EMBEDDING_MODELS`.
+    /// Defaults to all of them.
+    #[serde(default)]
+    pub models: Option<Vec<String>>,
+}
+
+/// Request payload for the semantic search endpoint.
+/// 
+/// The query text is embedded and used to find the nearest
+/// documents in the Qdrant collection.
+#[derive(Debug, Deserialize, Validate)]
+pub struct SearchRequest {
+    /// The text to search for.
+    /// Must not be empty.
+    #[validate(length(min = 1, message = "Query cannot be empty"))]
+    pub query: String,
+    /// Maximum number of results to return.
+    /// Defaults to `DEFAULT_SEARCH_LIMIT` and may not exceed `MAX_SEARCH_LIMIT`.
+    #[serde(default)]
+    pub limit: Option<u64>,
+    /// Optional read consistency override for clustered deployments.
+    #[serde(default)]
+    pub read_consistency: Option<ReadConsistencyLevel>,
+    /// Shard key to search in; required when the collection uses custom sharding.
+    #[serde(default)]
+    pub shard_key: Option<String>,
+    /// How documents are matched; defaults to dense vector search.
+    #[serde(default)]
+    pub mode: SearchMode,
+    /// Text that must appear in keyword matches (hybrid mode only).
+    /// Defaults to the query.
+    #[serde(default)]
+    pub keywords: Option<String>,
+    /// Weight of the keyword ranking from 0 to 1 (hybrid mode only).
+    /// Defaults to `HYBRID_KEYWORD_WEIGHT`.
+    #[serde(default)]
+    pub keyword_weight: Option<f32>,
+    /// Score a result must reach to be returned; compared against
+    /// `vector_score` in hybrid mode.
+    #[serde(default)]
+    pub score_threshold: Option<f32>,
+    /// Results to return even when fewer clear `score_threshold`; the
+    /// nearest ones are then returned and flagged as low confidence.
+    #[serde(default)]
+    pub min_results: Option<u64>,
+    /// Payload field to group results by, e.g. `metadata.parent_id`; `limit`
+    /// then counts groups (dense mode only).
+    #[serde(default)]
+    pub group_by: Option<String>,
+    /// Hits returned per group; defaults to 1 (requires `group_by`).
+    #[serde(default)]
+    pub group_size: Option<u32>,
+    /// Payload filter the results must match, checked with `DocumentFilter::parse`
+    /// like `/api/search/validate` does.
+    #[serde(default)]
+    pub filter: Option<Value>,
+}
+
+/// Request payload for the score distribution endpoint.
+/// 
+/// # Example Request
+/// ```json
+/// { "query": "What is Rust?", "limit": 500 }
+/// ```
+#[derive(Debug, Deserialize, Validate)]
+pub struct ScoreDistributionRequest {
+    /// The text to search for.
+    #[validate(length(min = 1, message = "Query cannot be empty"))]
+    pub query: String,
+    /// Number of nearest documents to summarize.
+    /// Defaults to and may not exceed `SCORE_DISTRIBUTION_LIMIT`.
+    #[serde(default)]
+    pub limit: Option<u64>,
+    /// Optional read consistency override for clustered deployments.
+    #[serde(default)]
+    pub read_consistency: Option<ReadConsistencyLevel>,
+    /// Shard key to search in; required when the collection uses custom sharding.
+    #[serde(default)]
+    pub shard_key: Option<String>,
+}
+
+/// How `/api/search` matches documents.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchMode {
+    /// Nearest documents by embedding
+    #[default]
+    Dense,
+    /// Embedding ranking fused with a full-text keyword ranking
+    Hybrid,
+}
+
+/// Id of a search hit: Qdrant point ids are unsigned integers or UUIDs.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum HitId {
+    /// Unsigned integer id, used by documents
+    Num(u64),
+    /// UUID id
+    Uuid(String),
+}
+
+/// One document matched by a search, as returned by the API.
+/// 
+/// Built from Qdrant's `ScoredPoint`, so the gRPC payload map never
+/// reaches a response. A point without a `text` payload gets an empty
+/// text, and one without `metadata` gets `null`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchHit {
+    pub id: HitId,
+    /// Similarity to the query; the fused rank score in hybrid mode
+    pub score: f32,
+    pub text: String,
+    pub metadata: Value,
+    /// Time of the document's last write in milliseconds since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<u64>,
+    /// The document's version number, when versioning is on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u64>,
+    /// Whether the document is waiting to be re-embedded
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub stale: bool,
+    /// The other payload fields stored with the document
+    #[serde(default, skip_serializing_if = "serde_json::Map::is_empty")]
+    pub payload: serde_json::Map<String, Value>,
+    /// The stored embedding, when the search asked for vectors
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vector: Option<Vec<f32>>,
+    /// Similarity to the query in hybrid mode, where `score` is the fused score
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vector_score: Option<f32>,
+    /// Whether the text matched the keywords, in hybrid mode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keyword_match: Option<bool>,
+    /// Whether the hit fell short of `score_threshold` and was only kept for `min_results`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub low_confidence: Option<bool>,
+}
+
+impl TryFrom<ScoredPoint> for SearchHit {
+    type Error = anyhow::Error;
+
+    fn try_from(point: ScoredPoint) -> anyhow::Result<Self> {
+        let id = match point.id.and_then(|id| id.point_id_options) {
+            Some(PointIdOptions::Num(num)) => HitId::Num(num),
+            Some(PointIdOptions::Uuid(uuid)) => HitId::Uuid(uuid),
+            None => return Err(anyhow::anyhow!("point has no id")),
+        };
+        let mut payload = point.payload;
+        let text = match payload.remove("text").map(Value::from) {
+            Some(Value::String(text)) => text,
+            None | Some(Value::Null) => String::new(),
+            Some(other) => return Err(anyhow::anyhow!("point {:?} has a non-string text: {}", id, other)),
+        };
+
+        let mut payload: serde_json::Map<String, Value> =
+            payload.into_iter().map(|(k, v)| (k, Value::from(v))).collect();
+        Ok(Self {
+            metadata: take_metadata(&mut payload),
+            updated_at: payload.remove("updated_at").and_then(|v| v.as_u64()),
+            version: payload.remove("version").and_then(|v| v.as_u64()),
+            stale: payload.remove("stale").and_then(|v| v.as_bool()).unwrap_or(false),
+            payload,
+            vector: point
+                .vectors
+                .map(QdrantService::dense_vector)
+                .filter(|vector| !vector.is_empty()),
+            id,
+            score: point.score,
+            text,
+            vector_score: None,
+            keyword_match: None,
+            low_confidence: None,
+        })
+    }
+}
+
+impl SearchHit {
+    /// Renders the hit in the version 1 shape: the document's `text`,
+    /// `metadata` and other stored fields nested under `payload`.
+    pub fn into_v1(self) -> Value {
+        let mut payload = self.payload;
+        payload.insert("text".to_string(), Value::from(self.text));
+        payload.insert("metadata".to_string(), self.metadata);
+        if let Some(updated_at) = self.updated_at {
+            payload.insert("updated_at".to_string(), Value::from(updated_at));
+        }
+        if let Some(version) = self.version {
+            payload.insert("version".to_string(), Value::from(version));
+        }
+        if self.stale {
+            payload.insert("stale".to_string(), Value::Bool(true));
+        }
+
+        let mut hit = serde_json::Map::new();
+        hit.insert("id".to_string(), serde_json::json!(self.id));
+        hit.insert("score".to_string(), Value::from(self.score));
+        hit.insert("payload".to_string(), Value::Object(payload));
+        if let Some(vector) = self.vector {
+            hit.insert("vector".to_string(), Value::from(vector));
+        }
+        if let Some(vector_score) = self.vector_score {
+            hit.insert("vector_score".to_string(), Value::from(vector_score));
+        }
+        if let Some(keyword_match) = self.keyword_match {
+            hit.insert("keyword_match".to_string(), Value::Bool(keyword_match));
+        }
+        if let Some(low_confidence) = self.low_confidence {
+            hit.insert("low_confidence".to_string(), Value::Bool(low_confidence));
+        }
+        Value::Object(hit)
+    }
+}
+
+/// Hits sharing a value of the `group_by` field.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchGroup {
+    /// The shared field value, a string or an integer
+    pub id: Value,
+    pub hits: Vec<SearchHit>,
+}
+
+/// Response payload of an ungrouped `/api/search`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SearchResults {
+    /// Matching documents, best first
+    pub hits: Vec<SearchHit>,
+    /// Time spent in Qdrant, excluding the query embedding
+    pub query_time_ms: u64,
+    /// Hits Qdrant returned before `score_threshold` was applied
+    pub total_candidates: usize,
+    pub score_threshold: Option<f32>,
+    /// Whether fewer than `min_results` hits cleared the threshold
+    pub low_confidence: bool,
+}
+
+impl SearchResults {
+    /// Renders the results in the shape of `version`.
+    /// 
+    /// Version 2 is the struct as it is. Version 1 lists the hits under
+    /// `results`, each in `SearchHit::into_v1`'s shape, and leaves out
+    /// the timing and candidate count added since.
+    pub fn render(self, version: ApiVersion) -> Value {
+        match version {
+            ApiVersion::V1 => serde_json::json!({
+                "results": self.hits.into_iter().map(SearchHit::into_v1).collect::<Vec<_>>(),
+                "score_threshold": self.score_threshold,
+                "low_confidence": self.low_confidence,
+            }),
+            ApiVersion::V2 => serde_json::json!(self),
+        }
+    }
+}
+
+impl SearchGroup {
+    /// Renders the group in the shape of `version`, see `SearchResults::render`.
+    pub fn render(self, version: ApiVersion) -> Value {
+        match version {
+            ApiVersion::V1 => serde_json::json!({
+                "id": self.id,
+                "hits": self.hits.into_iter().map(SearchHit::into_v1).collect::<Vec<_>>(),
+            }),
+            ApiVersion::V2 => serde_json::json!(self),
+        }
+    }
+}
+
+/// What `/api/ask` does when no retrieved document clears the score threshold.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NoContextBehavior {
+    /// Ask the chat model without context and report `context_used: false`
+    #[default]
+    AnswerAnyway,
+    /// Reply with `NO_CONTEXT_MESSAGE` without calling the chat model
+    Refuse,
+    /// Retry once with a relaxed threshold, then answer anyway
+    Relax,
+}
+
+impl FromStr for NoContextBehavior {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "answer_anyway" => Ok(Self::AnswerAnyway),
+            "refuse" => Ok(Self::Refuse),
+            "relax" => Ok(Self::Relax),
+            _ => Err(anyhow::anyhow!("expected 'answer_anyway', 'refuse' or 'relax', got '{}'", s)),
+        }
+    }
+}
+
+/// Request payload for the question answering endpoint.
+/// 
+/// The question is embedded, the nearest documents are retrieved and
+/// passed to the chat model as context.
+/// 
+/// # Example Request
+/// ```json
+/// { "question": "How do I rotate an API key?", "limit": 5 }
+/// ```
+#[derive(Debug, Deserialize, Validate)]
+pub struct AskRequest {
+    /// The question to answer.
+    #[validate(length(min = 1, message = "Question cannot be empty"))]
+    pub question: String,
+    /// Number of documents retrieved as context.
+    /// Defaults to `DEFAULT_SEARCH_LIMIT` and may not exceed `MAX_SEARCH_LIMIT`.
+    #[serde(default)]
+    pub limit: Option<u64>,
+    /// Shard key to search in; required when the collection uses custom sharding.
+    #[serde(default)]
+    pub shard_key: Option<String>,
+    /// Maximum tokens of the answer; may not exceed `MAX_COMPLETION_TOKENS`.
+    #[serde(default)]
+    pub max_tokens: Option<u32>,
+    /// Stop sequences replacing `STOP_SEQUENCES` (at most 4).
+    #[serde(default)]
+    pub stop: Option<Vec<String>>,
+    /// Optional id of a server-side conversation to continue, shared with `/api/chat`.
+    #[serde(default)]
+    pub conversation_id: Option<String>,
+    /// Rewrite a follow-up question into a standalone search query before
+    /// retrieval; defaults to true and only applies within a conversation.
+    #[serde(default)]
+    pub rewrite: Option<bool>,
+    /// Score a document must reach to be used as context; overrides `RAG_SCORE_THRESHOLD`.
+    #[serde(default)]
+    pub score_threshold: Option<f32>,
+    /// What to do when no document clears the threshold; overrides `NO_CONTEXT_BEHAVIOR`.
+    #[serde(default)]
+    pub no_context_behavior: Option<NoContextBehavior>,
+}
+
+/// Request payload for the admin collection creation endpoint.
+/// 
+/// # Example Request
+/// ```json
+/// { "name": "tenant-b", "vector_size": 1536, "distance": "cosine", "on_disk": true }
+/// ```
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateCollectionRequest {
+    /// Name of the new collection.
+    #[validate(length(min = 1, max = 255, message = "Name must be 1 to 255 characters"))]
+    pub name: String,
+    /// Dimension of the vectors stored in the collection.
+    #[validate(range(min = 1, message = "Vector size must be positive"))]
+    pub vector_size: u64,
+    /// Distance metric; defaults to `QDRANT_DISTANCE`.
+    #[serde(default)]
+    pub distance: Option<DistanceMetric>,
+    /// Keep vectors on disk; defaults to `QDRANT_ON_DISK`.
+    #[serde(default)]
+    pub on_disk: Option<bool>,
+}
+
+/// Request payload for the admin collection deletion endpoint.
+#[derive(Debug, Deserialize)]
+pub struct DeleteCollectionRequest {
+    /// Must repeat the collection name to confirm the deletion.
+    pub confirm: String,
+}
+
+/// Request payload for pointing the configured alias at another collection.
+#[derive(Debug, Deserialize)]
+pub struct SwitchAliasRequest {
+    /// Collection the alias should resolve to from now on.
+    pub collection: String,
+}
+
+/// Optional request payload for the reindex endpoint.
+/// 
+/// # Example Request
+/// ```json
+/// { "batch_size": 200, "shard_key": "tenant-a" }
+/// ```
+#[derive(Debug, Default, Deserialize)]
+pub struct ReindexRequest {
+    /// Documents re-embedded per OpenAI call (1 to 2048); defaults to 100.
+    #[serde(default)]
+    pub batch_size: Option<u32>,
+    /// Optional write ordering override for the upserts.
+    #[serde(default)]
+    pub write_ordering: Option<WriteOrderingLevel>,
+    /// Shard key to reindex; required when the collection uses custom sharding.
+    #[serde(default)]
+    pub shard_key: Option<String>,
+}
+
+/// Optional request payload for the reset endpoint.
+#[derive(Debug, Default, Deserialize)]
+pub struct ResetRequest {
+    /// Collection to reset; defaults to the configured collection (or alias).
+    #[serde(default)]
+    pub collection: Option<String>,
+    /// Optional write ordering override for the delete operation.
+    #[serde(default)]
+    pub write_ordering: Option<WriteOrderingLevel>,
+    /// Shard key to reset; required when the collection uses custom sharding.
+    #[serde(default)]
+    pub shard_key: Option<String>,
 }
 
 /// Generic API response wrapper.
@@ -41,6 +968,21 @@ pub struct ApiResponse<T> {
     /// Optional error message, only present on error
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error: Option<String>,
+    /// Category of the error, present on every error
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<ErrorCode>,
+    /// Finer-grained reason within the category, present for errors clients may act on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<String>,
+    /// OpenAI tokens consumed by the operation, reported by embedding and batch operations
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+    /// Tokens of each input counted locally, reported by `/api/embed`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokens: Option<Vec<usize>>,
+    /// Each problem of a rejected filter, with its path within the filter
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<Vec<FilterProblem>>,
 }
 
 impl<T: Default> ApiResponse<T> {
@@ -56,30 +998,126 @@ impl<T: Default> ApiResponse<T> {
             data,
             status: "success".to_string(),
             error: None,
+            code: None,
+            error_code: None,
+            usage: None,
+            tokens: None,
+            details: None,
         }
     }
 
+    /// Attaches the token usage of the operation to the response.
+    pub fn with_usage(mut self, usage: Usage) -> Self {
+        self.usage = Some(usage);
+        self
+    }
+
+    /// Attaches the locally counted tokens of each input to the response.
+    pub fn with_tokens(mut self, tokens: Option<Vec<usize>>) -> Self {
+        self.tokens = tokens;
+        self
+    }
+
     /// Creates an error response with the provided message.
     /// 
     /// # Arguments
+    /// * `code` - The category of the error
     /// * `error` - The error message
     /// 
     /// # Returns
     /// A new ApiResponse instance with error status
-    pub fn error(error: String) -> Self {
+    pub fn error(code: ErrorCode, error: String) -> Self {
         Self {
             data: T::default(),
             status: "error".to_string(),
             error: Some(error),
+            code: Some(code),
+            error_code: None,
+            usage: None,
+            tokens: None,
+            details: None,
+        }
+    }
+}
+
+/// Version of the response envelope, chosen per request with `x-api-version`.
+/// 
+/// Only the envelope differs between versions; `data` is the same in both.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ApiVersion {
+    /// `data`, `status` and `error` only, as in the first releases
+    V1,
+    /// The current envelope, with error codes, token usage and counts
+    #[default]
+    V2,
+}
+
+impl ApiVersion {
+    /// The version as sent in the `x-api-version` header.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::V1 => "1",
+            Self::V2 => "2",
+        }
+    }
+}
+
+impl std::fmt::Display for ApiVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ApiVersion {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "1" | "v1" => Ok(Self::V1),
+            "2" | "v2" => Ok(Self::V2),
+            _ => Err(anyhow::anyhow!("expected 1 or 2, got '{}'", s)),
         }
     }
 }
 
+/// Stable category of an error, returned as `code` in every error response.
+/// 
+/// Clients should branch on this rather than on the error message, which
+/// may change. New codes may be added, so treat unknown ones like `internal`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The request is malformed or its content is not acceptable
+    ValidationFailed,
+    /// The API key is missing, unknown or expired
+    Unauthorized,
+    /// The key or client address may not make the request
+    Forbidden,
+    /// The requested resource does not exist
+    NotFound,
+    /// The request conflicts with the current state of the resource
+    Conflict,
+    /// A rate limit, quota or token budget is used up
+    RateLimited,
+    /// The request took longer than its route's timeout
+    Timeout,
+    /// The service is overloaded or not ready; retrying later may succeed
+    Unavailable,
+    /// A call to OpenAI failed
+    #[serde(rename = "upstream_openai")]
+    UpstreamOpenAI,
+    /// A call to Qdrant failed
+    UpstreamQdrant,
+    /// Any other server-side failure
+    Internal,
+}
+
 /// Enumeration of possible API errors.
 /// 
 /// This enum represents the different types of errors that can occur
-/// during API request processing.
-#[derive(Debug, thiserror::Error)]
+/// during API request processing. Each variant maps to an HTTP status
+/// and is rendered with the standard `ApiResponse` error envelope.
+#[derive(Debug, Clone, thiserror::Error)]
 pub enum ApiError {
     /// Authentication-related errors
     #[error("Authentication failed: {0}")]
@@ -89,7 +1127,454 @@ pub enum ApiError {
     #[error("Invalid request: {0}")]
     Validation(String),
 
+    /// Request bodies that are not valid JSON for the endpoint
+    #[error("invalid JSON: {0}")]
+    InvalidJson(String),
+
+    /// JSON bodies sent without a JSON content type
+    #[error("missing `Content-Type: application/json` header; set it on requests with a JSON body")]
+    MissingJsonContentType,
+
+    /// The requested resource does not exist
+    #[error("Not found: {0}")]
+    NotFound(String),
+
+    /// The request conflicts with existing state
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
+    /// A conditional request header did not match the current state
+    #[error("Precondition failed: {0}")]
+    PreconditionFailed(String),
+
+    /// Well-formed requests whose content cannot be processed
+    #[error("Unprocessable request: {0}")]
+    Unprocessable(String),
+
+    /// A payload filter with mistakes, each listed in `details`
+    #[error("Unprocessable request: invalid filter: {}", FilterProblem::join(.0))]
+    InvalidFilter(Vec<FilterProblem>),
+
+    /// Requests too large to be passed on
+    #[error("Payload too large: {0}")]
+    PayloadTooLarge(String),
+
+    /// A route that exists but doesn't take the request's method
+    #[error("Method not allowed: {0}")]
+    MethodNotAllowed(String),
+
+    /// The key is valid but not entitled to the request
+    #[error("Forbidden: {message}")]
+    Forbidden {
+        /// Machine-readable reason, returned as `error_code`
+        code: &'static str,
+        message: String,
+    },
+
+    /// A token budget is used up, so OpenAI is not called
+    #[error("Too many requests: {message}")]
+    TooManyRequests {
+        /// Machine-readable reason, returned as `error_code`
+        code: &'static str,
+        message: String,
+    },
+
     /// Internal server errors
     #[error("Internal server error: {0}")]
     Internal(String),
-} 
\ No newline at end of file
+
+    /// A failed OpenAI call. Answered with a 500 like other internal
+    /// errors; only the `code` tells it apart.
+    #[error("Internal server error: {0}")]
+    OpenAI(String),
+
+    /// A failed Qdrant call, answered with a 500 and its own `code`
+    #[error("Internal server error: {0}")]
+    Qdrant(String),
+
+    /// The server is too busy to take the request right now
+    #[error("Service unavailable: {0}")]
+    ServiceUnavailable(String),
+
+    /// The request took longer than its route's timeout
+    #[error("Request timed out: {0}")]
+    Timeout(String),
+}
+
+impl From<Violation> for ApiError {
+    fn from(violation: Violation) -> Self {
+        Self::Forbidden {
+            code: violation.code(),
+            message: violation.message(),
+        }
+    }
+}
+
+impl From<BudgetExceeded> for ApiError {
+    fn from(exceeded: BudgetExceeded) -> Self {
+        Self::TooManyRequests {
+            code: exceeded.code(),
+            message: exceeded.message(),
+        }
+    }
+}
+
+impl From<ImageError> for ApiError {
+    fn from(error: ImageError) -> Self {
+        match error {
+            ImageError::Invalid(message) => Self::Validation(message),
+            ImageError::TooLarge(_) => Self::PayloadTooLarge(error.to_string()),
+        }
+    }
+}
+
+impl From<QueueError> for ApiError {
+    fn from(error: QueueError) -> Self {
+        Self::ServiceUnavailable(error.to_string())
+    }
+}
+
+impl ApiError {
+    /// Converts a rejected request body: a 413 from the body limit, anything
+    /// else a body that isn't valid JSON for the endpoint.
+    fn body_rejected(status: StatusCode, message: String) -> Self {
+        if status == StatusCode::PAYLOAD_TOO_LARGE {
+            Self::PayloadTooLarge(message)
+        } else {
+            Self::InvalidJson(message)
+        }
+    }
+
+    /// Returns the HTTP status code for this error.
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Self::Auth(_) => StatusCode::UNAUTHORIZED,
+            Self::Validation(_) | Self::InvalidJson(_) | Self::MissingJsonContentType => {
+                StatusCode::BAD_REQUEST
+            }
+            Self::Forbidden { .. } => StatusCode::FORBIDDEN,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::MethodNotAllowed(_) => StatusCode::METHOD_NOT_ALLOWED,
+            Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            Self::Unprocessable(_) | Self::InvalidFilter(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
+            Self::Internal(_) | Self::OpenAI(_) | Self::Qdrant(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::ServiceUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Self::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
+
+    /// Returns the category of this error, sent as `code`.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::Auth(_) => ErrorCode::Unauthorized,
+            Self::Validation(_)
+            | Self::InvalidJson(_)
+            | Self::MissingJsonContentType
+            | Self::Unprocessable(_)
+            | Self::InvalidFilter(_)
+            | Self::PayloadTooLarge(_)
+            | Self::MethodNotAllowed(_) => ErrorCode::ValidationFailed,
+            Self::Forbidden { .. } => ErrorCode::Forbidden,
+            Self::NotFound(_) => ErrorCode::NotFound,
+            Self::Conflict(_) | Self::PreconditionFailed(_) => ErrorCode::Conflict,
+            Self::TooManyRequests { .. } => ErrorCode::RateLimited,
+            Self::Internal(_) => ErrorCode::Internal,
+            Self::OpenAI(_) => ErrorCode::UpstreamOpenAI,
+            Self::Qdrant(_) => ErrorCode::UpstreamQdrant,
+            Self::ServiceUnavailable(_) => ErrorCode::Unavailable,
+            Self::Timeout(_) => ErrorCode::Timeout,
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let mut body = ApiResponse::<Value>::error(self.code(), self.to_string());
+        if let Self::Forbidden { code, .. } | Self::TooManyRequests { code, .. } = &self {
+            body.error_code = Some(code.to_string());
+        }
+        let status = self.status_code();
+        if let Self::InvalidFilter(problems) = self {
+            body.error_code = Some("invalid_filter".to_string());
+            body.details = Some(problems);
+        }
+        (status, Json(body)).into_response()
+    }
+}
+
+/// JSON body extractor that reports malformed payloads with the API's
+/// error envelope instead of axum's plain-text rejection.
+/// 
+/// Syntax errors, type mismatches and missing fields produce a 400 response:
+/// 
+/// ```json
+/// { "data": null, "status": "error", "error": "invalid JSON: ..." }
+/// ```
+/// 
+/// A body sent without `Content-Type: application/json` is rejected with a
+/// 400 telling the client to set the header, unless `LENIENT_JSON_CONTENT_TYPE`
+/// is enabled, in which case the body is parsed as JSON whatever its type.
+pub struct ApiJson<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for ApiJson<T>
+where
+    T: DeserializeOwned,
+    Arc<AppState>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        if Arc::<AppState>::from_ref(state).config.lenient_json_content_type {
+            let body = Bytes::from_request(request, state)
+                .await
+                .map_err(|rejection| ApiError::body_rejected(rejection.status(), rejection.body_text()))?;
+            return Json::<T>::from_bytes(&body)
+                .map(|Json(value)| Self(value))
+                .map_err(|rejection| ApiError::InvalidJson(rejection.body_text()));
+        }
+
+        match Json::<T>::from_request(request, state).await {
+            Ok(Json(value)) => Ok(Self(value)),
+            Err(JsonRejection::MissingJsonContentType(_)) => Err(ApiError::MissingJsonContentType),
+            Err(rejection) => Err(ApiError::body_rejected(rejection.status(), rejection.body_text())),
+        }
+    }
+}
+
+/// Query string extractor that reports unparsable parameters with the
+/// API's error envelope (400) instead of axum's plain-text rejection.
+pub struct ApiQuery<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for ApiQuery<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        Query::<T>::from_request_parts(parts, state)
+            .await
+            .map(|Query(value)| Self(value))
+            .map_err(|rejection| ApiError::Validation(rejection.body_text()))
+    }
+}
+
+/// Path parameter extractor that reports unparsable segments, e.g. a
+/// non-numeric document id, with the API's error envelope (400).
+pub struct ApiPath<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for ApiPath<T>
+where
+    T: DeserializeOwned + Send,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        match Path::<T>::from_request_parts(parts, state).await {
+            Ok(Path(value)) => Ok(Self(value)),
+            // A route whose parameters don't match its handler is our bug
+            Err(rejection) if rejection.status().is_server_error() => Err(ApiError::Internal(rejection.body_text())),
+            Err(rejection) => Err(ApiError::Validation(rejection.body_text())),
+        }
+    }
+}
+
+/// `ApiJson` for endpoints whose body may be left out.
+/// 
+/// An empty body, with or without a content type, stands for `T::default()`.
+/// Any other body is read like `ApiJson`, so malformed JSON is still a 400.
+pub struct OptionalApiJson<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for OptionalApiJson<T>
+where
+    T: DeserializeOwned + Default,
+    Arc<AppState>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let headers = request.headers().clone();
+        let extensions = request.extensions().clone();
+        let body = Bytes::from_request(request, state)
+            .await
+            .map_err(|rejection| ApiError::body_rejected(rejection.status(), rejection.body_text()))?;
+        if body.is_empty() {
+            return Ok(Self(T::default()));
+        }
+
+        let mut request = Request::new(axum::body::Body::from(body));
+        *request.headers_mut() = headers;
+        *request.extensions_mut() = extensions;
+        ApiJson::from_request(request, state)
+            .await
+            .map(|ApiJson(value)| Self(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use qdrant_client::{qdrant::PointId, Payload};
+    use serde_json::json;
+
+    /// A hit for a document with every payload field the service writes.
+    fn hit() -> SearchHit {
+        let payload = Payload::try_from(json!({
+            "text": "Rust has no garbage collector",
+            "metadata": { "lang": "en" },
+            "updated_at": 1_700_000_000_000u64,
+            "version": 3,
+            "stale": true,
+            "source": "faq",
+        }))
+        .unwrap();
+        SearchHit::try_from(ScoredPoint {
+            id: Some(PointId::from(7)),
+            payload: payload.into(),
+            score: 0.5,
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn search_results_v2_snapshot() {
+        let results = SearchResults {
+            hits: vec![hit()],
+            query_time_ms: 4,
+            total_candidates: 1,
+            score_threshold: None,
+            low_confidence: false,
+        };
+        assert_eq!(
+            results.render(ApiVersion::V2),
+            json!({
+                "hits": [{
+                    "id": 7,
+                    "score": 0.5,
+                    "text": "Rust has no garbage collector",
+                    "metadata": { "lang": "en" },
+                    "updated_at": 1_700_000_000_000u64,
+                    "version": 3,
+                    "stale": true,
+                    "payload": { "source": "faq" },
+                }],
+                "query_time_ms": 4,
+                "total_candidates": 1,
+                "score_threshold": null,
+                "low_confidence": false,
+            })
+        );
+    }
+
+    #[test]
+    fn search_results_v1_snapshot() {
+        let results = SearchResults {
+            hits: vec![SearchHit {
+                low_confidence: Some(true),
+                ..hit()
+            }],
+            query_time_ms: 4,
+            total_candidates: 1,
+            score_threshold: Some(0.8),
+            low_confidence: true,
+        };
+        assert_eq!(
+            results.render(ApiVersion::V1),
+            json!({
+                "results": [{
+                    "id": 7,
+                    "score": 0.5,
+                    "payload": {
+                        "text": "Rust has no garbage collector",
+                        "metadata": { "lang": "en" },
+                        "updated_at": 1_700_000_000_000u64,
+                        "version": 3,
+                        "stale": true,
+                        "source": "faq",
+                    },
+                    "low_confidence": true,
+                }],
+                "score_threshold": 0.800000011920929,
+                "low_confidence": true,
+            })
+        );
+    }
+
+    #[test]
+    fn search_group_v1_nests_payloads() {
+        let group = SearchGroup {
+            id: json!("faq"),
+            hits: vec![hit()],
+        };
+        let rendered = group.render(ApiVersion::V1);
+        assert_eq!(rendered["id"], "faq");
+        assert_eq!(rendered["hits"][0]["payload"]["text"], "Rust has no garbage collector");
+        assert!(rendered["hits"][0].get("text").is_none());
+    }
+
+    #[test]
+    fn hit_without_optional_fields_leaves_them_out() {
+        let hit = SearchHit::try_from(ScoredPoint {
+            id: Some(PointId::from("3f8d7c1e-2a4b-4c5d-8e9f-0a1b2c3d4e5f".to_string())),
+            score: 0.25,
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(
+            serde_json::to_value(hit).unwrap(),
+            json!({
+                "id": "3f8d7c1e-2a4b-4c5d-8e9f-0a1b2c3d4e5f",
+                "score": 0.25,
+                "text": "",
+                "metadata": null,
+            })
+        );
+    }
+
+    #[test]
+    fn errors_map_to_status_and_code() {
+        let cases = [
+            (ApiError::Auth("x".into()), StatusCode::UNAUTHORIZED, "unauthorized"),
+            (ApiError::Validation("x".into()), StatusCode::BAD_REQUEST, "validation_failed"),
+            (ApiError::InvalidJson("x".into()), StatusCode::BAD_REQUEST, "validation_failed"),
+            (ApiError::MissingJsonContentType, StatusCode::BAD_REQUEST, "validation_failed"),
+            (ApiError::NotFound("x".into()), StatusCode::NOT_FOUND, "not_found"),
+            (ApiError::MethodNotAllowed("x".into()), StatusCode::METHOD_NOT_ALLOWED, "validation_failed"),
+            (ApiError::Conflict("x".into()), StatusCode::CONFLICT, "conflict"),
+            (ApiError::PreconditionFailed("x".into()), StatusCode::PRECONDITION_FAILED, "conflict"),
+            (ApiError::Unprocessable("x".into()), StatusCode::UNPROCESSABLE_ENTITY, "validation_failed"),
+            (ApiError::InvalidFilter(Vec::new()), StatusCode::UNPROCESSABLE_ENTITY, "validation_failed"),
+            (ApiError::PayloadTooLarge("x".into()), StatusCode::PAYLOAD_TOO_LARGE, "validation_failed"),
+            (ApiError::Forbidden { code: "x", message: "x".into() }, StatusCode::FORBIDDEN, "forbidden"),
+            (ApiError::TooManyRequests { code: "x", message: "x".into() }, StatusCode::TOO_MANY_REQUESTS, "rate_limited"),
+            (ApiError::Internal("x".into()), StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+            (ApiError::OpenAI("x".into()), StatusCode::INTERNAL_SERVER_ERROR, "upstream_openai"),
+            (ApiError::Qdrant("x".into()), StatusCode::INTERNAL_SERVER_ERROR, "upstream_qdrant"),
+            (ApiError::ServiceUnavailable("x".into()), StatusCode::SERVICE_UNAVAILABLE, "unavailable"),
+            (ApiError::Timeout("x".into()), StatusCode::GATEWAY_TIMEOUT, "timeout"),
+        ];
+        for (error, status, code) in cases {
+            let name = format!("{:?}", error);
+            let response = error.into_response();
+            assert_eq!(response.status(), status, "{}", name);
+            assert!(response.status().is_client_error() || response.status().is_server_error());
+            let (_, body) = response.into_parts();
+            let body = futures::executor::block_on(axum::body::to_bytes(body, usize::MAX)).unwrap();
+            let body: Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["status"], "error", "{}", name);
+            assert_eq!(body["code"], code, "{}", name);
+        }
+    }
+}
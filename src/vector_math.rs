@@ -0,0 +1,218 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+/// Error returned when a vector has no direction and cannot be normalized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("cannot normalize a zero-magnitude vector")]
+pub struct ZeroVector;
+
+/// Magnitudes at or below this value are treated as zero.
+///
+/// Dividing by a near-zero norm would blow tiny rounding noise up into
+/// a meaningless unit vector, so such inputs are rejected as well.
+pub const MIN_NORM: f32 = 1e-12;
+
+/// Returns the Euclidean (L2) norm of a vector.
+pub fn l2_norm(vector: &[f32]) -> f32 {
+    vector.iter().map(|v| v * v).sum::<f32>().sqrt()
+}
+
+/// Returns the dot product of two vectors of equal length.
+pub fn dot(a: &[f32], b: &[f32]) -> f32 {
+    debug_assert_eq!(a.len(), b.len(), "vectors must have the same dimension");
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+/// Returns the cosine similarity of two vectors of equal length, in `[-1, 1]`.
+///
+/// # Returns
+/// * `Ok(f32)` - The cosine of the angle between the vectors
+/// * `Err(ZeroVector)` - If either vector has a zero or near-zero magnitude
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> Result<f32, ZeroVector> {
+    let norms = l2_norm(a) * l2_norm(b);
+    if !norms.is_finite() || norms <= MIN_NORM {
+        return Err(ZeroVector);
+    }
+    // Rounding can push the ratio slightly past ±1
+    Ok((dot(a, b) / norms).clamp(-1.0, 1.0))
+}
+
+/// Returns the Euclidean distance between two vectors of equal length.
+pub fn euclidean_distance(a: &[f32], b: &[f32]) -> f32 {
+    debug_assert_eq!(a.len(), b.len(), "vectors must have the same dimension");
+    a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum::<f32>().sqrt()
+}
+
+/// Returns the Manhattan (L1) distance between two vectors of equal length.
+pub fn manhattan_distance(a: &[f32], b: &[f32]) -> f32 {
+    debug_assert_eq!(a.len(), b.len(), "vectors must have the same dimension");
+    a.iter().zip(b).map(|(x, y)| (x - y).abs()).sum()
+}
+
+/// Returns Spearman's rank correlation of two orderings of the same items.
+///
+/// Each ordering lists the item indices `0..n` from first to last. The
+/// result is 1.0 for the same order and -1.0 for reversed orders.
+pub fn rank_correlation(a: &[usize], b: &[usize]) -> f64 {
+    let n = a.len();
+    if n < 2 {
+        return 1.0;
+    }
+    let mut position = vec![0usize; n];
+    for (rank, &item) in b.iter().enumerate() {
+        position[item] = rank;
+    }
+    let squared: f64 = a
+        .iter()
+        .enumerate()
+        .map(|(rank, &item)| (rank as f64 - position[item] as f64).powi(2))
+        .sum();
+    let n = n as f64;
+    1.0 - 6.0 * squared / (n * (n * n - 1.0))
+}
+
+/// Scales a vector in place to unit length.
+///
+/// # Returns
+/// * `Ok(())` - If the vector was normalized
+/// * `Err(ZeroVector)` - If the vector's magnitude is zero, near zero, or not finite
+pub fn normalize_in_place(vector: &mut [f32]) -> Result<(), ZeroVector> {
+    let norm = l2_norm(vector);
+    if !norm.is_finite() || norm <= MIN_NORM {
+        return Err(ZeroVector);
+    }
+    vector.iter_mut().for_each(|v| *v /= norm);
+    Ok(())
+}
+
+/// Returns a unit-length copy of a vector.
+///
+/// # Returns
+/// * `Ok(Vec<f32>)` - The normalized vector
+/// * `Err(ZeroVector)` - If the vector's magnitude is zero, near zero, or not finite
+pub fn normalize(vector: &[f32]) -> Result<Vec<f32>, ZeroVector> {
+    let mut normalized = vector.to_vec();
+    normalize_in_place(&mut normalized)?;
+    Ok(normalized)
+}
+
+/// Errors that can occur while decoding a base64-encoded vector.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum VectorDecodeError {
+    /// The input is not valid base64
+    #[error("invalid base64 vector: {0}")]
+    Base64(String),
+    /// The decoded byte length is not a whole number of f32 values
+    #[error("decoded vector has {0} bytes, which is not a multiple of 4")]
+    Length(usize),
+    /// The decoded vector doesn't have the advertised dimension
+    #[error("decoded vector has {actual} dimensions, expected {expected}")]
+    Dimension { expected: usize, actual: usize },
+}
+
+/// Encodes a vector as base64 over its little-endian f32 bytes,
+/// the same layout OpenAI uses for `encoding_format: base64`.
+pub fn encode_base64(vector: &[f32]) -> String {
+    let bytes: Vec<u8> = vector.iter().flat_map(|v| v.to_le_bytes()).collect();
+    STANDARD.encode(bytes)
+}
+
+/// Decodes a base64 buffer of little-endian f32 values.
+///
+/// # Arguments
+/// * `encoded` - Base64-encoded little-endian f32 bytes
+/// * `expected_dimension` - Dimension the vector must have, if known
+///
+/// # Returns
+/// * `Ok(Vec<f32>)` - The decoded vector
+/// * `Err(VectorDecodeError)` - If the input is invalid or has the wrong length
+pub fn decode_base64(encoded: &str, expected_dimension: Option<usize>) -> Result<Vec<f32>, VectorDecodeError> {
+    let bytes = STANDARD
+        .decode(encoded)
+        .map_err(|e| VectorDecodeError::Base64(e.to_string()))?;
+    if bytes.len() % 4 != 0 {
+        return Err(VectorDecodeError::Length(bytes.len()));
+    }
+
+    let vector: Vec<f32> = bytes
+        .chunks_exact(4)
+        .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect();
+    match expected_dimension {
+        Some(expected) if expected != vector.len() => Err(VectorDecodeError::Dimension {
+            expected,
+            actual: vector.len(),
+        }),
+        _ => Ok(vector),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-6, "{} is not {}", actual, expected);
+    }
+
+    #[test]
+    fn normalized_vectors_have_unit_length_and_keep_their_direction() {
+        let vector = [3.0, -4.0, 0.0];
+        let unit = normalize(&vector).unwrap();
+        assert_close(l2_norm(&unit), 1.0);
+        assert_eq!(unit, [0.6, -0.8, 0.0]);
+        assert_close(cosine_similarity(&vector, &unit).unwrap(), 1.0);
+
+        let mut already = unit.clone();
+        normalize_in_place(&mut already).unwrap();
+        assert_eq!(already, unit);
+    }
+
+    #[test]
+    fn small_magnitudes_are_normalized_but_near_zero_ones_are_rejected() {
+        let tiny = normalize(&[1e-5, 1e-5]).unwrap();
+        assert_close(l2_norm(&tiny), 1.0);
+
+        for vector in [vec![0.0; 4], vec![1e-13, 0.0], vec![], vec![f32::NAN, 1.0], vec![f32::INFINITY, 1.0]] {
+            assert_eq!(normalize(&vector), Err(ZeroVector), "{:?}", vector);
+            let mut in_place = vector.clone();
+            assert_eq!(normalize_in_place(&mut in_place), Err(ZeroVector));
+            // A rejected vector is left as it was
+            assert_eq!(format!("{:?}", in_place), format!("{:?}", vector));
+        }
+        assert_eq!(cosine_similarity(&[0.0, 0.0], &[1.0, 0.0]), Err(ZeroVector));
+    }
+
+    #[test]
+    fn base64_vectors_round_trip_bit_for_bit() {
+        let vector = [0.1, -2.5e-8, f32::MAX, f32::MIN_POSITIVE, -0.0, 1.0];
+        let encoded = encode_base64(&vector);
+        assert_eq!(encoded.len(), 4 * vector.len() * 4 / 3);
+        let decoded = decode_base64(&encoded, Some(vector.len())).unwrap();
+        let bits = |v: &[f32]| v.iter().map(|x| x.to_bits()).collect::<Vec<_>>();
+        assert_eq!(bits(&decoded), bits(&vector));
+        assert_eq!(decode_base64(&encoded, None).unwrap().len(), vector.len());
+        assert_eq!(decode_base64("", Some(0)), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn malformed_base64_vectors_are_rejected() {
+        assert!(matches!(decode_base64("not base64!", None), Err(VectorDecodeError::Base64(_))));
+        // Six bytes are one and a half f32 values
+        let odd = STANDARD.encode([0u8; 6]);
+        assert_eq!(decode_base64(&odd, None), Err(VectorDecodeError::Length(6)));
+        let encoded = encode_base64(&[1.0, 2.0]);
+        assert_eq!(
+            decode_base64(&encoded, Some(3)),
+            Err(VectorDecodeError::Dimension { expected: 3, actual: 2 })
+        );
+    }
+
+    #[test]
+    fn rank_correlation_compares_orderings() {
+        assert_eq!(rank_correlation(&[0, 1, 2, 3], &[0, 1, 2, 3]), 1.0);
+        assert_eq!(rank_correlation(&[0, 1, 2, 3], &[3, 2, 1, 0]), -1.0);
+        // One swap of neighbours out of four: 1 - 6 * 2 / 60
+        assert!((rank_correlation(&[0, 1, 2, 3], &[1, 0, 2, 3]) - 0.8).abs() < 1e-9);
+        assert_eq!(rank_correlation(&[0], &[0]), 1.0);
+    }
+}
@@ -0,0 +1,220 @@
+use serde::Serialize;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::{
+    services::{openai::CompletionOptions, qdrant::{SearchScope, ShardingMode}},
+    state::AppState,
+};
+
+/// Outcome of one warm-up call.
+#[derive(Debug, Clone, Serialize)]
+pub struct WarmupStep {
+    /// What was called: `embedding`, `chat` or `qdrant`
+    pub name: &'static str,
+    /// How long the call took
+    pub latency_ms: u64,
+    /// Why the call failed, if it did
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// What the warm-up phase did before the server was marked ready.
+#[derive(Debug, Clone, Serialize)]
+pub struct WarmupReport {
+    /// Calls that completed, in the order they ran
+    pub steps: Vec<WarmupStep>,
+    /// Whether `WARMUP_TIMEOUT_SECS` elapsed before every call completed
+    pub timed_out: bool,
+}
+
+impl WarmupReport {
+    /// Returns whether any completed call failed.
+    pub fn failed(&self) -> bool {
+        self.steps.iter().any(|step| step.error.is_some())
+    }
+}
+
+/// Whether the server is ready for traffic, as reported by `/ready`.
+#[derive(Debug, Default)]
+pub struct Readiness {
+    ready: AtomicBool,
+    /// Report of the warm-up phase, filled in as its calls complete;
+    /// `None` when `WARMUP` is off
+    report: Mutex<Option<WarmupReport>>,
+}
+
+impl Readiness {
+    /// Returns whether the server has been marked ready.
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Acquire)
+    }
+
+    /// Marks the server ready, keeping the warm-up report if there was one.
+    pub fn mark_ready(&self, report: Option<WarmupReport>) {
+        *self.report.lock().unwrap_or_else(|e| e.into_inner()) = report;
+        self.ready.store(true, Ordering::Release);
+    }
+
+    /// Adds a completed warm-up call to the report, so it can be seen
+    /// before the server is ready.
+    pub fn record(&self, step: WarmupStep) {
+        let mut report = self.report.lock().unwrap_or_else(|e| e.into_inner());
+        report
+            .get_or_insert_with(|| WarmupReport { steps: Vec::new(), timed_out: false })
+            .steps
+            .push(step);
+    }
+
+    /// Returns the warm-up report, with the calls completed so far while
+    /// warming up.
+    pub fn report(&self) -> Option<WarmupReport> {
+        self.report.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+/// Makes one call to each upstream so connections, TLS sessions and
+/// Qdrant's caches are in place before the first request arrives.
+///
+/// Runs a tiny embedding, a one-token chat completion with the cheapest
+/// model in `CHAT_MODELS` and a Qdrant search for a random vector, one
+/// after the other. With custom sharding there is no shard key to search
+/// in, so the collection's info is read instead. With `DISABLE_OPENAI`
+/// only Qdrant is called. Failures are recorded in the report rather than
+/// returned, and each call is added to `state.readiness` as it completes.
+///
+/// # Arguments
+/// * `state` - Application state with the services to call
+/// * `timeout` - How long the whole phase may take; calls still running
+///   then are abandoned
+/// * `shutdown` - Resolves when the server is asked to stop; the calls
+///   still running then are abandoned and no report is returned
+pub async fn run(state: &AppState, timeout: Duration, shutdown: impl Future<Output = ()>) -> Option<WarmupReport> {
+    let mut steps = Vec::new();
+    let mut push = |step: WarmupStep| {
+        state.readiness.record(step.clone());
+        steps.push(step);
+    };
+    let calls = async {
+        if !state.openai.is_disabled() {
+            push(
+                step("embedding", async {
+                    state
+                        .openai
+                        .submit(|openai| async move { openai.get_embedding("warm-up", None).await })
+                        .await??;
+                    Ok(())
+                })
+                .await,
+            );
+
+            let options = CompletionOptions {
+                model: state
+                    .config
+                    .openai_prices
+                    .cheapest(&state.config.chat_models)
+                    .map(str::to_string),
+                max_tokens: Some(1),
+                ..CompletionOptions::default()
+            };
+            push(
+                step("chat", async {
+                    state
+                        .openai
+                        .submit(move |openai| async move { openai.generate_completion("ping", &[], &options).await })
+                        .await??;
+                    Ok(())
+                })
+                .await,
+            );
+        }
+
+        let qdrant = &state.qdrant_service;
+        push(
+            step("qdrant", async {
+                match (state.config.sharding, qdrant.vector_size()) {
+                    (ShardingMode::Auto, Some(size)) => {
+                        qdrant.search_scores(random_vector(size as usize), 1, SearchScope::default()).await?;
+                    }
+                    _ => {
+                        qdrant.collection_vector_size(qdrant.collection()).await?;
+                    }
+                }
+                Ok(())
+            })
+            .await,
+        );
+    };
+    let timed_out = tokio::select! {
+        result = tokio::time::timeout(timeout, calls) => result.is_err(),
+        () = shutdown => return None,
+    };
+
+    Some(WarmupReport { steps, timed_out })
+}
+
+/// Times a warm-up call; OpenAI calls include their wait for a worker.
+async fn step(name: &'static str, call: impl Future<Output = anyhow::Result<()>>) -> WarmupStep {
+    let start = Instant::now();
+    let result = call.await;
+    WarmupStep {
+        name,
+        latency_ms: start.elapsed().as_millis() as u64,
+        error: result.err().map(|e| format!("{:#}", e)),
+    }
+}
+
+/// A vector of the given size with components spread over [-1, 1).
+///
+/// Seeded from a random UUID, so each warm-up searches somewhere else.
+fn random_vector(size: usize) -> Vec<f32> {
+    // xorshift64; the seed must not be zero
+    let mut x = (Uuid::new_v4().as_u128() as u64) | 1;
+    (0..size)
+        .map(|_| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            (x >> 40) as f32 / (1u64 << 23) as f32 - 1.0
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{extract::Request, middleware::Next};
+    use std::time::Duration;
+
+    use super::*;
+    use crate::test_support::{self, TestApp};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn calls_are_reported_as_they_complete() {
+        let app = test_support::app(&[]).await;
+        let report = run(&app.state, Duration::from_secs(10), std::future::pending()).await.expect("not interrupted");
+
+        let names: Vec<_> = report.steps.iter().map(|step| step.name).collect();
+        assert_eq!(names, ["embedding", "chat", "qdrant"]);
+        assert!(!report.failed() && !report.timed_out, "{:?}", report);
+        let recorded = app.state.readiness.report().expect("steps are recorded");
+        assert_eq!(recorded.steps.len(), 3);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn a_shutdown_abandons_the_warm_up() {
+        // An OpenAI that never answers
+        let openai = crate::demo::openai::router().layer(axum::middleware::from_fn(|request: Request, next: Next| async move {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            next.run(request).await
+        }));
+        let app = TestApp::with_openai(test_support::config(&[]), openai).await;
+
+        let shutdown = tokio::time::sleep(Duration::from_millis(100));
+        let warm_up = run(&app.state, Duration::from_secs(30), shutdown);
+        let report = tokio::time::timeout(Duration::from_secs(5), warm_up).await.expect("warm-up stops at the signal");
+        assert!(report.is_none());
+    }
+}
@@ -41,11 +41,11 @@ test_endpoint() {
     fi
 }
 
-# Reset the database
+# Reset the database; once ADMIN_API_KEY is set, /api/reset takes only the admin key
 echo -e "\n${GREEN}Resetting database...${NC}"
 curl -s -X POST http://127.0.0.1:3000/api/reset \
     -H "Content-Type: application/json" \
-    -H "x-api-key: your_api_key_here" | jq '.' || echo "Failed to reset database"
+    -H "x-api-key: ${ADMIN_API_KEY:-your_api_key_here}" | jq '.' || echo "Failed to reset database"
 
 # Test chat endpoint BEFORE embedding (should have no knowledge)
 echo -e "\n${GREEN}Testing chat BEFORE embedding (fresh database)...${NC}"
@@ -53,7 +53,7 @@ test_endpoint "/api/chat" '{"message": "Rust là ngôn ngữ lập trình gì?"}
 
 # Test document storage with embedding
 echo -e "\n${GREEN}Storing document with embedding...${NC}"
-test_endpoint "/api/embed" '{
+test_endpoint "/api/documents" '{
     "text": "Rust là một ngôn ngữ lập trình hệ thống hiện đại, tập trung vào hiệu suất, an toàn và đồng thời. Nó ngăn chặn các lỗi segmentation và đảm bảo an toàn thread.",
     "metadata": {
         "title": "Giới thiệu về Rust",